@@ -1,19 +1,136 @@
+mod bench;
 mod build;
 mod check;
+mod coverage;
+mod debug;
+mod doctor;
+mod embed;
 mod gen;
+mod migrate;
+mod package;
 mod run;
 mod show;
 mod test;
+mod version;
 
-use crate::ctx::Commands;
+use std::collections::BTreeMap;
+use std::io::BufRead;
+use std::process::{ExitStatus, Stdio};
+use clap::ArgMatches;
+use crate::ctx::{Commands, Context, DynResult, RunResult, StrError};
 
 pub fn init() -> Commands {
   let mut commands = Commands::new();
-  commands.insert("build", Box::new(build::Build));
-  commands.insert("check", Box::new(check::Check));
-  commands.insert("gen",   Box::new(gen::Gen));
-  commands.insert("run",   Box::new(run::Run));
-  commands.insert("show",  Box::new(show::Show));
-  commands.insert("test",  Box::new(test::Test));
+  commands.insert("bench",          Box::new(bench::Bench));
+  commands.insert("build",          Box::new(build::Build));
+  commands.insert("check",          Box::new(check::Check));
+  commands.insert("coverage",       Box::new(coverage::Coverage));
+  commands.insert("debug",          Box::new(debug::Debug));
+  commands.insert("doctor",         Box::new(doctor::Doctor));
+  commands.insert("embed",          Box::new(embed::Embed));
+  commands.insert("gen",            Box::new(gen::Gen));
+  commands.insert("migrate",        Box::new(migrate::Migrate));
+  commands.insert("package",        Box::new(package::Package));
+  commands.insert("run",            Box::new(run::Run));
+  commands.insert("show",           Box::new(show::Show));
+  commands.insert("test",           Box::new(test::Test));
+  commands.insert("version-header", Box::new(version::VersionHeader));
   commands
 }
+
+/// Expands a project alias into the command line to parse in its place.
+///
+/// Returns `None` when the requested command is built-in. Arguments following
+/// the alias in `argv`, the command line `args` were parsed from, are appended
+/// after its expansion.
+pub fn expand_alias(commands: &Commands, aliases: &BTreeMap<&str, &str>,
+                    argv: &[String], args: &ArgMatches) -> DynResult<Option<Vec<String>>>
+{
+  let (name, sub) = match args.subcommand() {
+    (name, Some(sub)) if !commands.contains_key(name) => (name, sub),
+    _ => return Ok(None)
+  };
+
+  let alias = aliases.get(name)
+    .ok_or_else(|| StrError(format!("No such command or alias: {}", name)))?;
+
+  let rest  = sub.values_of("").map(|x| x.collect::<Vec<&str>>()).unwrap_or_default();
+  let start = argv.len().checked_sub(rest.len() + 1)
+    .filter(|&i| argv[i] == name)
+    .ok_or_else(|| StrError(format!("Alias {} not found on the command line", name)))?;
+
+  let mut expanded = argv[.. start].to_vec();
+  expanded.extend(alias.split_whitespace().map(String::from));
+  expanded.extend(rest.into_iter().map(String::from));
+  Ok(Some(expanded))
+}
+
+/// Runs the project's hook commands in order, stopping at the first failure.
+pub fn run_hooks(ctx: &Context, name: &str, hooks: &[&str]) -> RunResult {
+  for &hook in hooks {
+    ctx.log.verbose(format!("Running {} hook: {}", name, hook));
+
+    #[cfg(windows)]
+    let mut cmd = std::process::Command::new("cmd");
+    #[cfg(windows)]
+    cmd.args(&["/C", hook]);
+
+    #[cfg(not(windows))]
+    let mut cmd = std::process::Command::new("sh");
+    #[cfg(not(windows))]
+    cmd.args(&["-c", hook]);
+
+    cmd.current_dir(&ctx.input_dir)
+      .env("JANK_INPUT_DIR", &ctx.input_dir)
+      .env("JANK_BUILD_DIR", &ctx.build_dir);
+
+    report_step(ctx, name, "started");
+    let status = run_tool(ctx, name, &mut cmd)?;
+    report_step(ctx, name, match status.success() {
+      true  => "finished",
+      false => "failed"
+    });
+
+    if !status.success() {
+      return Err(Box::new(StrError(format!("Hook {} failed ({}): {}", name, status, hook))));
+    }
+  }
+  Ok(())
+}
+
+/// Reports the progress of a build step, ie a hook or a native build tool, as a
+/// `build-step` event. States are "started", then "finished" or "failed".
+pub fn report_step(ctx: &Context, step: &str, state: &str) {
+  ctx.log.event("build-step", serde_json::json!({ "step": step, "state": state }));
+}
+
+/// Runs a native tool as a build step, failing when it does.
+pub fn run_step(ctx: &Context, tool: &str, cmd: &mut std::process::Command) -> RunResult {
+  report_step(ctx, tool, "started");
+  let status = run_tool(ctx, tool, cmd)?;
+  match status.success() {
+    true  => {
+      report_step(ctx, tool, "finished");
+      Ok(())
+    },
+    false => {
+      report_step(ctx, tool, "failed");
+      Err(Box::new(StrError(format!("{} failed ({})", tool, status))))
+    }
+  }
+}
+
+/// Runs a native tool to completion. Its output is forwarded as `tool-output`
+/// events with `--message-format json`, keeping stdout line-delimited.
+pub fn run_tool(ctx: &Context, tool: &str, cmd: &mut std::process::Command) -> std::io::Result<ExitStatus> {
+  if !ctx.log.is_json() {
+    return cmd.status();
+  }
+
+  let mut child = cmd.stdout(Stdio::piped()).spawn()?;
+  let stdout    = std::io::BufReader::new(child.stdout.take().unwrap());
+  for line in stdout.lines() {
+    ctx.log.event("tool-output", serde_json::json!({ "tool": tool, "line": line? }));
+  }
+  child.wait()
+}
@@ -0,0 +1,101 @@
+use clap::{App, Arg};
+use std::path::PathBuf;
+
+use crate::cmd::run_tool;
+use crate::cmd::test::{get_host_platform, get_test_exe};
+use crate::ctx::{Command, Context, RunResult, StrError};
+use crate::gen::benchmarks;
+
+pub struct Bench;
+
+impl Command for Bench {
+  fn init<'a, 'b>(&self, cmd: App<'a, 'b>) -> App<'a, 'b> {
+    cmd.about("Runs the project's benchmarks, writing their results as JSON")
+      .arg(Arg::with_name("profile")
+           .long("profile")
+           .value_name("PROFILE")
+           .help("Profile whose build is run, Release by default")
+           .takes_value(true))
+      .arg(Arg::with_name("target")
+           .long("target")
+           .value_name("TARGET")
+           .help("Benchmark target to run, all of them by default")
+           .takes_value(true))
+      .arg(Arg::with_name("filter")
+           .long("filter")
+           .value_name("REGEX")
+           .help("Only run the benchmarks whose name matches this regex")
+           .takes_value(true))
+      .arg(Arg::with_name("output")
+           .long("output")
+           .value_name("DIR")
+           .help("Folder of the JSON results, the build's bench folder by default")
+           .takes_value(true))
+  }
+
+  /// Each target's results are written to `<target>.json` in Google Benchmark's
+  /// own format, for CI jobs to track over time.
+  fn run(&self, ctx: &Context) -> RunResult {
+    let args     = ctx.args.subcommand_matches("bench");
+    let platform = get_host_platform();
+    let only     = args.and_then(|x| x.value_of("target"));
+    let targets  = ctx.project.targets.iter()
+      .filter(|(name, t)| {
+        t.benchmark && ctx.project.filter.matches_platform(platform) && t.filter.matches_platform(platform) &&
+          only.map_or(true, |x| x == **name)
+      })
+      .collect::<Vec<_>>();
+    if targets.is_empty() {
+      return Err(Box::new(StrError(format!("No benchmark targets to run on {}", platform.to_str()))));
+    }
+
+    let dir = match args.and_then(|x| x.value_of("output")) {
+      None    => ctx.build_dir.join("bench"),
+      Some(x) => PathBuf::from(x)
+    };
+    std::fs::create_dir_all(&dir)?;
+
+    for (name, target) in targets {
+      let profiles = benchmarks::get_profiles(ctx, target, platform);
+      let default  = profiles.iter().cloned().find(|&x| x == "Release").or_else(|| profiles.first().cloned());
+      let profile  = match (args.and_then(|x| x.value_of("profile")), default) {
+        (Some(p), _) if profiles.contains(&p) => p,
+        (Some(p), _) => return Err(Box::new(StrError(format!("Profile {} does not build {}", p, name)))),
+        (None, Some(p)) => p,
+        (None, None)    => return Err(Box::new(StrError(format!("No optimized profile builds {}", name))))
+      };
+
+      let exe    = get_test_exe(ctx, name, target, platform, profile)?;
+      let output = dir.join([name, ".json"].join(""));
+      let mut cmd = std::process::Command::new(&exe);
+      cmd.current_dir(exe.parent().unwrap())
+        .arg(format!("--benchmark_out={}", output.display()))
+        .arg("--benchmark_out_format=json");
+      if let Some(filter) = args.and_then(|x| x.value_of("filter")) {
+        cmd.arg(format!("--benchmark_filter={}", filter));
+      }
+
+      let status = run_tool(ctx, name, &mut cmd)?;
+      if !status.success() {
+        return Err(Box::new(StrError(format!("Benchmark {} failed ({})", name, status))));
+      }
+
+      if ctx.log.is_json() {
+        let results: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(&output)?)?;
+        for b in results["benchmarks"].as_array().into_iter().flatten() {
+          ctx.log.event("benchmark", serde_json::json!({
+            "target":     name,
+            "name":       b["name"],
+            "iterations": b["iterations"],
+            "real_time":  b["real_time"],
+            "cpu_time":   b["cpu_time"],
+            "time_unit":  b["time_unit"]
+          }));
+        }
+      }
+
+      ctx.log.info(format!("Benchmark {} results written to {}", name, output.display()));
+    }
+    Ok(())
+  }
+}
@@ -1,15 +1,486 @@
-use clap::{App};
+use clap::{App, Arg};
+use serde::Serialize;
+use std::path::{Path, PathBuf};
 
-use crate::ctx::{Command, Context, RunResult};
+use crate::cmd::{report_step, run_hooks, run_step, run_tool};
+use crate::ctx::{hash_bytes, Command, Context, DynResult, MacOSPackage, PlatformType, RunResult, StrError,
+                 Target, TargetType, HASH_INIT};
+use crate::gen::{symbols, wix};
 
 pub struct Build;
 
 impl Command for Build {
   fn init<'a, 'b>(&self, cmd: App<'a, 'b>) -> App<'a, 'b> {
     cmd.about("Builds the project's targets")
+      .arg(Arg::with_name("profile")
+           .long("profile")
+           .value_name("PROFILE")
+           .help("Profile to build, all of them by default")
+           .takes_value(true))
+      .arg(Arg::with_name("bundle")
+           .long("bundle")
+           .help("Packages Android applications as App Bundles instead of APKs"))
+      .arg(Arg::with_name("upload-symbols")
+           .long("upload-symbols")
+           .help("Uploads the debug symbols of Release builds to the configured service"))
   }
 
-  fn run(&self, _ctx: &Context) -> RunResult {
-    Ok(())
+  fn run(&self, ctx: &Context) -> RunResult {
+    let args = ctx.args.subcommand_matches("build");
+    let profiles = match args.and_then(|x| x.value_of("profile")) {
+      None    => ctx.profiles.clone(),
+      Some(p) => match ctx.profiles.contains(&p) {
+        true  => vec![p],
+        false => return Err(Box::new(StrError(format!("No such profile: {}", p))))
+      }
+    };
+
+    run_hooks(ctx, "pre_build", &ctx.project.hooks.pre_build)?;
+    // TODO invoke the native build tools of the other platforms
+    build_android(ctx, &profiles, args.map_or(false, |x| x.is_present("bundle")))?;
+    package_macos(ctx, &profiles)?;
+    package_windows(ctx, &profiles)?;
+    let artifacts = write_artifacts(ctx)?;
+
+    if args.map_or(false, |x| x.is_present("upload-symbols")) {
+      upload_symbols(ctx, &artifacts)?;
+    }
+
+    run_hooks(ctx, "post_build", &ctx.project.hooks.post_build)
+  }
+}
+
+
+// Artifacts
+// -----------------------------------------------------------------------------
+
+/// Outputs of the build, for packaging and upload steps to consume without
+/// knowing where each generator's native tools put them.
+#[derive(Serialize)]
+struct Artifact<'a> {
+  target:   &'a str,
+  platform: &'static str,
+  profile:  &'a str,
+  #[serde(rename = "type")]
+  kind:     &'static str,
+  path:     String,
+  size:     u64,
+  hash:     String,
+  #[serde(skip)]
+  symbols:  PathBuf
+}
+
+const ARTIFACTS_FILE: &str = "artifacts.json";
+
+const PLATFORMS: &[PlatformType] = &[
+  PlatformType::Windows,
+  PlatformType::Linux,
+  PlatformType::MacOS,
+  PlatformType::Android,
+  PlatformType::HTML5
+];
+
+fn write_artifacts<'a>(ctx: &Context<'a>) -> DynResult<Vec<Artifact<'a>>> {
+  let mut artifacts = Vec::new();
+
+  for (name, target) in &ctx.project.targets {
+    for &platform in PLATFORMS {
+      if !ctx.project.filter.matches_platform(platform) || !target.filter.matches_platform(platform) {
+        continue;
+      }
+
+      for &profile in &ctx.profiles {
+        let artifact = get_artifact_path(ctx, name, target, platform, profile);
+        if let Some((kind, path, symbols)) = artifact {
+          // Only record what the native tools actually produced.
+          if let Ok(bytes) = std::fs::read(ctx.build_dir.join(&path)) {
+            artifacts.push(Artifact {
+              target:   name,
+              platform: platform.to_str(),
+              profile,
+              kind,
+              path:     path.to_str().unwrap().replace('\\', "/"),
+              size:     bytes.len() as u64,
+              hash:     format!("{:016x}", hash_bytes(HASH_INIT, &bytes)),
+              symbols
+            });
+          }
+        }
+      }
+    }
+  }
+
+  for artifact in &artifacts {
+    ctx.log.event("artifact", serde_json::to_value(artifact)?);
+  }
+
+  let f = std::fs::File::create(ctx.build_dir.join(ARTIFACTS_FILE))?;
+  serde_json::to_writer_pretty(f, &artifacts)?;
+  Ok(artifacts)
+}
+
+/// Gradle names the packages after the module, Release ones are unsigned without a key.
+fn get_apk_name(ctx: &Context, name: &str, target: &Target, profile: &str) -> String {
+  let base   = [name, "_Android-", profile.to_lowercase().as_str()].join("");
+  let signed = target.android.keystore.or(ctx.project.android.keystore).is_some() ||
+    ctx.env.jank_android_keystore.is_some();
+  match (profile, signed) {
+    ("Release", false) => [base.as_str(), "-unsigned.apk"].join(""),
+    _                  => [base.as_str(), ".apk"].join("")
+  }
+}
+
+fn get_aar_name(name: &str, profile: &str) -> String {
+  [name, "_Android-", profile.to_lowercase().as_str(), ".aar"].join("")
+}
+
+/// Name of a target in the Xcode project, suffixed with the platform when it is
+/// built for many Apple platforms.
+pub fn get_xcode_target(ctx: &Context, name: &str, target: &Target, platform: PlatformType) -> String {
+  let apple = [PlatformType::MacOS, PlatformType::IOS, PlatformType::TVOS, PlatformType::WatchOS];
+  let count = apple.iter()
+    .filter(|&&p| ctx.project.filter.matches_platform(p) && target.filter.matches_platform(p))
+    .count();
+  match count {
+    1 => name.to_string(),
+    _ => format!("{} ({})", name, platform.to_str())
+  }
+}
+
+/// Output location relative to the build directory, following the conventions
+/// of the generator handling each platform. TODO the other Apple platforms
+pub fn get_artifact_path(ctx: &Context, name: &str, target: &Target, platform: PlatformType,
+                         profile: &str) -> Option<(&'static str, PathBuf, PathBuf)>
+{
+  let (kind, file) = match (platform, target.target_type) {
+    (PlatformType::Windows, TargetType::Console)       |
+    (PlatformType::Windows, TargetType::Application)   => ("executable",     [name, ".exe"].join("")),
+    (PlatformType::Windows, TargetType::StaticLibrary) => ("static_library", [name, ".lib"].join("")),
+    (PlatformType::Windows, TargetType::SharedLibrary) => ("shared_library", [name, ".dll"].join("")),
+
+    (PlatformType::MacOS,   TargetType::Console)       => ("executable",     name.to_string()),
+    (PlatformType::MacOS,   TargetType::Application)   => ("application",    [name, ".app"].join("")),
+    (PlatformType::MacOS,   TargetType::StaticLibrary) => ("static_library", ["lib", name, ".a"].join("")),
+    (PlatformType::MacOS,   TargetType::SharedLibrary) => ("shared_library", ["lib", name, ".dylib"].join("")),
+
+    (PlatformType::HTML5,   TargetType::Application)   => ("executable",     [name, ".wasm"].join("")),
+
+    (PlatformType::Android, TargetType::Application)   => ("package",        get_apk_name(ctx, name, target, profile)),
+    (PlatformType::Android, TargetType::SharedLibrary) => ("android_library", get_aar_name(name, profile)),
+    (PlatformType::Android, _)                         => return None,
+
+    (_,                     TargetType::Console)       |
+    (_,                     TargetType::Application)   => ("executable",     name.to_string()),
+    (_,                     TargetType::StaticLibrary) => ("static_library", ["lib", name, ".a"].join("")),
+    (_,                     TargetType::SharedLibrary) => ("shared_library", ["lib", name, ".so"].join("")),
+    _ => return None
+  };
+
+  let dir = match platform {
+    PlatformType::Windows => PathBuf::from("x64").join(profile).join(name), // TODO other architectures
+    PlatformType::MacOS   => PathBuf::from("build").join(profile),
+    PlatformType::HTML5   => PathBuf::from([name, "_HTML5"].join("")).join("dist"),
+    PlatformType::Android => match kind {
+      "package" => PathBuf::from([name, "_Android"].join("")).join("build/outputs/apk")
+        .join(profile.to_lowercase()),
+      _         => PathBuf::from([name, "_Android"].join("")).join("build/outputs/aar")
+    },
+    _                     => PathBuf::from([name, "_", platform.to_str()].join(""))
+  };
+
+  let path = dir.join(&file);
+  let symbols = match platform {
+    PlatformType::Windows => path.with_extension("pdb"),
+    PlatformType::MacOS   => dir.join([file.as_str(), ".dSYM"].join("")),
+    _                     => path.clone()
+  };
+
+  // Application bundles are directories, hash their executable instead.
+  match kind {
+    "application" => Some((kind, path.join("Contents").join("MacOS").join(name), symbols)),
+    _             => Some((kind, path, symbols))
+  }
+}
+
+
+// Android
+// -----------------------------------------------------------------------------
+
+const ANDROID_DIST: &str = "dist/android";
+
+/// Runs Gradle for the Android applications and libraries, then copies their
+/// packages to `dist/android/<profile>` so CI does not need to know Gradle's layout.
+pub fn build_android(ctx: &Context, profiles: &[&str], bundle: bool) -> RunResult {
+  if !ctx.project.filter.matches_platform(PlatformType::Android) ||
+    !ctx.build_dir.join("settings.gradle").is_file()
+  {
+    return Ok(());
+  }
+
+  let modules = ctx.project.targets.iter()
+    .filter(|(_, t)| {
+      (t.target_type == TargetType::Application || t.target_type == TargetType::SharedLibrary) &&
+        t.filter.matches_platform(PlatformType::Android)
+    })
+    .collect::<Vec<_>>();
+  if modules.is_empty() {
+    return Ok(());
+  }
+
+  // Libraries are always packaged as AARs.
+  let is_bundle = |t: &Target| bundle && t.target_type == TargetType::Application;
+
+  let mut tasks = Vec::new();
+  for (name, target) in &modules {
+    let action = match is_bundle(target) {
+      true  => "bundle",
+      false => "assemble"
+    };
+    for &profile in profiles {
+      tasks.push(format!(":{}_Android:{}{}", name, action, get_build_type_task(profile)));
+    }
+  }
+
+  #[cfg(windows)]      let wrapper = "gradlew.bat";
+  #[cfg(not(windows))] let wrapper = "gradlew";
+  let gradle = match ctx.build_dir.join(wrapper).is_file() {
+    true  => ctx.build_dir.join(wrapper),
+    false => PathBuf::from("gradle")
+  };
+
+  report_step(ctx, "gradle", "started");
+  let mut cmd = std::process::Command::new(&gradle);
+  cmd.args(&tasks).current_dir(&ctx.build_dir);
+  let status = run_tool(ctx, "gradle", &mut cmd)?;
+  report_step(ctx, "gradle", match status.success() {
+    true  => "finished",
+    false => "failed"
+  });
+  if !status.success() {
+    return Err(Box::new(StrError(format!("Gradle failed ({})", status))));
+  }
+
+  for (name, target) in &modules {
+    for &profile in profiles {
+      let src = ctx.build_dir.join(match is_bundle(target) {
+        true  => get_bundle_path(name, profile),
+        false => get_artifact_path(ctx, name, target, PlatformType::Android, profile).unwrap().1
+      });
+
+      let dir = ctx.build_dir.join(ANDROID_DIST).join(profile.to_lowercase());
+      std::fs::create_dir_all(&dir)?;
+
+      let dst = dir.join(src.file_name().unwrap());
+      std::fs::copy(&src, &dst)?;
+      match ctx.log.is_json() {
+        true  => ctx.log.event("package", serde_json::json!({ "target": name, "profile": profile, "path": dst })),
+        false => println!("{}", dst.display())
+      }
+    }
+  }
+
+  Ok(())
+}
+
+/// Gradle tasks are named after the build types, which are the lowercase profile names.
+fn get_build_type_task(profile: &str) -> String {
+  let build_type = profile.to_lowercase();
+  let mut chars  = build_type.chars();
+  match chars.next() {
+    None    => String::new(),
+    Some(c) => c.to_uppercase().chain(chars).collect()
+  }
+}
+
+fn get_bundle_path(name: &str, profile: &str) -> PathBuf {
+  let build_type = profile.to_lowercase();
+  PathBuf::from([name, "_Android"].join(""))
+    .join("build/outputs/bundle")
+    .join(&build_type)
+    .join([name, "_Android-", build_type.as_str(), ".aab"].join(""))
+}
+
+
+// macOS
+// -----------------------------------------------------------------------------
+
+const MACOS_DIST: &str = "dist/macos";
+
+/// Signs the macOS applications built by Xcode, then packages them to `dist/macos/<profile>`.
+/// With notarization credentials, Release packages are only copied there once notarized
+/// and stapled, so CI never publishes one Gatekeeper would reject.
+fn package_macos(ctx: &Context, profiles: &[&str]) -> RunResult {
+  let identity = ctx.env.jank_macos_signing_identity.as_deref().or(ctx.project.macos.signing_identity);
+  let identity = match identity {
+    None    => return Ok(()),
+    Some(x) => x
+  };
+
+  let apps = ctx.project.targets.iter().filter(|(_, t)| {
+    t.target_type == TargetType::Application &&
+      ctx.project.filter.matches_platform(PlatformType::MacOS) &&
+      t.filter.matches_platform(PlatformType::MacOS)
+  });
+
+  for (name, _) in apps {
+    for &profile in profiles {
+      // Only package what Xcode actually built.
+      let app = ctx.build_dir.join("build").join(profile).join([name, ".app"].join(""));
+      if !app.is_dir() {
+        continue;
+      }
+
+      let mut cmd = std::process::Command::new("codesign");
+      cmd.args(&["--deep", "--force", "--options", "runtime", "--timestamp", "--sign", identity]);
+      if let Some(x) = ctx.project.macos.entitlements {
+        cmd.arg("--entitlements").arg(ctx.input_dir.join(x));
+      }
+      run_step(ctx, "codesign", cmd.arg(&app))?;
+      run_step(ctx, "codesign", std::process::Command::new("codesign")
+               .args(&["--verify", "--deep", "--strict"]).arg(&app))?;
+
+      let notarize = profile == symbols::PROFILE && has_notary_credentials(ctx);
+      let package  = create_macos_package(ctx, name, profile, &app)?;
+      if notarize {
+        notarize_macos_package(ctx, &package, &app)?;
+      }
+
+      let dir = ctx.build_dir.join(MACOS_DIST).join(profile.to_lowercase());
+      std::fs::create_dir_all(&dir)?;
+
+      let dst = dir.join(package.file_name().unwrap());
+      std::fs::rename(&package, &dst)?;
+      match ctx.log.is_json() {
+        true  => ctx.log.event("package", serde_json::json!({
+          "target":    name,
+          "profile":   profile,
+          "path":      dst,
+          "notarized": notarize
+        })),
+        false => println!("{}", dst.display())
+      }
+    }
+  }
+
+  Ok(())
+}
+
+/// Packages are created next to the application, and moved to the dist folder once complete.
+fn create_macos_package(ctx: &Context, name: &str, profile: &str, app: &Path) -> DynResult<PathBuf> {
+  let dir = app.parent().unwrap();
+  match ctx.project.macos.package {
+    MacOSPackage::Dmg => {
+      let dmg = dir.join([name, "-", profile.to_lowercase().as_str(), ".dmg"].join(""));
+      run_step(ctx, "hdiutil", std::process::Command::new("hdiutil")
+               .args(&["create", "-volname", name, "-format", "UDZO", "-ov", "-srcfolder"]).arg(app)
+               .arg(&dmg))?;
+      Ok(dmg)
+    },
+    MacOSPackage::Zip => {
+      let zip = dir.join([name, "-", profile.to_lowercase().as_str(), ".zip"].join(""));
+      run_step(ctx, "ditto", std::process::Command::new("ditto")
+               .args(&["-c", "-k", "--keepParent"]).arg(app).arg(&zip))?;
+      Ok(zip)
+    }
   }
 }
+
+fn has_notary_credentials(ctx: &Context) -> bool {
+  ctx.env.jank_notary_keychain_profile.is_some() || ctx.env.jank_notary_apple_id.is_some()
+}
+
+/// Submits a package to Apple's notary service and staples the ticket. Zip archives cannot
+/// be stapled, the application is stapled then archived again in their place.
+fn notarize_macos_package(ctx: &Context, package: &Path, app: &Path) -> RunResult {
+  let env = &ctx.env;
+  let mut cmd = std::process::Command::new("xcrun");
+  cmd.args(&["notarytool", "submit", "--wait"]).arg(package);
+  match &env.jank_notary_keychain_profile {
+    Some(profile) => { cmd.arg("--keychain-profile").arg(profile); },
+    None          => {
+      let (password, team) = match (&env.jank_notary_password, &env.jank_notary_team_id) {
+        (Some(password), Some(team)) => (password, team),
+        _ => return Err(Box::new(StrError(
+          "Notarizing with an Apple ID needs JANK_NOTARY_PASSWORD and JANK_NOTARY_TEAM_ID".to_string())))
+      };
+      cmd.arg("--apple-id").arg(env.jank_notary_apple_id.as_ref().unwrap())
+        .arg("--password").arg(password)
+        .arg("--team-id").arg(team);
+    }
+  }
+  run_step(ctx, "notarytool", &mut cmd)?;
+
+  let is_zip = package.extension().map_or(false, |x| x == "zip");
+  let stapled = match is_zip {
+    true  => app,
+    false => package
+  };
+  run_step(ctx, "stapler", std::process::Command::new("xcrun").args(&["stapler", "staple"]).arg(stapled))?;
+  run_step(ctx, "stapler", std::process::Command::new("xcrun").args(&["stapler", "validate"]).arg(stapled))?;
+
+  if is_zip {
+    std::fs::remove_file(package)?;
+    run_step(ctx, "ditto", std::process::Command::new("ditto")
+             .args(&["-c", "-k", "--keepParent"]).arg(app).arg(package))?;
+  }
+  Ok(())
+}
+
+
+// Windows
+// -----------------------------------------------------------------------------
+
+const WINDOWS_DIST: &str = "dist/windows";
+
+/// Builds the MSI of the installer generated by `gen::wix`, for each profile
+/// whose application was built, to `dist/windows/<profile>`.
+fn package_windows(ctx: &Context, profiles: &[&str]) -> RunResult {
+  let mut wxs = ctx.build_dir.join(&ctx.project.name);
+  wxs.set_extension("wxs");
+  if ctx.project.wix.is_none() || !wxs.is_file() {
+    return Ok(());
+  }
+
+  let version = wix::get_version(ctx);
+  for &profile in profiles {
+    if !ctx.build_dir.join("x64").join(profile).is_dir() {
+      continue;
+    }
+
+    let dir = ctx.build_dir.join(WINDOWS_DIST).join(profile.to_lowercase());
+    std::fs::create_dir_all(&dir)?;
+
+    let msi = dir.join([ctx.project.name, "-", version, ".msi"].join(""));
+    run_step(ctx, "wix", std::process::Command::new("wix")
+             .arg("build").arg(&wxs)
+             .args(&["-arch", "x64", "-d"]).arg(format!("BuildDir={}", ctx.build_dir.display()))
+             .arg("-d").arg(format!("Profile={}", profile))
+             .arg("-o").arg(&msi))?;
+    match ctx.log.is_json() {
+      true  => ctx.log.event("package", serde_json::json!({ "profile": profile, "path": msi })),
+      false => println!("{}", msi.display())
+    }
+  }
+
+  Ok(())
+}
+
+// Symbols
+// -----------------------------------------------------------------------------
+
+fn upload_symbols(ctx: &Context, artifacts: &[Artifact]) -> RunResult {
+  if ctx.project.symbols.service.is_none() {
+    return Err(Box::new(StrError("No symbol upload service configured".to_string())));
+  }
+
+  let commands = artifacts.iter()
+    .filter(|x| x.profile == symbols::PROFILE && x.kind != "static_library")
+    .filter_map(|x| {
+      let path = ctx.build_dir.join(&x.symbols);
+      let dir  = path.parent().unwrap();
+      symbols::upload_command(ctx, dir.to_str().unwrap(), path.to_str().unwrap())
+    })
+    .collect::<Vec<String>>();
+
+  run_hooks(ctx, "upload_symbols", &commands.iter().map(String::as_str).collect::<Vec<&str>>())
+}
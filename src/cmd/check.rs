@@ -1,6 +1,11 @@
+use std::path::PathBuf;
+
 use clap::{App};
 
-use crate::ctx::{Command, Context, RunResult};
+use crate::ctx::{Architecture, Command, Context, PlatformType, Profiles, RunResult, Settings, StrError,
+                 TargetType};
+use crate::diag::Diagnostics;
+use crate::platform::android::ndk_revision;
 
 pub struct Check;
 
@@ -9,7 +14,141 @@ impl Command for Check {
     cmd.about("Checks whether the project's configuration is valid")
   }
 
-  fn run(&self, _ctx: &Context) -> RunResult {
-    Ok(())
+  /// Reports the problems found here along with the ones found while loading the project.
+  fn run(&self, ctx: &Context) -> RunResult {
+    for (index, name) in ctx.project.targets.keys().enumerate() {
+      for prof in &ctx.profiles {
+        check_defines(&ctx.diagnostics, &collect_defines(ctx, index, prof), name, prof);
+      }
+    }
+
+    check_android_ndk(&ctx.diagnostics, ctx);
+
+    match ctx.diagnostics.report(&ctx.warnings) {
+      0 => Ok(()),
+      n => Err(Box::new(StrError(format!("{} problem(s) found", n))))
+    }
+  }
+}
+
+/// A preprocessor definition along with where it was declared.
+struct Define<'b> {
+  name:   &'b str,
+  value:  &'b str,
+  origin: String
+}
+
+/// Defines which contradict each other when both are set.
+const EXCLUSIVE: &[(&str, &str)] = &[
+  ("NDEBUG", "DEBUG"),
+  ("NDEBUG", "_DEBUG")
+];
+
+fn collect_defines<'b>(ctx: &'b Context, index: usize, profile: &str) -> Vec<Define<'b>> {
+  let mut sources = Vec::new();
+
+  sources.extend(profile_settings(&ctx.defaults, profile)
+                 .map(|s| (s, format!("default profile {}", profile))));
+  sources.push((&ctx.project.settings, "project".to_string()));
+  sources.extend(profile_settings(&ctx.project.profiles, profile)
+                 .map(|s| (s, format!("project profile {}", profile))));
+
+  for &i in ctx.extends[index].iter().chain(std::iter::once(&index)) {
+    let target = ctx.get_target(i);
+    let name   = ctx.get_target_name(i);
+    sources.push((&target.settings, format!("target {}", name)));
+    sources.extend(profile_settings(&target.profiles, profile)
+                   .map(|s| (s, format!("target {} profile {}", name, profile))));
+  }
+
+  let mut defines = sources.iter().flat_map(|(settings, origin)| {
+    settings.defines.iter().map(move |def| {
+      let mut it = def.splitn(2, '=');
+      Define {
+        name:   it.next().unwrap().trim(),
+        value:  it.next().unwrap_or("1").trim(),
+        origin: origin.clone()
+      }
+    })
+  }).collect::<Vec<Define>>();
+
+  // The setting is emitted as a define by the Visual Studio generator.
+  for &i in ctx.extends[index].iter().chain(std::iter::once(&index)) {
+    let target = ctx.get_target(i);
+    let level = ctx.get_setting(target, profile, PlatformType::Any, Architecture::Any,
+                                |s| s.iterator_debug_level);
+    if let Some(level) = level {
+      defines.push(Define {
+        name:   "_ITERATOR_DEBUG_LEVEL",
+        value:  *["0", "1", "2"].get(level as usize).unwrap_or(&"?"),
+        origin: format!("target {} iterator_debug_level", ctx.get_target_name(i))
+      });
+    }
+  }
+
+  defines
+}
+
+fn profile_settings<'a, 'b>(profiles: &'b Profiles<'a>,
+                            profile: &str) -> impl Iterator<Item = &'b Settings<'a>>
+{
+  profiles.get(profile).into_iter().flatten().map(|p| &p.settings)
+}
+
+fn check_defines(diagnostics: &Diagnostics, defines: &[Define], target: &str, profile: &str) {
+  for (i, a) in defines.iter().enumerate() {
+    for b in &defines[i + 1 ..] {
+      let conflict = match a.name == b.name {
+        true  => a.value != b.value,
+        false => EXCLUSIVE.iter().any(|&(x, y)| {
+          ((a.name == x && b.name == y) || (a.name == y && b.name == x)) &&
+            a.value != "0" && b.value != "0"
+        })
+      };
+
+      if conflict {
+        diagnostics.error_at(&["targets", target, "defines"],
+                             format!("Target {} ({}): {}={} from {} conflicts with {}={} from {}",
+                                     target, profile,
+                                     a.name, a.value, a.origin,
+                                     b.name, b.value, b.origin));
+      }
+    }
+  }
+}
+
+/// Android targets pinning an NDK version need it installed, either as
+/// ANDROID_NDK_HOME or side by side in the SDK's ndk folder.
+fn check_android_ndk(diagnostics: &Diagnostics, ctx: &Context) {
+  if !ctx.project.filter.matches_platform(PlatformType::Android) {
+    return;
+  }
+
+  let mut versions = ctx.project.targets.iter().filter(|(_, t)| {
+    (t.target_type == TargetType::Application || t.target_type == TargetType::SharedLibrary) &&
+      t.filter.matches_platform(PlatformType::Android)
+  }).filter_map(|(_, t)| t.android.ndk_version.or(ctx.project.android.ndk_version))
+    .collect::<Vec<&str>>();
+  versions.sort_unstable();
+  versions.dedup();
+
+  let sdk = ctx.env.android_sdk_root.as_ref().or_else(|| ctx.env.android_home.as_ref());
+
+  for version in versions {
+    let path = match (&ctx.env.android_ndk_home, sdk) {
+      (Some(ndk), _)    => PathBuf::from(ndk),
+      (None, Some(sdk)) => PathBuf::from(sdk).join("ndk").join(version),
+      (None, None)      => {
+        diagnostics.error(format!("NDK {} not found: neither ANDROID_NDK_HOME nor ANDROID_SDK_ROOT are set",
+                                  version));
+        continue;
+      }
+    };
+
+    match ndk_revision(&path) {
+      Some(ref x) if x == version => {},
+      Some(x) => diagnostics.error(format!("NDK {} is required but {:?} is version {}", version, path, x)),
+      None    => diagnostics.error(format!("NDK {} is required but was not found in {:?}", version, path))
+    }
   }
 }
@@ -0,0 +1,177 @@
+use clap::{App, Arg};
+use std::path::{Path, PathBuf};
+
+use crate::cmd::{run_step, run_tool};
+use crate::cmd::test::{find_test_targets, get_host_platform, get_test_exe};
+use crate::ctx::{Command, Context, PlatformType, RunResult, StrError};
+
+pub struct Coverage;
+
+impl Command for Coverage {
+  fn init<'a, 'b>(&self, cmd: App<'a, 'b>) -> App<'a, 'b> {
+    cmd.about("Runs the test targets' Coverage builds and reports their code coverage")
+      .arg(Arg::with_name("target")
+           .long("target")
+           .value_name("TARGET")
+           .help("Test target to cover, all of them by default")
+           .takes_value(true))
+      .arg(Arg::with_name("no-html")
+           .long("no-html")
+           .help("Only write the lcov data, without the HTML report"))
+  }
+
+  /// Coverage data is written to the `coverage` folder of the build, as `coverage.info`
+  /// and an `html` report on Linux and macOS, as Cobertura files on Windows.
+  fn run(&self, ctx: &Context) -> RunResult {
+    if !ctx.profiles.contains(&"Coverage") {
+      return Err(Box::new(StrError(concat!("No Coverage profile, enable it with ",
+                                           "builtin_profiles = [\"Coverage\"]").to_string())));
+    }
+
+    let args     = ctx.args.subcommand_matches("coverage");
+    let html     = !args.map_or(false, |x| x.is_present("no-html"));
+    let platform = get_host_platform();
+    let targets  = find_test_targets(ctx, platform, args.and_then(|x| x.value_of("target")))?;
+
+    let dir = ctx.build_dir.join("coverage");
+    if dir.exists() {
+      std::fs::remove_dir_all(&dir)?;
+    }
+    std::fs::create_dir_all(&dir)?;
+
+    let mut exes = Vec::new();
+    for (name, target, _) in targets {
+      let exe = get_test_exe(ctx, name, target, platform, "Coverage")?;
+
+      // Counters accumulate in the gcov data of previous runs.
+      if platform == PlatformType::Linux {
+        remove_files(exe.parent().unwrap(), "gcda")?;
+      }
+
+      let mut cmd = std::process::Command::new(&exe);
+      cmd.current_dir(exe.parent().unwrap())
+        .env("LLVM_PROFILE_FILE", dir.join([name, "-%p.profraw"].join("")));
+
+      if platform == PlatformType::Windows {
+        let output = dir.join([name, ".cobertura.xml"].join(""));
+        cmd = std::process::Command::new("Microsoft.CodeCoverage.Console");
+        cmd.arg("collect").arg("--output").arg(&output).args(&["--output-format", "cobertura"]).arg(&exe);
+      }
+
+      // Failed tests still produce coverage data, `janky test` reports them.
+      let status = run_tool(ctx, name, &mut cmd)?;
+      if !status.success() {
+        ctx.warn(format!("Test target {} failed ({})", name, status));
+      }
+      exes.push(exe);
+    }
+
+    match platform {
+      PlatformType::Windows => {
+        ctx.log.info(format!("Coverage written to {}", dir.display()));
+        return Ok(());
+      },
+      PlatformType::MacOS => collect_llvm(ctx, &dir, &exes, html)?,
+      _                   => collect_gcov(ctx, &dir, &exes, html)?
+    }
+
+    let info = std::fs::read_to_string(dir.join("coverage.info"))?;
+    let (found, hit) = info.lines().fold((0, 0), |(found, hit), line| {
+      let count = |prefix| line.strip_prefix(prefix).and_then(|x| x.parse::<u64>().ok()).unwrap_or(0);
+      (found + count("LF:"), hit + count("LH:"))
+    });
+    let percent = match found {
+      0 => 0.0,
+      _ => hit as f64 * 100.0 / found as f64
+    };
+
+    match ctx.log.is_json() {
+      true  => ctx.log.event("coverage", serde_json::json!({
+        "lines":   found,
+        "covered": hit,
+        "percent": percent,
+        "report":  dir
+      })),
+      false => ctx.log.info(format!("{:.1}% line coverage, {} of {} lines, written to {}",
+                                    percent, hit, found, dir.display()))
+    }
+    Ok(())
+  }
+}
+
+/// GCC's gcov data is written next to the objects, lcov gathers it from the build folders.
+fn collect_gcov(ctx: &Context, dir: &Path, exes: &[PathBuf], html: bool) -> RunResult {
+  let raw = dir.join("raw.info");
+  let mut cmd = std::process::Command::new("lcov");
+  cmd.args(&["--capture", "--output-file"]).arg(&raw);
+  for exe in exes {
+    cmd.arg("--directory").arg(exe.parent().unwrap());
+  }
+  run_step(ctx, "lcov", &mut cmd)?;
+
+  // Only report the project's sources, not the system and third-party headers.
+  let info = dir.join("coverage.info");
+  run_step(ctx, "lcov", std::process::Command::new("lcov")
+      .arg("--extract").arg(&raw).arg(ctx.input_dir.join("*"))
+      .arg("--output-file").arg(&info))?;
+
+  if html {
+    run_step(ctx, "genhtml", std::process::Command::new("genhtml")
+        .arg(&info).arg("--output-directory").arg(dir.join("html")))?;
+  }
+  Ok(())
+}
+
+/// Clang's raw profiles are merged, then exported with the coverage mapping of the executables.
+fn collect_llvm(ctx: &Context, dir: &Path, exes: &[PathBuf], html: bool) -> RunResult {
+  let profdata = dir.join("coverage.profdata");
+  let mut cmd  = std::process::Command::new("xcrun");
+  cmd.args(&["llvm-profdata", "merge", "-sparse", "-o"]).arg(&profdata);
+  for entry in std::fs::read_dir(dir)? {
+    let path = entry?.path();
+    if path.extension().map_or(false, |x| x == "profraw") {
+      cmd.arg(path);
+    }
+  }
+  run_step(ctx, "llvm-profdata", &mut cmd)?;
+
+  let llvm_cov = |format: &str| {
+    let mut cmd = std::process::Command::new("xcrun");
+    cmd.args(&["llvm-cov", if format == "lcov" { "export" } else { "show" }])
+      .arg(["-format=", format].join(""))
+      .arg("-instr-profile").arg(&profdata)
+      .arg(&exes[0]);
+    for exe in &exes[1 ..] {
+      cmd.arg("-object").arg(exe);
+    }
+    cmd.arg(&ctx.input_dir);
+    cmd
+  };
+
+  // The lcov export goes to stdout, keep it out of the tool output.
+  let output = llvm_cov("lcov").output()?;
+  if !output.status.success() {
+    return Err(Box::new(StrError(format!("llvm-cov failed ({})", output.status))));
+  }
+  std::fs::write(dir.join("coverage.info"), output.stdout)?;
+
+  if html {
+    let output_dir = format!("-output-dir={}", dir.join("html").display());
+    run_step(ctx, "llvm-cov", llvm_cov("html").arg(output_dir))?;
+  }
+  Ok(())
+}
+
+/// Removes the files with the given extension under a folder.
+fn remove_files(dir: &Path, extension: &str) -> std::io::Result<()> {
+  for entry in std::fs::read_dir(dir)? {
+    let path = entry?.path();
+    if path.is_dir() {
+      remove_files(&path, extension)?;
+    }
+    else if path.extension().map_or(false, |x| x == extension) {
+      std::fs::remove_file(path)?;
+    }
+  }
+  Ok(())
+}
@@ -0,0 +1,151 @@
+use clap::{App, Arg};
+
+use crate::cmd::build::{get_artifact_path, get_xcode_target};
+use crate::cmd::run::build_html5;
+use crate::cmd::run_step;
+use crate::cmd::test::get_host_platform;
+use crate::ctx::{Command, Context, PlatformType, RunResult, StrError, Target, TargetType};
+
+pub struct Debug;
+
+impl Command for Debug {
+  fn init<'a, 'b>(&self, cmd: App<'a, 'b>) -> App<'a, 'b> {
+    cmd.about("Builds a target's Debug profile and starts it under the platform's debugger")
+      .arg(Arg::with_name("target")
+           .value_name("TARGET")
+           .help("Executable to debug, the first one by default")
+           .index(1))
+      .arg(Arg::with_name("profile")
+           .long("profile")
+           .value_name("PROFILE")
+           .help("Profile to build and debug, Debug by default")
+           .takes_value(true))
+      .arg(Arg::with_name("platform")
+           .long("platform")
+           .value_name("PLATFORM")
+           .help("Debugs the html5 build in a browser instead of the host platform's")
+           .takes_value(true))
+      .arg(Arg::with_name("debugger")
+           .long("debugger")
+           .value_name("DEBUGGER")
+           .help("lldb or gdb, devenv or windbg on Windows, lldb or devenv by default")
+           .takes_value(true))
+      .arg(Arg::with_name("browser")
+           .long("browser")
+           .value_name("BROWSER")
+           .help("Browser started by emrun for html5, chrome by default")
+           .takes_value(true))
+      .arg(Arg::with_name("args")
+           .value_name("ARGS")
+           .help("Arguments of the debugged program, after --")
+           .multiple(true)
+           .last(true))
+  }
+
+  fn run(&self, ctx: &Context) -> RunResult {
+    let args     = ctx.args.subcommand_matches("debug");
+    let platform = match args.and_then(|x| x.value_of("platform")) {
+      None          => get_host_platform(),
+      Some("html5") => PlatformType::HTML5,
+      Some(p)       => return Err(Box::new(StrError(format!("Cannot debug on platform {}", p))))
+    };
+    let profile = match args.and_then(|x| x.value_of("profile")) {
+      None    => ctx.profiles.iter().cloned().find(|&x| x == "Debug").unwrap_or(ctx.profiles[0]),
+      Some(p) => match ctx.profiles.contains(&p) {
+        true  => p,
+        false => return Err(Box::new(StrError(format!("No such profile: {}", p))))
+      }
+    };
+
+    let only = args.and_then(|x| x.value_of("target"));
+    let (name, target) = ctx.project.targets.iter()
+      .find(|(name, t)| {
+        (t.target_type == TargetType::Application || t.target_type == TargetType::Console) &&
+          ctx.project.filter.matches_platform(platform) && t.filter.matches_platform(platform) &&
+          only.map_or(true, |x| x == **name)
+      })
+      .ok_or_else(|| StrError(format!("No {} executable to debug", platform.to_str())))?;
+
+    if platform == PlatformType::HTML5 {
+      return debug_html5(ctx, name, profile, args.and_then(|x| x.value_of("browser")).unwrap_or("chrome"));
+    }
+
+    build(ctx, name, target, platform, profile)?;
+
+    let exe = ctx.build_dir.join(get_artifact_path(ctx, name, target, platform, profile).unwrap().1);
+    let exe_args = args.and_then(|x| x.values_of("args")).map_or_else(Vec::new, |x| x.collect::<Vec<_>>());
+
+    let debugger = args.and_then(|x| x.value_of("debugger")).unwrap_or(match platform {
+      PlatformType::Windows => "devenv",
+      _                     => "lldb"
+    });
+    let mut cmd = std::process::Command::new(debugger);
+    match debugger {
+      "lldb"   => cmd.arg("--").arg(&exe).args(&exe_args),
+      "gdb"    => cmd.arg("--args").arg(&exe).args(&exe_args),
+      "devenv" => cmd.arg("/debugexe").arg(&exe).args(&exe_args),
+      "windbg" => cmd.arg(&exe).args(&exe_args),
+      _        => return Err(Box::new(StrError(format!("Unknown debugger: {}", debugger))))
+    };
+
+    // Debuggers are interactive, their output is never forwarded as events.
+    let status = cmd.current_dir(exe.parent().unwrap()).status()?;
+    match status.success() {
+      true  => Ok(()),
+      false => Err(Box::new(StrError(format!("{} failed ({})", debugger, status))))
+    }
+  }
+}
+
+/// Builds the target with the native build tools of the host platform, in the
+/// folders `build::get_artifact_path` expects.
+fn build(ctx: &Context, name: &str, target: &Target, platform: PlatformType, profile: &str) -> RunResult {
+  match platform {
+    PlatformType::Windows => {
+      let mut sln = ctx.build_dir.join(&ctx.project.name);
+      sln.set_extension("sln");
+      run_step(ctx, "devenv", std::process::Command::new("devenv")
+               .arg(&sln)
+               .args(&["/Build", format!("{}|x64", profile).as_str(), "/Project", name]))
+    },
+    PlatformType::MacOS => {
+      let mut project = ctx.build_dir.join(&ctx.project.name);
+      project.set_extension("xcodeproj");
+      run_step(ctx, "xcodebuild", std::process::Command::new("xcodebuild")
+               .arg("-project").arg(&project)
+               .arg("-target").arg(get_xcode_target(ctx, name, target, platform))
+               .args(&["-configuration", profile, "build"]))
+    },
+    _ => {
+      let dir = ctx.build_dir.join([name, "_", platform.to_str()].join(""));
+      if !dir.join("CMakeLists.txt").is_file() {
+        return Err(Box::new(StrError(format!("Target {} has no {} build, run gen first",
+                                             name, platform.to_str()))));
+      }
+      run_step(ctx, "cmake", std::process::Command::new("cmake")
+               .arg(format!("-DCMAKE_BUILD_TYPE={}", profile)).arg(".").current_dir(&dir))?;
+      run_step(ctx, "cmake", std::process::Command::new("cmake")
+               .args(&["--build", "."]).current_dir(&dir))
+    }
+  }
+}
+
+/// Emscripten keeps the DWARF debug info of Debug builds, which Chrome's C/C++
+/// DevTools extension maps back to the sources.
+fn debug_html5(ctx: &Context, name: &str, profile: &str, browser: &str) -> RunResult {
+  let dir = ctx.build_dir.join([name, "_HTML5"].join(""));
+  if !dir.join("CMakeLists.txt").is_file() {
+    return Err(Box::new(StrError(format!("Target {} has no HTML5 build, run gen first", name))));
+  }
+
+  build_html5(ctx, &dir, Some(profile))?;
+
+  let status = std::process::Command::new("emrun")
+    .args(&["--browser", browser])
+    .arg(dir.join("dist").join([name, ".html"].join("")))
+    .status()?;
+  match status.success() {
+    true  => Ok(()),
+    false => Err(Box::new(StrError(format!("emrun failed ({})", status))))
+  }
+}
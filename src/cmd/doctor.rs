@@ -0,0 +1,89 @@
+use clap::{App, Arg};
+
+use crate::ctx::{Command, Context, PlatformType, RunResult, StrError, TargetType};
+use crate::platform::builds_on_host;
+
+pub struct Doctor;
+
+impl Command for Doctor {
+  fn init<'a, 'b>(&self, cmd: App<'a, 'b>) -> App<'a, 'b> {
+    cmd.about("Checks the host for the tools building the project's platforms")
+      .arg(Arg::with_name("all")
+           .long("all")
+           .help("Also checks the platforms the project does not build"))
+  }
+
+  /// Fails when a target cannot be built for one of its platforms on this host.
+  /// Platforms only built from other hosts are reported without failing.
+  fn run(&self, ctx: &Context) -> RunResult {
+    let all = ctx.args.subcommand_matches("doctor").map_or(false, |x| x.is_present("all"));
+
+    // Required tools missing, by platform.
+    let mut missing = Vec::new();
+    for p in &ctx.platforms {
+      let platform = p.get_platform_type();
+      if !all && !ctx.project.filter.matches_platform(platform) {
+        continue;
+      }
+
+      if !builds_on_host(platform) {
+        println!("{} (not buildable here)", platform.to_str());
+        continue;
+      }
+
+      println!("{}", platform.to_str());
+      let tools = p.run(ctx);
+      for tool in &tools {
+        match &tool.found {
+          Ok(x)  => println!("  {:<9}{:<16} {}", "ok", tool.name, x),
+          Err(e) => {
+            let status = if tool.required { "missing" } else { "optional" };
+            println!("  {:<9}{:<16} {}", status, tool.name, e);
+          }
+        }
+      }
+
+      let names = tools.iter()
+        .filter(|x| x.required && x.found.is_err())
+        .map(|x| x.name)
+        .collect::<Vec<&str>>();
+      if !names.is_empty() {
+        missing.push((platform, names));
+      }
+    }
+
+    println!("\nTargets");
+    let mut blocked = 0;
+    for (name, target) in &ctx.project.targets {
+      if target.target_type == TargetType::None {
+        continue;
+      }
+
+      let mut platforms = Vec::new();
+      let mut can_build = true;
+      for &p in PlatformType::ALL {
+        if !ctx.project.filter.matches_platform(p) || !target.filter.matches_platform(p) {
+          continue;
+        }
+        match missing.iter().find(|x| x.0 == p) {
+          None if !builds_on_host(p) => platforms.push(format!("{} (not buildable here)", p.to_str())),
+          None                       => platforms.push(p.to_str().to_string()),
+          Some(x)                    => {
+            platforms.push(format!("{} (missing {})", p.to_str(), x.1.join(", ")));
+            can_build = false;
+          }
+        }
+      }
+
+      if !can_build {
+        blocked += 1;
+      }
+      println!("  {:<25} {}", name, platforms.join(", "));
+    }
+
+    match blocked {
+      0 => Ok(()),
+      n => Err(Box::new(StrError(format!("{} target(s) cannot be built on this host", n))))
+    }
+  }
+}
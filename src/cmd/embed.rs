@@ -0,0 +1,33 @@
+use clap::{App, Arg};
+use std::path::Path;
+
+use crate::ctx::{Command, Context, RunResult, StrError};
+use crate::gen::embed;
+
+pub struct Embed;
+
+impl Command for Embed {
+  fn init<'a, 'b>(&self, cmd: App<'a, 'b>) -> App<'a, 'b> {
+    cmd.about("Converts a target's embedded files to C sources, run by the generated projects as they build")
+      .arg(Arg::with_name("target")
+           .long("target")
+           .value_name("TARGET")
+           .help("Target embedding the files")
+           .takes_value(true)
+           .required(true))
+      .arg(Arg::with_name("file")
+           .long("file")
+           .value_name("FILE")
+           .help("Only convert this file, relative to the input folder")
+           .takes_value(true))
+  }
+
+  fn run(&self, ctx: &Context) -> RunResult {
+    let args = ctx.args.subcommand_matches("embed").unwrap();
+    let name = args.value_of("target").unwrap();
+    let index = ctx.project.targets.keys().position(|x| *x == name)
+      .ok_or_else(|| StrError(format!("No such target: {}", name)))?;
+
+    embed::write_sources(ctx, index, args.value_of("file").map(Path::new))
+  }
+}
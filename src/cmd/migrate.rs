@@ -0,0 +1,236 @@
+//! Upgrades the configuration files to the current schema, see
+//! `ProjectInfo::CONFIG_VERSION`. Files are rewritten line by line, keeping
+//! their comments, layout and variables. Values written as inline tables are
+//! left as they are, janky still reads them using their old schema.
+
+use clap::{App, Arg};
+
+use crate::ctx::{Command, Context, OutputMode, ProjectInfo, RunResult};
+use crate::diag::split_key;
+use crate::gen::text::{preview, report_write};
+
+pub struct Migrate;
+
+impl Command for Migrate {
+  fn init<'a, 'b>(&self, cmd: App<'a, 'b>) -> App<'a, 'b> {
+    cmd.about("Upgrades the project's configuration files to the current schema")
+      .arg(Arg::with_name("dry-run")
+           .long("dry-run")
+           .help("List the files which would be migrated without writing them"))
+      .arg(Arg::with_name("diff")
+           .long("diff")
+           .help("Print a unified diff of every file which would be migrated without writing them"))
+  }
+
+  fn run(&self, ctx: &Context) -> RunResult {
+    let args    = ctx.args.subcommand_matches("migrate").unwrap();
+    let output  = match (args.is_present("diff"), args.is_present("dry-run")) {
+      (true, _) => OutputMode::Diff,
+      (_, true) => OutputMode::DryRun,
+      _         => OutputMode::Write
+    };
+    let root    = ctx.input_dir.join(ctx.args.value_of("config").unwrap_or("Jank.toml"));
+    let version = ctx.project.config_version.unwrap_or(1);
+
+    if version >= ProjectInfo::CONFIG_VERSION {
+      ctx.log.info(format!("Configuration is already at version {}", version));
+      return Ok(());
+    }
+
+    // Included files and workspace members follow the version of the root project.
+    for (path, old) in ctx.diagnostics.sources() {
+      let mut text = old.clone();
+      for m in MIGRATIONS.iter().filter(|m| m.from >= version) {
+        ctx.log.verbose(format!("{}: {}", path.display(), m.about));
+        text = (m.run)(&text);
+      }
+      if path == root {
+        text = set_config_version(&text);
+      }
+
+      if text == old {
+        continue;
+      }
+
+      let key = path.strip_prefix(&ctx.input_dir).unwrap_or(&path);
+      match output {
+        OutputMode::Write => {
+          ctx.log.info(format!("Migrated {}", key.display()));
+          std::fs::write(&path, &text)?;
+          report_write(ctx.log, key, Some(old.as_bytes()), Some(text.as_bytes()));
+        },
+        output => preview(ctx.log, output, key, Some(old.as_bytes()), Some(text.as_bytes()))
+      }
+    }
+
+    Ok(())
+  }
+}
+
+/// Rewrite of the files using the schema `from` to the next one.
+struct Migration {
+  from:  u32,
+  about: &'static str,
+  run:   fn(&str) -> String
+}
+
+const MIGRATIONS: &[Migration] = &[
+  Migration { from: 1, about: "renaming arch to architecture in profiles", run: rename_profile_arch }
+];
+
+/// Profiles scoped to an architecture name it with `architecture`, `arch` is
+/// still accepted as an alias but no longer written.
+fn rename_profile_arch(text: &str) -> String {
+  rewrite_keys(text, |table, key| {
+    let is_profile = match table {
+      [a, _]       => a == "profiles",
+      [a, _, b, _] => a == "targets" && b == "profiles",
+      _            => false
+    };
+    match is_profile && key == "arch" {
+      true  => Some("architecture"),
+      false => None
+    }
+  })
+}
+
+/// Sets the `config_version` of the `[project]` table to the current one,
+/// right below its header.
+fn set_config_version(text: &str) -> String {
+  let line = format!("config_version = {}", ProjectInfo::CONFIG_VERSION);
+  let mut out   = Vec::new();
+  let mut table = Vec::new();
+  let mut found = false;
+
+  for l in text.lines() {
+    let trimmed = l.trim_start();
+    if trimmed.starts_with('[') {
+      table = header_key(trimmed);
+      out.push(l.to_string());
+      if table == ["project"] && !found {
+        out.push(line.clone());
+        found = true;
+      }
+      continue;
+    }
+    if table == ["project"] && key_of(trimmed).map_or(false, |k| k == ["config_version"]) {
+      continue;
+    }
+    out.push(l.to_string());
+  }
+
+  if !found {
+    out.push(String::new());
+    out.push("[project]".to_string());
+    out.push(line);
+  }
+
+  join_lines(out, text)
+}
+
+/// Renames the keys for which `f` returns a new name, given the table they are in.
+/// Texts without any such key are returned as they are.
+fn rewrite_keys<F>(text: &str, f: F) -> String where F: Fn(&[String], &str) -> Option<&'static str> {
+  let mut table   = Vec::new();
+  let mut out     = Vec::new();
+  let mut renamed = false;
+
+  for l in text.lines() {
+    let trimmed = l.trim_start();
+    if trimmed.starts_with('[') {
+      table = header_key(trimmed);
+    }
+    else if let Some(key) = key_of(trimmed).filter(|x| x.len() == 1) {
+      if let Some(name) = f(&table, &key[0]) {
+        let indent = &l[.. l.len() - trimmed.len()];
+        let value  = &trimmed[trimmed.find('=').unwrap() ..];
+        out.push(format!("{}{} {}", indent, name, value));
+        renamed = true;
+        continue;
+      }
+    }
+    out.push(l.to_string());
+  }
+
+  match renamed {
+    true  => join_lines(out, text),
+    false => text.to_string()
+  }
+}
+
+/// Joins the rewritten lines, keeping the line endings and the trailing newline
+/// of the original text.
+fn join_lines(lines: Vec<String>, text: &str) -> String {
+  let eol = match text.contains("\r\n") {
+    true  => "\r\n",
+    false => "\n"
+  };
+
+  let mut s = lines.join(eol);
+  if text.ends_with('\n') {
+    s.push_str(eol);
+  }
+  s
+}
+
+fn header_key(line: &str) -> Vec<String> {
+  split_key(line.trim_start_matches('[').split(']').next().unwrap_or(""))
+}
+
+/// Key of a key/value line, none for comments and other lines.
+fn key_of(line: &str) -> Option<Vec<String>> {
+  match line.starts_with('#') {
+    true  => None,
+    false => line.find('=').map(|pos| split_key(&line[.. pos]))
+  }
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn rename_profiles() {
+    let text = concat!("[profiles.Debug]\narch = \"x64\"\n\n",
+                       "[targets.a.profiles.Release]\n  arch = \"arm64\" # kept\n");
+    assert_eq!(rename_profile_arch(text),
+               concat!("[profiles.Debug]\narchitecture = \"x64\"\n\n",
+                       "[targets.a.profiles.Release]\n  architecture = \"arm64\" # kept\n"));
+
+    // Only the keys of profile tables are renamed, other texts are left untouched.
+    let text = "[project]\narch = 1\n[targets.a]\narch = 2";
+    assert_eq!(rename_profile_arch(text), text);
+  }
+
+  #[test]
+  fn line_endings() {
+    assert_eq!(rename_profile_arch("[profiles.Debug]\r\narch = \"x64\"\r\n"),
+               "[profiles.Debug]\r\narchitecture = \"x64\"\r\n");
+    assert_eq!(set_config_version("[project]\r\nname = \"a\""),
+               format!("[project]\r\nconfig_version = {}\r\nname = \"a\"", ProjectInfo::CONFIG_VERSION));
+
+    // Without any rename, mixed line endings are kept as well.
+    let text = "[profiles.Debug]\r\nlto = \"thin\"\n";
+    assert_eq!(rename_profile_arch(text), text);
+  }
+
+  #[test]
+  fn config_version() {
+    let current = format!("config_version = {}", ProjectInfo::CONFIG_VERSION);
+    assert_eq!(set_config_version("[project]\nconfig_version = 1\nname = \"a\"\n"),
+               format!("[project]\n{}\nname = \"a\"\n", current));
+
+    // The project table is added when missing.
+    assert_eq!(set_config_version("[targets.a]\ntype = \"Console\"\n"),
+               format!("[targets.a]\ntype = \"Console\"\n\n[project]\n{}\n", current));
+  }
+
+  #[test]
+  fn header_keys() {
+    assert_eq!(header_key("[project]"), &["project"]);
+    assert_eq!(header_key("[targets.\"a.b\".profiles.Debug] # comment"),
+               &["targets", "a.b", "profiles", "Debug"]);
+    assert_eq!(header_key("[[profiles.Debug]]"), &["profiles", "Debug"]);
+  }
+}
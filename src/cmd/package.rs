@@ -0,0 +1,92 @@
+use clap::{App, Arg};
+
+use crate::cmd::run_step;
+use crate::ctx::{Command, Context, RunResult, StrError};
+use crate::gen::desktop;
+
+pub struct Package;
+
+const LINUX_DIST: &str = "dist/linux";
+
+impl Command for Package {
+  fn init<'a, 'b>(&self, cmd: App<'a, 'b>) -> App<'a, 'b> {
+    cmd.about("Builds the project's Linux applications and bundles them as AppImages")
+      .arg(Arg::with_name("profile")
+           .long("profile")
+           .value_name("PROFILE")
+           .help("Profile to build and package, Release by default")
+           .takes_value(true))
+      .arg(Arg::with_name("target")
+           .long("target")
+           .value_name("TARGET")
+           .help("Application to package, all of them by default")
+           .takes_value(true))
+  }
+
+  /// Each application is installed by CMake to an AppDir, which linuxdeploy
+  /// completes with its shared libraries and an AppRun before writing the
+  /// AppImage to `dist/linux/<profile>`.
+  fn run(&self, ctx: &Context) -> RunResult {
+    let args    = ctx.args.subcommand_matches("package");
+    let profile = match args.and_then(|x| x.value_of("profile")) {
+      None    => ctx.profiles.iter().cloned().find(|&x| x == "Release").unwrap_or(ctx.profiles[0]),
+      Some(p) => match ctx.profiles.contains(&p) {
+        true  => p,
+        false => return Err(Box::new(StrError(format!("No such profile: {}", p))))
+      }
+    };
+
+    let only = args.and_then(|x| x.value_of("target"));
+    let apps = desktop::get_applications(ctx).into_iter()
+      .filter(|&i| only.map_or(true, |x| x == ctx.get_target_name(i)))
+      .collect::<Vec<_>>();
+    if apps.is_empty() {
+      return Err(Box::new(StrError("No Linux application to package".to_string())));
+    }
+    if ctx.project.linux.icon.is_none() {
+      return Err(Box::new(StrError("AppImages require an icon, set one in the [linux] table".to_string())));
+    }
+
+    let dist = ctx.build_dir.join(LINUX_DIST).join(profile.to_lowercase());
+    std::fs::create_dir_all(&dist)?;
+
+    for index in apps {
+      let name = ctx.get_target_name(index);
+      let dir  = ctx.build_dir.join([name, "_Linux"].join(""));
+      if !dir.join("CMakeLists.txt").is_file() {
+        return Err(Box::new(StrError(format!("Target {} has no Linux build, run gen first", name))));
+      }
+
+      run_step(ctx, "cmake", std::process::Command::new("cmake")
+               .arg(format!("-DCMAKE_BUILD_TYPE={}", profile)).arg(".").current_dir(&dir))?;
+      run_step(ctx, "cmake", std::process::Command::new("cmake")
+               .args(&["--build", "."]).current_dir(&dir))?;
+
+      // Files left over from a previous install would end up in the image.
+      let app_dir = ctx.build_dir.join([name, "_AppDir"].join(""));
+      if app_dir.is_dir() {
+        std::fs::remove_dir_all(&app_dir)?;
+      }
+      run_step(ctx, "cmake", std::process::Command::new("cmake")
+               .args(&["--install", ".", "--strip", "--prefix"]).arg(app_dir.join("usr"))
+               .current_dir(&dir))?;
+
+      let desktop_file = app_dir.join("usr/share/applications")
+        .join(desktop::get_path(ctx, index).file_name().unwrap());
+      let image = dist.join(format!("{}-{}-x86_64.AppImage", name, ctx.project.version));
+      run_step(ctx, "linuxdeploy", std::process::Command::new("linuxdeploy")
+               .arg("--appdir").arg(&app_dir)
+               .arg("--desktop-file").arg(&desktop_file)
+               .args(&["--output", "appimage"])
+               .env("OUTPUT", &image)
+               .current_dir(&dist))?;
+
+      match ctx.log.is_json() {
+        true  => ctx.log.event("package", serde_json::json!({ "profile": profile, "path": image })),
+        false => println!("{}", image.display())
+      }
+    }
+
+    Ok(())
+  }
+}
@@ -1,15 +1,448 @@
-use clap::{App};
+use clap::{App, Arg};
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, SystemTime};
 
-use crate::ctx::{Command, Context, RunResult};
+use crate::cmd::build::{build_android, get_artifact_path, get_xcode_target};
+use crate::cmd::run_step;
+use crate::ctx::{Command, Context, DynResult, PlatformType, RunResult, StrError, TargetType};
+use crate::gen::flags;
 
 pub struct Run;
 
 impl Command for Run {
   fn init<'a, 'b>(&self, cmd: App<'a, 'b>) -> App<'a, 'b> {
     cmd.about("Runs the project's main executable")
+      .arg(Arg::with_name("platform")
+           .long("platform")
+           .value_name("PLATFORM")
+           .help("Platform to run on, html5, ios or android")
+           .takes_value(true))
+      .arg(Arg::with_name("target")
+           .long("target")
+           .value_name("TARGET")
+           .help("Application to run, the first one by default")
+           .takes_value(true))
+      .arg(Arg::with_name("host")
+           .long("host")
+           .value_name("ADDRESS")
+           .help("Address the development server listens on, 127.0.0.1 by default")
+           .takes_value(true))
+      .arg(Arg::with_name("port")
+           .long("port")
+           .value_name("PORT")
+           .help("Port of the development server, 8080 by default")
+           .takes_value(true))
+      .arg(Arg::with_name("no-watch")
+           .long("no-watch")
+           .help("Serves the application without rebuilding it when sources change"))
+      .arg(Arg::with_name("profile")
+           .long("profile")
+           .value_name("PROFILE")
+           .help("Profile to build and run on iOS and Android, Debug by default")
+           .takes_value(true))
+      .arg(Arg::with_name("device")
+           .long("device")
+           .value_name("DEVICE")
+           .help("iOS simulator name or UDID, iOS device UDID or adb serial, picked when omitted")
+           .takes_value(true))
+      .arg(Arg::with_name("physical")
+           .long("physical")
+           .help("Installs to a device connected over USB with ios-deploy, instead of a simulator"))
   }
 
-  fn run(&self, _ctx: &Context) -> RunResult {
-    Ok(())
+  fn run(&self, ctx: &Context) -> RunResult {
+    let args = ctx.args.subcommand_matches("run");
+    let platform = match args.and_then(|x| x.value_of("platform")) {
+      Some("html5")   => PlatformType::HTML5,
+      Some("ios")     => PlatformType::IOS,
+      Some("android") => PlatformType::Android,
+      Some(p)         => return Err(Box::new(StrError(format!("Cannot run on platform {}", p)))),
+      None            => return Err(Box::new(StrError("No platform to run on, see --platform".to_string())))
+    };
+
+    let index = ctx.project.targets.iter().position(|(name, t)| {
+      t.target_type == TargetType::Application &&
+        ctx.project.filter.matches_platform(platform) &&
+        t.filter.matches_platform(platform) &&
+        args.and_then(|x| x.value_of("target")).map_or(true, |x| x == *name)
+    }).ok_or_else(|| StrError(format!("No {} application to run", platform.to_str())))?;
+
+    if platform != PlatformType::HTML5 {
+      let profile = match args.and_then(|x| x.value_of("profile")) {
+        None    => ctx.profiles.iter().cloned().find(|&x| x == "Debug").unwrap_or(ctx.profiles[0]),
+        Some(p) => match ctx.profiles.contains(&p) {
+          true  => p,
+          false => return Err(Box::new(StrError(format!("No such profile: {}", p))))
+        }
+      };
+      let device = args.and_then(|x| x.value_of("device"));
+      return match (platform, args.map_or(false, |x| x.is_present("physical"))) {
+        (PlatformType::Android, _) => run_android(ctx, index, profile, device),
+        (_, true)                  => run_ios_device(ctx, index, profile, device),
+        (_, false)                 => run_ios_simulator(ctx, index, profile, device)
+      };
+    }
+
+    let port = match args.and_then(|x| x.value_of("port")) {
+      None    => 8080,
+      Some(x) => x.parse::<u16>().map_err(|_| StrError(format!("Invalid port: {}", x)))?
+    };
+
+    let host = args.and_then(|x| x.value_of("host")).unwrap_or("127.0.0.1");
+    serve_html5(ctx, index, host, port, !args.map_or(false, |x| x.is_present("no-watch")))
+  }
+}
+
+
+// iOS
+// -----------------------------------------------------------------------------
+
+/// Installs the application to a simulator, booting it first when named, then
+/// launches it with its output forwarded until it exits.
+fn run_ios_simulator(ctx: &Context, index: usize, profile: &str, device: Option<&str>) -> RunResult {
+  let app    = build_ios(ctx, index, profile, "iphonesimulator")?;
+  let device = match device {
+    None    => "booted",
+    Some(x) => {
+      // Fails when the simulator is already booted, which is fine.
+      let _ = std::process::Command::new("xcrun").args(&["simctl", "boot", x]).output()?;
+      x
+    }
+  };
+  let _ = std::process::Command::new("open").args(&["-a", "Simulator"]).status();
+
+  run_step(ctx, "simctl", std::process::Command::new("xcrun")
+           .args(&["simctl", "install", device]).arg(&app))?;
+
+  let bundle_id = get_bundle_id(&app)?;
+  ctx.log.info(format!("Launching {} on {}", bundle_id, device));
+  run_step(ctx, "simctl", std::process::Command::new("xcrun")
+           .args(&["simctl", "launch", "--console-pty", "--terminate-running-process", device])
+           .arg(&bundle_id))
+}
+
+/// Installs the application to a device with ios-deploy, which launches it under
+/// LLDB to forward its output until it exits.
+fn run_ios_device(ctx: &Context, index: usize, profile: &str, device: Option<&str>) -> RunResult {
+  let app = build_ios(ctx, index, profile, "iphoneos")?;
+  let mut cmd = std::process::Command::new("ios-deploy");
+  cmd.args(&["--noninteractive", "--debug", "--bundle"]).arg(&app);
+  if let Some(id) = device {
+    cmd.args(&["--id", id]);
+  }
+  run_step(ctx, "ios-deploy", &mut cmd)
+}
+
+/// Builds the application's Xcode target for an SDK, returning its bundle. Products
+/// go to their own folder of the build, to find the bundle whatever its name.
+fn build_ios(ctx: &Context, index: usize, profile: &str, sdk: &str) -> DynResult<PathBuf> {
+  let name   = ctx.get_target_name(index);
+  let target = ctx.get_target(index);
+  let mut project = ctx.build_dir.join(&ctx.project.name);
+  project.set_extension("xcodeproj");
+  if !project.is_dir() {
+    return Err(Box::new(StrError(format!("Target {} has no Xcode project, run gen first", name))));
+  }
+
+  let xcode_target = get_xcode_target(ctx, name, target, PlatformType::IOS);
+  let dir = ctx.build_dir.join("build").join([profile, "-", sdk].join("")).join(name);
+  run_step(ctx, "xcodebuild", std::process::Command::new("xcodebuild")
+           .arg("-project").arg(&project)
+           .args(&["-target", xcode_target.as_str(), "-configuration", profile, "-sdk", sdk, "build"])
+           .arg(format!("CONFIGURATION_BUILD_DIR={}", dir.display())))?;
+
+  for entry in std::fs::read_dir(&dir)? {
+    let path = entry?.path();
+    if path.extension().map_or(false, |x| x == "app") {
+      return Ok(path);
+    }
+  }
+  Err(Box::new(StrError(format!("No application bundle built in {}", dir.display()))))
+}
+
+/// Reads the bundle identifier from the built application's Info.plist.
+fn get_bundle_id(app: &Path) -> DynResult<String> {
+  let output = std::process::Command::new("/usr/libexec/PlistBuddy")
+    .args(&["-c", "Print :CFBundleIdentifier"])
+    .arg(app.join("Info.plist"))
+    .output()?;
+  match output.status.success() {
+    true  => Ok(String::from_utf8_lossy(&output.stdout).trim().to_string()),
+    false => Err(Box::new(StrError(format!("No bundle identifier in {}", app.display()))))
+  }
+}
+
+
+// Android
+// -----------------------------------------------------------------------------
+
+/// Builds the application's APK with Gradle, installs it to a device and starts
+/// its activity, then forwards the device's log of the application's process.
+fn run_android(ctx: &Context, index: usize, profile: &str, device: Option<&str>) -> RunResult {
+  let name   = ctx.get_target_name(index);
+  let target = ctx.get_target(index);
+  if !ctx.build_dir.join("settings.gradle").is_file() {
+    return Err(Box::new(StrError(format!("Target {} has no Gradle project, run gen first", name))));
+  }
+
+  build_android(ctx, &[profile], false)?;
+  let apk = get_artifact_path(ctx, name, target, PlatformType::Android, profile).unwrap().1;
+  let apk = ctx.build_dir.join(apk);
+
+  let serial = match device {
+    Some(x) => x.to_string(),
+    None    => get_android_device(ctx)?
+  };
+  let adb = |args: &[&str]| {
+    let mut cmd = std::process::Command::new("adb");
+    cmd.args(&["-s", serial.as_str()]).args(args);
+    cmd
+  };
+
+  run_step(ctx, "adb", adb(&["install", "-r"]).arg(&apk))?;
+
+  let package  = flags::android_application_id(ctx, target);
+  let activity = [package, "/", flags::android_activity_class(flags::android_activity(ctx, target))].join("");
+  ctx.log.info(format!("Launching {} on {}", package, serial));
+  run_step(ctx, "adb", &mut adb(&["logcat", "-c"]))?;
+  run_step(ctx, "adb", &mut adb(&["shell", "am", "start", "-W", "-n", activity.as_str()]))?;
+
+  // The process may take a moment to show up after the activity is started.
+  let mut pid = String::new();
+  for _ in 0 .. 10 {
+    let output = adb(&["shell", "pidof", "-s", package]).output()?;
+    pid = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    if !pid.is_empty() {
+      break;
+    }
+    std::thread::sleep(Duration::from_millis(200));
+  }
+  if pid.is_empty() {
+    return Err(Box::new(StrError(format!("{} is not running on {}", package, serial))));
+  }
+
+  run_step(ctx, "logcat", &mut adb(&["logcat", format!("--pid={}", pid).as_str()]))
+}
+
+/// Serial of the only connected device or emulator, or of the first one when there are many.
+fn get_android_device(ctx: &Context) -> DynResult<String> {
+  let output = std::process::Command::new("adb").arg("devices").output()?;
+  if !output.status.success() {
+    return Err(Box::new(StrError(format!("adb devices failed ({})", output.status))));
+  }
+
+  let stdout  = String::from_utf8_lossy(&output.stdout);
+  let devices = stdout.lines().skip(1)
+    .filter_map(|x| match x.split_whitespace().collect::<Vec<_>>()[..] {
+      [serial, "device"] => Some(serial),
+      _                  => None
+    })
+    .collect::<Vec<_>>();
+
+  match devices.len() {
+    0 => Err(Box::new(StrError("No Android device connected, see adb devices".to_string()))),
+    1 => Ok(devices[0].to_string()),
+    _ => {
+      ctx.log.info(format!("Running on {}, see --device to pick another of {}",
+                           devices[0], devices.join(", ")));
+      Ok(devices[0].to_string())
+    }
   }
 }
+
+
+// HTML5
+// -----------------------------------------------------------------------------
+
+/// Polled by the pages served, which reload when the build generation changes.
+const RELOAD_PATH: &str = "/__janky/generation";
+
+const RELOAD_SCRIPT: &str = concat!(
+  "<script>(function() {\n",
+  "  var generation;\n",
+  "  setInterval(function() {\n",
+  "    fetch('/__janky/generation').then(function(r) { return r.text(); }).then(function(x) {\n",
+  "      if (generation !== undefined && generation !== x) location.reload();\n",
+  "      generation = x;\n",
+  "    }).catch(function() {});\n",
+  "  }, 1000);\n",
+  "})();</script>\n");
+
+/// Builds the application with Emscripten and serves its dist folder, then
+/// rebuilds it whenever one of its sources is modified. Only local connections
+/// are accepted unless another address is given.
+fn serve_html5(ctx: &Context, index: usize, host: &str, port: u16, watch: bool) -> RunResult {
+  let name = ctx.get_target_name(index);
+  let dir  = ctx.build_dir.join([name, "_HTML5"].join(""));
+  if !dir.join("CMakeLists.txt").is_file() {
+    return Err(Box::new(StrError(format!("Target {} has no HTML5 build, run gen first", name))));
+  }
+
+  build_html5(ctx, &dir, None)?;
+
+  let generation = Arc::new(AtomicUsize::new(0));
+  let listener   = TcpListener::bind((host, port))?;
+  let url = match host {
+    "0.0.0.0" | "::"        => format!("http://localhost:{}/{}.html", port, name),
+    _ if host.contains(':') => format!("http://[{}]:{}/{}.html", host, port, name),
+    _                       => format!("http://{}:{}/{}.html", host, port, name)
+  };
+  match ctx.log.is_json() {
+    true  => ctx.log.event("serving", serde_json::json!({ "target": name, "url": url })),
+    false => println!("Serving {}", url)
+  }
+
+  let server = {
+    let log        = ctx.log;
+    let dist       = dir.join("dist");
+    let generation = generation.clone();
+    std::thread::spawn(move || {
+      for stream in listener.incoming().filter_map(Result::ok) {
+        let dist       = dist.clone();
+        let generation = generation.clone();
+        std::thread::spawn(move || {
+          if let Err(e) = serve_request(stream, &dist, &generation) {
+            log.diagnostic("warning", e, serde_json::Value::Null);
+          }
+        });
+      }
+    })
+  };
+
+  if !watch {
+    let _ = server.join();
+    return Ok(());
+  }
+
+  let mut stamp = get_sources_stamp(ctx, index);
+  loop {
+    std::thread::sleep(Duration::from_millis(500));
+
+    let next = get_sources_stamp(ctx, index);
+    if next == stamp {
+      continue;
+    }
+
+    stamp = next;
+    match build_html5(ctx, &dir, None) {
+      Ok(_)  => { generation.fetch_add(1, Ordering::SeqCst); },
+      Err(e) => ctx.log.error(e)
+    }
+  }
+}
+
+/// Configures and builds an HTML5 target's CMake folder, keeping the configured
+/// profile unless one is given.
+pub fn build_html5(ctx: &Context, dir: &Path, profile: Option<&str>) -> RunResult {
+  let run = |program: &str, args: &[&str]| {
+    run_step(ctx, program, std::process::Command::new(program).args(args).current_dir(dir))
+  };
+
+  #[cfg(windows)]      let emcmake = "emcmake.bat";
+  #[cfg(not(windows))] let emcmake = "emcmake";
+  match profile {
+    None    => run(emcmake, &["cmake", "."])?,
+    Some(p) => run(emcmake, &["cmake", format!("-DCMAKE_BUILD_TYPE={}", p).as_str(), "."])?
+  }
+  run("cmake", &["--build", "."])
+}
+
+/// Latest modification time of the target's sources, including the targets it extends.
+/// New files are only picked up after generating the project again.
+fn get_sources_stamp(ctx: &Context, index: usize) -> Option<SystemTime> {
+  ctx.extends[index].iter().chain(std::iter::once(&index))
+    .flat_map(|&i| ctx.sources[i].iter())
+    .filter(|x| x.meta.is_file())
+    .filter_map(|x| ctx.input_dir.join(&x.path).metadata().and_then(|m| m.modified()).ok())
+    .max()
+}
+
+/// Minimal HTTP server for the dist folder. Pthreads need SharedArrayBuffer,
+/// which browsers only enable on cross-origin isolated pages.
+fn serve_request(stream: TcpStream, dist: &Path, generation: &AtomicUsize) -> std::io::Result<()> {
+  let mut reader = BufReader::new(stream);
+  let mut line   = String::new();
+  reader.read_line(&mut line)?;
+
+  let mut header = String::new();
+  while reader.read_line(&mut header)? > 2 {
+    header.clear();
+  }
+
+  let url  = line.split_whitespace().nth(1).unwrap_or("/");
+  let path = url.split('?').next().unwrap();
+
+  let (status, content_type, body) = match path {
+    RELOAD_PATH => ("200 OK", "text/plain", generation.load(Ordering::SeqCst).to_string().into_bytes()),
+    _ => match get_file_path(dist, path).and_then(|x| std::fs::read(&x).ok().map(|body| (x, body))) {
+      None            => ("404 Not Found", "text/plain", b"Not Found".to_vec()),
+      Some((x, body)) => {
+        let content_type = get_content_type(&x);
+        match content_type {
+          "text/html" => ("200 OK", content_type, inject_reload_script(body)),
+          _           => ("200 OK", content_type, body)
+        }
+      }
+    }
+  };
+
+  let mut stream = reader.into_inner();
+  write!(stream, concat!("HTTP/1.1 {}\r\n",
+                         "Content-Type: {}\r\n",
+                         "Content-Length: {}\r\n",
+                         "Cache-Control: no-cache\r\n",
+                         "Cross-Origin-Opener-Policy: same-origin\r\n",
+                         "Cross-Origin-Embedder-Policy: require-corp\r\n",
+                         "Connection: close\r\n\r\n"),
+         status, content_type, body.len())?;
+  stream.write_all(&body)?;
+  stream.flush()
+}
+
+/// Requests only resolve to files under the dist folder, parent folders, roots,
+/// drive prefixes and symbolic links leading out of it are rejected.
+fn get_file_path(dist: &Path, path: &str) -> Option<PathBuf> {
+  let rel = Path::new(path.trim_start_matches('/'));
+  if !rel.components().all(|x| matches!(x, Component::Normal(_))) {
+    return None;
+  }
+
+  let file = dist.join(rel);
+  let file = match file.is_dir() {
+    true  => file.join("index.html"),
+    false => file
+  };
+  let root = dist.canonicalize().ok()?;
+  match file.canonicalize() {
+    Ok(x) if x.starts_with(&root) => Some(x),
+    _                             => None
+  }
+}
+
+fn get_content_type(path: &Path) -> &'static str {
+  match path.extension().and_then(|x| x.to_str()).unwrap_or("") {
+    "html" => "text/html",
+    "js"   => "text/javascript",
+    "wasm" => "application/wasm",
+    "css"  => "text/css",
+    "json" => "application/json",
+    "png"  => "image/png",
+    "jpg"  => "image/jpeg",
+    "svg"  => "image/svg+xml",
+    "ico"  => "image/x-icon",
+    _      => "application/octet-stream"
+  }
+}
+
+fn inject_reload_script(mut body: Vec<u8>) -> Vec<u8> {
+  let end = b"</body>";
+  let pos = body.windows(end.len()).rposition(|x| x == end).unwrap_or(body.len());
+  let tail = body.split_off(pos);
+  body.extend_from_slice(RELOAD_SCRIPT.as_bytes());
+  body.extend(tail);
+  body
+}
@@ -1,15 +1,164 @@
-use clap::{App};
+use clap::{App, Arg, ArgMatches, SubCommand};
 
-use crate::ctx::{Command, Context, RunResult};
+use crate::ctx::{Architecture, Command, Context, PlatformType, RunResult, StrError, TargetType};
+use crate::gen::{flags, join_prefix};
+use crate::gen::subst::Vars;
 
 pub struct Show;
 
 impl Command for Show {
   fn init<'a, 'b>(&self, cmd: App<'a, 'b>) -> App<'a, 'b> {
     cmd.about("Displays information")
+      .subcommand(SubCommand::with_name("flags")
+                  .about("Prints the compiler and linker flags of a target")
+                  .arg(Arg::with_name("target")
+                       .long("target")
+                       .value_name("NAME")
+                       .required(true)
+                       .takes_value(true))
+                  .arg(Arg::with_name("platform")
+                       .long("platform")
+                       .value_name("PLATFORM")
+                       .default_value("linux")
+                       .takes_value(true))
+                  .arg(Arg::with_name("profile")
+                       .long("profile")
+                       .value_name("PROFILE")
+                       .default_value("Debug")
+                       .takes_value(true)))
+      .subcommand(SubCommand::with_name("graph")
+                  .about("Prints the targets and their dependencies as a graph")
+                  .arg(Arg::with_name("format")
+                       .long("format")
+                       .value_name("FORMAT")
+                       .possible_values(&["dot", "mermaid"])
+                       .default_value("dot")
+                       .takes_value(true)))
   }
 
-  fn run(&self, _ctx: &Context) -> RunResult {
-    Ok(())
+  fn run(&self, ctx: &Context) -> RunResult {
+    let args = ctx.args.subcommand_matches("show").unwrap();
+    match args.subcommand() {
+      ("flags", Some(sub)) => show_flags(ctx, sub),
+      ("graph", Some(sub)) => show_graph(ctx, sub),
+      _                    => Ok(())
+    }
   }
 }
+
+/// Same flags as the CMake generator, with paths made absolute so the output
+/// can be used from any directory.
+fn show_flags(ctx: &Context, args: &ArgMatches) -> RunResult {
+  let name     = args.value_of("target").unwrap();
+  let profile  = args.value_of("profile").unwrap();
+  let platform = args.value_of("platform").unwrap();
+
+  let index = ctx.project.targets.keys().position(|x| *x == name)
+    .ok_or_else(|| StrError(format!("No such target: {}", name)))?;
+  let target = ctx.get_target(index);
+
+  let platform = match PlatformType::parse(platform) {
+    Some(p @ PlatformType::Android) |
+    Some(p @ PlatformType::HTML5)   |
+    Some(p @ PlatformType::Linux)   => p,
+    Some(p) => return Err(Box::new(StrError(format!(
+      "Flags for {} are stored in the generated project files", p.to_str())))),
+    None => return Err(Box::new(StrError(format!("No such platform: {}", platform))))
+  };
+
+  if !ctx.profiles.contains(&profile) {
+    return Err(Box::new(StrError(format!("No such profile: {}", profile))));
+  }
+
+  let is_exe = match target.target_type {
+    TargetType::Application | TargetType::Console => platform != PlatformType::Android,
+    _                                             => false
+  };
+
+  let profile_lc = profile.to_lowercase();
+  let vars = Vars {
+    profile:  &profile_lc,
+    arch:     flags::gcc_arch_name(platform),
+    platform: flags::gcc_platform_name(platform)
+  };
+
+  let prefix = ctx.input_dir.to_str().unwrap();
+  let arch   = Architecture::parse(vars.arch).unwrap_or(Architecture::Any);
+  let (config_cflags, config_ldflags) = flags::gcc_config_flags(ctx, index, profile, platform, arch,
+                                                                is_exe);
+
+  let mut cflags  = vec![flags::gcc_cflags(ctx, index, platform), config_cflags];
+  let mut ldflags = vec![config_ldflags];
+
+  for &i in ctx.extends[index].iter().chain(std::iter::once(&index)) {
+    let s = &ctx.get_target(i).settings;
+    cflags.extend(s.include_dirs.iter().map(|x| ["-I", &join_prefix(prefix, "/", &vars.expand(x))].join("")));
+    cflags.extend(s.defines.iter().map(|x| format!("-D{}", vars.expand(x))));
+    ldflags.extend(s.lib_dirs.iter().map(|x| ["-L", &join_prefix(prefix, "/", &vars.expand(x))].join("")));
+    let (libs, files) = flags::get_libs(ctx, ctx.get_target(i));
+    ldflags.extend(libs.iter().map(|x| format!("-l{}", x)));
+    ldflags.extend(files.iter().map(|x| join_prefix(prefix, "/", &vars.expand(x))));
+  }
+
+  for &prof in &[None, Some(profile)] {
+    let (extra_cflags, extra_cxxflags, extra_ldflags) =
+      flags::get_extra_flags(ctx, index, "make", prof, platform, arch);
+    cflags.extend(extra_cflags.iter().chain(extra_cxxflags.iter()).map(|x| x.to_string()));
+    ldflags.extend(extra_ldflags.iter().map(|x| x.to_string()));
+  }
+
+  println!("cflags: {}",  join_flags(&cflags));
+  println!("ldflags: {}", join_flags(&ldflags));
+  Ok(())
+}
+
+fn join_flags(flags: &[String]) -> String {
+  flags.iter().map(|x| x.trim()).filter(|x| !x.is_empty()).collect::<Vec<&str>>().join(" ")
+}
+
+/// Targets with their type and platforms, depends edges are solid and extends
+/// edges are dashed.
+fn show_graph(ctx: &Context, args: &ArgMatches) -> RunResult {
+  let mermaid = args.value_of("format") == Some("mermaid");
+
+  println!("{}", match mermaid {
+    true  => "graph LR".to_string(),
+    false => format!("digraph \"{}\" {{\n  rankdir = LR;\n  node [shape = box];", ctx.project.name)
+  });
+
+  for (index, (name, target)) in ctx.project.targets.iter().enumerate() {
+    let platforms = match target.filter.platforms.is_empty() && ctx.project.filter.platforms.is_empty() {
+      true  => "all platforms".to_string(),
+      false => PlatformType::ALL.iter()
+        .filter(|&&p| ctx.project.filter.matches_platform(p) && target.filter.matches_platform(p))
+        .map(|p| p.to_str())
+        .collect::<Vec<&str>>()
+        .join(", ")
+    };
+    let kind = format!("{:?}", target.target_type).to_lowercase();
+
+    match mermaid {
+      true  => println!("  t{}[\"{}<br/>{}<br/>{}\"]", index, name, kind, platforms),
+      false => println!("  \"{}\" [label = \"{}\\n{}\\n{}\"];", name, name, kind, platforms)
+    }
+  }
+
+  for (index, (name, target)) in ctx.project.targets.iter().enumerate() {
+    let edges = target.depends.iter().map(|x| (x, false)).chain(target.extends.iter().map(|x| (x, true)));
+    for (other, extends) in edges {
+      let other_index = ctx.project.targets.keys().position(|x| x == other)
+        .ok_or_else(|| StrError(format!("No such target: {}", other)))?;
+      match (mermaid, extends) {
+        (true,  false) => println!("  t{} --> t{}", index, other_index),
+        (true,  true)  => println!("  t{} -. extends .-> t{}", index, other_index),
+        (false, false) => println!("  \"{}\" -> \"{}\";", name, other),
+        (false, true)  => println!("  \"{}\" -> \"{}\" [style = dashed, label = \"extends\"];", name, other)
+      }
+    }
+  }
+
+  if !mermaid {
+    println!("}}");
+  }
+  Ok(())
+}
@@ -1,15 +1,218 @@
-use clap::{App};
+use clap::{App, Arg};
+use std::path::{Path, PathBuf};
+use std::time::Instant;
 
-use crate::ctx::{Command, Context, RunResult};
+use crate::cmd::build::get_artifact_path;
+use crate::ctx::{Command, Context, DynResult, PlatformType, RunResult, StrError, Target, TestFramework};
 
 pub struct Test;
 
 impl Command for Test {
   fn init<'a, 'b>(&self, cmd: App<'a, 'b>) -> App<'a, 'b> {
-    cmd.about("Runs the project's test suite")
+    cmd.about("Runs the project's test suite, one test case at a time")
+      .arg(Arg::with_name("profile")
+           .long("profile")
+           .value_name("PROFILE")
+           .help("Profile whose build is tested, Debug by default")
+           .takes_value(true))
+      .arg(Arg::with_name("target")
+           .long("target")
+           .value_name("TARGET")
+           .help("Test target to run, all of them by default")
+           .takes_value(true))
+      .arg(Arg::with_name("filter")
+           .long("filter")
+           .value_name("TEXT")
+           .help("Only run the test cases whose name contains this text")
+           .takes_value(true))
+      .arg(Arg::with_name("list")
+           .long("list")
+           .help("List the test cases without running them"))
   }
 
-  fn run(&self, _ctx: &Context) -> RunResult {
-    Ok(())
+  /// Test targets are run from the native build tools' outputs, build them first.
+  fn run(&self, ctx: &Context) -> RunResult {
+    let args    = ctx.args.subcommand_matches("test");
+    let profile = match args.and_then(|x| x.value_of("profile")) {
+      None    => ctx.profiles.iter().cloned().find(|&x| x == "Debug").unwrap_or(ctx.profiles[0]),
+      Some(p) => match ctx.profiles.contains(&p) {
+        true  => p,
+        false => return Err(Box::new(StrError(format!("No such profile: {}", p))))
+      }
+    };
+    let filter = args.and_then(|x| x.value_of("filter")).unwrap_or("");
+    let list   = args.map_or(false, |x| x.is_present("list"));
+
+    let platform = get_host_platform();
+    let targets  = find_test_targets(ctx, platform, args.and_then(|x| x.value_of("target")))?;
+
+    let mut total  = 0;
+    let mut failed = 0;
+    for (name, target, framework) in targets {
+      let exe = get_test_exe(ctx, name, target, platform, profile)?;
+
+      for case in list_cases(&exe, framework)?.into_iter().filter(|x| x.contains(filter)) {
+        if list {
+          match ctx.log.is_json() {
+            true  => ctx.log.event("test-case", serde_json::json!({ "target": name, "name": case })),
+            false => println!("{}: {}", name, case)
+          }
+          continue;
+        }
+
+        let start  = Instant::now();
+        let output = std::process::Command::new(&exe)
+          .args(&get_run_args(framework, &case))
+          .current_dir(exe.parent().unwrap())
+          .output()?;
+        let passed = output.status.success();
+
+        total += 1;
+        if !passed {
+          failed += 1;
+        }
+
+        let result = match passed {
+          true  => "passed",
+          false => "failed"
+        };
+        match ctx.log.is_json() {
+          true  => ctx.log.event("test", serde_json::json!({
+            "target":   name,
+            "name":     case,
+            "result":   result,
+            "duration": start.elapsed().as_secs_f64(),
+            "output":   match passed {
+              true  => serde_json::Value::Null,
+              false => [String::from_utf8_lossy(&output.stdout), String::from_utf8_lossy(&output.stderr)]
+                .concat().into()
+            }
+          })),
+          false => {
+            println!("test {} {} ... {}", name, case, result);
+            if !passed {
+              print!("{}", String::from_utf8_lossy(&output.stdout));
+              eprint!("{}", String::from_utf8_lossy(&output.stderr));
+            }
+          }
+        }
+      }
+    }
+
+    if list {
+      return Ok(());
+    }
+
+    ctx.log.info(format!("{} tests, {} passed, {} failed", total, total - failed, failed));
+    match failed {
+      0 => Ok(()),
+      _ => Err(Box::new(StrError(format!("{} of {} tests failed", failed, total))))
+    }
+  }
+}
+
+/// Test targets are only run on the platform janky runs on.
+pub fn get_host_platform() -> PlatformType {
+  if cfg!(windows) {
+    PlatformType::Windows
+  }
+  else if cfg!(target_os = "macos") {
+    PlatformType::MacOS
+  }
+  else {
+    PlatformType::Linux
+  }
+}
+
+/// Test target, along with its name and framework.
+pub type TestTarget<'a> = (&'a str, &'a Target<'a>, TestFramework);
+
+/// Test targets built on the platform, or only the named one.
+pub fn find_test_targets<'a>(ctx: &Context<'a>, platform: PlatformType,
+                             only: Option<&str>) -> DynResult<Vec<TestTarget<'a>>>
+{
+  let targets = ctx.project.targets.iter()
+    .filter(|(name, t)| {
+      ctx.project.filter.matches_platform(platform) && t.filter.matches_platform(platform) &&
+        only.map_or(true, |x| x == **name)
+    })
+    .filter_map(|(name, t)| t.test_framework.map(|f| (*name, t, f)))
+    .collect::<Vec<_>>();
+  match targets.is_empty() {
+    true  => Err(Box::new(StrError(format!("No test targets to run on {}", platform.to_str())))),
+    false => Ok(targets)
+  }
+}
+
+/// Executable of a test or benchmark target built by the native build tools.
+pub fn get_test_exe(ctx: &Context, name: &str, target: &Target, platform: PlatformType,
+                    profile: &str) -> DynResult<PathBuf>
+{
+  let exe = ctx.build_dir.join(get_artifact_path(ctx, name, target, platform, profile).unwrap().1);
+  match exe.is_file() {
+    true  => Ok(exe),
+    false => Err(Box::new(StrError(format!("Target {} has no {} build, run build first ({})",
+                                           name, profile, exe.display()))))
+  }
+}
+
+/// Discovers the test cases of a test executable, using its framework's own listing.
+fn list_cases(exe: &Path, framework: TestFramework) -> DynResult<Vec<String>> {
+  let args: &[&str] = match framework {
+    TestFramework::GTest   => &["--gtest_list_tests"],
+    TestFramework::Catch2  => &["--list-tests", "--verbosity", "quiet"],
+    TestFramework::Doctest => &["--list-test-cases", "--no-intro", "--no-version"]
+  };
+
+  let output = std::process::Command::new(exe).args(args).current_dir(exe.parent().unwrap()).output()?;
+  if !output.status.success() {
+    return Err(Box::new(StrError(format!("Failed to list the test cases of {} ({})",
+                                         exe.display(), output.status))));
+  }
+
+  let stdout = String::from_utf8_lossy(&output.stdout);
+  Ok(match framework {
+    // Suites are listed as "Suite." followed by their indented cases, parameters in comments.
+    TestFramework::GTest => {
+      let mut suite = "";
+      let mut cases = Vec::new();
+      for line in stdout.lines() {
+        let name = line.split('#').next().unwrap().trim();
+        match line.starts_with(' ') {
+          false => suite = name,
+          true  => cases.push([suite, name].join(""))
+        }
+      }
+      cases
+    },
+    TestFramework::Catch2 => stdout.lines()
+      .map(str::trim)
+      .filter(|x| !x.is_empty())
+      .map(String::from)
+      .collect(),
+    // Cases are listed between separator lines, after the doctest banner.
+    TestFramework::Doctest => stdout.lines()
+      .map(str::trim)
+      .filter(|x| !x.is_empty() && !x.starts_with("[doctest]") && !x.starts_with("==="))
+      .map(String::from)
+      .collect()
+  })
+}
+
+/// Arguments running only the given test case. Characters with a special meaning
+/// in the framework's filters are escaped.
+fn get_run_args(framework: TestFramework, case: &str) -> Vec<String> {
+  let escape = |specials: &[char]| case.chars().fold(String::new(), |mut s, c| {
+    if specials.contains(&c) {
+      s.push('\\');
+    }
+    s.push(c);
+    s
+  });
+
+  match framework {
+    TestFramework::GTest   => vec![format!("--gtest_filter={}", case)],
+    TestFramework::Catch2  => vec![escape(&['\\', ',', '[', ']', '*', '"', '~'])],
+    TestFramework::Doctest => vec![format!("--test-case={}", escape(&['\\', ',', '*', '?']))]
   }
 }
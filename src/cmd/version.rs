@@ -0,0 +1,26 @@
+use clap::{App, Arg};
+
+use crate::ctx::{Command, Context, RunResult, StrError};
+use crate::gen::version;
+
+pub struct VersionHeader;
+
+impl Command for VersionHeader {
+  fn init<'a, 'b>(&self, cmd: App<'a, 'b>) -> App<'a, 'b> {
+    cmd.about("Updates the project's version header, run by the generated projects before each build")
+      .arg(Arg::with_name("profile")
+           .long("profile")
+           .value_name("PROFILE")
+           .help("Profile being built, defines the header's profile macros")
+           .takes_value(true))
+  }
+
+  fn run(&self, ctx: &Context) -> RunResult {
+    if ctx.project.version_header.is_none() {
+      return Err(Box::new(StrError("The project has no [version_header]".to_string())));
+    }
+
+    let args = ctx.args.subcommand_matches("version-header").unwrap();
+    version::write(ctx, args.value_of("profile"))
+  }
+}
@@ -1,9 +1,13 @@
 use clap::{App, ArgMatches};
-use serde::Deserialize;
-use serde_repr::Deserialize_repr;
+use serde::{Deserialize, Serialize};
+use serde_repr::{Deserialize_repr, Serialize_repr};
 use std::borrow::Cow;
-use std::collections::{BTreeMap, HashMap};
+use std::collections::{BTreeMap, BTreeSet};
 use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::diag::Diagnostics;
 
 
 // Lazy Error Handling
@@ -39,13 +43,30 @@ pub trait Platform : Sync {
 
   fn supports_architecture(&self, a: Architecture) -> bool;
 
-  fn run(&self, ctx: &Context) -> RunResult;
+  /// Probes the host for the tools building this platform, see the doctor command.
+  fn run(&self, ctx: &Context) -> Vec<Tool>;
+}
+
+/// Tool looked for while probing a platform's toolchain.
+#[derive(Debug)]
+pub struct Tool {
+  pub name:     &'static str,
+  /// Tools not required only enable optional features, ie faster builds.
+  pub required: bool,
+  /// Version or location of the tool when found, why it wasn't otherwise.
+  pub found:    Result<String, String>
 }
 
 pub trait Generator : Sync {
   fn supports_platform(&self, p: PlatformType) -> bool;
 
   fn run(&self, ctx: &Context) -> RunResult;
+
+  /// Generates the build files of a platform defined by an external descriptor.
+  /// Only the generators named by such descriptors are expected to implement it.
+  fn run_external(&self, _ctx: &Context, p: &ExternalPlatform) -> RunResult {
+    Err(Box::new(StrError(format!("Generator does not support external platforms ({})", p.name))))
+  }
 }
 
 
@@ -61,8 +82,11 @@ pub type Platforms  = Vec<Box<dyn Platform>>;
 
 pub type TargetFiles  = Vec<FileInfo>;
 pub type AllFiles     = Vec<TargetFiles>;
-pub type Profiles<'a> = HashMap<&'a str, Vec<Profile<'a>>>;
+pub type Profiles<'a> = BTreeMap<&'a str, Vec<Profile<'a>>>;
+pub type Scopes<'a>   = BTreeMap<&'a str, Scope<'a>>;
+pub type ProfileBases<'a> = BTreeMap<&'a str, &'a str>;
 pub type Strings<'a>  = Cow<'a, [&'a str]>;
+pub type ExtSettings<'a> = BTreeMap<&'a str, toml::value::Table>;
 pub type Extends      = Vec<Vec<usize>>;
 
 pub struct Context<'a> {
@@ -78,23 +102,98 @@ pub struct Context<'a> {
   pub env:       &'a Env,            // Environment variables
   pub args:      &'a ArgMatches<'a>, // Command-line arguments
   pub project:   &'a Project<'a>,    // Parsed project definition
-  pub extends:   &'a Extends,        // Lists of referencing target indices, by target index
-  pub extended:  &'a Extends,        // Inverse of extends
-  pub sources:   &'a AllFiles,       // Resolved source files, by target index
-  pub resources: &'a AllFiles,       // Resolved resource files, by target index
-  pub assets:    &'a AllFiles,       // Resolved asset files, by target index
-  pub metafiles: &'a TargetFiles,    // Resolved files at the project's root
+  pub extends:   Extends,            // Lists of referencing target indices, by target index
+  pub extended:  Extends,            // Inverse of extends
+  pub sources:   AllFiles,           // Resolved source files, by target index
+  pub resources: AllFiles,           // Resolved resource files, by target index
+  pub assets:    AllFiles,           // Resolved asset files, by target index
+  pub embeds:    AllFiles,           // Resolved files to embed, by target index
+  pub shaders:   Vec<AllFiles>,      // Resolved shader sources, by target index then shader rule
+  pub metafiles: TargetFiles,        // Resolved files at the project's root
+  pub externals: Vec<ExternalPlatform>, // Platforms loaded from descriptor files
 
   pub profiles: Vec<&'a str>,        // Names for all the build profiles
-  pub defaults: Profiles<'a>         // Built-in default settings for profiles
+  pub defaults: Profiles<'a>,        // Built-in default settings for profiles
+  pub profile_bases: ProfileBases<'a>, // Profiles extended by other profiles
+
+  pub manifest: Manifest,            // Hashes of the previously generated files
+  pub output:   OutputMode,          // Whether generated files are written or previewed
+  pub warnings: Warnings,            // Warnings reported so far
+  pub log:      Log,                 // Progress messages, filtered by verbosity
+  pub diagnostics: Diagnostics,      // Configuration problems not reported yet
+  pub timings:  Timings              // Time spent in each phase so far
 }
 
 impl<'a> Context<'a> {
-  pub fn get_target(&self, index: usize) -> &Target<'a> {
+  pub fn warn<S: std::fmt::Display>(&self, msg: S) {
+    self.warnings.warn(msg);
+  }
+
+  pub fn get_target(&self, index: usize) -> &'a Target<'a> {
     self.project.targets.values().nth(index).unwrap()
   }
+
+  pub fn get_target_name(&self, index: usize) -> &'a str {
+    self.project.targets.keys().nth(index).unwrap()
+  }
+
+  /// Built-in profile at the root of a profile's `extends` chain.
+  pub fn get_base_profile<'b>(&self, profile: &'b str) -> &'b str where 'a: 'b {
+    let mut name = profile;
+    while let Some(&base) = self.profile_bases.get(name) {
+      name = base;
+    }
+    name
+  }
+
+  /// Resolves a setting for the given target and profile, from the most specific
+  /// source to the most general: target profile, target, project profile, project
+  /// and finally the built-in defaults. Overrides from the environment come first.
+  /// Generators building every architecture at once pass `Architecture::Any`.
+  pub fn get_setting<T, F>(&self, target: &Target, profile: &str, platform: PlatformType,
+                           arch: Architecture, f: F) -> Option<T> where F: Fn(&Settings) -> Option<T>
+  {
+    let from_profiles = |profiles: &Profiles| {
+      profiles.get(profile).and_then(|x| {
+        x.iter().rev()
+          .filter(|p| p.matches(platform, arch))
+          .find_map(|p| f(&p.settings))
+      })
+    };
+
+    f(&self.project.overrides)
+      .or_else(|| from_profiles(&target.profiles))
+      .or_else(|| f(&target.settings))
+      .or_else(|| from_profiles(&self.project.profiles))
+      .or_else(|| f(&self.project.settings))
+      .or_else(|| from_profiles(&self.defaults))
+  }
+
+  /// Resolves a setting applying to a whole target, which scopes and profiles cannot
+  /// set: overrides from the environment, then the target and finally the project.
+  pub fn get_target_setting<T, F>(&self, target: &Target<'a>, f: F) -> Option<T> where
+    F: Fn(&Settings<'a>) -> Option<T>
+  {
+    f(&self.project.overrides)
+      .or_else(|| f(&target.settings))
+      .or_else(|| f(&self.project.settings))
+  }
+
+  /// Settings of an external platform for the given target, its own values
+  /// overriding the ones of the targets it extends and of the project.
+  pub fn get_ext(&self, index: usize, platform: &str) -> toml::value::Table {
+    let mut table = self.project.settings.ext.get(platform).cloned().unwrap_or_default();
+    for &i in self.extends[index].iter().chain(std::iter::once(&index)) {
+      if let Some(t) = self.get_target(i).settings.ext.get(platform) {
+        table.extend(t.clone());
+      }
+    }
+    table
+  }
 }
 
+/// File matched by a target. Its path was checked to be valid UTF-8 when the
+/// files were resolved, see `paths`.
 #[derive(Debug)]
 pub struct FileInfo {
   pub path: PathBuf,
@@ -112,7 +211,7 @@ impl FileInfo {
     self.path.file_name().unwrap().to_str().unwrap()
   }
   pub fn extension(&self) -> &'_ str {
-    self.path.extension().unwrap().to_str().unwrap()
+    self.path.extension().map_or("", |x| x.to_str().unwrap())
   }
 
   pub fn is_source_no_objc(&self) -> bool {
@@ -130,6 +229,257 @@ impl FileInfo {
   }
 }
 
+/// Content hashes of the files written by the generators, keyed by their path
+/// relative to the build directory. Used to detect generated files which were
+/// edited by hand since the last run, before overwriting them. Files from the
+/// last run which were not generated again are stale.
+#[derive(Clone, Debug, Default)]
+pub struct Manifest {
+  hashes:  Arc<Mutex<BTreeMap<PathBuf, u64>>>,
+  current: Arc<Mutex<BTreeSet<PathBuf>>>
+}
+
+impl Manifest {
+  const FILE_NAME: &'static str = ".jank-manifest";
+
+  pub fn load(build_dir: &Path) -> Self {
+    let mut hashes = BTreeMap::new();
+    if let Ok(s) = std::fs::read_to_string(build_dir.join(Self::FILE_NAME)) {
+      for line in s.lines() {
+        if let (Some(hash), Some(path)) = (line.get(.. 16), line.get(17 ..)) {
+          if let Ok(hash) = u64::from_str_radix(hash, 16) {
+            hashes.insert(PathBuf::from(path), hash);
+          }
+        }
+      }
+    }
+    Manifest { hashes: Arc::new(Mutex::new(hashes)), current: Arc::default() }
+  }
+
+  pub fn save(&self, build_dir: &Path) -> std::io::Result<()> {
+    let mut s = String::new();
+    for (path, hash) in self.hashes.lock().unwrap().iter() {
+      s.push_str(&format!("{:016x} {}\n", hash, path.to_str().unwrap()));
+    }
+    std::fs::write(build_dir.join(Self::FILE_NAME), s)
+  }
+
+  pub fn get(&self, path: &Path) -> Option<u64> {
+    self.hashes.lock().unwrap().get(path).cloned()
+  }
+
+  pub fn insert(&self, path: PathBuf, hash: u64) {
+    self.current.lock().unwrap().insert(path.clone());
+    self.hashes.lock().unwrap().insert(path, hash);
+  }
+
+  pub fn remove(&self, path: &Path) {
+    self.hashes.lock().unwrap().remove(path);
+  }
+
+  /// Files generated by the last run but not by this one so far.
+  pub fn stale(&self) -> Vec<(PathBuf, u64)> {
+    let current = self.current.lock().unwrap();
+    self.hashes.lock().unwrap().iter()
+      .filter(|(path, _)| !current.contains(*path))
+      .map(|(path, &hash)| (path.clone(), hash))
+      .collect()
+  }
+}
+
+/// Whether the generators write their files, or only report what would change.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OutputMode {
+  Write,
+  /// Lists the files which would be created, modified or deleted.
+  DryRun,
+  /// Also prints a unified diff of every change.
+  Diff
+}
+
+/// Warnings reported while resolving and generating the project. They are only
+/// counted here, strict mode fails the run when any was reported. Quiet runs
+/// still count them without printing them.
+#[derive(Clone, Debug)]
+pub struct Warnings {
+  count: Arc<Mutex<usize>>,
+  log:   Log
+}
+
+impl Warnings {
+  pub fn new(log: Log) -> Self {
+    Warnings { count: Arc::default(), log }
+  }
+
+  pub fn warn<S: std::fmt::Display>(&self, msg: S) {
+    self.warn_with(msg, serde_json::Value::Null);
+  }
+
+  /// Same as `warn`, with the fields only found in JSON events, ie the warning's location.
+  pub fn warn_with<S: std::fmt::Display>(&self, msg: S, fields: serde_json::Value) {
+    if self.log.verbosity != Verbosity::Quiet {
+      self.log.diagnostic("warning", msg, fields);
+    }
+    *self.count.lock().unwrap() += 1;
+  }
+
+  pub fn count(&self) -> usize {
+    *self.count.lock().unwrap()
+  }
+
+  pub fn log(&self) -> Log {
+    self.log
+  }
+}
+
+/// How much is reported about the run, from `-q` to `-vv`.
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
+pub enum Verbosity {
+  Quiet,
+  Normal,
+  /// Files written and skipped, targets filtered out, hooks run.
+  Verbose,
+  /// Also every file matched or linked.
+  Trace
+}
+
+/// How messages are reported, from `--message-format`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MessageFormat {
+  /// Text on stderr, for people.
+  Human,
+  /// Line-delimited JSON events on stdout, for editors and build orchestrators.
+  /// Each event is an object whose `reason` tells its shape.
+  Json
+}
+
+/// Progress messages, printed to stderr so they never mix with a command's output,
+/// or to stdout as `message` events along with the others.
+#[derive(Clone, Copy, Debug)]
+pub struct Log {
+  pub verbosity: Verbosity,
+  pub format:    MessageFormat
+}
+
+impl Log {
+  pub fn new(quiet: bool, verbose: u64, format: MessageFormat) -> Self {
+    Log {
+      verbosity: match (quiet, verbose) {
+        (true, _) => Verbosity::Quiet,
+        (_, 0)    => Verbosity::Normal,
+        (_, 1)    => Verbosity::Verbose,
+        (_, _)    => Verbosity::Trace
+      },
+      format
+    }
+  }
+
+  pub fn info<S: std::fmt::Display>(&self, msg: S) {
+    self.print(Verbosity::Normal, msg);
+  }
+
+  pub fn verbose<S: std::fmt::Display>(&self, msg: S) {
+    self.print(Verbosity::Verbose, msg);
+  }
+
+  pub fn trace<S: std::fmt::Display>(&self, msg: S) {
+    self.print(Verbosity::Trace, msg);
+  }
+
+  /// Errors are reported regardless of the verbosity.
+  pub fn error<S: std::fmt::Display>(&self, msg: S) {
+    self.diagnostic("error", msg, serde_json::Value::Null);
+  }
+
+  /// Reports a warning or an error, the fields only appear in JSON events.
+  pub fn diagnostic<S: std::fmt::Display>(&self, level: &str, msg: S, fields: serde_json::Value) {
+    match self.format {
+      MessageFormat::Human => eprintln!("{}: {}", level, msg),
+      MessageFormat::Json  => self.event("diagnostic", with_fields(serde_json::json!({
+        "level":   level,
+        "message": msg.to_string()
+      }), fields))
+    }
+  }
+
+  /// Prints a JSON event, ignored unless `--message-format json` was given.
+  pub fn event(&self, reason: &str, fields: serde_json::Value) {
+    if self.format == MessageFormat::Json {
+      println!("{}", with_fields(serde_json::json!({ "reason": reason }), fields));
+    }
+  }
+
+  pub fn is_json(&self) -> bool {
+    self.format == MessageFormat::Json
+  }
+
+  fn print<S: std::fmt::Display>(&self, level: Verbosity, msg: S) {
+    if self.verbosity >= level {
+      match self.format {
+        MessageFormat::Human => eprintln!("{}", msg),
+        MessageFormat::Json  => self.event("message", serde_json::json!({
+          "level":   format!("{:?}", level).to_lowercase(),
+          "message": msg.to_string()
+        }))
+      }
+    }
+  }
+}
+
+/// Adds the fields of an object to an event, other values are ignored.
+fn with_fields(mut event: serde_json::Value, fields: serde_json::Value) -> serde_json::Value {
+  if let (Some(event), serde_json::Value::Object(fields)) = (event.as_object_mut(), fields) {
+    event.extend(fields);
+  }
+  event
+}
+
+/// Time spent in each phase of the run, in the order they completed.
+#[derive(Clone, Debug, Default)]
+pub struct Timings {
+  phases: Arc<Mutex<Vec<(String, Duration)>>>
+}
+
+impl Timings {
+  pub fn time<T, F>(&self, name: &str, f: F) -> T where F: FnOnce() -> T {
+    let start  = Instant::now();
+    let result = f();
+    self.phases.lock().unwrap().push((name.to_string(), start.elapsed()));
+    result
+  }
+
+  /// Reports the phases as a "timings" event with JSON messages, as text on stderr otherwise.
+  pub fn report(&self, log: &Log) {
+    let phases = self.phases.lock().unwrap();
+    let ms     = |d: &Duration| d.as_secs_f64() * 1000.0;
+    let total  = phases.iter().map(|(_, d)| ms(d)).sum::<f64>();
+    match log.is_json() {
+      true => log.event("timings", serde_json::json!({
+        "phases": phases.iter().map(|(name, d)| serde_json::json!({ "name": name, "ms": ms(d) }))
+          .collect::<Vec<serde_json::Value>>(),
+        "total_ms": total
+      })),
+      false => {
+        for (name, d) in phases.iter() {
+          log.info(format!("{:>10.3} ms  {}", ms(d), name));
+        }
+        log.info(format!("{:>10.3} ms  total", total));
+      }
+    }
+  }
+}
+
+/// 64-bit FNV-1a, stable across runs and toolchains unlike the std hashers.
+pub const HASH_INIT: u64 = 0xcbf2_9ce4_8422_2325;
+
+pub fn hash_bytes(mut hash: u64, bytes: &[u8]) -> u64 {
+  for &b in bytes {
+    hash ^= u64::from(b);
+    hash  = hash.wrapping_mul(0x0100_0000_01b3);
+  }
+  hash
+}
+
 #[derive(Debug, Default, Deserialize)]
 #[serde(default)]
 pub struct Env {
@@ -137,7 +487,95 @@ pub struct Env {
   pub cxxflags: String,
   pub ldflags:  String,
 
-  pub jank_xcode_team: Option<String>
+  pub jank_xcode_team: Option<String>,
+  /// Overrides the project's Android keystore, ie to use a CI secret file.
+  pub jank_android_keystore: Option<String>,
+  /// Overrides the project's macOS signing identity, ie to use a CI certificate.
+  pub jank_macos_signing_identity: Option<String>,
+
+  /// Notarization credentials, either a keychain profile stored by `notarytool
+  /// store-credentials` or an Apple ID with its app-specific password and team.
+  pub jank_notary_keychain_profile: Option<String>,
+  pub jank_notary_apple_id:         Option<String>,
+  pub jank_notary_password:         Option<String>,
+  pub jank_notary_team_id:          Option<String>,
+
+  /// Locations of the Android SDK and NDK, used to validate the pinned NDK version.
+  pub android_ndk_home: Option<String>,
+  pub android_sdk_root: Option<String>,
+  pub android_home:     Option<String>,
+
+  /// Parsed from the `JANK_SETTINGS_` variables instead.
+  #[serde(skip)]
+  pub settings: EnvSettings
+}
+
+impl Env {
+  pub fn from_env() -> Result<Self, envy::Error> {
+    let mut env: Env = envy::from_env()?;
+    env.settings = envy::prefixed("JANK_SETTINGS_").from_env()?;
+    Ok(env)
+  }
+}
+
+/// Settings overridden from the environment, ie `JANK_SETTINGS_OPTIMIZE=Speed`
+/// or `JANK_SETTINGS_DEFINES=CI,BUILD_NUMBER=42`, so CI can tweak builds without
+/// editing the configuration. Lists are added to the project's, other values
+/// take precedence over every target and profile.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct EnvSettings {
+  pub defines:      Vec<String>,
+  pub undefs:       Vec<String>,
+  pub include_dirs: Vec<String>,
+  pub lib_dirs:     Vec<String>,
+  pub libs:         Vec<String>,
+  pub sanitizers:   Vec<String>,
+
+  #[serde(deserialize_with = "env_enum")]
+  pub optimize:          Option<Optimize>,
+  #[serde(deserialize_with = "env_enum")]
+  pub lto:               Option<Lto>,
+  #[serde(deserialize_with = "env_enum")]
+  pub debug_info:        Option<DebugInfo>,
+  #[serde(deserialize_with = "env_enum")]
+  pub simd:              Option<Simd>,
+  pub coverage:          Option<bool>,
+  pub strip:             Option<bool>,
+  pub compiler_launcher: Option<String>
+}
+
+/// envy passes enums along as plain strings, which derived enums don't accept.
+fn env_enum<'de, D, T>(d: D) -> Result<Option<T>, D::Error> where
+  D: serde::Deserializer<'de>,
+  T: Deserialize<'de>
+{
+  use serde::de::IntoDeserializer;
+  T::deserialize(String::deserialize(d)?.into_deserializer()).map(Some)
+}
+
+impl EnvSettings {
+  pub fn apply<'a>(&'a self, project: &mut Project<'a>) {
+    let strs = |v: &'a Vec<String>| v.iter().map(String::as_str);
+    let settings = &mut project.info.settings;
+    settings.defines.to_mut().extend(strs(&self.defines));
+    settings.undefs.to_mut().extend(strs(&self.undefs));
+    settings.include_dirs.to_mut().extend(strs(&self.include_dirs));
+    settings.lib_dirs.to_mut().extend(strs(&self.lib_dirs));
+    settings.libs.to_mut().extend(strs(&self.libs));
+    settings.sanitizers.to_mut().extend(strs(&self.sanitizers));
+
+    project.overrides = Settings {
+      optimize:          self.optimize,
+      lto:               self.lto,
+      debug_info:        self.debug_info,
+      simd:              self.simd,
+      coverage:          self.coverage,
+      strip:             self.strip,
+      compiler_launcher: self.compiler_launcher.as_deref(),
+      ..Default::default()
+    };
+  }
 }
 
 
@@ -154,7 +592,228 @@ pub struct Project<'a> {
   #[serde(default)]
   pub profiles: Profiles<'a>,
 
-  pub targets: HashMap<&'a str, Target<'a>>
+  /// Command aliases, expanded to a built-in command and its preset arguments.
+  #[serde(default)]
+  pub alias: BTreeMap<&'a str, &'a str>,
+
+  #[serde(default)]
+  pub hooks: Hooks<'a>,
+
+  /// Generators run as external commands, by generator name.
+  #[serde(default)]
+  pub plugins: BTreeMap<&'a str, PluginInfo<'a>>,
+
+  #[serde(default)]
+  pub symbols: Symbols<'a>,
+
+  #[serde(default)]
+  pub codegen: Codegen<'a>,
+
+  #[serde(default)]
+  pub macos: MacOS<'a>,
+
+  #[serde(default)]
+  pub linux: LinuxSettings<'a>,
+
+  /// Windows installer written by the `wix` generator, only when the table is present.
+  #[serde(default)]
+  pub wix: Option<WixSettings<'a>>,
+
+  /// Header embedding the project's version, only written when the table is present.
+  #[serde(default)]
+  pub version_header: Option<VersionHeader<'a>>,
+
+  /// Glob patterns of additional files defining targets and profiles, relative to the input folder.
+  #[serde(default)]
+  pub include: Vec<&'a str>,
+
+  #[serde(default)]
+  pub workspace: Workspace<'a>,
+
+  #[serde(default)]
+  pub targets: BTreeMap<&'a str, Target<'a>>,
+
+  /// Profiles extended by other profiles, resolved once the project is loaded.
+  #[serde(skip)]
+  pub bases: ProfileBases<'a>,
+
+  /// Settings from the environment, taking precedence over all others.
+  #[serde(skip)]
+  pub overrides: Settings<'a>
+}
+
+impl<'a> Project<'a> {
+  /// Merges the targets and profiles of an included file. Profiles are combined,
+  /// targets must not already be defined.
+  pub fn include(&mut self, fragment: ProjectFragment<'a>) -> Result<(), StrError> {
+    for (name, profiles) in fragment.profiles {
+      self.profiles.entry(name).or_default().extend(profiles);
+    }
+
+    for (name, target) in fragment.targets {
+      if self.targets.contains_key(name) {
+        return Err(StrError(format!("Target {} is already defined", name)));
+      }
+      self.targets.insert(name, target);
+    }
+
+    Ok(())
+  }
+
+  /// Merges the settings computed by a script, like the settings of profiles.
+  pub fn apply_script(&mut self, script: ScriptSettings<'a>) -> Result<(), StrError> {
+    self.info.settings.merge_mut(&script.project);
+    for (name, profiles) in script.profiles {
+      self.profiles.entry(name).or_default().extend(profiles);
+    }
+
+    for (name, settings) in script.targets {
+      match self.targets.get_mut(name) {
+        None         => return Err(StrError(format!("No such target: {}", name))),
+        Some(target) => target.settings.merge_mut(&settings)
+      }
+    }
+
+    Ok(())
+  }
+
+  /// Moves the scoped settings of the project and its targets into their profiles,
+  /// which then resolve them like any other profile.
+  pub fn resolve_scopes(&mut self, names: &[&'a str]) -> Result<(), StrError> {
+    let scopes = std::mem::take(&mut self.info.scopes);
+    Scope::add_profiles(&mut self.profiles, scopes, names)
+      .map_err(|e| StrError(format!("{} in project settings", e)))?;
+
+    for (name, target) in self.targets.iter_mut() {
+      let scopes = std::mem::take(&mut target.scopes);
+      Scope::add_profiles(&mut target.profiles, scopes, names)
+        .map_err(|e| StrError(format!("{} in target {}", e, name)))?;
+    }
+
+    Ok(())
+  }
+
+  /// Base profile of each project profile declaring one with `extends`.
+  pub fn profile_bases(&self) -> Result<ProfileBases<'a>, StrError> {
+    let mut bases = ProfileBases::new();
+    for (&name, profiles) in &self.profiles {
+      if let Some(base) = profiles.iter().find_map(|p| p.extends) {
+        bases.insert(name, base);
+      }
+    }
+
+    for &name in bases.keys() {
+      if get_profile_depth(&bases, name).is_none() {
+        return Err(StrError(format!("Profile {} extends itself", name)));
+      }
+    }
+
+    Ok(bases)
+  }
+
+  /// Starts the profiles of the project and its targets from the ones they extend.
+  pub fn extend_profiles(&mut self, bases: &ProfileBases<'a>) {
+    extend_profiles(&mut self.profiles, bases);
+    for target in self.targets.values_mut() {
+      extend_profiles(&mut target.profiles, bases);
+    }
+  }
+}
+
+/// Prepends the settings of their base to the profiles extending another one,
+/// resolving the bases first so inherited settings are carried down the chain.
+pub fn extend_profiles<'a>(profiles: &mut Profiles<'a>, bases: &ProfileBases<'a>) {
+  let mut names = bases.keys().cloned().collect::<Vec<&str>>();
+  names.sort_by_key(|&x| get_profile_depth(bases, x));
+
+  for name in names {
+    let mut v = profiles.get(bases[name]).cloned().unwrap_or_default();
+    v.extend(profiles.remove(name).unwrap_or_default());
+    if !v.is_empty() {
+      profiles.insert(name, v);
+    }
+  }
+}
+
+/// Number of profiles between a profile and its built-in base, none on cycles.
+fn get_profile_depth(bases: &ProfileBases, name: &str) -> Option<usize> {
+  let mut depth = 0;
+  let mut name  = name;
+  while let Some(&base) = bases.get(name) {
+    depth += 1;
+    if depth > bases.len() {
+      return None;
+    }
+    name = base;
+  }
+  Some(depth)
+}
+
+/// Other projects composed into this one, each member folder having its own Jank.toml.
+/// Their targets and profiles are merged into the project, so every generator
+/// emits them in the same solution, workspace or build tree.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+#[serde(deny_unknown_fields)]
+pub struct Workspace<'a> {
+  #[serde(borrow)]
+  pub members: Vec<&'a str>
+}
+
+/// Only the include patterns and workspace members, read before the project to
+/// load every file it needs.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct ProjectIncludes {
+  pub project:   ProjectNames,
+  pub include:   Vec<String>,
+  pub workspace: WorkspaceIncludes
+}
+
+/// Values of the `${project.*}` substitution variables, along with the scripts
+/// given to them, see the `script` module.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct ProjectNames {
+  pub name:    String,
+  pub version: String,
+  pub scripts: Vec<String>
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct WorkspaceIncludes {
+  pub members: Vec<String>
+}
+
+/// Contents of an included configuration file.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+#[serde(deny_unknown_fields)]
+pub struct ProjectFragment<'a> {
+  #[serde(borrow)]
+  pub profiles: Profiles<'a>,
+  pub targets:  BTreeMap<&'a str, Target<'a>>
+}
+
+/// Settings computed by a script, see the `script` module.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+#[serde(deny_unknown_fields)]
+pub struct ScriptSettings<'a> {
+  #[serde(borrow)]
+  pub project:  Settings<'a>,
+  pub profiles: Profiles<'a>,
+  pub targets:  BTreeMap<&'a str, Settings<'a>>
+}
+
+/// Contents of a workspace member's files, its other tables only apply to itself.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct MemberFragment<'a> {
+  #[serde(borrow)]
+  pub profiles: Profiles<'a>,
+  pub targets:  BTreeMap<&'a str, Target<'a>>
 }
 
 impl<'a> std::ops::Deref for Project<'a> {
@@ -165,8 +824,210 @@ impl<'a> std::ops::Deref for Project<'a> {
   }
 }
 
+/// Shell commands run by janky itself around its own commands, from the input folder.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+#[serde(deny_unknown_fields)]
+pub struct Hooks<'a> {
+  #[serde(borrow)]
+  pub pre_gen:    Strings<'a>,
+  pub post_gen:   Strings<'a>,
+  pub pre_build:  Strings<'a>,
+  pub post_build: Strings<'a>
+}
+
+/// Generator implemented by an external command, see `gen::plugin` for its protocol.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PluginInfo<'a> {
+  /// Program to run, relative to the input folder when it has a folder, otherwise found in the PATH.
+  pub command: &'a str,
+
+  #[serde(default)]
+  pub args: Vec<&'a str>,
+
+  /// Platforms the plugin generates build files for, all of them when empty.
+  #[serde(default)]
+  pub platforms: Vec<PlatformType>,
+
+  /// Passed through to the plugin untouched.
+  #[serde(default)]
+  pub properties: toml::value::Table
+}
+
+/// Crash reporting service receiving the debug symbols of Release builds.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+#[serde(deny_unknown_fields)]
+pub struct Symbols<'a> {
+  pub service: Option<SymbolService>,
+
+  /// Sentry organization and project slugs.
+  pub org:     &'a str,
+  pub project: &'a str,
+
+  /// Crashpad symbol server, receiving Breakpad symbols.
+  pub url: &'a str,
+
+  /// BugSplat database, application name and version. The version defaults to the project's.
+  pub database: &'a str,
+  pub app:      &'a str,
+  pub version:  &'a str
+}
+
+/// Compilers of the schemas found in the targets' sources, see `gen::codegen`.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+#[serde(deny_unknown_fields)]
+pub struct Codegen<'a> {
+  /// Protocol Buffers compiler, "protoc" from the PATH by default.
+  pub protoc: &'a str,
+  pub protoc_args: Vec<&'a str>,
+
+  /// FlatBuffers compiler, "flatc" from the PATH by default.
+  pub flatc: &'a str,
+  pub flatc_args: Vec<&'a str>
+}
+
+/// Signing and notarization of the macOS applications once built, see `cmd::build`.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+#[serde(deny_unknown_fields)]
+pub struct MacOS<'a> {
+  /// Identity passed to codesign, ie "Developer ID Application: Name (TEAMID)". Applications
+  /// are only signed and packaged when it is set, here or from the environment.
+  pub signing_identity: Option<&'a str>,
+
+  /// Entitlements property list, relative to the input folder.
+  pub entitlements: Option<&'a str>,
+
+  /// Disk image or zip archive of the signed application, a disk image by default.
+  pub package: MacOSPackage
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum MacOSPackage {
+  Dmg,
+  Zip
+}
+
+impl Default for MacOSPackage {
+  fn default() -> Self { MacOSPackage::Dmg }
+}
+
+/// Desktop integration of the Linux applications, see `gen::desktop`.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+#[serde(deny_unknown_fields)]
+pub struct LinuxSettings<'a> {
+  /// Reverse-DNS identifier naming the desktop entry and icon, the target's name by default.
+  pub app_id: Option<&'a str>,
+
+  /// Freedesktop menu categories, ie ["Game", "ActionGame"], "Utility" by default.
+  pub categories: Vec<&'a str>,
+
+  /// Icon of the desktop entry relative to the input folder, a 256x256 PNG or an SVG.
+  pub icon: Option<&'a str>,
+
+  /// Runs the applications in a terminal.
+  pub terminal: bool,
+
+  /// Debian and RPM packaging of the libraries and console targets, only when the table is present.
+  pub package: Option<LinuxPackage<'a>>
+}
+
+/// Distribution packages of the project, see `gen::distro`.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LinuxPackage<'a> {
+  /// Name and email of the person responsible for the packages, ie "Jane Doe <jane@example.com>".
+  pub maintainer: &'a str,
+
+  /// License identifier of the packaged files, "Proprietary" by default.
+  #[serde(default)]
+  pub license: Option<&'a str>,
+
+  #[serde(default)]
+  pub homepage: Option<&'a str>,
+
+  /// Debian archive section of the packages, "devel" by default.
+  #[serde(default)]
+  pub section: Option<&'a str>
+}
+
+/// Windows installer of an application, see `gen::wix`.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+#[serde(deny_unknown_fields)]
+pub struct WixSettings<'a> {
+  /// Application installed, the first one built for Windows by default.
+  pub target: Option<&'a str>,
+
+  /// Shown by Apps & Features, the project's name by default.
+  pub manufacturer: Option<&'a str>,
+
+  /// Identifies the product across versions, so newer installers replace older ones.
+  /// Derived from the project's name by default, it must never change once released.
+  pub upgrade_code: Option<&'a str>,
+
+  /// Icon of the shortcuts and of Apps & Features, relative to the input folder.
+  pub icon: Option<&'a str>,
+
+  /// Adds a desktop shortcut along with the Start Menu one.
+  pub desktop_shortcut: bool
+}
+
+/// Header defining the project's name, version, git commit and profile, see `gen::version`.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+#[serde(deny_unknown_fields)]
+pub struct VersionHeader<'a> {
+  /// Where to write the header relative to the input folder, "janky_version.h" by default.
+  pub path: &'a str,
+
+  /// Prefix of the macros, the project's name in uppercase by default.
+  pub prefix: &'a str,
+
+  /// File whose variables are expanded in place of the default layout, relative to the input folder.
+  pub template: Option<&'a str>
+}
+
+/// Platform whose definition is loaded from a descriptor file instead of being
+/// built into janky, such as game consoles whose SDKs cannot be redistributed.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ExternalPlatform {
+  pub name: String,
+
+  /// Registered generator handling this platform.
+  pub generator: String,
+
+  #[serde(default)]
+  pub architectures: Vec<Architecture>,
+
+  /// Passed through to the generator untouched.
+  #[serde(default)]
+  pub properties: toml::value::Table
+}
+
 #[derive(Debug, Deserialize)]
 #[serde(deny_unknown_fields)]
+pub struct PlatformDescriptor {
+  #[serde(rename = "platform")]
+  pub platforms: Vec<ExternalPlatform>
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum SymbolService {
+  Sentry,
+  Crashpad,
+  BugSplat
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
 pub struct ProjectInfo<'a> {
   pub name:    &'a str,
   pub version: &'a str,
@@ -177,46 +1038,328 @@ pub struct ProjectInfo<'a> {
   #[serde(default)]
   pub min_janky_version: &'a str,
 
-  #[serde(flatten)]
-  pub filter: TargetFilter,
+  /// Schema of the configuration, files without one use the first. Older ones
+  /// are upgraded by the migrate command.
+  #[serde(default)]
+  pub config_version: Option<u32>,
+
+  /// Fail the run on any warning, same as `--strict`.
+  #[serde(default)]
+  pub strict: bool,
+
+  /// Optional built-in profiles to enable, see `Settings::OPTIONAL_PROFILES`.
+  #[serde(default)]
+  pub builtin_profiles: Vec<&'a str>,
+
+  /// Source roots outside of the input folder, by name. Relative to the input
+  /// folder or absolute, files are matched from them using `@name/pattern`.
+  #[serde(default)]
+  pub roots: BTreeMap<&'a str, &'a str>,
+
+  /// Descriptor files defining additional platforms, relative to the input folder.
+  #[serde(default)]
+  pub external_platforms: Vec<&'a str>,
+
+  /// Generators run by `gen` when none are given on the command line. Defaults
+  /// to every generator supporting one of the project's platforms.
+  #[serde(default)]
+  pub generators: Vec<&'a str>,
+
+  /// Replaces the symlinks to assets and resources when they can't be created.
+  #[serde(default)]
+  pub link_fallback: LinkFallback,
+
+  /// Rhai scripts computing settings, relative to the input folder. Their
+  /// results are merged into the project once it is loaded, see `script`.
+  #[serde(default)]
+  pub scripts: Vec<&'a str>,
+
+  #[serde(flatten)]
+  pub filter: TargetFilter,
+
+  #[serde(flatten)]
+  pub settings: Settings<'a>,
+
+  /// Overrides scoped to a platform, an architecture or a profile, ie `[project.settings.windows]`.
+  #[serde(default)]
+  #[serde(rename = "settings")]
+  pub scopes: Scopes<'a>,
+
+  #[serde(default)]
+  pub text: BTreeMap<&'a str, TextFormat>,
+
+  #[serde(default)]
+  pub visual_studio: VisualStudioSettings,
+
+  #[serde(default)]
+  pub xcode: XcodeSettings,
+
+  #[serde(default)]
+  #[serde(borrow)]
+  pub cmake: CMakeSettings<'a>,
+
+  #[serde(default)]
+  #[serde(borrow)]
+  pub android: AndroidSettings<'a>,
+
+  #[serde(default)]
+  #[serde(borrow)]
+  pub html5: Html5Settings<'a>
+}
+
+impl<'a> ProjectInfo<'a> {
+  /// Current schema of the configuration, see the migrate command.
+  pub const CONFIG_VERSION: u32 = 2;
+}
+
+/// Encoding options for generated text files, by generator name. Unset values
+/// fall back to the generator's defaults for each file it writes.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize)]
+#[serde(default)]
+#[serde(deny_unknown_fields)]
+pub struct TextFormat {
+  pub line_endings: Option<LineEnding>,
+  pub bom: Option<bool>,
+  pub indent_style: Option<IndentStyle>,
+  pub indent_size: Option<u8>
+}
+
+impl TextFormat {
+  pub fn or(self, o: Self) -> Self {
+    TextFormat {
+      line_endings: self.line_endings.or(o.line_endings),
+      bom:          self.bom.or(o.bom),
+      indent_style: self.indent_style.or(o.indent_style),
+      indent_size:  self.indent_size.or(o.indent_size)
+    }
+  }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct VisualStudioSettings {
+
+}
+
+impl Default for VisualStudioSettings {
+  fn default() -> Self {
+    VisualStudioSettings {}
+  }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct XcodeSettings {
+  pub group_by_target: bool
+}
+
+impl Default for XcodeSettings {
+  fn default() -> Self {
+    XcodeSettings {
+      group_by_target: true
+    }
+  }
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(default)]
+#[serde(deny_unknown_fields)]
+pub struct CMakeSettings<'a> {
+  /// Cross-compilation toolchains, by name. Each one gets its own build of the
+  /// Linux targets, named after it instead of the platform.
+  #[serde(borrow)]
+  pub toolchains: BTreeMap<&'a str, Toolchain<'a>>
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(default)]
+#[serde(deny_unknown_fields)]
+pub struct Html5Settings<'a> {
+  /// Initial size of the heap in bytes, Emscripten's default when unset.
+  pub initial_memory: Option<u32>,
+  pub pthreads: bool,
+  /// Workers created at startup when using pthreads.
+  pub pool_size: Option<u8>,
+  /// WebGL version, 1 or 2.
+  pub webgl: u8,
+  /// Compiles with the WebAssembly SIMD instructions, ie for the SSE and NEON intrinsics.
+  pub simd: bool,
+  /// Lets synchronous native code yield to the browser's event loop.
+  pub asyncify: bool,
+  /// Files and folders packaged along with the application, relative to the input folder.
+  #[serde(borrow)]
+  pub preload_files: Vec<&'a str>,
+  /// Same as the preloaded files, but embedded in the generated javascript.
+  pub embed_files: Vec<&'a str>,
+  /// HTML page template, relative to the input folder.
+  pub shell_file: Option<&'a str>
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(default)]
+#[serde(deny_unknown_fields)]
+pub struct Html5Target<'a> {
+  /// Overrides the project's HTML page template.
+  #[serde(borrow)]
+  pub shell_file: Option<&'a str>,
+  /// Files and folders copied next to the page (scripts, styles, icons), relative to the input folder.
+  pub web_assets: Vec<&'a str>
+}
 
-  #[serde(flatten)]
-  pub settings: Settings<'a>,
+impl<'a> Default for Html5Settings<'a> {
+  fn default() -> Self {
+    Html5Settings {
+      initial_memory: None,
+      pthreads:       false,
+      pool_size:      None,
+      simd:           false,
+      webgl:          2,
+      asyncify:       false,
+      preload_files:  Vec::new(),
+      embed_files:    Vec::new(),
+      shell_file:     None
+    }
+  }
+}
 
-  #[serde(default)]
-  pub visual_studio: VisualStudioSettings,
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(default)]
+#[serde(deny_unknown_fields)]
+pub struct Toolchain<'a> {
+  /// Toolchain file provided by the SDK, relative to the input folder or absolute.
+  #[serde(borrow)]
+  pub file: Option<&'a str>,
+  /// Root of the target's filesystem, where its headers and libraries are found.
+  pub sysroot: Option<&'a str>,
+  /// Prefix of the compiler executables, ie "aarch64-linux-gnu-".
+  pub prefix: Option<&'a str>,
+  /// Target processor, also used as the `${arch}` substitution variable.
+  pub processor: Option<&'a str>
+}
 
-  #[serde(default)]
-  pub xcode: XcodeSettings
+/// Android application metadata. Unset values fall back to the project's, then
+/// to the Gradle generator's defaults.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(default)]
+#[serde(deny_unknown_fields)]
+pub struct AndroidSettings<'a> {
+  #[serde(borrow)]
+  pub application_id: Option<&'a str>,
+  pub version_code: Option<u32>,
+  pub version_name: Option<&'a str>,
+
+  pub min_sdk: Option<u8>,
+  pub target_sdk: Option<u8>,
+  pub compile_sdk: Option<u8>,
+  pub build_tools: Option<&'a str>,
+  /// Pinned NDK version, as found in its source.properties. Gradle's default when unset.
+  pub ndk_version: Option<&'a str>,
+  /// CMake version used by Gradle's externalNativeBuild.
+  pub cmake_version: Option<&'a str>,
+
+  /// Java activity hosting the native code.
+  pub activity: Option<AndroidActivity>,
+
+  /// Lists the application in the Android TV launcher, requires a banner asset.
+  pub tv: Option<bool>,
+
+  /// Release signing key, the keystore is relative to the input folder. The
+  /// passwords are read by Gradle from the named environment variables.
+  pub keystore: Option<&'a str>,
+  pub key_alias: Option<&'a str>,
+  pub store_password_env: Option<&'a str>,
+  pub key_password_env: Option<&'a str>,
+
+  /// Replaces the generator's default manifest entries when set.
+  pub manifest: Option<AndroidManifest<'a>>,
+
+  /// Maven coordinates added to the module's Gradle dependencies.
+  pub dependencies: Option<AndroidDependencies<'a>>
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(default)]
 #[serde(deny_unknown_fields)]
-pub struct VisualStudioSettings {
+pub struct AndroidDependencies<'a> {
+  #[serde(borrow)]
+  pub implementation: Vec<&'a str>,
+  /// Also exposed to the modules depending on a library.
+  pub api: Vec<&'a str>
+}
 
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AndroidActivity {
+  /// android.app.NativeActivity, driven from native_app_glue.
+  Native,
+  /// Jetpack's GameActivity, from the games-activity package.
+  Game
 }
 
-impl Default for VisualStudioSettings {
-  fn default() -> Self {
-    VisualStudioSettings {}
-  }
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(default)]
+#[serde(deny_unknown_fields)]
+pub struct AndroidManifest<'a> {
+  #[serde(borrow)]
+  pub permissions: Vec<&'a str>,
+  pub features: Vec<AndroidFeature<'a>>,
+  /// Value of the activity's android:screenOrientation.
+  pub orientation: Option<&'a str>,
+  /// Added to the launcher's intent filter.
+  pub intent_filters: Vec<AndroidIntentFilter<'a>>,
+  pub meta_data: BTreeMap<&'a str, &'a str>
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 #[serde(deny_unknown_fields)]
-pub struct XcodeSettings {
-  pub group_by_target: bool
+pub struct AndroidFeature<'a> {
+  pub name: &'a str,
+  #[serde(borrow)]
+  #[serde(default)]
+  pub version: Option<&'a str>,
+  #[serde(default = "default_true")]
+  pub required: bool
 }
 
-impl Default for XcodeSettings {
-  fn default() -> Self {
-    XcodeSettings {
-      group_by_target: true
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(default)]
+#[serde(deny_unknown_fields)]
+pub struct AndroidIntentFilter<'a> {
+  #[serde(borrow)]
+  pub actions: Vec<&'a str>,
+  pub categories: Vec<&'a str>,
+  pub schemes: Vec<&'a str>
+}
+
+fn default_true() -> bool { true }
+
+impl<'a> AndroidSettings<'a> {
+  pub fn or(&self, o: &Self) -> Self {
+    AndroidSettings {
+      application_id: self.application_id.or(o.application_id),
+      version_code:   self.version_code.or(o.version_code),
+      version_name:   self.version_name.or(o.version_name),
+      min_sdk:        self.min_sdk.or(o.min_sdk),
+      target_sdk:     self.target_sdk.or(o.target_sdk),
+      compile_sdk:    self.compile_sdk.or(o.compile_sdk),
+      build_tools:    self.build_tools.or(o.build_tools),
+      ndk_version:    self.ndk_version.or(o.ndk_version),
+      cmake_version:  self.cmake_version.or(o.cmake_version),
+      activity:       self.activity.or(o.activity),
+      tv:             self.tv.or(o.tv),
+
+      keystore:           self.keystore.or(o.keystore),
+      key_alias:          self.key_alias.or(o.key_alias),
+      store_password_env: self.store_password_env.or(o.store_password_env),
+      key_password_env:   self.key_password_env.or(o.key_password_env),
+
+      manifest:     self.manifest.clone().or_else(|| o.manifest.clone()),
+      dependencies: self.dependencies.clone().or_else(|| o.dependencies.clone())
     }
   }
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Default, Deserialize, Serialize)]
 #[serde(deny_unknown_fields)]
 pub struct TargetFilter {
   #[serde(default)]
@@ -234,26 +1377,57 @@ impl TargetFilter {
   pub fn matches_architecture(&self, a: Architecture) -> bool {
     self.architectures.is_empty() || self.architectures.contains(&a)
   }
+
+  /// Keeps the platforms and architectures also found in the given ones, when
+  /// any are given. Returns false when none are left.
+  pub fn intersect(&mut self, platforms: &[PlatformType], archs: &[Architecture]) -> bool {
+    fn keep<T: Copy + PartialEq>(own: &mut Vec<T>, other: &[T]) -> bool {
+      if other.is_empty() {
+        return true;
+      }
+      *own = match own.is_empty() {
+        true  => other.to_vec(),
+        false => own.iter().cloned().filter(|x| other.contains(x)).collect()
+      };
+      !own.is_empty()
+    }
+    keep(&mut self.platforms, platforms) && keep(&mut self.architectures, archs)
+  }
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Default, Deserialize, Serialize)]
 #[serde(deny_unknown_fields)]
 pub struct Target<'a> {
   #[serde(default)]
   #[serde(rename = "type")]
   pub target_type: TargetType,
 
-  /// Source code files (h, hpp, c, cpp, m, mm, etc)
+  /// Source code files (h, hpp, c, cpp, m, mm, etc), patterns starting with `!` exclude files
   #[serde(borrow)]
   pub sources: Vec<&'a str>,
 
-  /// Resource data files (copied to target, platform-independent)
+  /// Resource data files, copied next to the executable, in the bundle or in the Android assets
   #[serde(default)]
   pub resources: Vec<&'a str>,
 
   /// Asset data files (embedded in target, platform-specific rules)
   pub assets: Option<&'a str>,
 
+  /// Data files compiled into the target as C arrays, see `gen::embed`
+  #[serde(default)]
+  pub embed: Vec<&'a str>,
+
+  /// Shader sources compiled for the target's platforms, see `gen::shaders`
+  #[serde(default)]
+  pub shaders: Vec<ShaderRule<'a>>,
+
+  /// Unit test framework of a console target, linked along with a generated main, see `gen::tests`
+  pub test_framework: Option<TestFramework>,
+
+  /// Google Benchmark console target, only built by the optimized profiles, see `gen::benchmarks`
+  #[serde(default)]
+  pub benchmark: bool,
+
   #[serde(default)]
   pub depends: Vec<&'a str>,
 
@@ -266,23 +1440,196 @@ pub struct Target<'a> {
   #[serde(flatten)]
   pub settings: Settings<'a>,
 
+  /// Overrides scoped to a platform, an architecture or a profile, and combinations
+  /// of two of them, ie `[targets.X.settings.release]` or `[targets.X.settings.android.arm64]`.
+  #[serde(default)]
+  #[serde(rename = "settings")]
+  pub scopes: Scopes<'a>,
+
   #[serde(default)]
   pub profiles: Profiles<'a>,
 
+  /// Platforms building the files directly inside a folder, or matching a glob pattern.
+  #[serde(default)]
+  pub filters: BTreeMap<PathBuf, Vec<PlatformType>>,
+
+  /// Compile option overrides for the files matching a glob pattern.
+  #[serde(default)]
+  pub file_settings: BTreeMap<&'a str, FileSettings<'a>>,
+
+  #[serde(default)]
+  #[serde(borrow)]
+  pub android: AndroidSettings<'a>,
+
   #[serde(default)]
-  pub filters: HashMap<PathBuf, Vec<PlatformType>>
+  #[serde(borrow)]
+  pub html5: Html5Target<'a>
 }
 
-impl Target<'_> {
+impl<'a> Target<'a> {
+  /// Files matching no filter fall back to their platform suffix, ie `*_win32.cpp`.
   pub fn match_file(&self, file: &Path, platform: PlatformType) -> bool {
-    match self.filters.get(file.parent().unwrap()) {
-      None    => true,
-      Some(f) => f.contains(&platform)
+    let mut filtered = false;
+    for (key, platforms) in &self.filters {
+      let pattern = key.to_str().unwrap();
+      let matches = match pattern.contains(|c| c == '*' || c == '?' || c == '[') {
+        false => file.parent() == Some(key.as_path()),
+        true  => glob::Pattern::new(pattern).map_or(false, |p| p.matches_path(file))
+      };
+
+      if matches {
+        if platforms.contains(&platform) {
+          return true;
+        }
+        filtered = true;
+      }
     }
+
+    !filtered && get_suffix_platforms(file).map_or(true, |x| x.contains(&platform))
+  }
+
+  /// Whether the file is built at all for the given platform.
+  pub fn builds_file(&self, file: &Path, platform: PlatformType) -> bool {
+    self.match_file(file, platform) && !self.file_settings.iter().any(|(pattern, settings)| {
+      settings.is_excluded_platform(platform) &&
+        glob::Pattern::new(pattern).map(|p| p.matches_path(file)).unwrap_or(false)
+    })
+  }
+
+  /// Merges the settings of every file_settings pattern matching the given file.
+  /// Patterns are applied in lexical order, later options overriding earlier ones.
+  pub fn get_file_settings(&self, file: &Path) -> Option<FileSettings<'a>> {
+    self.file_settings.iter()
+      .filter(|(pattern, _)| {
+        glob::Pattern::new(pattern).map(|p| p.matches_path(file)).unwrap_or(false)
+      })
+      .fold(None, |merged, (_, settings)| {
+        let mut merged = merged.unwrap_or_default();
+        merged.merge_mut(settings);
+        Some(merged)
+      })
   }
 }
 
-#[derive(Debug, Deserialize)]
+const APPLE_PLATFORMS: &[PlatformType] = &[
+  PlatformType::MacOS, PlatformType::IOS, PlatformType::TVOS, PlatformType::WatchOS
+];
+
+const POSIX_PLATFORMS: &[PlatformType] = &[
+  PlatformType::Linux, PlatformType::MacOS, PlatformType::IOS, PlatformType::TVOS, PlatformType::WatchOS,
+  PlatformType::Android, PlatformType::HTML5
+];
+
+/// Platforms implied by the suffix of a file name, if it has one.
+fn get_suffix_platforms(file: &Path) -> Option<&'static [PlatformType]> {
+  let stem = file.file_stem()?.to_str()?;
+  let pos  = stem.rfind('_')?;
+  let platforms: &'static [PlatformType] = match &stem[pos + 1 ..] {
+    "win32" | "windows"    => &[PlatformType::Windows],
+    "linux"                => &[PlatformType::Linux],
+    "macos" | "osx"        => &[PlatformType::MacOS],
+    "ios"                  => &[PlatformType::IOS],
+    "tvos"                 => &[PlatformType::TVOS],
+    "watchos"              => &[PlatformType::WatchOS],
+    "android"              => &[PlatformType::Android],
+    "html5" | "emscripten" => &[PlatformType::HTML5],
+    "apple" | "darwin"     => APPLE_PLATFORMS,
+    "posix"                => POSIX_PLATFORMS,
+    _                      => return None
+  };
+  Some(platforms)
+}
+
+/// Shader sources compiled by the same compiler and arguments.
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct ShaderRule<'a> {
+  /// Shader source files, patterns starting with `!` exclude files
+  #[serde(borrow)]
+  pub files: Vec<&'a str>,
+
+  pub compiler: ShaderCompiler,
+
+  /// Program to run, relative to the input folder when it has a folder, otherwise found in the PATH.
+  /// The compiler's usual program by default.
+  pub program: Option<&'a str>,
+
+  #[serde(default)]
+  pub args: Vec<&'a str>,
+
+  /// Extension appended to the compiled files, the compiler's usual one by default.
+  pub extension: Option<&'a str>,
+
+  /// Platforms compiling the shaders, all of the target's when empty.
+  #[serde(default)]
+  pub platforms: Vec<PlatformType>
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TestFramework {
+  /// GoogleTest, linking the gtest library.
+  GTest,
+  /// Catch2 v3, linking the Catch2 library.
+  Catch2,
+  /// doctest, header only.
+  Doctest
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ShaderCompiler {
+  /// DirectX Shader Compiler, HLSL to DXIL or SPIR-V.
+  Dxc,
+  /// glslangValidator, GLSL or HLSL to SPIR-V.
+  Glslang,
+  /// SPIRV-Cross, SPIR-V to GLSL, HLSL or MSL.
+  SpirvCross
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(default)]
+#[serde(deny_unknown_fields)]
+pub struct FileSettings<'a> {
+  #[serde(borrow)]
+  pub defines: Strings<'a>,
+
+  /// Warnings to disable. Numbers are MSVC warnings, names are GCC/Clang warnings.
+  pub disable_warnings: Strings<'a>,
+
+  pub warning_level: Option<u8>,
+  pub optimize: Option<Optimize>,
+  pub compile_as_c: Option<bool>,
+
+  /// Matching files are still part of the project, but not built for these.
+  pub excluded_platforms: Vec<PlatformType>,
+  pub excluded_profiles: Strings<'a>
+}
+
+impl<'a> FileSettings<'a> {
+  /// Lists are extended in place, the merged settings only borrow the project strings.
+  pub fn merge_mut(&mut self, o: &Self) {
+    self.defines.to_mut().extend(o.defines.iter());
+    self.disable_warnings.to_mut().extend(o.disable_warnings.iter());
+
+    merge_opt_mut(&mut self.warning_level, &o.warning_level);
+    merge_opt_mut(&mut self.optimize,      &o.optimize);
+    merge_opt_mut(&mut self.compile_as_c,  &o.compile_as_c);
+
+    self.excluded_platforms.extend(&o.excluded_platforms);
+    self.excluded_profiles.to_mut().extend(o.excluded_profiles.iter());
+  }
+
+  pub fn is_excluded_platform(&self, platform: PlatformType) -> bool {
+    self.excluded_platforms.contains(&platform)
+  }
+
+  pub fn is_excluded_profile(&self, profile: &str) -> bool {
+    self.excluded_profiles.contains(&profile)
+  }
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
 #[serde(deny_unknown_fields)]
 pub struct Profile<'a> {
   #[serde(default)]
@@ -293,6 +1640,9 @@ pub struct Profile<'a> {
   #[serde(rename = "platform")]
   pub platform_type: PlatformType,
 
+  /// Profile whose settings this one starts from, ie `extends = "Release"`.
+  pub extends: Option<&'a str>,
+
   #[serde(borrow)]
   #[serde(flatten)]
   pub settings: Settings<'a>
@@ -303,23 +1653,81 @@ impl<'a> Profile<'a> {
     Profile {
       architecture:  Architecture::default(),
       platform_type: PlatformType::default(),
+      extends:       None,
       settings
     }
   }
 
+  /// Whether the profile applies to a platform and architecture, `Any` matching all of them.
+  pub fn matches(&self, platform: PlatformType, arch: Architecture) -> bool {
+    (self.platform_type == PlatformType::Any || platform == PlatformType::Any ||
+     self.platform_type == platform) &&
+      (self.architecture == Architecture::Any || arch == Architecture::Any || self.architecture == arch)
+  }
+
   // pub fn merge(&self, profiles: &'a Profiles<'a>, name: &'a str) -> Self {
 
   // }
 }
 
+/// Settings scoped by a platform, architecture or profile name, along with the
+/// scopes nested in it.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct Scope<'a> {
+  #[serde(borrow)]
+  #[serde(flatten)]
+  pub settings: Settings<'a>,
+
+  #[serde(flatten)]
+  pub nested: BTreeMap<&'a str, Settings<'a>>
+}
+
+impl<'a> Scope<'a> {
+  /// Converts scopes into profiles. Scopes without a profile name apply to all the
+  /// given profiles, and the more specific scopes take precedence.
+  pub fn add_profiles(profiles: &mut Profiles<'a>, scopes: Scopes<'a>,
+                      names: &[&'a str]) -> Result<(), StrError>
+  {
+    let mut all = Vec::new();
+    for (key, scope) in scopes {
+      all.push((vec![key], scope.settings));
+      all.extend(scope.nested.into_iter().map(|(nested, s)| (vec![key, nested], s)));
+    }
+    all.sort_by_key(|(keys, _)| keys.len());
+
+    for (keys, settings) in all {
+      let mut profile = Profile::new(settings);
+      let mut name    = None;
+      for key in keys {
+        let profile_name = names.iter().find(|x| x.eq_ignore_ascii_case(key));
+        match (PlatformType::parse(key), Architecture::parse(key), profile_name) {
+          (Some(p), _, _) if profile.platform_type == PlatformType::Any => profile.platform_type = p,
+          (_, Some(a), _) if profile.architecture  == Architecture::Any => profile.architecture  = a,
+          (_, _, Some(&n)) if name.is_none() => name = Some(n),
+          _ => return Err(StrError(format!("Invalid settings scope {}", key)))
+        }
+      }
+
+      match name {
+        Some(n) => profiles.entry(n).or_default().push(profile),
+        None    => for &n in names {
+          profiles.entry(n).or_default().push(profile.clone());
+        }
+      }
+    }
+
+    Ok(())
+  }
+}
+
 
 // Misc. Types
 // -----------------------------------------------------------------------------
 
-#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
 #[repr(i8)]
 pub enum Architecture {
-  #[serde(skip)]
+  #[serde(skip_deserializing)]
   Any   = -1,
   X86   =  0,
   X64   =  1,
@@ -327,10 +1735,10 @@ pub enum Architecture {
   ARM64 =  3,
 }
 
-#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
 #[repr(i8)]
 pub enum PlatformType {
-  #[serde(skip)]
+  #[serde(skip_deserializing)]
   Any     = -1,
   Windows =  0,
   Linux   =  1,
@@ -343,6 +1751,10 @@ pub enum PlatformType {
 }
 
 impl PlatformType {
+  pub const ALL: &'static [PlatformType] = &[
+    Self::Windows, Self::Linux, Self::MacOS, Self::IOS, Self::TVOS, Self::WatchOS, Self::Android, Self::HTML5
+  ];
+
   pub fn to_str(self) -> &'static str {
     match self {
       Self::Any => unreachable!(),
@@ -356,12 +1768,17 @@ impl PlatformType {
       Self::HTML5   => "HTML5"
     }
   }
+
+  /// Parses a platform name as written by `to_str`, ignoring case.
+  pub fn parse(s: &str) -> Option<Self> {
+    Self::ALL.iter().cloned().find(|p| p.to_str().eq_ignore_ascii_case(s))
+  }
 }
 
-#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
 pub enum TargetType {
   /// Automatically detect the target type based on source file names.
-  #[serde(skip)]
+  #[serde(skip_deserializing)]
   Auto,
   /// Doesn't participate in any build. Used to contain files only.
   None,
@@ -377,6 +1794,25 @@ pub enum TargetType {
   SharedLibrary
 }
 
+impl Architecture {
+  pub const ALL: &'static [Architecture] = &[Self::X86, Self::X64, Self::ARM, Self::ARM64];
+
+  pub fn to_str(self) -> &'static str {
+    match self {
+      Self::Any   => unreachable!(),
+      Self::X86   => "x86",
+      Self::X64   => "x64",
+      Self::ARM   => "arm",
+      Self::ARM64 => "arm64"
+    }
+  }
+
+  /// Parses an architecture name as written by `to_str`, ignoring case.
+  pub fn parse(s: &str) -> Option<Self> {
+    Self::ALL.iter().cloned().find(|a| a.to_str().eq_ignore_ascii_case(s))
+  }
+}
+
 impl Default for Architecture {
   fn default() -> Self { Architecture::Any }
 }
@@ -386,8 +1822,41 @@ impl Default for PlatformType {
 impl Default for TargetType {
   fn default() -> Self { TargetType::Auto }
 }
+impl Default for LinkFallback {
+  fn default() -> Self { LinkFallback::HardLink }
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+pub enum LineEnding {
+  LF,
+  CRLF
+}
+
+/// Windows only allows symlinks in developer mode or with a privilege. Hard
+/// links can't cross volumes, copies are used for those.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LinkFallback {
+  HardLink,
+  Copy
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum IndentStyle {
+  Tab,
+  Space
+}
+
+/// Compiler family used on Windows.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq)]
+pub enum Toolset {
+  MSVC,
+  /// clang-cl, through Visual Studio's ClangCL platform toolset.
+  LLVM
+}
 
-#[derive(Clone, Copy, Debug, Deserialize)]
+#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
 pub enum Optimize {
   None,
   Size,
@@ -395,7 +1864,44 @@ pub enum Optimize {
   Full
 }
 
-#[derive(Clone, Copy, Debug, Deserialize_repr)]
+/// Windows API flavor, Visual Studio defines UNICODE/_UNICODE or _MBCS to match.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq)]
+pub enum CharacterSet {
+  Unicode,
+  MultiByte
+}
+
+impl CharacterSet {
+  /// Defines implied by the character set, conflicting with the other one's.
+  pub fn defines(self) -> &'static [&'static str] {
+    match self {
+      Self::Unicode   => &["UNICODE", "_UNICODE"],
+      Self::MultiByte => &["_MBCS"]
+    }
+  }
+}
+
+/// Link-time optimization, thin splits the work to link faster.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Lto {
+  Off,
+  Thin,
+  Full
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum FloatModel {
+  /// Allows reordering and contractions, may break IEEE compliance.
+  Fast,
+  /// Value-safe optimizations only, the compilers' default.
+  Precise,
+  /// Also honors floating-point exceptions and the rounding mode.
+  Strict
+}
+
+#[derive(Clone, Copy, Debug, Deserialize_repr, Serialize_repr)]
 #[repr(u8)]
 pub enum CStandard {
   C89 = 89,
@@ -403,7 +1909,76 @@ pub enum CStandard {
   C11 = 11
 }
 
-#[derive(Clone, Copy, Debug, Deserialize_repr)]
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq)]
+pub enum StdLib {
+  /// LLVM's implementation, the only one available on Android and Apple platforms.
+  #[serde(rename = "libc++")]
+  LibCXX,
+  /// GNU's implementation, the default on Linux.
+  #[serde(rename = "libstdc++")]
+  LibStdCXX
+}
+
+impl StdLib {
+  pub fn to_str(self) -> &'static str {
+    match self {
+      Self::LibCXX    => "libc++",
+      Self::LibStdCXX => "libstdc++"
+    }
+  }
+}
+
+/// Where the debug information is kept, which also selects its format.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum DebugInfo {
+  None,
+  /// In the objects and binaries: /Z7 or DWARF.
+  Embedded,
+  /// In separate files: PDB (/Zi), dSYM bundles or split DWARF.
+  Separate
+}
+
+/// Vector instruction sets, NEON is the only one available on ARM.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Simd {
+  SSE2,
+  AVX,
+  AVX2,
+  AVX512,
+  NEON
+}
+
+impl Simd {
+  pub fn to_str(self) -> &'static str {
+    match self {
+      Self::SSE2   => "sse2",
+      Self::AVX    => "avx",
+      Self::AVX2   => "avx2",
+      Self::AVX512 => "avx512",
+      Self::NEON   => "neon"
+    }
+  }
+
+  pub fn supports_architecture(self, a: Architecture) -> bool {
+    match a {
+      Architecture::Any                       => true,
+      Architecture::X86 | Architecture::X64   => self != Simd::NEON,
+      Architecture::ARM | Architecture::ARM64 => self == Simd::NEON
+    }
+  }
+}
+
+/// MSVC runtime library, statically linked (/MT) or from the redistributable DLL (/MD).
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum MsvcRuntime {
+  Static,
+  Dynamic
+}
+
+#[derive(Clone, Copy, Debug, Deserialize_repr, Serialize_repr)]
 #[repr(u8)]
 pub enum CXXStandard {
   CXX03 =  3,
@@ -416,12 +1991,13 @@ pub enum CXXStandard {
 // Build Settings
 // -----------------------------------------------------------------------------
 
-#[derive(Debug, Default, Deserialize)]
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
 #[serde(default)]
 #[serde(deny_unknown_fields)]
 pub struct Settings<'a> {
   // General
-  // - toolset (msvc, clang, gcc ; version)
+  // - toolset (clang, gcc ; version)
+  pub toolset: Option<Toolset>,
 
   // Compiler
   #[serde(borrow)]
@@ -430,11 +2006,15 @@ pub struct Settings<'a> {
 
   pub warning_level: Option<u8>,
   pub warning_as_error: Option<bool>,
+  /// Warnings to disable. Numbers are MSVC warnings, names are GCC/Clang warnings.
+  pub disable_warnings: Strings<'a>,
 
   // Optimizations
   pub optimize: Option<Optimize>,
   pub strict_aliasing: Option<bool>,
   pub omit_frame_pointer: Option<bool>,
+  pub fp_model: Option<FloatModel>,
+  pub lto: Option<Lto>,
 
   // Preprocessor
   pub defines: Strings<'a>,
@@ -442,29 +2022,100 @@ pub struct Settings<'a> {
 
   // Codegen
   pub enable_exceptions: Option<bool>,
-  // - simd (neon, sse, avx, ...)
+  /// Runtime checks to instrument the code with: address, thread or undefined.
+  pub sanitizers: Strings<'a>,
+  /// Instrument the code to collect coverage data.
+  pub coverage: Option<bool>,
+  /// Debug information, generated by the debug profiles when unset.
+  pub debug_info: Option<DebugInfo>,
+  /// Remove the symbols from the linked binaries, Windows keeps them in PDB files anyway.
+  pub strip: Option<bool>,
+  /// Instruction set extensions the code is allowed to use.
+  pub simd: Option<Simd>,
   // - FP abi (soft, softFP, hard)
-  // - PIC
+  /// Position-independent code, always on for shared libraries on most platforms.
+  pub pic: Option<bool>,
+
+  // Symbol visibility, symbols are already hidden by default on Windows.
+  pub visibility_hidden: Option<bool>,
+  pub visibility_inlines_hidden: Option<bool>,
+
+  // Security
+  pub control_flow_guard: Option<bool>,
+  pub spectre_mitigation: Option<bool>,
+  pub buffer_security_check: Option<bool>,
+  pub aslr: Option<bool>,
 
   // Language
   pub enable_rtti: Option<bool>,
   pub c_standard: Option<CStandard>,
   pub cxx_standard: Option<CXXStandard>,
-  // - stdlib: static/shared, debug/release, msvc/llvm/gcc/stlport/runtime
+  pub stdlib: Option<StdLib>,
+  pub stdlib_static: Option<bool>,
+  /// C runtime linked by MSVC, the debug variant follows the profile.
+  pub msvc_runtime: Option<MsvcRuntime>,
+  /// Standard library checks from 0 (none) to 2 (full). Changes the ABI of the
+  /// containers, prebuilt libraries must be built at the same level.
+  pub iterator_debug_level: Option<u8>,
 
   // PCH
-  // - Enable, file, build file
+  /// Header to precompile, as included by the sources.
+  pub pch: Option<&'a str>,
+  /// Source file building the precompiled header, relative to the input folder.
+  pub pch_source: Option<&'a str>,
+  /// Headers included before the first line of every source file.
+  pub force_includes: Strings<'a>,
+  /// Pass long include and define lists to the tools through response files.
+  pub response_files: Option<bool>,
+  /// Program wrapping the compiler invocations, such as ccache or sccache.
+  pub compiler_launcher: Option<&'a str>,
 
   // Linker
   pub link_incremental: Option<bool>,
   pub lib_dirs: Strings<'a>,
+  /// Libraries to link: system library names, paths to library files containing a
+  /// separator, or names of library targets in the project.
   pub libs: Strings<'a>,
+  /// Apple system frameworks to link, ie `Metal`. Scope them by platform in the
+  /// settings tables, a platform without any links the generator's defaults.
+  pub frameworks: Strings<'a>,
+  /// Apple system frameworks linked weakly, for APIs missing from older systems.
+  pub weak_frameworks: Strings<'a>,
+
+  // Output
+  /// Name of the built binary, without prefix or extension. Defaults to the target name.
+  pub output_name: Option<&'a str>,
+  /// Folder receiving the built binaries, relative to the input folder.
+  pub output_dir: Option<&'a str>,
+
+  // Raw flags, appended after the generated ones. The project's also receive the
+  // CFLAGS, CXXFLAGS and LDFLAGS environment variables. C flags apply to C++ too.
+  pub extra_cflags: Strings<'a>,
+  pub extra_cxxflags: Strings<'a>,
+  pub extra_ldflags: Strings<'a>,
+  /// Raw flags replacing the ones above for a generator, by generator name.
+  pub extra_flags: BTreeMap<&'a str, ExtraFlags<'a>>,
 
   // Platform specific
   pub android_target_api_level: Option<u8>,
+  pub windows_character_set: Option<CharacterSet>,
 
   // Architecture specific
-  pub arm_thumb_mode: Option<bool>
+  pub arm_thumb_mode: Option<bool>,
+
+  /// Settings of external platforms, by platform name. Opaque to janky.
+  pub ext: ExtSettings<'a>
+}
+
+/// Raw flags of a generator, each list replacing the generic one when set.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(default)]
+#[serde(deny_unknown_fields)]
+pub struct ExtraFlags<'a> {
+  #[serde(borrow)]
+  pub cflags: Option<Strings<'a>>,
+  pub cxxflags: Option<Strings<'a>>,
+  pub ldflags: Option<Strings<'a>>
 }
 
 impl<'a> Settings<'a> {
@@ -475,6 +2126,9 @@ impl<'a> Settings<'a> {
       optimize:           Some(Optimize::None),
       strict_aliasing:    Some(false),
       omit_frame_pointer: Some(false),
+      fp_model:           Some(FloatModel::Precise),
+      lto:                Some(Lto::Off),
+      iterator_debug_level: Some(1),
       link_incremental:   Some(true),
       ..Default::default()
     }
@@ -487,70 +2141,201 @@ impl<'a> Settings<'a> {
       optimize:           Some(Optimize::Full),
       strict_aliasing:    Some(true),
       omit_frame_pointer: Some(true),
+      fp_model:           Some(FloatModel::Fast),
+      lto:                Some(Lto::Full),
       link_incremental:   Some(false),
       ..Default::default()
     }
   }
 
-  pub fn defaults() -> Profiles<'a> {
+  /// Built-in profiles only added when listed in the project's `builtin_profiles`.
+  pub const OPTIONAL_PROFILES: &'static [&'static str] = &["Coverage", "DebugASan", "DebugTSan"];
+
+  fn optional(name: &str) -> Option<Self> {
+    let (sanitizers, coverage) = match name {
+      "Coverage"  => (&[] as &'static [&'static str], Some(true)),
+      "DebugASan" => (&["address", "undefined"][..],  None),
+      "DebugTSan" => (&["thread"][..],                None),
+      _           => return None
+    };
+    Some(Settings {
+      sanitizers: Cow::Borrowed(sanitizers),
+      coverage,
+      ..Self::debug()
+    })
+  }
+
+  pub fn defaults(optional: &[&'a str]) -> Profiles<'a> {
     let mk = |x: Settings<'a>| vec!(Profile::new(x));
     let mut m = Profiles::new();
     m.insert("Debug",   mk(Self::debug()));
     m.insert("Release", mk(Self::release()));
+    for &name in optional {
+      if let Some(s) = Self::optional(name) {
+        m.insert(name, mk(s));
+      }
+    }
     m
   }
 
-  pub fn merge_mut<'b>(&'b mut self, o: &'a Self) where 'a: 'b {
+  /// Names of the settings set here which apply to whole targets, scopes and profiles
+  /// cannot set them. See `Context::get_target_setting`.
+  pub fn get_target_keys(&self) -> Vec<&'static str> {
+    let keys = [
+      ("simd",                      self.simd.is_some()),
+      ("pic",                       self.pic.is_some()),
+      ("visibility_hidden",         self.visibility_hidden.is_some()),
+      ("visibility_inlines_hidden", self.visibility_inlines_hidden.is_some()),
+      ("stdlib",                    self.stdlib.is_some()),
+      ("stdlib_static",             self.stdlib_static.is_some()),
+      ("pch",                       self.pch.is_some()),
+      ("pch_source",                self.pch_source.is_some()),
+      ("response_files",            self.response_files.is_some()),
+      ("compiler_launcher",         self.compiler_launcher.is_some()),
+      ("output_name",               self.output_name.is_some()),
+      ("output_dir",                self.output_dir.is_some())
+    ];
+    keys.iter().filter(|x| x.1).map(|x| x.0).collect()
+  }
+
+  pub fn merge_mut(&mut self, o: &Self) {
+    merge_opt_mut(&mut self.toolset, &o.toolset);
+
     merge_vecs_mut(&mut self.include_dirs, &o.include_dirs);
 
     merge_opt_mut(&mut self.warning_level,    &o.warning_level);
     merge_opt_mut(&mut self.warning_as_error, &o.warning_as_error);
+    merge_vecs_mut(&mut self.disable_warnings, &o.disable_warnings);
 
     merge_opt_mut(&mut self.optimize,           &o.optimize);
     merge_opt_mut(&mut self.strict_aliasing,    &o.strict_aliasing);
     merge_opt_mut(&mut self.omit_frame_pointer, &o.omit_frame_pointer);
+    merge_opt_mut(&mut self.fp_model,           &o.fp_model);
+    merge_opt_mut(&mut self.lto,                &o.lto);
 
     merge_vecs_mut(&mut self.defines, &o.defines);
     merge_vecs_mut(&mut self.undefs,  &o.undefs);
 
     merge_opt_mut(&mut self.enable_exceptions, &o.enable_exceptions);
+    merge_vecs_mut(&mut self.sanitizers,       &o.sanitizers);
+    merge_opt_mut(&mut self.coverage,          &o.coverage);
+    merge_opt_mut(&mut self.debug_info,        &o.debug_info);
+    merge_opt_mut(&mut self.strip,             &o.strip);
+    merge_opt_mut(&mut self.simd,              &o.simd);
+    merge_opt_mut(&mut self.pic,               &o.pic);
+
+    merge_opt_mut(&mut self.visibility_hidden,         &o.visibility_hidden);
+    merge_opt_mut(&mut self.visibility_inlines_hidden, &o.visibility_inlines_hidden);
+
+    merge_opt_mut(&mut self.control_flow_guard,    &o.control_flow_guard);
+    merge_opt_mut(&mut self.spectre_mitigation,    &o.spectre_mitigation);
+    merge_opt_mut(&mut self.buffer_security_check, &o.buffer_security_check);
+    merge_opt_mut(&mut self.aslr,                  &o.aslr);
 
     merge_opt_mut(&mut self.enable_rtti,  &o.enable_rtti);
     merge_opt_mut(&mut self.c_standard,   &o.c_standard);
     merge_opt_mut(&mut self.cxx_standard, &o.cxx_standard);
 
+    merge_opt_mut(&mut self.stdlib,        &o.stdlib);
+    merge_opt_mut(&mut self.stdlib_static, &o.stdlib_static);
+    merge_opt_mut(&mut self.msvc_runtime,  &o.msvc_runtime);
+    merge_opt_mut(&mut self.iterator_debug_level, &o.iterator_debug_level);
+
+    merge_opt_mut (&mut self.pch,            &o.pch);
+    merge_opt_mut (&mut self.pch_source,     &o.pch_source);
+    merge_vecs_mut(&mut self.force_includes, &o.force_includes);
+    merge_opt_mut (&mut self.response_files, &o.response_files);
+    merge_opt_mut (&mut self.compiler_launcher, &o.compiler_launcher);
+
     merge_opt_mut (&mut self.link_incremental, &o.link_incremental);
     merge_vecs_mut(&mut self.lib_dirs,         &o.lib_dirs);
     merge_vecs_mut(&mut self.libs,             &o.libs);
+    merge_vecs_mut(&mut self.frameworks,       &o.frameworks);
+    merge_vecs_mut(&mut self.weak_frameworks,  &o.weak_frameworks);
+
+    merge_opt_mut(&mut self.output_name, &o.output_name);
+    merge_opt_mut(&mut self.output_dir,  &o.output_dir);
+
+    merge_vecs_mut(&mut self.extra_cflags,   &o.extra_cflags);
+    merge_vecs_mut(&mut self.extra_cxxflags, &o.extra_cxxflags);
+    merge_vecs_mut(&mut self.extra_ldflags,  &o.extra_ldflags);
+    self.extra_flags.extend(o.extra_flags.iter().map(|(&k, v)| (k, v.clone())));
+
+    for (&name, table) in &o.ext {
+      self.ext.entry(name).or_default().extend(table.clone());
+    }
   }
 
   pub fn merge(&'a self, o: &'a Self) -> Self {
     Settings {
+      toolset: self.toolset.or(o.toolset),
+
       include_dirs:     merge_vecs(&self.include_dirs, &o.include_dirs),
 
       warning_level:    self.warning_level.or(o.warning_level),
       warning_as_error: self.warning_as_error.or(o.warning_as_error),
+      disable_warnings: merge_vecs(&self.disable_warnings, &o.disable_warnings),
 
       optimize:           self.optimize.or(o.optimize),
       strict_aliasing:    self.strict_aliasing.or(o.strict_aliasing),
       omit_frame_pointer: self.omit_frame_pointer.or(o.omit_frame_pointer),
+      fp_model:           self.fp_model.or(o.fp_model),
+      lto:                self.lto.or(o.lto),
 
       defines: merge_vecs(&self.defines, &o.defines),
       undefs:  merge_vecs(&self.undefs, &o.defines),
 
       enable_exceptions: self.enable_exceptions.or(o.enable_exceptions),
+      sanitizers:        merge_vecs(&self.sanitizers, &o.sanitizers),
+      coverage:          self.coverage.or(o.coverage),
+      debug_info:        self.debug_info.or(o.debug_info),
+      strip:             self.strip.or(o.strip),
+      simd:              self.simd.or(o.simd),
+      pic:               self.pic.or(o.pic),
+
+      visibility_hidden:         self.visibility_hidden.or(o.visibility_hidden),
+      visibility_inlines_hidden: self.visibility_inlines_hidden.or(o.visibility_inlines_hidden),
+
+      control_flow_guard:    self.control_flow_guard.or(o.control_flow_guard),
+      spectre_mitigation:    self.spectre_mitigation.or(o.spectre_mitigation),
+      buffer_security_check: self.buffer_security_check.or(o.buffer_security_check),
+      aslr:                  self.aslr.or(o.aslr),
 
       enable_rtti:  self.enable_rtti.or(o.enable_rtti),
       c_standard:   self.c_standard.or(o.c_standard),
       cxx_standard: self.cxx_standard.or(o.cxx_standard),
 
+      stdlib:        self.stdlib.or(o.stdlib),
+      stdlib_static: self.stdlib_static.or(o.stdlib_static),
+      msvc_runtime:  self.msvc_runtime.or(o.msvc_runtime),
+      iterator_debug_level: self.iterator_debug_level.or(o.iterator_debug_level),
+
+      pch:            self.pch.or(o.pch),
+      pch_source:     self.pch_source.or(o.pch_source),
+      force_includes: merge_vecs(&self.force_includes, &o.force_includes),
+      response_files: self.response_files.or(o.response_files),
+      compiler_launcher: self.compiler_launcher.or(o.compiler_launcher),
+
       link_incremental: self.link_incremental.or(o.link_incremental),
       lib_dirs:         merge_vecs(&self.lib_dirs, &o.lib_dirs),
       libs:             merge_vecs(&self.libs, &o.libs),
+      frameworks:       merge_vecs(&self.frameworks, &o.frameworks),
+      weak_frameworks:  merge_vecs(&self.weak_frameworks, &o.weak_frameworks),
+
+      output_name: self.output_name.or(o.output_name),
+      output_dir:  self.output_dir.or(o.output_dir),
+
+      extra_cflags:   merge_vecs(&self.extra_cflags, &o.extra_cflags),
+      extra_cxxflags: merge_vecs(&self.extra_cxxflags, &o.extra_cxxflags),
+      extra_ldflags:  merge_vecs(&self.extra_ldflags, &o.extra_ldflags),
+      extra_flags:    o.extra_flags.clone().into_iter().chain(self.extra_flags.clone()).collect(),
 
       android_target_api_level: self.android_target_api_level.or(o.android_target_api_level),
+      windows_character_set:    self.windows_character_set.or(o.windows_character_set),
+
+      arm_thumb_mode: self.arm_thumb_mode.or(o.arm_thumb_mode),
 
-      arm_thumb_mode: self.arm_thumb_mode.or(o.arm_thumb_mode)
+      ext: merge_ext(&self.ext, &o.ext)
     }
   }
 
@@ -602,10 +2387,10 @@ fn merge_opt_mut<T: Copy>(a: &mut Option<T>, b: &Option<T>) {
   }
 }
 
-fn merge_vecs_mut<'a, 'b>(a: &'b mut Strings<'a>, b: &'a Strings<'a>) where 'a: 'b {
+fn merge_vecs_mut<'a>(a: &mut Strings<'a>, b: &Strings<'a>) {
   if a.is_empty() {
     if !b.is_empty() {
-      *a = Cow::Borrowed(&*b);
+      *a = b.clone();
     }
   }
   else {
@@ -615,6 +2400,15 @@ fn merge_vecs_mut<'a, 'b>(a: &'b mut Strings<'a>, b: &'a Strings<'a>) where 'a:
   }
 }
 
+/// Merges the tables key by key, with the values of `a` taking precedence.
+fn merge_ext<'a>(a: &ExtSettings<'a>, b: &ExtSettings<'a>) -> ExtSettings<'a> {
+  let mut m = b.clone();
+  for (&name, table) in a {
+    m.entry(name).or_default().extend(table.clone());
+  }
+  m
+}
+
 fn merge_vecs<'a>(a: &'a Strings, b: &'a Strings) -> Strings<'a> {
   if a.is_empty() {
     Cow::Borrowed(&*b)
@@ -628,3 +2422,213 @@ fn merge_vecs<'a>(a: &'a Strings, b: &'a Strings) -> Strings<'a> {
     v.into()
   }
 }
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn filter(platforms: &[PlatformType], architectures: &[Architecture]) -> TargetFilter {
+    TargetFilter { platforms: platforms.to_vec(), architectures: architectures.to_vec() }
+  }
+
+  const PROJECT: &str = "[project]\nname = \"p\"\nversion = \"1.0.0\"\n";
+
+  /// Project with its scopes and profiles resolved, as done by `Project::load`.
+  fn project(text: &str) -> Project {
+    let mut project = toml::from_str::<Project>(text).unwrap();
+    let names = crate::load::profile_names(&Settings::defaults(&[]), &project);
+    project.resolve_scopes(&names).unwrap();
+    project.bases = project.profile_bases().unwrap();
+    let bases = project.bases.clone();
+    project.extend_profiles(&bases);
+    project
+  }
+
+  /// Context of a project, without any generator, platform or file.
+  fn context<'a>(project: &'a Project<'a>, env: &'a Env, args: &'a ArgMatches<'a>) -> Context<'a> {
+    let log = Log::new(true, 0, MessageFormat::Human);
+    let mut defaults = Settings::defaults(&[]);
+    extend_profiles(&mut defaults, &project.bases);
+    let none = vec![Vec::new(); project.targets.len()];
+    Context {
+      commands:      Commands::new(),
+      platforms:     Platforms::new(),
+      generators:    Generators::new(),
+      input_dir:     PathBuf::new(),
+      build_dir:     PathBuf::new(),
+      build_rel:     PathBuf::new(),
+      input_rel:     PathBuf::new(),
+      env,
+      args,
+      project,
+      extends:       none.clone(),
+      extended:      none,
+      sources:       AllFiles::new(),
+      resources:     AllFiles::new(),
+      assets:        AllFiles::new(),
+      embeds:        AllFiles::new(),
+      shaders:       Vec::new(),
+      metafiles:     TargetFiles::new(),
+      externals:     Vec::new(),
+      profiles:      crate::load::profile_names(&defaults, project),
+      defaults,
+      profile_bases: project.bases.clone(),
+      manifest:      Manifest::default(),
+      output:        OutputMode::DryRun,
+      warnings:      Warnings::new(log),
+      log,
+      diagnostics:   Diagnostics::default(),
+      timings:       Timings::default()
+    }
+  }
+
+  #[test]
+  fn intersect_narrows() {
+    use PlatformType::*;
+    let mut f = filter(&[Windows, Linux, MacOS], &[]);
+    assert!(f.intersect(&[Linux, Android], &[Architecture::X64]));
+    assert_eq!(f.platforms, &[Linux]);
+    assert_eq!(f.architectures, &[Architecture::X64]);
+  }
+
+  #[test]
+  fn intersect_empty() {
+    use PlatformType::*;
+    let mut f = filter(&[], &[Architecture::ARM64]);
+    assert!(f.intersect(&[], &[]));
+    assert!(f.platforms.is_empty());
+    assert_eq!(f.architectures, &[Architecture::ARM64]);
+
+    // An empty filter matches everything, the given values are kept as they are.
+    let mut f = filter(&[], &[]);
+    assert!(f.intersect(&[Windows], &[]));
+    assert_eq!(f.platforms, &[Windows]);
+  }
+
+  #[test]
+  fn intersect_disjoint() {
+    use PlatformType::*;
+    assert!(!filter(&[Windows], &[]).intersect(&[Linux], &[]));
+    assert!(!filter(&[], &[Architecture::X86]).intersect(&[Windows], &[Architecture::ARM]));
+  }
+
+  #[test]
+  fn scopes_to_profiles() {
+    let text   = "[windows]\nlto = \"thin\"\n[arm64.release]\nstrip = true\n";
+    let scopes = toml::from_str::<Scopes>(text).unwrap();
+    let mut profiles = Profiles::new();
+    Scope::add_profiles(&mut profiles, scopes, &["Debug", "Release"]).unwrap();
+
+    // Scopes without a profile name go to every profile, the most specific ones last.
+    let scope = |p: &Profile| (p.platform_type, p.architecture, p.settings.strip);
+    let debug = profiles["Debug"].iter().map(scope).collect::<Vec<_>>();
+    assert_eq!(debug, [(PlatformType::Any, Architecture::ARM64, None),
+                       (PlatformType::Windows, Architecture::Any, None)]);
+    let release = profiles["Release"].iter().map(scope).collect::<Vec<_>>();
+    assert_eq!(release[2], (PlatformType::Any, Architecture::ARM64, Some(true)));
+
+    for invalid in &["[release.debug]\nstrip = true", "[windows.linux]\nstrip = true"] {
+      let scopes = toml::from_str::<Scopes>(invalid).unwrap();
+      assert!(Scope::add_profiles(&mut Profiles::new(), scopes, &["Debug", "Release"]).is_err());
+    }
+  }
+
+  #[test]
+  fn get_setting_scoped() {
+    let text = [PROJECT, concat!("lto = \"thin\"\n",
+                                 "[project.settings.arm64]\nlto = \"off\"\n",
+                                 "[targets.a]\ntype = \"Console\"\nsources = []\n",
+                                 "[targets.a.settings.windows]\nstrip = true\n",
+                                 "[targets.a.settings.x64.release]\nlto = \"full\"\n")].concat();
+    let project = project(&text);
+    let (env, args) = (Env::default(), ArgMatches::new());
+    let ctx    = context(&project, &env, &args);
+    let target = ctx.get_target(0);
+    let lto    = |profile, arch| ctx.get_setting(target, profile, PlatformType::Android, arch, |s| s.lto);
+
+    assert_eq!(lto("Debug",   Architecture::ARM64), Some(Lto::Off));
+    assert_eq!(lto("Debug",   Architecture::X64),   Some(Lto::Thin));
+    assert_eq!(lto("Release", Architecture::X64),   Some(Lto::Full));
+    assert_eq!(lto("Release", Architecture::ARM64), Some(Lto::Off));
+    assert_eq!(lto("Release", Architecture::Any),   Some(Lto::Full));
+
+    let strip = |platform| ctx.get_setting(target, "Debug", platform, Architecture::X64, |s| s.strip);
+    assert_eq!(strip(PlatformType::Windows), Some(true));
+    assert_eq!(strip(PlatformType::Linux),   None);
+  }
+
+  #[test]
+  fn extend_profiles_chain() {
+    let text = [PROJECT, concat!("[[profiles.Release]]\nstrip = true\n",
+                                 "[[profiles.Fast]]\nextends = \"Release\"\nlto = \"thin\"\n",
+                                 "[[profiles.Faster]]\nextends = \"Fast\"\n",
+                                 "[targets.a]\ntype = \"Console\"\nsources = []\n",
+                                 "[[targets.a.profiles.Fast]]\nstrip = false\n")].concat();
+    let project = project(&text);
+    let (env, args) = (Env::default(), ArgMatches::new());
+    let ctx    = context(&project, &env, &args);
+    let target = ctx.get_target(0);
+    let get    = |profile, f: fn(&Settings) -> Option<bool>| {
+      ctx.get_setting(target, profile, PlatformType::Any, Architecture::Any, f)
+    };
+
+    assert_eq!(ctx.get_base_profile("Faster"), "Release");
+    assert_eq!(ctx.get_base_profile("Debug"),  "Debug");
+
+    // Settings carry down the chain, built-in defaults included.
+    let lto = |profile| ctx.get_setting(target, profile, PlatformType::Any, Architecture::Any, |s| s.lto);
+    assert_eq!(lto("Faster"), Some(Lto::Thin));
+    assert_eq!(lto("Fast"),   Some(Lto::Thin));
+    assert_eq!(lto("Debug"),  Some(Lto::Off));
+    let optimize = ctx.get_setting(target, "Faster", PlatformType::Any, Architecture::Any, |s| s.optimize);
+    assert!(matches!(optimize, Some(Optimize::Full)));
+
+    // The target's own profiles are extended the same way.
+    assert_eq!(get("Release", |s| s.strip), Some(true));
+    assert_eq!(get("Fast",    |s| s.strip), Some(false));
+    assert_eq!(get("Faster",  |s| s.strip), Some(false));
+  }
+
+  #[test]
+  fn extend_profiles_cycle() {
+    let text = [PROJECT, concat!("[[profiles.A]]\nextends = \"B\"\n",
+                                 "[[profiles.B]]\nextends = \"A\"\n")].concat();
+    let project = toml::from_str::<Project>(&text).unwrap();
+    assert!(project.profile_bases().is_err());
+  }
+
+  #[test]
+  fn suffix_platforms() {
+    let get = |file| get_suffix_platforms(Path::new(file));
+    assert_eq!(get("src/window_win32.cpp"), Some(&[PlatformType::Windows][..]));
+    assert_eq!(get("src/thread_macos.mm"),  Some(&[PlatformType::MacOS][..]));
+    assert!(get("src/file_posix.cpp").unwrap().contains(&PlatformType::Linux));
+    assert!(!get("src/file_posix.cpp").unwrap().contains(&PlatformType::Windows));
+    assert_eq!(get("src/a.cpp"),       None);
+    assert_eq!(get("src/my_file.cpp"), None);
+  }
+
+  #[test]
+  fn match_file_filters() {
+    let text = concat!("type = \"Console\"\nsources = []\n",
+                       "[filters]\n\"src/win\" = [\"Windows\"]\n",
+                       "\"src/**/*_gl.cpp\" = [\"Linux\", \"Android\"]\n");
+    let target = toml::from_str::<Target>(text).unwrap();
+    let matches = |file, platform| target.match_file(Path::new(file), platform);
+
+    // Folder filters only apply to the files directly in that folder.
+    assert!(matches("src/win/window.cpp", PlatformType::Windows));
+    assert!(!matches("src/win/window.cpp", PlatformType::Linux));
+    assert!(matches("src/win/sub/window.cpp", PlatformType::Linux));
+
+    // Suffixes apply when no filter matches, and filters take precedence over them.
+    assert!(matches("src/main.cpp", PlatformType::Linux));
+    assert!(matches("src/file_posix.cpp", PlatformType::Linux));
+    assert!(!matches("src/file_posix.cpp", PlatformType::Windows));
+    assert!(matches("src/win/file_linux.cpp", PlatformType::Windows));
+    assert!(!matches("src/win/file_linux.cpp", PlatformType::Linux));
+    assert!(matches("src/render/draw_gl.cpp", PlatformType::Android));
+    assert!(!matches("src/render/draw_gl.cpp", PlatformType::Windows));
+  }
+}
@@ -0,0 +1,412 @@
+//! Problems found in the project's configuration, reported along with the line
+//! of the configuration file they originate from.
+//!
+//! The borrowed values the project is deserialized into carry no spans, keys are
+//! instead located by parsing the files again for the spans of their values, see
+//! `spans`. Tables written with a header have none, their lines are scanned for.
+//! Parse errors carry their own position, unknown keys also get the closest
+//! valid key of their table as a suggestion.
+
+use serde::Serialize;
+use std::fmt::Display;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use crate::ctx::{Profile, ProjectInfo, Target, Warnings};
+use crate::spans;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Severity {
+  Warning,
+  Error
+}
+
+/// Line and column in a configuration file, both starting at 1.
+#[derive(Clone, Debug)]
+pub struct Location {
+  pub path: PathBuf,
+  pub line: usize,
+  pub col:  usize
+}
+
+#[derive(Clone, Debug)]
+pub struct Diagnostic {
+  pub severity: Severity,
+  pub message:  String,
+  pub location: Option<Location>,
+  pub help:     Option<String>
+}
+
+/// Returned once configuration problems were found, the diagnostics describe them.
+#[derive(Debug)]
+pub struct ConfigError(pub usize);
+
+impl Display for ConfigError {
+  fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+    write!(f, "{} error(s) found in the project's configuration", self.0)
+  }
+}
+
+impl std::error::Error for ConfigError {}
+
+/// Configuration file, as read from disk before any variable is expanded.
+#[derive(Debug)]
+struct Source {
+  path: PathBuf,
+  text: String
+}
+
+/// Diagnostics collected so far, along with the files they refer to.
+#[derive(Clone, Debug, Default)]
+pub struct Diagnostics {
+  sources: Arc<Mutex<Vec<Source>>>,
+  items:   Arc<Mutex<Vec<Diagnostic>>>
+}
+
+impl Diagnostics {
+  pub fn add_source(&self, path: &Path, text: &str) {
+    self.sources.lock().unwrap().push(Source { path: path.to_path_buf(), text: text.to_string() });
+  }
+
+  pub fn error<S: Display>(&self, msg: S) {
+    self.push(Severity::Error, msg, None);
+  }
+
+  pub fn warning<S: Display>(&self, msg: S) {
+    self.push(Severity::Warning, msg, None);
+  }
+
+  /// Reports an error at the given key, ie `["targets", "Game", "stdlib"]`. When
+  /// the key itself is not written in any file, the closest table defining it is used.
+  pub fn error_at<S: Display>(&self, key: &[&str], msg: S) {
+    self.push(Severity::Error, msg, self.locate(key));
+  }
+
+  pub fn warning_at<S: Display>(&self, key: &[&str], msg: S) {
+    self.push(Severity::Warning, msg, self.locate(key));
+  }
+
+  /// Reports a failure to parse or deserialize a configuration file. Load-time
+  /// rewrites keep the lines of the file as read, the column is only kept when
+  /// the line itself was left untouched.
+  pub fn toml_error<S: Display>(&self, path: &Path, text: &[u8], e: &toml::de::Error, msg: S) {
+    let location = self.sources.lock().unwrap().iter().find(|x| x.path == path).and_then(|source| {
+      let (line, col) = e.line_col()?;
+      let original = source.text.lines().nth(line)?;
+      let col = match String::from_utf8_lossy(text).lines().nth(line) == Some(original) {
+        true  => col,
+        false => original.len() - original.trim_start().len()
+      };
+      Some(Location { path: path.to_path_buf(), line: line + 1, col: col + 1 })
+    });
+    self.items.lock().unwrap().push(Diagnostic {
+      severity: Severity::Error,
+      message:  format!("{}: {}", msg, e),
+      location,
+      help:     suggest_key(&e.to_string())
+    });
+  }
+
+  /// Configuration files loaded so far, as read from disk.
+  pub fn sources(&self) -> Vec<(PathBuf, String)> {
+    self.sources.lock().unwrap().iter().map(|x| (x.path.clone(), x.text.clone())).collect()
+  }
+
+  pub fn has_errors(&self) -> bool {
+    self.num_errors() != 0
+  }
+
+  pub fn num_errors(&self) -> usize {
+    self.items.lock().unwrap().iter().filter(|x| x.severity == Severity::Error).count()
+  }
+
+  /// Fails once any error was collected, leaving the diagnostics to be reported.
+  pub fn check_errors(&self) -> Result<(), ConfigError> {
+    match self.num_errors() {
+      0 => Ok(()),
+      n => Err(ConfigError(n))
+    }
+  }
+
+  /// Prints the diagnostics collected so far, warnings are also counted for strict
+  /// mode. Returns the number of errors.
+  pub fn report(&self, warnings: &Warnings) -> usize {
+    let items   = std::mem::take(&mut *self.items.lock().unwrap());
+    let sources = self.sources.lock().unwrap();
+    let log     = warnings.log();
+    let mut num_errors = 0;
+
+    for d in items {
+      let mut msg = d.message;
+      let fields  = match log.is_json() {
+        false => {
+          if let Some(loc) = &d.location {
+            msg.push_str(&format_location(&sources, loc));
+          }
+          if let Some(help) = &d.help {
+            msg.push_str(&format!("\n  = help: {}", help));
+          }
+          serde_json::Value::Null
+        },
+        true => serde_json::json!({
+          "span": d.location.as_ref().map(|loc| serde_json::json!({
+            "file":   loc.path,
+            "line":   loc.line,
+            "column": loc.col,
+            "text":   sources.iter().find(|x| x.path == loc.path)
+              .and_then(|x| x.text.lines().nth(loc.line - 1))
+          })),
+          "help": d.help
+        })
+      };
+
+      match d.severity {
+        Severity::Warning => warnings.warn_with(msg, fields),
+        Severity::Error   => {
+          log.diagnostic("error", msg, fields);
+          num_errors += 1;
+        }
+      }
+    }
+
+    num_errors
+  }
+
+  /// Prints the diagnostics and exits when any of them is an error.
+  pub fn abort_on_errors(&self, warnings: &Warnings) {
+    if self.has_errors() {
+      self.fail(warnings);
+    }
+    self.report(warnings);
+  }
+
+  /// Prints the diagnostics and exits.
+  pub fn fail(&self, warnings: &Warnings) -> ! {
+    let num_errors = self.report(warnings);
+    let log        = warnings.log();
+    let msg        = ConfigError(num_errors).to_string();
+    match log.is_json() {
+      true  => log.event("finished", serde_json::json!({ "success": false, "message": msg })),
+      false => eprintln!("{}", msg)
+    }
+    std::process::exit(1)
+  }
+
+  fn push<S: Display>(&self, severity: Severity, msg: S, location: Option<Location>) {
+    let message = msg.to_string();
+    self.items.lock().unwrap().push(Diagnostic { severity, message, location, help: None });
+  }
+
+  /// Deepest match of the key in the loaded files, the first file winning ties.
+  fn locate(&self, key: &[&str]) -> Option<Location> {
+    let sources = self.sources.lock().unwrap();
+    let mut best: Option<(usize, Location)> = None;
+    for source in sources.iter() {
+      if let Some((depth, line, col)) = find_key(&source.text, key) {
+        if best.as_ref().map_or(true, |x| depth > x.0) {
+          best = Some((depth, Location { path: source.path.clone(), line, col }));
+        }
+      }
+    }
+    best.map(|x| x.1)
+  }
+}
+
+fn format_location(sources: &[Source], loc: &Location) -> String {
+  let mut s = format!("\n  --> {}:{}:{}", loc.path.display(), loc.line, loc.col);
+  let text = sources.iter().find(|x| x.path == loc.path)
+    .and_then(|x| x.text.lines().nth(loc.line - 1));
+  if let Some(text) = text {
+    let width = loc.line.to_string().len();
+    s.push_str(&format!("\n {:w$} |\n {} | {}", "", loc.line, text, w = width));
+  }
+  s
+}
+
+/// Finds the longest prefix of a key, at the span of its value or else the line
+/// of its table header. Returns the matched depth, the line and the column.
+fn find_key(text: &str, key: &[&str]) -> Option<(usize, usize, usize)> {
+  let spanned = spans::Node::parse(text).ok().and_then(|x| x.find(key)).map(|(depth, pos)| {
+    let line = text[.. pos].matches('\n').count() + 1;
+    let col  = text[.. pos].rfind('\n').map_or(pos, |x| pos - x - 1) + 1;
+    (depth, line, col)
+  });
+
+  match (spanned, scan_key(text, key)) {
+    (Some(a), Some(b)) if b.0 > a.0 => Some(b),
+    (None, b)                       => b,
+    (a, _)                          => a
+  }
+}
+
+/// Finds the line defining the longest prefix of a key, as a table header or
+/// as a key/value pair.
+fn scan_key(text: &str, key: &[&str]) -> Option<(usize, usize, usize)> {
+  let mut table = Vec::new();
+  let mut best  = None;
+
+  for (index, line) in text.lines().enumerate() {
+    let trimmed = line.trim_start();
+    let col     = line.len() - trimmed.len() + 1;
+
+    let path = if trimmed.starts_with('[') {
+      let header = trimmed.trim_start_matches('[');
+      let header = header.split(']').next().unwrap_or("");
+      table = split_key(header);
+      table.clone()
+    }
+    else if trimmed.starts_with('#') {
+      continue;
+    }
+    else if let Some(pos) = trimmed.find('=') {
+      let mut path = table.clone();
+      path.extend(split_key(&trimmed[.. pos]));
+      path
+    }
+    else {
+      continue;
+    };
+
+    let depth = path.iter().zip(key).take_while(|(a, b)| a == *b).count();
+    if depth > 0 && depth > best.map_or(0, |x: (usize, usize, usize)| x.0) {
+      best = Some((depth, index + 1, col));
+      if depth == key.len() {
+        break;
+      }
+    }
+  }
+
+  best
+}
+
+/// Splits a dotted key, leaving the dots of quoted keys in place.
+pub fn split_key(s: &str) -> Vec<String> {
+  let mut parts = vec![String::new()];
+  let mut quote = None;
+  for c in s.trim().chars() {
+    match (c, quote) {
+      ('"', None) | ('\'', None)    => quote = Some(c),
+      (_, Some(q)) if c == q         => quote = None,
+      ('.', None)                    => parts.push(String::new()),
+      (_, None) if c.is_whitespace() => {},
+      _                              => parts.last_mut().unwrap().push(c)
+    }
+  }
+  parts
+}
+
+
+// Suggestions
+// -----------------------------------------------------------------------------
+
+/// Suggests the closest valid key for serde's unknown field errors, ie
+/// "unknown field `stdib`, expected one of ... for key `targets.Game`".
+fn suggest_key(msg: &str) -> Option<String> {
+  let field   = between(msg, "unknown field `", "`")?;
+  let section = between(msg, "for key `", "`");
+
+  // Tables with flattened settings do not list the keys they expect.
+  let expected = msg.find(", expected ").map_or("", |pos| {
+    let s = &msg[pos ..];
+    &s[.. s.find(" for key ").unwrap_or(s.len())]
+  });
+  let mut keys = expected.split('`').skip(1).step_by(2).map(String::from).collect::<Vec<String>>();
+
+  if keys.is_empty() {
+    keys = match section.map(|x| x.split('.').next().unwrap()) {
+      Some("targets")  => serialized_keys::<Target>(),
+      Some("project")  => serialized_keys::<ProjectInfo>(),
+      Some("profiles") => serialized_keys::<Profile>(),
+      _                => Vec::new()
+    };
+  }
+
+  let max = std::cmp::max(2, field.len() / 3);
+  let best = keys.iter()
+    .map(|x| (edit_distance(field, x), x))
+    .filter(|x| x.0 <= max)
+    .min_by_key(|x| x.0)?;
+
+  Some(match section {
+    Some(s) => format!("did you mean `{}` in [{}]?", best.1, s),
+    None    => format!("did you mean `{}`?", best.1)
+  })
+}
+
+fn between<'s>(s: &'s str, start: &str, end: &str) -> Option<&'s str> {
+  let s = &s[s.find(start)? + start.len() ..];
+  Some(&s[.. s.find(end)?])
+}
+
+/// Levenshtein distance, counted in characters.
+fn edit_distance(a: &str, b: &str) -> usize {
+  let b = b.chars().collect::<Vec<char>>();
+  let mut row = (0 ..= b.len()).collect::<Vec<usize>>();
+  for (i, ca) in a.chars().enumerate() {
+    let mut prev = row[0];
+    row[0] = i + 1;
+    for (j, &cb) in b.iter().enumerate() {
+      let cur = row[j + 1];
+      row[j + 1] = match ca == cb {
+        true  => prev,
+        false => 1 + prev.min(row[j]).min(cur)
+      };
+      prev = cur;
+    }
+  }
+  row[b.len()]
+}
+
+/// Keys of a struct with flattened fields, which only its derived Serialize lists.
+fn serialized_keys<T: Default + Serialize>() -> Vec<String> {
+  match serde_json::to_value(T::default()) {
+    Ok(serde_json::Value::Object(map)) => map.into_iter().map(|x| x.0).collect(),
+    _                                  => Vec::new()
+  }
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn distance() {
+    assert_eq!(edit_distance("", ""), 0);
+    assert_eq!(edit_distance("", "abc"), 3);
+    assert_eq!(edit_distance("stdib", "stdlib"), 1);
+    assert_eq!(edit_distance("defnies", "defines"), 2);
+    assert_eq!(edit_distance("kitten", "sitting"), 3);
+    assert_eq!(edit_distance("ü", "u"), 1);
+  }
+
+  #[test]
+  fn suggest_listed() {
+    let msg = "unknown field `verison`, expected one of `name`, `version` for key `project.macos`";
+    assert_eq!(suggest_key(msg).as_deref(), Some("did you mean `version` in [project.macos]?"));
+
+    let msg = "unknown field `verison`, expected `version`";
+    assert_eq!(suggest_key(msg).as_deref(), Some("did you mean `version`?"));
+
+    let msg = "unknown field `zzz`, expected one of `name`, `version` for key `project.macos`";
+    assert_eq!(suggest_key(msg), None);
+  }
+
+  #[test]
+  fn suggest_flattened() {
+    let msg = "unknown field `stdib` for key `targets.Game`";
+    assert_eq!(suggest_key(msg).as_deref(), Some("did you mean `stdlib` in [targets.Game]?"));
+
+    let msg = "unknown field `filter` for key `targets.Game`";
+    assert_eq!(suggest_key(msg).as_deref(), Some("did you mean `filters` in [targets.Game]?"));
+
+    let msg = "unknown field `platfroms` for key `project`";
+    assert_eq!(suggest_key(msg).as_deref(), Some("did you mean `platforms` in [project]?"));
+
+    let msg = "unknown field `extend` for key `profiles.Debug`";
+    assert_eq!(suggest_key(msg).as_deref(), Some("did you mean `extends` in [profiles.Debug]?"));
+
+    assert_eq!(suggest_key("unknown field `stdib` for key `alias`"), None);
+    assert_eq!(suggest_key("invalid type: integer `1`, expected a string"), None);
+  }
+}
@@ -1,17 +1,221 @@
+pub mod benchmarks;
 mod cmake;
+pub mod codegen;
+pub mod desktop;
+pub mod distro;
+pub mod embed;
+mod filelist;
+pub mod flags;
 mod gradle;
 mod make;
+pub mod plugin;
+pub mod shaders;
+pub mod subst;
+pub mod symbols;
+pub mod tests;
+pub mod text;
+pub mod version;
 mod vs;
+pub mod wix;
 mod xcode;
 
-use crate::ctx::Generators;
+use std::path::{Path, PathBuf};
+use uuid::Uuid;
+
+use crate::ctx::{Context, FileInfo, Generators, LinkFallback, OutputMode, HASH_INIT, hash_bytes};
 
 pub fn init() -> Generators {
   let mut generators = Generators::new();
-  generators.insert("cmake",  Box::new(cmake::CMake));
-  generators.insert("gradle", Box::new(gradle::Gradle));
-  generators.insert("make",   Box::new(make::Make));
-  generators.insert("vs",     Box::new(vs::VisualStudio));
-  generators.insert("xcode",  Box::new(xcode::XCode));
+  generators.insert("cmake",    Box::new(cmake::CMake));
+  generators.insert("filelist", Box::new(filelist::FileList));
+  generators.insert("gradle",   Box::new(gradle::Gradle));
+  generators.insert("make",     Box::new(make::Make));
+  generators.insert("vs",       Box::new(vs::VisualStudio));
+  generators.insert("wix",      Box::new(wix::Wix));
+  generators.insert("xcode",    Box::new(xcode::XCode));
   generators
 }
+
+/// Locates a path relative to the input folder from the generated files, using
+/// the input folder's `prefix`. Absolute paths, such as the files of source roots
+/// on other drives, are left untouched.
+pub fn join_prefix(prefix: &str, sep: &str, path: &str) -> String {
+  match Path::new(path).is_absolute() {
+    true  => path.to_string(),
+    false => [prefix, sep, path].join("")
+  }
+}
+
+/// Resource files of a target and the ones it extends. They are copied next to
+/// the executable, in the bundle's resources or in the application's assets,
+/// without their folders.
+pub fn get_resources<'a>(ctx: &'a Context, index: usize) -> Vec<&'a FileInfo> {
+  ctx.extends[index].iter().chain(std::iter::once(&index))
+    .flat_map(|&i| ctx.resources[i].iter())
+    .filter(|x| x.meta.is_file())
+    .collect()
+}
+
+/// Links `path` to the file at `src`, relative to the link's folder. Hosts not
+/// allowing the process to create symlinks get the project's `link_fallback`
+/// instead, left untouched while its content matches.
+pub fn link_file(ctx: &Context, src: &Path, path: &Path) -> std::io::Result<()> {
+  let old    = std::fs::read_link(path).ok();
+  let target = path.parent().unwrap().join(src);
+  if old.as_ref().map_or(false, |x| x == src) {
+    return Ok(());
+  }
+
+  #[cfg(windows)]
+  {
+    let data = std::fs::read(&target)?;
+    if old.is_none() && std::fs::read(path).map_or(false, |x| x == data) {
+      return Ok(());
+    }
+  }
+
+  if ctx.output != OutputMode::Write {
+    let key = path.strip_prefix(&ctx.build_dir).unwrap_or(path);
+    let old = old.as_ref().map(|x| x.to_string_lossy().into_owned());
+    let new = src.to_string_lossy();
+    text::preview(ctx.log, ctx.output, key, old.as_ref().map(|x| x.as_bytes()), Some(new.as_bytes()));
+    return Ok(());
+  }
+
+  if path.symlink_metadata().is_ok() {
+    std::fs::remove_file(path)?;
+  }
+
+  #[cfg(unix)]
+  let result = std::os::unix::fs::symlink(src, path);
+  #[cfg(windows)]
+  let result = std::os::windows::fs::symlink_file(src, path);
+
+  match result {
+    Err(e) if cfg!(windows) => {
+      ctx.log.trace(format!("Cannot symlink {} ({}), using the link fallback", path.display(), e));
+      let linked = ctx.project.link_fallback == LinkFallback::HardLink &&
+        std::fs::hard_link(&target, path).is_ok();
+      match linked {
+        true  => Ok(()),
+        false => copy_file(ctx, &target, path)
+      }
+    },
+    result => result
+  }
+}
+
+/// Copies a file, leaving the destination untouched when it already has the same content.
+pub fn copy_file(ctx: &Context, src: &Path, dst: &Path) -> std::io::Result<()> {
+  let data = std::fs::read(src)?;
+  let old  = std::fs::read(dst).ok();
+  if old.as_ref().map_or(false, |x| *x == data) {
+    return Ok(());
+  }
+
+  let key = dst.strip_prefix(&ctx.build_dir).unwrap_or(dst);
+  match ctx.output {
+    OutputMode::Write => {
+      // Replace hard links instead of writing through them.
+      if old.is_some() {
+        std::fs::remove_file(dst)?;
+      }
+      std::fs::write(dst, &data)?;
+      text::report_write(ctx.log, key, old.as_deref(), Some(&data));
+      Ok(())
+    },
+    output            => {
+      text::preview(ctx.log, output, key, old.as_deref(), Some(&data));
+      Ok(())
+    }
+  }
+}
+
+/// Same GUID on every run for the same parts, so unchanged projects are generated
+/// byte for byte and Visual Studio keeps its per-project user settings.
+pub fn stable_uuid(parts: &[&str]) -> String {
+  let hash = |init| parts.iter().fold(init, |h, x| hash_bytes(hash_bytes(h, x.as_bytes()), b"\0"));
+  let mut bytes = [0u8; 16];
+  bytes[.. 8].copy_from_slice(&hash(HASH_INIT).to_be_bytes());
+  bytes[8 ..].copy_from_slice(&hash(!HASH_INIT).to_be_bytes());
+
+  // Version 4 and RFC 4122 variant bits, as Visual Studio's own GUIDs.
+  bytes[6] = (bytes[6] & 0x0F) | 0x40;
+  bytes[8] = (bytes[8] & 0x3F) | 0x80;
+  Uuid::from_bytes(bytes).to_string().to_uppercase()
+}
+
+/// Command line running janky on this project from the generated projects, ie
+/// to update files as they build. Uses the executable generating the project.
+pub fn janky_command(ctx: &Context, command: &str) -> String {
+  let exe = std::env::current_exe().unwrap_or_else(|_| PathBuf::from(env!("CARGO_PKG_NAME")));
+  let config = match ctx.args.value_of("config") {
+    None    => String::new(),
+    Some(x) => format!(" --config \"{}\"", x)
+  };
+  format!("\"{}\" -q \"{}\" --build \"{}\"{} {}",
+          exe.display(), ctx.input_dir.display(), ctx.build_dir.display(), config, command)
+}
+
+/// Writes a file updated by the generated projects as they build, instead of
+/// recording it in the manifest. Unchanged files are left untouched. Parallel
+/// builds may update it at the same time, it is replaced through a temporary file.
+pub fn update_file(ctx: &Context, path: &Path, data: &[u8]) -> std::io::Result<()> {
+  let old = std::fs::read(path).ok();
+  if old.as_ref().map_or(false, |x| *x == data) {
+    ctx.log.trace(format!("Unchanged {}", path.display()));
+    return Ok(());
+  }
+
+  let key = path.strip_prefix(&ctx.build_dir)
+    .or_else(|_| path.strip_prefix(&ctx.input_dir))
+    .unwrap_or(path);
+  if ctx.output != OutputMode::Write {
+    text::preview(ctx.log, ctx.output, key, old.as_deref(), Some(data));
+    return Ok(());
+  }
+
+  ctx.log.verbose(format!("Writing {}", key.display()));
+  if let Some(dir) = path.parent() {
+    std::fs::create_dir_all(dir)?;
+  }
+  let mut tmp = path.to_path_buf().into_os_string();
+  tmp.push(format!(".{}.jank-tmp", std::process::id()));
+  std::fs::write(&tmp, data)?;
+  std::fs::rename(&tmp, path)?;
+  text::report_write(ctx.log, key, old.as_deref(), Some(data));
+  Ok(())
+}
+
+/// Deletes the files generated by the last run but not by this one, unless they
+/// were modified since.
+pub fn remove_stale_files(ctx: &Context) -> std::io::Result<()> {
+  for (key, hash) in ctx.manifest.stale() {
+    let path = ctx.build_dir.join(&key);
+    let data = match std::fs::read(&path) {
+      Ok(x) if hash_bytes(HASH_INIT, &x) == hash => x,
+      Ok(_) => {
+        ctx.warn(format!("{} is no longer generated but was modified, leaving it in place",
+                         path.display()));
+        ctx.manifest.remove(&key);
+        continue;
+      },
+      Err(_) => {
+        ctx.manifest.remove(&key);
+        continue;
+      }
+    };
+
+    match ctx.output {
+      OutputMode::Write => {
+        ctx.log.verbose(format!("Deleting {}", key.display()));
+        std::fs::remove_file(&path)?;
+        ctx.manifest.remove(&key);
+        text::report_write(ctx.log, &key, Some(&data), None);
+      },
+      output => text::preview(ctx.log, output, &key, Some(&data), None)
+    }
+  }
+
+  Ok(())
+}
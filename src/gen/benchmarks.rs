@@ -0,0 +1,34 @@
+//! Benchmark targets, the console targets with `benchmark = true`.
+//!
+//! They are linked with Google Benchmark and its main, running every benchmark
+//! registered by their sources. Timing unoptimized code is meaningless, so they
+//! are only built by the profiles optimizing it, ie Release but not Debug. CMake
+//! and Visual Studio skip them in the other profiles, while Xcode builds them in
+//! every configuration. `janky bench` runs them and writes their results as JSON.
+
+use crate::ctx::{Architecture, Context, Optimize, PlatformType, Target};
+
+pub const CMAKE_PACKAGE: &str = "benchmark";
+pub const CMAKE_TARGET:  &str = "benchmark::benchmark_main";
+
+/// Libraries linked by name, for generators without packages.
+pub fn get_libraries(platform: PlatformType) -> &'static [&'static str] {
+  match platform {
+    PlatformType::Windows => &["benchmark_main", "benchmark", "shlwapi"],
+    _                     => &["benchmark_main", "benchmark"]
+  }
+}
+
+/// Whether a profile builds the target, always true unless it is a benchmark.
+pub fn is_built(ctx: &Context, target: &Target, profile: &str, platform: PlatformType) -> bool {
+  let optimize = ctx.get_setting(target, profile, platform, Architecture::Any, |s| s.optimize);
+  !target.benchmark || match optimize {
+    None | Some(Optimize::None) => false,
+    Some(_)                     => true
+  }
+}
+
+/// Profiles building a benchmark target on the platform.
+pub fn get_profiles<'a>(ctx: &Context<'a>, target: &Target, platform: PlatformType) -> Vec<&'a str> {
+  ctx.profiles.iter().cloned().filter(|x| is_built(ctx, target, x, platform)).collect()
+}
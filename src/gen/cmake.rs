@@ -1,7 +1,13 @@
-use std::fs::{File, create_dir_all};
-use std::io::{BufWriter, Write};
+use std::fs::create_dir_all;
+use std::io::Write;
 
-use crate::ctx::{Context, Generator, PlatformType, RunResult, Target, TargetType};
+use crate::ctx::{AndroidActivity, Architecture, Context, FileInfo, Generator, PlatformType, RunResult,
+                 Target, TargetType, Toolchain};
+use super::{flags, get_resources, join_prefix};
+use super::subst::Vars;
+use super::symbols;
+use super::{benchmarks, codegen, desktop, distro, embed, shaders, tests, version};
+use super::text::{self, TextFile};
 
 const PLATFORMS: [PlatformType; 3] = [
   PlatformType::Android,
@@ -19,25 +25,46 @@ impl Generator for CMake {
 
   fn run(&self, ctx: &Context) -> RunResult {
     if !PLATFORMS.iter().any(|x| ctx.project.filter.matches_platform(*x)) {
+      ctx.log.verbose("CMake: skipped, the project builds none of its platforms");
       return Ok(());
     }
 
+    // Cross-compiled builds are variants of the Linux platform.
+    let toolchains = &ctx.project.cmake.toolchains;
+    let variants = PLATFORMS.iter().map(|&p| (p, p.to_str(), None))
+      .chain(toolchains.iter().map(|(&name, t)| (PlatformType::Linux, name, Some(t))))
+      .collect::<Vec<(PlatformType, &str, Option<&Toolchain>)>>();
+
     let targets = ctx.project.targets.iter().enumerate().map(|(index, (name, target))| {
-      PLATFORMS.iter().map(move |&platform| {
+      variants.iter().map(move |&(platform, suffix, toolchain)| {
         match target.filter.matches_platform(platform) {
-          false => None,
+          false => {
+            ctx.log.verbose(format!("CMake: target {} is not built for {}", name, suffix));
+            None
+          },
           true  => {
             Some(Build {
-              name, target, index, platform,
-              path: [name, "_", platform.to_str()].join("")
+              name, target, index, platform, suffix, toolchain,
+              path: [name, "_", suffix].join("")
             })
           }
         }
       }).flatten()
-    }).flatten();
+    }).flatten().collect::<Vec<Build>>();
+
+    for (&name, toolchain) in toolchains {
+      write_toolchain_file(ctx, name, toolchain)?;
+    }
+
+    for build in &targets {
+      write_lists_txt(ctx, build)?;
+    }
 
-    for build in targets {
-      write_lists_txt(ctx, &build)?;
+    for &(platform, suffix, _) in &variants {
+      let builds = targets.iter().filter(|x| x.suffix == suffix).collect::<Vec<&Build>>();
+      if ctx.project.filter.matches_platform(platform) && !builds.is_empty() {
+        write_root_lists_txt(ctx, suffix, &builds)?;
+      }
     }
 
     Ok(())
@@ -47,20 +74,173 @@ impl Generator for CMake {
 type IO = std::io::Result<()>;
 
 struct Build<'a> {
-  index:    usize,
-  path:     String,
-  name:     &'a str,
-  target:   &'a Target<'a>,
-  platform: PlatformType
+  index:     usize,
+  path:      String,
+  name:      &'a str,
+  suffix:    &'a str, // Platform or toolchain name
+  target:    &'a Target<'a>,
+  platform:  PlatformType,
+  toolchain: Option<&'a Toolchain<'a>>
+}
+
+/// Extended libraries are built once as their own targets and linked, other
+/// extended targets have their sources compiled into each target extending them.
+fn is_linked(ctx: &Context, index: usize, platform: PlatformType) -> bool {
+  let target = ctx.get_target(index);
+  target.filter.matches_platform(platform) && match target.target_type {
+    TargetType::StaticLibrary | TargetType::SharedLibrary => true,
+    _                                                     => false
+  }
+}
+
+/// Code generation options, CMake also passes them to the link where they must match.
+fn get_html5_compile_flags(ctx: &Context) -> String {
+  let mut flags = String::new();
+  if ctx.project.html5.pthreads {
+    flags.push_str(" -pthread");
+  }
+  if ctx.project.html5.simd {
+    flags.push_str(" -msimd128");
+  }
+  flags
 }
 
-fn write_lists_txt(ctx: &Context, build: &Build) -> IO {
-  let mut f = BufWriter::new(File::create({
+/// Emscripten settings are link flags, packaged files are mounted at their path in the input folder.
+fn get_html5_link_flags(ctx: &Context, target: &Target) -> String {
+  let html5 = &ctx.project.html5;
+  let file  = |x: &str| ctx.input_dir.join(x).to_str().unwrap().replace('\\', "/");
+
+  let mut flags = String::from(" -s WASM=1");
+
+  if html5.webgl == 2 {
+    flags.push_str(" -s USE_WEBGL2=1 -s MIN_WEBGL_VERSION=2");
+  }
+
+  if let Some(bytes) = html5.initial_memory {
+    flags.push_str(&format!(" -s INITIAL_MEMORY={}", bytes));
+  }
+
+  if html5.pthreads {
+    flags.push_str(" -s USE_PTHREADS=1");
+    if let Some(n) = html5.pool_size {
+      flags.push_str(&format!(" -s PTHREAD_POOL_SIZE={}", n));
+    }
+  }
+
+  if html5.asyncify {
+    flags.push_str(" -s ASYNCIFY=1");
+  }
+
+  flags.push_str(concat!(" -s EXIT_RUNTIME=1",
+                         " -s DISABLE_DEPRECATED_FIND_EVENT_TARGET_BEHAVIOR=1",
+                         " --emrun"));
+
+  for x in &html5.preload_files {
+    flags.push_str(&format!(" --preload-file {}@{}", file(x), x));
+  }
+
+  for x in &html5.embed_files {
+    flags.push_str(&format!(" --embed-file {}@{}", file(x), x));
+  }
+
+  if let Some(x) = target.html5.shell_file.or(html5.shell_file) {
+    flags.push_str(&format!(" --shell-file {}", file(x)));
+  }
+
+  flags
+}
+
+/// Declares every target of a platform once, so shared libraries are compiled a single time.
+fn write_root_lists_txt(ctx: &Context, suffix: &str, builds: &[&Build]) -> IO {
+  let mut f = TextFile::create(ctx, "cmake", text::LF, {
+    let mut path = ctx.build_dir.join(["CMake_", suffix].join(""));
+    create_dir_all(&path)?;
+    path.push("CMakeLists.txt");
+    path
+  })?;
+
+  let cmake_version = "3.10.2"; // TODO dont hardcode
+  write!(f, "cmake_minimum_required(VERSION {})\n", cmake_version)?;
+
+  if builds[0].toolchain.is_some() {
+    write_use_toolchain(&mut f, suffix)?;
+  }
+
+  write!(f, "project({})\n\n", ctx.project.name)?;
+
+  // CTest only runs the tests registered below the directory enabling them.
+  if builds.iter().any(|x| x.target.test_framework.is_some()) {
+    f.write_all(b"enable_testing()\n\n")?;
+  }
+
+  for build in builds {
+    write_add_subdirectory(&mut f, build.name, &build.path)?;
+  }
+
+  f.flush()?;
+  Ok(())
+}
+
+/// Targets are included at most once, whether from the root or from the targets extending them.
+fn write_add_subdirectory<W>(f: &mut W, name: &str, path: &str) -> IO where W: Write {
+  write!(f, concat!("if(NOT TARGET {name})\n",
+                    "  add_subdirectory(${{CMAKE_CURRENT_LIST_DIR}}/../{path} {path})\n",
+                    "endif()\n"),
+         name = name,
+         path = path)
+}
+
+/// The generated toolchain file wraps the SDK's own, if any, and fills in the rest
+/// from the project's settings.
+fn write_toolchain_file(ctx: &Context, name: &str, toolchain: &Toolchain) -> IO {
+  let path = ctx.build_dir.join(["toolchain_", name, ".cmake"].join(""));
+  let mut f = TextFile::create(ctx, "cmake", text::LF, path)?;
+
+  let prefix = ["${CMAKE_CURRENT_LIST_DIR}/", ctx.input_rel.to_str().unwrap()].join("");
+  if let Some(file) = toolchain.file {
+    write!(f, "include(\"{}\")\n", join_prefix(&prefix, "/", file))?;
+  }
+
+  f.write_all(b"set(CMAKE_SYSTEM_NAME Linux)\n")?;
+
+  if let Some(processor) = toolchain.processor {
+    write!(f, "set(CMAKE_SYSTEM_PROCESSOR {})\n", processor)?;
+  }
+
+  if let Some(sysroot) = toolchain.sysroot {
+    write!(f, concat!("set(CMAKE_SYSROOT \"{}\")\n",
+                      "set(CMAKE_FIND_ROOT_PATH_MODE_PROGRAM NEVER)\n",
+                      "set(CMAKE_FIND_ROOT_PATH_MODE_LIBRARY ONLY)\n",
+                      "set(CMAKE_FIND_ROOT_PATH_MODE_INCLUDE ONLY)\n",
+                      "set(CMAKE_FIND_ROOT_PATH_MODE_PACKAGE ONLY)\n"),
+           join_prefix(&prefix, "/", sysroot))?;
+  }
+
+  if let Some(cc) = toolchain.prefix {
+    write!(f, concat!("set(CMAKE_C_COMPILER {0}gcc)\n",
+                      "set(CMAKE_CXX_COMPILER {0}g++)\n",
+                      "set(CMAKE_ASM_COMPILER {0}gcc)\n"),
+           cc)?;
+  }
+
+  f.flush()
+}
+
+/// Must come before the first project() call, an explicit -DCMAKE_TOOLCHAIN_FILE takes precedence.
+fn write_use_toolchain<W>(f: &mut W, name: &str) -> IO where W: Write {
+  write!(f, concat!("if(NOT CMAKE_TOOLCHAIN_FILE)\n",
+                    "  set(CMAKE_TOOLCHAIN_FILE ${{CMAKE_CURRENT_LIST_DIR}}/../toolchain_{}.cmake)\n",
+                    "endif()\n"),
+         name)
+}
+
+fn write_lists_txt<'a>(ctx: &Context<'a>, build: &Build<'a>) -> IO {
+  let mut f = TextFile::create(ctx, "cmake", text::LF, {
     let mut path = ctx.build_dir.join(&build.path);
     create_dir_all(&path)?;
     path.push("CMakeLists.txt");
     path
-  })?);
+  })?;
 
   let (target_type, ld_type, target_subtype) = match build.target.target_type {
     TargetType::Application => {
@@ -69,20 +249,29 @@ fn write_lists_txt(ctx: &Context, build: &Build) -> IO {
         _                     => ("executable", "EXE",   "")
       }
     },
+    TargetType::Console       => ("executable", "EXE", ""),
     TargetType::StaticLibrary => ("library", "STATIC", " STATIC"),
     TargetType::SharedLibrary => ("library", "SHARED", " SHARED"),
     _ => unreachable!()
   };
 
+  // GameActivity comes with its own glue code, linked from its prefab package.
+  let is_game = build.platform == PlatformType::Android &&
+    build.target.target_type == TargetType::Application &&
+    flags::android_activity(ctx, build.target) == AndroidActivity::Game;
+
   let sources = match build.platform { // TODO dont hardcode
-    PlatformType::Android => "  ${ANDROID_NDK}/sources/android/native_app_glue/android_native_app_glue.c\n",
+    PlatformType::Android if !is_game =>
+      "  ${ANDROID_NDK}/sources/android/native_app_glue/android_native_app_glue.c\n",
     _ => ""
   };
 
   let includes = match build.platform { // TODO dont hardcode
-    PlatformType::Android =>
+    PlatformType::Android if !is_game =>
       concat!("  ${ANDROID_NDK}/sources/android/native_app_glue\n",
               "  ${ANDROID_NDK}/sources/third_party/shaderc/include\n"),
+    PlatformType::Android =>
+      "  ${ANDROID_NDK}/sources/third_party/shaderc/include\n",
     _ => ""
   };
 
@@ -107,12 +296,39 @@ fn write_lists_txt(ctx: &Context, build: &Build) -> IO {
   };
 
   let cmake_version = "3.10.2"; // TODO dont hardcode
-  write!(f, concat!("cmake_minimum_required(VERSION {})\n",
-                    "project({})\n\n",
+  write!(f, "cmake_minimum_required(VERSION {})\n", cmake_version)?;
+
+  if build.toolchain.is_some() {
+    write_use_toolchain(&mut f, build.suffix)?;
+  }
+
+  write!(f, concat!("project({})\n\n",
                     "if(NOT CMAKE_CONFIGURATION_TYPES AND NOT CMAKE_BUILD_TYPE)\n",
                     "  set(CMAKE_BUILD_TYPE Debug)\n",
                     "endif()\n\n"),
-         cmake_version, build.name)?;
+         build.name)?;
+
+  if build.platform == PlatformType::Android {
+    write_android_arch(&mut f)?;
+  }
+
+  let uses_extension = |exts: &[&str]| {
+    ctx.extends[build.index].iter().cloned().chain(std::iter::once(build.index)).any(|i| {
+      let target = ctx.get_target(i);
+      ctx.sources[i].iter().any(|x| {
+        x.meta.is_file() && exts.contains(&x.extension()) && target.builds_file(&x.path, build.platform)
+      })
+    })
+  };
+
+  if build.platform != PlatformType::HTML5 {
+    if uses_extension(&["s", "S"]) {
+      f.write_all(b"enable_language(ASM)\n\n")?;
+    }
+    if uses_extension(&["nasm"]) {
+      f.write_all(b"enable_language(ASM_NASM)\n\n")?;
+    }
+  }
 
   if build.platform == PlatformType::HTML5 {
     f.write_all(concat!("if(NOT ${CMAKE_SYSTEM_NAME} MATCHES \"Emscripten\")\n",
@@ -122,72 +338,115 @@ fn write_lists_txt(ctx: &Context, build: &Build) -> IO {
                         "set(CMAKE_RUNTIME_OUTPUT_DIRECTORY \"${CMAKE_CURRENT_SOURCE_DIR}/dist\")\n\n")
                 .as_bytes())?;
 
-    // TODO hardcoded
-    let flags = concat!(" -s WASM=1",
-                        // " -s USE_PTHREADS=1",
-                        // " -s PTHREAD_POOL_SIZE=4",
-                        " -s USE_WEBGL2=1",
-                        " -s EXIT_RUNTIME=1",
-                        " -s DISABLE_DEPRECATED_FIND_EVENT_TARGET_BEHAVIOR=1",
-                        " --emrun",
-                        " --preload-file ../../demo");
+    let flags = get_html5_link_flags(ctx, build.target);
+
+    let cflags = get_html5_compile_flags(ctx);
+    if !cflags.is_empty() {
+      write!(f, concat!("set(CMAKE_C_FLAGS \"${{CMAKE_C_FLAGS}}{0}\")\n",
+                        "set(CMAKE_CXX_FLAGS \"${{CMAKE_CXX_FLAGS}}{0}\")\n"),
+             cflags)?;
+    }
     write!(f, "set(CMAKE_EXE_LINKER_FLAGS \"${{CMAKE_EXE_LINKER_FLAGS}}{}\")\n\n", flags)?;
   }
 
   let rel    = ctx.input_rel.join("..");
   let prefix = rel.to_str().unwrap();
-  let platform_lc = match build.platform {
-    PlatformType::Android => "android",
-    PlatformType::Linux   => "linux",
-    PlatformType::HTML5   => "html5",
-    _                     => unreachable!()
+  let platform_lc = flags::gcc_platform_name(build.platform);
+  let arch_lc     = match build.platform {
+    PlatformType::Android => "${JANK_ARCH}", // Gradle runs CMake once per ABI.
+    _ => build.toolchain.and_then(|x| x.processor)
+      .unwrap_or_else(|| flags::gcc_arch_name(build.platform))
   };
 
-  let arch_lc = match build.platform { // TODO
-    PlatformType::Android => "arm64",
-    PlatformType::Linux   => "x64",
-    PlatformType::HTML5   => "wasm32",
-    _                     => unreachable!()
-  };
+  let is_exe = ld_type == "EXE";
+  let cflags = flags::gcc_cflags(ctx, build.index, build.platform);
+  if flags::use_response_files(ctx, build.target) {
+    f.write_all(concat!("set(CMAKE_C_USE_RESPONSE_FILE_FOR_INCLUDES ON)\n",
+                        "set(CMAKE_C_USE_RESPONSE_FILE_FOR_OBJECTS ON)\n",
+                        "set(CMAKE_C_USE_RESPONSE_FILE_FOR_LIBRARIES ON)\n",
+                        "set(CMAKE_CXX_USE_RESPONSE_FILE_FOR_INCLUDES ON)\n",
+                        "set(CMAKE_CXX_USE_RESPONSE_FILE_FOR_OBJECTS ON)\n",
+                        "set(CMAKE_CXX_USE_RESPONSE_FILE_FOR_LIBRARIES ON)\n\n").as_bytes())?;
+  }
 
-  let g = match build.platform {
-    PlatformType::HTML5 => "-g4",
-    _                   => "-g"
-  };
+  // Left alone when the launcher is missing or already set by the developer.
+  if let Some(launcher) = flags::get_compiler_launcher(ctx, build.target) {
+    write!(f, concat!("if(NOT DEFINED CMAKE_CXX_COMPILER_LAUNCHER)\n",
+                      "  find_program(JANK_COMPILER_LAUNCHER {})\n",
+                      "  if(JANK_COMPILER_LAUNCHER)\n",
+                      "    set(CMAKE_C_COMPILER_LAUNCHER ${{JANK_COMPILER_LAUNCHER}})\n",
+                      "    set(CMAKE_CXX_COMPILER_LAUNCHER ${{JANK_COMPILER_LAUNCHER}})\n",
+                      "  endif()\n",
+                      "endif()\n\n"),
+           launcher)?;
+  }
+
+  write!(f, "set(CMAKE_CXX_FLAGS \"{}\")\n", cflags)?;
 
-  let extra_debug_ldflags = match build.platform {
-    PlatformType::HTML5 => " -s ASSERTIONS=2 -s DEMANGLE_SUPPORT=1",
-    _                   => ""
+  // Android builds every ABI from the same lists, the others a single architecture.
+  let archs = match build.platform {
+    PlatformType::Android => flags::android_architectures(ctx, build.target),
+    _                     => vec![Architecture::parse(arch_lc).unwrap_or(Architecture::Any)]
   };
 
-  // TODO hardcoded flags
-  // TODO -Wpedantic is annoying with GCC
-  let cflags          = "-Wall -Wextra -fno-exceptions -fno-rtti";
-  let debug_cflags    = format!("-I{}/3rdparty/include/debug -D_DEBUG=1 {}", prefix, g);
-  let release_cflags  = format!("-I{}/3rdparty/include/release -Werror", prefix);
-  let debug_ldflags   = format!("-L{}/3rdparty/lib/{}/{}/debug{}", prefix, platform_lc, arch_lc, extra_debug_ldflags);
-  let release_ldflags = format!("-L{}/3rdparty/lib/{}/{}/release", prefix, platform_lc, arch_lc);
-  write!(f, concat!("set(CMAKE_CXX_FLAGS \"{cflags}\")\n",
-                    "set(CMAKE_CXX_FLAGS_DEBUG \"{debug_cflags}\")\n",
-                    "set(CMAKE_CXX_FLAGS_MINSIZEREL \"{release_cflags}\")\n",
-                    "set(CMAKE_CXX_FLAGS_RELWITHDEBINFO \"{release_cflags}\")\n",
-                    "set(CMAKE_CXX_FLAGS_RELEASE \"{release_cflags}\")\n",
-                    "set(CMAKE_{ld_type}_LINKER_FLAGS_DEBUG \"{debug_ldflags}\")\n",
-                    "set(CMAKE_{ld_type}_LINKER_FLAGS_MINSIZEREL \"{release_ldflags}\")\n",
-                    "set(CMAKE_{ld_type}_LINKER_FLAGS_RELWITHDEBINFO \"{release_ldflags}\")\n",
-                    "set(CMAKE_{ld_type}_LINKER_FLAGS_RELEASE \"{release_ldflags}\")\n\n",
-                    "add_{target_type}({target_name}{target_subtype}\n"),
-         cflags          = cflags,
-         debug_cflags    = debug_cflags,
-         release_cflags  = release_cflags,
-         ld_type         = ld_type,
-         debug_ldflags   = debug_ldflags,
-         release_ldflags = release_ldflags,
-         target_name     = build.name,
-         target_type     = target_type,
-         target_subtype  = target_subtype)?;
+  write_per_arch(&mut f, &archs, |f, arch| {
+    let (debug_cflags,   debug_ldflags)   = flags::gcc_config_flags(ctx, build.index, "Debug",
+                                                                    build.platform, arch, is_exe);
+    let (release_cflags, release_ldflags) = flags::gcc_config_flags(ctx, build.index, "Release",
+                                                                    build.platform, arch, is_exe);
+    write!(f, concat!("set(CMAKE_CXX_FLAGS_DEBUG \"{debug_cflags}\")\n",
+                      "set(CMAKE_CXX_FLAGS_MINSIZEREL \"{release_cflags}\")\n",
+                      "set(CMAKE_CXX_FLAGS_RELWITHDEBINFO \"{release_cflags}\")\n",
+                      "set(CMAKE_CXX_FLAGS_RELEASE \"{release_cflags}\")\n",
+                      "set(CMAKE_{ld_type}_LINKER_FLAGS_DEBUG \"{debug_ldflags}\")\n",
+                      "set(CMAKE_{ld_type}_LINKER_FLAGS_MINSIZEREL \"{release_ldflags}\")\n",
+                      "set(CMAKE_{ld_type}_LINKER_FLAGS_RELWITHDEBINFO \"{release_ldflags}\")\n",
+                      "set(CMAKE_{ld_type}_LINKER_FLAGS_RELEASE \"{release_ldflags}\")\n\n"),
+           debug_cflags    = debug_cflags,
+           release_cflags  = release_cflags,
+           ld_type         = ld_type,
+           debug_ldflags   = debug_ldflags,
+           release_ldflags = release_ldflags)?;
 
-  for &index in &ctx.extends[build.index] {
+    // Other profiles are selected with a CMAKE_BUILD_TYPE of the same name.
+    for &prof in ctx.profiles.iter().filter(|&&x| x != "Debug" && x != "Release") {
+      let (prof_cflags, prof_ldflags) = flags::gcc_config_flags(ctx, build.index, prof,
+                                                                build.platform, arch, is_exe);
+      write!(f, concat!("set(CMAKE_CXX_FLAGS_{name} \"{cflags}\")\n",
+                        "set(CMAKE_{ld_type}_LINKER_FLAGS_{name} \"{ldflags}\")\n\n"),
+             name    = prof.to_uppercase(),
+             cflags  = prof_cflags,
+             ld_type = ld_type,
+             ldflags = prof_ldflags)?;
+    }
+
+    // Raw flags are appended last, after the ones of the configuration.
+    write_extra_flags(f, ld_type, "", flags::get_extra_flags(ctx, build.index, "cmake", None,
+                                                             build.platform, arch))?;
+    for &prof in &ctx.profiles {
+      let suffix = ["_", &prof.to_uppercase()].join("");
+      write_extra_flags(f, ld_type, &suffix, flags::get_extra_flags(ctx, build.index, "cmake",
+                                                                    Some(prof), build.platform, arch))?;
+    }
+    Ok(())
+  })?;
+
+  if let Some(pic) = flags::get_pic(ctx, build.target) {
+    write!(f, "set(CMAKE_POSITION_INDEPENDENT_CODE {})\n\n", match pic {
+      true  => "ON",
+      false => "OFF"
+    })?;
+  }
+
+  write!(f, "add_{target_type}({target_name}{target_subtype}\n",
+         target_name    = build.name,
+         target_type    = target_type,
+         target_subtype = target_subtype)?;
+
+  let (linked, inlined): (Vec<usize>, Vec<usize>) = ctx.extends[build.index].iter()
+    .partition(|&&i| is_linked(ctx, i, build.platform));
+
+  for &index in &inlined {
     write_sources(&mut f, ctx, prefix, build.platform, index, ctx.get_target(index))?;
   }
 
@@ -199,23 +458,65 @@ fn write_lists_txt(ctx: &Context, build: &Build) -> IO {
                     "target_include_directories({target_name} PRIVATE\n"),
          target_name = build.name)?;
 
+  let vars = Vars {
+    profile:  "$<LOWER_CASE:$<CONFIG>>",
+    arch:     arch_lc,
+    platform: platform_lc
+  };
+
   for &index in &ctx.extends[build.index] {
-    write_includes(&mut f, prefix, ctx.get_target(index))?;
+    write_includes(&mut f, prefix, &vars, ctx.get_target(index))?;
   }
 
-  write_includes(&mut f, prefix, &build.target)?;
+  write_includes(&mut f, prefix, &vars, &build.target)?;
 
   f.write_all(includes.as_bytes())?;
+  f.write_all(b"  )\n\n")?;
 
-  write!(f, concat!("  )\n\n",
-                    "target_link_libraries({target_name} PRIVATE\n"),
-         target_name = build.name)?;
+  for &index in &linked {
+    let name = ctx.get_target_name(index);
+    write_add_subdirectory(&mut f, name, &[name, "_", build.suffix].join(""))?;
+  }
+
+  // Android applications get the libraries they depend on from their AARs.
+  let prefab_deps = match (build.platform, build.target.target_type) {
+    (PlatformType::Android, TargetType::Application) => flags::android_library_deps(ctx, build.target),
+    _                                                => Vec::new()
+  };
+
+  for dep in &prefab_deps {
+    write!(f, "find_package({}_Android REQUIRED CONFIG)\n\n", dep)?;
+  }
+
+  if is_game {
+    f.write_all(b"find_package(game-activity REQUIRED CONFIG)\n\n")?;
+  }
+
+  write!(f, "target_link_libraries({} PRIVATE\n", build.name)?;
+
+  for &index in &linked {
+    write!(f, "  {}\n", ctx.get_target_name(index))?;
+  }
+
+  for dep in &prefab_deps {
+    write!(f, "  {0}_Android::{0}\n", dep)?;
+  }
+
+  if is_game {
+    f.write_all(b"  game-activity::game-activity_static\n")?;
+  }
+
+  for &index in &ctx.extends[build.index] {
+    write_lib_dirs(&mut f, prefix, &vars, ctx.get_target(index))?;
+  }
+
+  write_lib_dirs(&mut f, prefix, &vars, &build.target)?;
 
   for &index in &ctx.extends[build.index] {
-    write_libraries(&mut f, ctx.get_target(index))?;
+    write_libraries(&mut f, ctx, prefix, &vars, ctx.get_target(index))?;
   }
 
-  write_libraries(&mut f, &build.target)?;
+  write_libraries(&mut f, ctx, prefix, &vars, &build.target)?;
 
   write!(f, concat!("{libraries}  )\n\n",
                     "target_compile_definitions({target_name} PRIVATE\n"),
@@ -223,64 +524,406 @@ fn write_lists_txt(ctx: &Context, build: &Build) -> IO {
          libraries   = libraries)?;
 
   for &index in &ctx.extends[build.index] {
-    write_defines(&mut f, ctx.get_target(index))?;
+    write_defines(&mut f, &vars, ctx.get_target(index))?;
   }
 
-  write_defines(&mut f, &build.target)?;
+  write_defines(&mut f, &vars, &build.target)?;
 
   write!(f, concat!("  )\n\n",
                     "set_target_properties({target_name} PROPERTIES\n",
                     "  CXX_STANDARD 17\n",
                     "  CXX_STANDARD_REQUIRED YES\n",
-                    "  CXX_EXTENSIONS NO\n",
-                    "  )\n"),
+                    "  CXX_EXTENSIONS NO\n"),
          target_name = build.name)?;
 
+  if let Some(name) = flags::get_output_name(ctx, build.target) {
+    write!(f, "  OUTPUT_NAME \"{}\"\n", vars.expand(name))?;
+  }
+
+  // Multi-config generators append the profile folder unless the path uses ${profile}.
+  if let Some(dir) = flags::get_output_dir(ctx, build.target) {
+    let base = ["${CMAKE_CURRENT_SOURCE_DIR}/", prefix].join("");
+    let dir  = join_prefix(&base, "/", &vars.expand(dir));
+    for kind in &["RUNTIME", "LIBRARY", "ARCHIVE"] {
+      write!(f, "  {}_OUTPUT_DIRECTORY \"{}\"\n", kind, dir)?;
+    }
+  }
+
+  f.write_all(b"  )\n")?;
+
+  // Precompiled headers need CMake 3.16, older versions simply build without them.
+  if let Some(pch) = flags::get_pch(ctx, build.target) {
+    write!(f, concat!("\nif(COMMAND target_precompile_headers)\n",
+                      "  target_precompile_headers({} PRIVATE <{}>)\n",
+                      "endif()\n"),
+           build.name, pch)?;
+  }
+
+  // Always out of date, the git commit may have changed since the last build.
+  if let Some(cmd) = version::command(ctx, "$<CONFIG>") {
+    write!(f, concat!("\nadd_custom_target({name}_version\n",
+                      "  COMMAND {cmd}\n",
+                      "  COMMENT \"Updating the version header\")\n",
+                      "add_dependencies({name} {name}_version)\n"),
+           name = build.name,
+           cmd  = cmd.replace('\\', "/"))?;
+  }
+
+  write_embeds(&mut f, ctx, build, prefix)?;
+  write_schemas(&mut f, ctx, build, &inlined)?;
+  write_shaders(&mut f, ctx, build)?;
+  write_tests(&mut f, ctx, build)?;
+  write_benchmark(&mut f, ctx, build)?;
+
+  if build.platform != PlatformType::HTML5 && build.target.target_type != TargetType::StaticLibrary {
+    let dir  = format!("$<TARGET_FILE_DIR:{}>", build.name);
+    let file = format!("$<TARGET_FILE:{}>", build.name);
+    if let Some(cmd) = symbols::upload_command(ctx, &dir, &file) {
+      write!(f, concat!("\nif(CMAKE_BUILD_TYPE STREQUAL \"{}\")\n",
+                        "  add_custom_command(TARGET {} POST_BUILD\n",
+                        "    COMMAND {}\n",
+                        "    COMMENT \"Uploading debug symbols\")\n",
+                        "endif()\n"),
+             symbols::PROFILE, build.name, cmd)?;
+    }
+  }
+
+  if build.platform == PlatformType::HTML5 {
+    write_html5_web_assets(&mut f, ctx, build)?;
+  }
+
+  match build.target.target_type {
+    TargetType::StaticLibrary => {},
+    _ if build.platform == PlatformType::Android => {}, // Packaged as assets by Gradle.
+    _ => write_resources(&mut f, ctx, build, prefix)?
+  }
+
+  match build.target.target_type {
+    TargetType::StaticLibrary |
+    TargetType::SharedLibrary => write_install_rules(&mut f, ctx, build, prefix, &vars, &linked)?,
+    TargetType::Application if build.platform == PlatformType::Linux => {
+      write_desktop(&mut f, ctx, build, prefix)?
+    },
+    TargetType::Console if build.platform == PlatformType::Linux &&
+      distro::get_packaged(ctx).contains(&build.index) => {
+      write!(f, concat!("\ninclude(GNUInstallDirs)\n\n",
+                        "install(TARGETS {} RUNTIME DESTINATION ${{CMAKE_INSTALL_BINDIR}})\n"),
+             build.name)?
+    },
+    _ => {}
+  }
+
+  for &index in &inlined {
+    write_file_settings(&mut f, ctx, prefix, build.platform, index, ctx.get_target(index))?;
+  }
+
+  write_file_settings(&mut f, ctx, prefix, build.platform, build.index, &build.target)?;
+
   if build.platform == PlatformType::HTML5 {
     #[cfg(unix)]
     write_html5_shell_scripts(ctx, build)?;
+
+    #[cfg(windows)]
+    write_html5_batch_scripts(ctx, build)?;
   }
 
   f.flush()?;
   Ok(())
 }
 
+/// Installs the library along with its headers and a package configuration,
+/// letting other CMake projects use it through find_package().
+fn write_install_rules<W>(f: &mut W, ctx: &Context, build: &Build, prefix: &str, vars: &Vars,
+                          linked: &[usize]) -> IO where
+  W: Write
+{
+  write!(f, concat!("\ninclude(GNUInstallDirs)\n",
+                    "include(CMakePackageConfigHelpers)\n\n",
+                    "install(TARGETS {name} EXPORT {name}Targets\n",
+                    "  ARCHIVE DESTINATION ${{CMAKE_INSTALL_LIBDIR}}\n",
+                    "  LIBRARY DESTINATION ${{CMAKE_INSTALL_LIBDIR}}\n",
+                    "  RUNTIME DESTINATION ${{CMAKE_INSTALL_BINDIR}}\n",
+                    "  INCLUDES DESTINATION ${{CMAKE_INSTALL_INCLUDEDIR}}\n",
+                    "  )\n"),
+         name = build.name)?;
+
+  // Headers of external dependencies are not part of the library's interface.
+  for inc in build.target.settings.include_dirs.iter().filter(|x| !x.starts_with("external/")) {
+    write!(f, concat!("install(DIRECTORY {}/ DESTINATION ${{CMAKE_INSTALL_INCLUDEDIR}}\n",
+                      "  FILES_MATCHING PATTERN \"*.h\" PATTERN \"*.hpp\")\n"),
+           join_prefix(prefix, "/", &vars.expand(inc)))?;
+  }
+
+  write!(f, concat!("install(EXPORT {name}Targets\n",
+                    "  NAMESPACE {name}::\n",
+                    "  DESTINATION ${{CMAKE_INSTALL_LIBDIR}}/cmake/{name}\n",
+                    "  )\n\n",
+                    "file(WRITE ${{CMAKE_CURRENT_BINARY_DIR}}/{name}Config.cmake\n",
+                    "  \"include(CMakeFindDependencyMacro)\\n\"\n"),
+         name = build.name)?;
+
+  for &index in linked {
+    write!(f, "  \"find_dependency({})\\n\"\n", ctx.get_target_name(index))?;
+  }
+
+  write!(f, concat!("  \"include(\\\"\\${{CMAKE_CURRENT_LIST_DIR}}/{name}Targets.cmake\\\")\\n\")\n",
+                    "write_basic_package_version_file(${{CMAKE_CURRENT_BINARY_DIR}}/{name}ConfigVersion.cmake\n",
+                    "  VERSION {version}\n",
+                    "  COMPATIBILITY SameMajorVersion)\n",
+                    "install(FILES\n",
+                    "  ${{CMAKE_CURRENT_BINARY_DIR}}/{name}Config.cmake\n",
+                    "  ${{CMAKE_CURRENT_BINARY_DIR}}/{name}ConfigVersion.cmake\n",
+                    "  DESTINATION ${{CMAKE_INSTALL_LIBDIR}}/cmake/{name}\n",
+                    "  )\n"),
+         name    = build.name,
+         version = ctx.project.version)?;
+
+  if build.platform == PlatformType::Linux {
+    write_pkg_config(f, ctx, build, linked)?;
+  }
+
+  Ok(())
+}
+
+/// Describes the installed library to pkg-config, for consumers outside of CMake.
+/// Installs the application with its resources, desktop entry and icon, laid
+/// out for `janky package` to bundle as an AppImage.
+fn write_desktop<W>(f: &mut W, ctx: &Context, build: &Build, prefix: &str) -> IO where W: Write {
+  write!(f, concat!("\ninclude(GNUInstallDirs)\n\n",
+                    "install(TARGETS {} RUNTIME DESTINATION ${{CMAKE_INSTALL_BINDIR}})\n",
+                    "install(FILES \"{}\" DESTINATION ${{CMAKE_INSTALL_DATADIR}}/applications)\n"),
+         build.name, desktop::get_path(ctx, build.index).display())?;
+
+  // PNG icons are expected at 256x256, the size linuxdeploy looks for first.
+  if let Some((path, name)) = desktop::get_icon(ctx, build.index) {
+    let size = match name.ends_with(".svg") {
+      true  => "scalable",
+      false => "256x256"
+    };
+    write!(f, concat!("install(FILES \"{}\" DESTINATION ${{CMAKE_INSTALL_DATADIR}}/icons/hicolor/{}/apps\n",
+                      "  RENAME {})\n"),
+           path.display(), size, name)?;
+  }
+
+  // Resources are found next to the executable, as in the build folder.
+  let dir = ["${CMAKE_CURRENT_SOURCE_DIR}/", prefix].join("");
+  for file in &get_resources(ctx, build.index) {
+    write!(f, "install(FILES \"{}\" DESTINATION ${{CMAKE_INSTALL_BINDIR}})\n",
+           join_prefix(&dir, "/", file.to_str()))?;
+  }
+
+  Ok(())
+}
+
+fn write_pkg_config<W>(f: &mut W, ctx: &Context, build: &Build, linked: &[usize]) -> IO where
+  W: Write
+{
+  let requires = linked.iter().map(|&i| ctx.get_target_name(i)).collect::<Vec<&str>>();
+
+  let mut libs    = Vec::new();
+  let mut defines = Vec::new();
+  for &i in ctx.extends[build.index].iter().chain(std::iter::once(&build.index)) {
+    let s = &ctx.get_target(i).settings;
+    libs.extend(flags::get_libs(ctx, ctx.get_target(i)).0.iter().map(|x| ["-l", x].join("")));
+    defines.extend(s.defines.iter().map(|x| ["-D", x].join("")));
+  }
+
+  write!(f, concat!("\nfile(WRITE ${{CMAKE_CURRENT_BINARY_DIR}}/{name}.pc\n",
+                    "  \"prefix=${{CMAKE_INSTALL_PREFIX}}\\n\"\n",
+                    "  \"libdir=\\${{prefix}}/${{CMAKE_INSTALL_LIBDIR}}\\n\"\n",
+                    "  \"includedir=\\${{prefix}}/${{CMAKE_INSTALL_INCLUDEDIR}}\\n\\n\"\n",
+                    "  \"Name: {name}\\n\"\n",
+                    "  \"Description: {description}\\n\"\n",
+                    "  \"Version: {version}\\n\"\n",
+                    "  \"Requires: {requires}\\n\"\n",
+                    "  \"Libs: -L\\${{libdir}} -l{name}\\n\"\n",
+                    "  \"Libs.private: {libs}\\n\"\n",
+                    "  \"Cflags: -I\\${{includedir}} {defines}\\n\")\n",
+                    "install(FILES ${{CMAKE_CURRENT_BINARY_DIR}}/{name}.pc\n",
+                    "  DESTINATION ${{CMAKE_INSTALL_LIBDIR}}/pkgconfig\n",
+                    "  )\n"),
+         name        = build.name,
+         description = ctx.project.description.replace('"', "\\\""),
+         version     = ctx.project.version,
+         requires    = requires.join(", "),
+         libs        = libs.join(" "),
+         defines     = defines.join(" ").replace('"', "\\\""))
+}
+
 fn write_sources<W>(f: &mut W, ctx: &Context, prefix: &str, platform: PlatformType,
                     index: usize, target: &Target) -> IO where
   W: Write
 {
   let srcs = ctx.sources[index].iter().filter(|x| {
-    x.is_source_no_objc() && target.match_file(&x.path, platform)
+    x.is_source_no_objc() && is_cmake_source(x) && target.builds_file(&x.path, platform)
+  });
+
+  for src in srcs {
+    let excluded = match target.get_file_settings(&src.path) {
+      None    => Vec::new(),
+      Some(s) => ctx.profiles.iter().filter(|x| s.is_excluded_profile(x)).collect::<Vec<_>>()
+    };
+
+    match excluded.is_empty() {
+      true  => write!(f, "  {}\n", join_prefix(prefix, "/", src.to_str()))?,
+      false => {
+        // Generator expressions keep the file out of the excluded configurations.
+        let configs = excluded.iter()
+          .map(|x| format!("$<CONFIG:{}>", x))
+          .collect::<Vec<String>>();
+        write!(f, "  $<$<NOT:$<OR:{}>>:{}>\n", configs.join(","), join_prefix(prefix, "/", src.to_str()))?;
+      }
+    }
+  }
+
+  Ok(())
+}
+
+fn write_file_settings<W>(f: &mut W, ctx: &Context, prefix: &str, platform: PlatformType,
+                          index: usize, target: &Target) -> IO where
+  W: Write
+{
+  let srcs = ctx.sources[index].iter().filter(|x| {
+    x.is_source_no_objc() && is_cmake_source(x) && target.builds_file(&x.path, platform)
   });
 
   for src in srcs {
-    write!(f, "  {}/{}\n", prefix, src.to_str())?;
+    if let Some(settings) = target.get_file_settings(&src.path) {
+      write!(f, "\nset_source_files_properties({} PROPERTIES\n", join_prefix(prefix, "/", src.to_str()))?;
+
+      if !settings.defines.is_empty() {
+        write!(f, "  COMPILE_DEFINITIONS \"{}\"\n", settings.defines.join(";"))?;
+      }
+
+      let compile_flags = flags::gcc_file_flags(&settings);
+      if !compile_flags.is_empty() {
+        write!(f, "  COMPILE_FLAGS \"{}\"\n", compile_flags)?;
+      }
+
+      if settings.compile_as_c == Some(true) {
+        f.write_all(b"  LANGUAGE C\n")?;
+      }
+
+      f.write_all(b"  )\n")?;
+    }
   }
 
   Ok(())
 }
 
-fn write_includes<W>(f: &mut W, prefix: &str, target: &Target) -> IO where W: Write {
+/// Maps the ABI Gradle is building to the architecture names used by the other platforms.
+fn write_android_arch<W>(f: &mut W) -> IO where W: Write {
+  f.write_all(concat!("if(ANDROID_ABI STREQUAL \"armeabi-v7a\")\n",
+                      "  set(JANK_ARCH arm)\n",
+                      "elseif(ANDROID_ABI STREQUAL \"arm64-v8a\")\n",
+                      "  set(JANK_ARCH arm64)\n",
+                      "elseif(ANDROID_ABI STREQUAL \"x86\")\n",
+                      "  set(JANK_ARCH x86)\n",
+                      "elseif(ANDROID_ABI STREQUAL \"x86_64\")\n",
+                      "  set(JANK_ARCH x64)\n",
+                      "endif()\n\n").as_bytes())
+}
+
+/// Writes the output of `w` once when it is the same for every architecture, otherwise
+/// once per architecture, selected by the `JANK_ARCH` of `write_android_arch`.
+fn write_per_arch<W, F>(f: &mut W, archs: &[Architecture], w: F) -> IO where
+  W: Write,
+  F: Fn(&mut Vec<u8>, Architecture) -> IO
+{
+  let mut outputs = Vec::with_capacity(archs.len());
+  for &arch in archs {
+    let mut out = Vec::new();
+    w(&mut out, arch)?;
+    outputs.push(out);
+  }
+
+  if outputs.windows(2).all(|x| x[0] == x[1]) {
+    return f.write_all(outputs.first().map_or(&[][..], |x| x));
+  }
+
+  for (i, (arch, out)) in archs.iter().zip(&outputs).enumerate() {
+    write!(f, "{}(JANK_ARCH STREQUAL {})\n", match i {
+      0 => "if",
+      _ => "elseif"
+    }, arch.to_str())?;
+    for line in String::from_utf8_lossy(out).lines().filter(|x| !x.is_empty()) {
+      write!(f, "  {}\n", line)?;
+    }
+  }
+
+  f.write_all(b"endif()\n\n")
+}
+
+/// Module-definition and MASM files only apply to Windows toolchains.
+fn is_cmake_source(file: &FileInfo) -> bool {
+  match file.extension() {
+    "def" | "asm" => false,
+    _             => true
+  }
+}
+
+fn write_includes<W>(f: &mut W, prefix: &str, vars: &Vars, target: &Target) -> IO where W: Write {
   for inc in &*target.settings.include_dirs {
-    write!(f, "  {}/{}\n", prefix, inc)?;
+    write!(f, "  {}\n", join_prefix(prefix, "/", &vars.expand(inc)))?;
   }
 
   Ok(())
 }
 
-fn write_defines<W>(f: &mut W, target: &Target) -> IO where W: Write {
+/// CMake 3.10 has no target_link_directories, pass them as linker flags instead.
+fn write_lib_dirs<W>(f: &mut W, prefix: &str, vars: &Vars, target: &Target) -> IO where W: Write {
+  for dir in &*target.settings.lib_dirs {
+    write!(f, "  \"-L{}\"\n", join_prefix(prefix, "/", &vars.expand(dir)))?;
+  }
+
+  Ok(())
+}
+
+fn write_extra_flags<W>(f: &mut W, ld_type: &str, suffix: &str,
+                        (cflags, cxxflags, ldflags): (Vec<&str>, Vec<&str>, Vec<&str>)) -> IO where
+  W: Write
+{
+  if cflags.is_empty() && cxxflags.is_empty() && ldflags.is_empty() {
+    return Ok(());
+  }
+
+  write!(f, concat!("set(CMAKE_C_FLAGS{suffix} \"${{CMAKE_C_FLAGS{suffix}}} {cflags}\")\n",
+                    "set(CMAKE_CXX_FLAGS{suffix} \"${{CMAKE_CXX_FLAGS{suffix}}} {cflags} {cxxflags}\")\n"),
+         suffix   = suffix,
+         cflags   = cflags.join(" "),
+         cxxflags = cxxflags.join(" "))?;
+
+  // Static libraries are archived, not linked.
+  if ld_type != "STATIC" {
+    write!(f, "set(CMAKE_{0}_LINKER_FLAGS{1} \"${{CMAKE_{0}_LINKER_FLAGS{1}}} {2}\")\n",
+           ld_type, suffix, ldflags.join(" "))?;
+  }
+
+  f.write_all(b"\n")
+}
+
+fn write_defines<W>(f: &mut W, vars: &Vars, target: &Target) -> IO where W: Write {
   for def in &*target.settings.defines {
-    write!(f, "  {}\n", def)?;
+    write!(f, "  {}\n", vars.expand(def))?;
   }
 
   Ok(())
 }
 
-fn write_libraries<W>(f: &mut W, target: &Target) -> IO where W: Write {
-  for lib in &*target.settings.libs {
+fn write_libraries<W>(f: &mut W, ctx: &Context, prefix: &str, vars: &Vars, target: &Target) -> IO where
+  W: Write
+{
+  let (libs, files) = flags::get_libs(ctx, target);
+  for lib in libs {
     write!(f, "  {}\n", lib)?;
   }
 
+  // Relative paths would be passed to the linker as is, from the build folder.
+  let base = ["${CMAKE_CURRENT_SOURCE_DIR}/", prefix].join("");
+  for file in files {
+    write!(f, "  \"{}\"\n", join_prefix(&base, "/", &vars.expand(file)))?;
+  }
+
   Ok(())
 }
 
@@ -288,17 +931,218 @@ fn write_libraries<W>(f: &mut W, target: &Target) -> IO where W: Write {
 // HTML5 helper scripts
 // -----------------------------------------------------------------------------
 
+/// Resources are copied next to the binary, Emscripten packages them in the page's data instead.
+fn write_resources<W>(f: &mut W, ctx: &Context, build: &Build, prefix: &str) -> IO where W: Write {
+  let resources = get_resources(ctx, build.index);
+  if resources.is_empty() {
+    return Ok(());
+  }
+
+  let dir = ["${CMAKE_CURRENT_SOURCE_DIR}/", prefix].join("");
+
+  if build.platform == PlatformType::HTML5 {
+    for file in &resources {
+      write!(f, "\nset_property(TARGET {} APPEND_STRING PROPERTY LINK_FLAGS \" --preload-file {}@{}\")\n",
+             build.name, join_prefix(&dir, "/", file.to_str()), file.name())?;
+    }
+    return Ok(());
+  }
+
+  write!(f, "\nadd_custom_command(TARGET {} POST_BUILD\n", build.name)?;
+
+  for file in &resources {
+    write!(f, "  COMMAND ${{CMAKE_COMMAND}} -E copy_if_different \"{}\" \"$<TARGET_FILE_DIR:{}>/{}\"\n",
+           join_prefix(&dir, "/", file.to_str()), build.name, file.name())?;
+  }
+
+  f.write_all(b"  COMMENT \"Copying resources\")\n")?;
+  Ok(())
+}
+
+/// Converts each embedded file to a C source whenever it changes, then compiles
+/// the sources along with the target.
+fn write_embeds<W>(f: &mut W, ctx: &Context, build: &Build, prefix: &str) -> IO where W: Write {
+  let embeds = embed::get_embeds(ctx, build.index);
+  if embeds.is_empty() {
+    return Ok(());
+  }
+
+  let dir = ["${CMAKE_CURRENT_SOURCE_DIR}/", prefix].join("");
+
+  for e in &embeds {
+    write!(f, concat!("\nadd_custom_command(OUTPUT \"{output}\"\n",
+                      "  COMMAND {cmd}\n",
+                      "  DEPENDS \"{input}\"\n",
+                      "  COMMENT \"Embedding {path}\")\n"),
+           output = e.output.to_str().unwrap().replace('\\', "/"),
+           cmd    = embed::command(ctx, build.index, Some(e.file)).replace('\\', "/"),
+           input  = join_prefix(&dir, "/", e.file.to_str()),
+           path   = e.file.to_str().replace('\\', "/"))?;
+  }
+
+  write!(f, "\ntarget_sources({} PRIVATE\n", build.name)?;
+  for e in &embeds {
+    write!(f, "  \"{}\"\n", e.output.to_str().unwrap().replace('\\', "/"))?;
+  }
+  write!(f, concat!("  )\n",
+                    "target_include_directories({} PRIVATE \"{}\")\n"),
+         build.name, embed::get_dir(ctx, build.index).to_str().unwrap().replace('\\', "/"))?;
+  Ok(())
+}
+
+/// Compiles the schemas of the target and the ones it inlines whenever they
+/// change, then compiles the generated sources along with the target.
+fn write_schemas<W>(f: &mut W, ctx: &Context, build: &Build, inlined: &[usize]) -> IO where W: Write {
+  let schemas = inlined.iter().chain(std::iter::once(&build.index))
+    .flat_map(|&i| codegen::get_schemas(ctx, build.index, i, build.platform))
+    .collect::<Vec<_>>();
+  if schemas.is_empty() {
+    return Ok(());
+  }
+
+  for schema in &schemas {
+    f.write_all(b"\nadd_custom_command(OUTPUT")?;
+    for output in &schema.outputs {
+      write!(f, " \"{}\"", output.to_str().unwrap().replace('\\', "/"))?;
+    }
+    write!(f, concat!("\n  COMMAND {cmd}\n",
+                      "  DEPENDS \"{input}\"\n",
+                      "  COMMENT \"Compiling {path}\")\n"),
+           cmd   = schema.command.replace('\\', "/"),
+           input = ctx.input_dir.join(&schema.file.path).to_str().unwrap().replace('\\', "/"),
+           path  = schema.file.to_str().replace('\\', "/"))?;
+  }
+
+  write!(f, "\ntarget_sources({} PRIVATE\n", build.name)?;
+  for output in schemas.iter().flat_map(|x| x.outputs.iter()) {
+    write!(f, "  \"{}\"\n", output.to_str().unwrap().replace('\\', "/"))?;
+  }
+  write!(f, concat!("  )\n",
+                    "target_include_directories({} PRIVATE \"{}\")\n"),
+         build.name, codegen::get_dir(ctx, build.index).to_str().unwrap().replace('\\', "/"))?;
+  Ok(())
+}
+
+/// Compiles the target's shaders whenever they change, before the target is built.
+fn write_shaders<W>(f: &mut W, ctx: &Context, build: &Build) -> IO where W: Write {
+  let shaders = shaders::get_shaders(ctx, build.index, build.platform);
+  if shaders.is_empty() {
+    return Ok(());
+  }
+
+  for shader in &shaders {
+    write!(f, concat!("\nadd_custom_command(OUTPUT \"{output}\"\n",
+                      "  COMMAND {cmd}\n",
+                      "  DEPENDS \"{input}\"\n",
+                      "  COMMENT \"Compiling shader {path}\")\n"),
+           output = shader.output.to_str().unwrap().replace('\\', "/"),
+           cmd    = shader.command.replace('\\', "/"),
+           input  = ctx.input_dir.join(&shader.file.path).to_str().unwrap().replace('\\', "/"),
+           path   = shader.file.to_str().replace('\\', "/"))?;
+  }
+
+  write!(f, "\nadd_custom_target({}_shaders DEPENDS\n", build.name)?;
+  for shader in &shaders {
+    write!(f, "  \"{}\"\n", shader.output.to_str().unwrap().replace('\\', "/"))?;
+  }
+  write!(f, concat!("  )\n",
+                    "add_dependencies({0} {0}_shaders)\n"),
+         build.name)?;
+  Ok(())
+}
+
+/// Links test targets with their framework and registers their test cases with
+/// CTest once built. Cross compiled tests cannot run to discover them.
+fn write_tests<W>(f: &mut W, ctx: &Context, build: &Build) -> IO where W: Write {
+  let framework = match build.target.test_framework {
+    None    => return Ok(()),
+    Some(x) => x
+  };
+
+  let package = tests::get_cmake_package(framework);
+  write!(f, concat!("\nfind_package({package} REQUIRED CONFIG)\n",
+                    "target_sources({name} PRIVATE \"{main}\")\n",
+                    "target_link_libraries({name} PRIVATE {target})\n",
+                    "target_compile_definitions({name} PRIVATE {define})\n\n",
+                    "if(NOT CMAKE_CROSSCOMPILING)\n",
+                    "  enable_testing()\n",
+                    "  include({module})\n",
+                    "  {discover}({name})\n",
+                    "endif()\n"),
+         package  = package.name,
+         name     = build.name,
+         main     = tests::get_main_path(ctx, build.index).to_str().unwrap().replace('\\', "/"),
+         target   = package.target,
+         define   = tests::get_define(framework),
+         module   = package.module,
+         discover = package.discover)
+}
+
+/// Links benchmark targets with Google Benchmark's main. Builds of unoptimized
+/// profiles leave them out of the default target.
+fn write_benchmark<W>(f: &mut W, ctx: &Context, build: &Build) -> IO where W: Write {
+  if !build.target.benchmark {
+    return Ok(());
+  }
+
+  write!(f, concat!("\nfind_package({package} REQUIRED CONFIG)\n",
+                    "target_link_libraries({name} PRIVATE {target})\n\n",
+                    "if(CMAKE_BUILD_TYPE AND NOT CMAKE_BUILD_TYPE MATCHES \"^({profiles})$\")\n",
+                    "  set_target_properties({name} PROPERTIES EXCLUDE_FROM_ALL TRUE)\n",
+                    "endif()\n"),
+         package  = benchmarks::CMAKE_PACKAGE,
+         name     = build.name,
+         target   = benchmarks::CMAKE_TARGET,
+         profiles = benchmarks::get_profiles(ctx, build.target, build.platform).join("|"))
+}
+
+/// Copies the web assets next to the generated page after each link, so the
+/// dist folder can be served as is.
+fn write_html5_web_assets<W>(f: &mut W, ctx: &Context, build: &Build) -> IO where W: Write {
+  let shell = build.target.html5.shell_file.or(ctx.project.html5.shell_file);
+  if let Some(x) = shell {
+    write!(f, "\nset_property(TARGET {} APPEND PROPERTY LINK_DEPENDS \"{}\")\n",
+           build.name, ctx.input_dir.join(x).to_str().unwrap().replace('\\', "/"))?;
+  }
+
+  if build.target.html5.web_assets.is_empty() {
+    return Ok(());
+  }
+
+  write!(f, "\nadd_custom_command(TARGET {} POST_BUILD\n", build.name)?;
+
+  for &asset in &build.target.html5.web_assets {
+    let path = ctx.input_dir.join(asset);
+    let name = path.file_name().map_or(asset, |x| x.to_str().unwrap());
+    let copy = match path.is_dir() {
+      true  => "copy_directory",
+      false => "copy_if_different"
+    };
+
+    if !path.exists() {
+      ctx.warn(format!("Missing web asset for target {} ({})", build.name, asset));
+    }
+
+    write!(f, "  COMMAND ${{CMAKE_COMMAND}} -E {} \"{}\" \"${{CMAKE_RUNTIME_OUTPUT_DIRECTORY}}/{}\"\n",
+           copy, path.to_str().unwrap().replace('\\', "/"), name)?;
+  }
+
+  f.write_all(b"  COMMENT \"Copying web assets\")\n")?;
+  Ok(())
+}
+
 #[cfg(unix)]
 fn write_html5_shell_scripts(ctx: &Context, build: &Build) -> IO {
-  fn write_script<W>(path: &std::path::Path, w: W) -> IO where W: FnOnce(&mut File) -> IO {
-    let mut f = File::create(&path)?;
+  fn write_script<W>(ctx: &Context, path: &std::path::Path, w: W) -> IO where
+    W: FnOnce(&mut TextFile) -> IO
+  {
+    let mut f = TextFile::create(ctx, "cmake", text::LF, &path)?;
     w(&mut f)?;
     f.flush()?;
-    std::fs::set_permissions(&path, std::os::unix::fs::PermissionsExt::from_mode(0o755))?;
-    Ok(())
+    f.set_executable()
   }
 
-  write_script(&ctx.build_dir.join(["build_", build.name, "_HTML5.sh"].join("")), |f| {
+  write_script(ctx, &ctx.build_dir.join(["build_", build.name, "_HTML5.sh"].join("")), |f| {
     write!(f, concat!("#!/bin/sh -e\n",
                       "cd \"$(dirname \"$(readlink \"$0\")\")/{}_HTML5\"\n",
                       "case $(uname) in\n",
@@ -312,7 +1156,7 @@ fn write_html5_shell_scripts(ctx: &Context, build: &Build) -> IO {
     Ok(())
   })?;
 
-  write_script(&ctx.build_dir.join(["run_", build.name, "_HTML5.sh"].join("")), |f| {
+  write_script(ctx, &ctx.build_dir.join(["run_", build.name, "_HTML5.sh"].join("")), |f| {
     write!(f, concat!("#!/bin/sh -e\n",
                       "emrun --no_browser --hostname 0.0.0.0 --port 8080 ",
                       "\"$(dirname \"$(readlink \"$0\")\")/{0}_HTML5/dist/{0}.html\"\n"),
@@ -322,3 +1166,26 @@ fn write_html5_shell_scripts(ctx: &Context, build: &Build) -> IO {
 
   Ok(())
 }
+
+/// Windows equivalents of the shell scripts, Emscripten's wrappers are batch files there.
+#[cfg(windows)]
+fn write_html5_batch_scripts(ctx: &Context, build: &Build) -> IO {
+  let mut f = TextFile::create(ctx, "cmake", text::CRLF,
+                               ctx.build_dir.join(["build_", build.name, "_HTML5.bat"].join("")))?;
+  write!(f, concat!("@echo off\n",
+                    "cd /d \"%~dp0{}_HTML5\" || exit /b 1\n",
+                    "call emcmake cmake . || exit /b 1\n",
+                    "cmake --build . --parallel %NUMBER_OF_PROCESSORS% %*\n"),
+         build.name)?;
+  f.flush()?;
+
+  let mut f = TextFile::create(ctx, "cmake", text::CRLF,
+                               ctx.build_dir.join(["run_", build.name, "_HTML5.bat"].join("")))?;
+  write!(f, concat!("@echo off\n",
+                    "call emrun --no_browser --hostname 0.0.0.0 --port 8080 ",
+                    "\"%~dp0{0}_HTML5\\dist\\{0}.html\"\n"),
+         build.name)?;
+  f.flush()?;
+
+  Ok(())
+}
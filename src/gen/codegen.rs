@@ -0,0 +1,117 @@
+//! Sources generated from the schemas listed in a target's sources, Protocol
+//! Buffers `.proto` files compiled by protoc and FlatBuffers `.fbs` files
+//! compiled by flatc. Both are configured by the project's `[codegen]` table.
+//!
+//! Each generator runs the compiler as a custom build step whenever a schema
+//! changes, writing into the `<target>_codegen` folder of the build. That folder
+//! is added to the target's include paths and the generated sources are compiled
+//! along with the target's own. Protocol Buffers headers keep the schema's path,
+//! ie `net/packet.proto` is included as `net/packet.pb.h`, FlatBuffers headers
+//! only keep its name, ie `packet_generated.h`.
+//!
+//! The runtime libraries are linked like any other, from the target's settings.
+
+use std::path::{Path, PathBuf};
+
+use crate::ctx::{Context, FileInfo, PlatformType};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Compiler {
+  Protoc,
+  Flatc
+}
+
+/// Schema from a target's sources, along with the command compiling it.
+pub struct Schema<'a> {
+  pub file:     &'a FileInfo,
+  pub compiler: Compiler,
+  pub command:  String,
+  pub outputs:  Vec<PathBuf>
+}
+
+impl<'a> Schema<'a> {
+  /// Generated files to compile, FlatBuffers only generates headers.
+  pub fn sources(&self) -> impl Iterator<Item = &'_ PathBuf> {
+    self.outputs.iter().filter(|x| x.extension().map_or(false, |x| x == "cc"))
+  }
+}
+
+pub fn get_compiler(file: &FileInfo) -> Option<Compiler> {
+  match file.extension() {
+    "proto" => Some(Compiler::Protoc),
+    "fbs"   => Some(Compiler::Flatc),
+    _       => None
+  }
+}
+
+/// Folder of the generated files, added to the target's include paths.
+pub fn get_dir(ctx: &Context, index: usize) -> PathBuf {
+  ctx.build_dir.join([ctx.get_target_name(index), "_codegen"].join(""))
+}
+
+/// Schemas of the target `source_index` built on the platform, compiled into the
+/// folder of the target `index`. It is the same target or one extending it.
+pub fn get_schemas<'a>(ctx: &'a Context, index: usize, source_index: usize,
+                       platform: PlatformType) -> Vec<Schema<'a>>
+{
+  let dir    = get_dir(ctx, index);
+  let target = ctx.get_target(source_index);
+  ctx.sources[source_index].iter()
+    .filter(|x| x.meta.is_file() && target.builds_file(&x.path, platform))
+    .filter_map(|file| get_compiler(file).map(|compiler| get_schema(ctx, &dir, file, compiler)))
+    .collect()
+}
+
+/// Whether the target or one it extends has schemas, on any platform.
+pub fn has_schemas(ctx: &Context, index: usize) -> bool {
+  ctx.extends[index].iter().chain(std::iter::once(&index))
+    .any(|&i| ctx.sources[i].iter().any(|x| x.meta.is_file() && get_compiler(x).is_some()))
+}
+
+/// Creates the folders of the generated files, the compilers expect them to exist.
+pub fn create_dirs(ctx: &Context) -> std::io::Result<()> {
+  for index in (0 .. ctx.project.targets.len()).filter(|&i| has_schemas(ctx, i)) {
+    std::fs::create_dir_all(get_dir(ctx, index))?;
+  }
+  Ok(())
+}
+
+fn get_schema<'a>(ctx: &Context, dir: &Path, file: &'a FileInfo, compiler: Compiler) -> Schema<'a> {
+  // Schemas from source roots outside of the input folder import relative to their own folder.
+  let (root, rel) = match file.path.is_absolute() {
+    false => (ctx.input_dir.as_path(), file.path.as_path()),
+    true  => (file.path.parent().unwrap(), Path::new(file.path.file_name().unwrap()))
+  };
+  let stem = rel.with_extension("");
+  let info = &ctx.project.codegen;
+
+  let (command, outputs) = match compiler {
+    Compiler::Protoc => {
+      let base = dir.join(&stem).into_os_string().into_string().unwrap();
+      (format!("{} --cpp_out=\"{}\" --proto_path=\"{}\"{} \"{}\"",
+               get_program(info.protoc, "protoc"), dir.display(), root.display(),
+               get_args(&info.protoc_args), root.join(rel).display()),
+       vec![PathBuf::from([&base, ".pb.cc"].join("")), PathBuf::from([&base, ".pb.h"].join(""))])
+    },
+    Compiler::Flatc => {
+      let name = stem.file_name().unwrap().to_str().unwrap();
+      (format!("{} --cpp -o \"{}\" -I \"{}\"{} \"{}\"",
+               get_program(info.flatc, "flatc"), dir.display(), root.display(),
+               get_args(&info.flatc_args), root.join(rel).display()),
+       vec![dir.join([name, "_generated.h"].join(""))])
+    }
+  };
+
+  Schema { file, compiler, command, outputs }
+}
+
+fn get_program<'a>(program: &'a str, default: &'a str) -> &'a str {
+  match program {
+    "" => default,
+    p  => p
+  }
+}
+
+fn get_args(args: &[&str]) -> String {
+  args.iter().map(|x| format!(" {}", x)).collect()
+}
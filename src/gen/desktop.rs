@@ -0,0 +1,82 @@
+//! Desktop integration of the Linux applications, configured by the project's
+//! `[linux]` table.
+//!
+//! Each application gets a freedesktop entry, written along with the project to
+//! `<app_id>.desktop` in the build folder. CMake installs it with the executable,
+//! the resources and the icon to the standard prefix layout, which `janky package`
+//! turns into an AppDir and then an AppImage with linuxdeploy.
+
+use std::io::Write;
+use std::path::PathBuf;
+
+use crate::ctx::{Context, PlatformType, RunResult, TargetType};
+use crate::gen::text::{self, TextFile};
+
+/// Indices of the applications built for Linux.
+pub fn get_applications(ctx: &Context) -> Vec<usize> {
+  match ctx.project.filter.matches_platform(PlatformType::Linux) {
+    false => Vec::new(),
+    true  => ctx.project.targets.values().enumerate()
+      .filter(|(_, t)| {
+        t.target_type == TargetType::Application && t.filter.matches_platform(PlatformType::Linux)
+      })
+      .map(|(index, _)| index)
+      .collect()
+  }
+}
+
+/// The configured identifier is shared by every application, suffixed by their
+/// names when there are several.
+pub fn get_app_id(ctx: &Context, index: usize) -> String {
+  let name = ctx.get_target_name(index);
+  match ctx.project.linux.app_id {
+    None                                       => name.to_string(),
+    Some(x) if get_applications(ctx).len() > 1 => [x, ".", name].join(""),
+    Some(x)                                    => x.to_string()
+  }
+}
+
+pub fn get_path(ctx: &Context, index: usize) -> PathBuf {
+  ctx.build_dir.join(format!("{}.desktop", get_app_id(ctx, index)))
+}
+
+/// Installed name of the icon, keeping the extension of its source.
+pub fn get_icon(ctx: &Context, index: usize) -> Option<(PathBuf, String)> {
+  ctx.project.linux.icon.map(|x| {
+    let path = ctx.input_dir.join(x);
+    let ext  = path.extension().and_then(|x| x.to_str()).unwrap_or("png").to_lowercase();
+    (path, format!("{}.{}", get_app_id(ctx, index), ext))
+  })
+}
+
+/// Writes the desktop entry of every Linux application.
+pub fn write_files(ctx: &Context) -> RunResult {
+  let apps = get_applications(ctx);
+  for &index in &apps {
+    let name = ctx.get_target_name(index);
+    let categories = match ctx.project.linux.categories.is_empty() {
+      true  => "Utility".to_string(),
+      false => ctx.project.linux.categories.join(";")
+    };
+
+    let mut f = TextFile::create(ctx, "desktop", text::LF, get_path(ctx, index))?;
+    write!(f, concat!("# Generated by janky, do not edit.\n",
+                      "[Desktop Entry]\n",
+                      "Type=Application\n",
+                      "Name={title}\n",
+                      "Exec={name}\n",
+                      "Icon={icon}\n",
+                      "Categories={categories};\n",
+                      "Terminal={terminal}\n"),
+           title      = if apps.len() > 1 { name } else { ctx.project.name },
+           name       = name,
+           icon       = get_app_id(ctx, index),
+           categories = categories,
+           terminal   = ctx.project.linux.terminal)?;
+    if !ctx.project.description.is_empty() {
+      write!(f, "Comment={}\n", ctx.project.description)?;
+    }
+    f.flush()?;
+  }
+  Ok(())
+}
@@ -0,0 +1,315 @@
+//! Debian and RPM packaging of the Linux libraries and console targets,
+//! configured by the project's `[linux.package]` table.
+//!
+//! Each target becomes its own binary package, built from the root CMake project
+//! of the Linux platform. Their contents follow the CMake install rules: console
+//! targets install their executable, libraries their archive or shared object
+//! along with their headers, CMake package configuration and pkg-config file.
+//!
+//! The `debian` folder and `<project>.spec` are written along with the project
+//! to the build folder, where `dpkg-buildpackage -b` or `rpmbuild -bb` build
+//! the packages. Applications are bundled as AppImages instead, see `desktop`.
+
+use std::io::Write;
+use std::path::Path;
+
+use crate::ctx::{Context, IndentStyle, LineEnding, LinuxPackage, PlatformType, RunResult, TargetType,
+                 TextFormat};
+use crate::gen::text::{self, TextFile};
+
+/// Makefiles require their recipes to be indented with tabs.
+const MAKEFILE: TextFormat = TextFormat {
+  line_endings: Some(LineEnding::LF),
+  bom:          Some(false),
+  indent_style: Some(IndentStyle::Tab),
+  indent_size:  Some(1)
+};
+
+/// Indices of the targets packaged for Linux, leaving out tests and benchmarks.
+pub fn get_packaged(ctx: &Context) -> Vec<usize> {
+  match ctx.project.filter.matches_platform(PlatformType::Linux) {
+    false => Vec::new(),
+    true  => ctx.project.targets.values().enumerate()
+      .filter(|(_, t)| t.filter.matches_platform(PlatformType::Linux) && is_packaged(t.target_type) &&
+              t.test_framework.is_none() && !t.benchmark)
+      .map(|(index, _)| index)
+      .collect()
+  }
+}
+
+fn is_packaged(target_type: TargetType) -> bool {
+  matches!(target_type, TargetType::Console | TargetType::StaticLibrary | TargetType::SharedLibrary)
+}
+
+/// Package names are lowercase, libraries are prefixed by "lib" as distributions expect.
+pub fn get_package_name(ctx: &Context, index: usize) -> String {
+  let name = ctx.get_target_name(index).to_lowercase().replace('_', "-");
+  match ctx.get_target(index).target_type {
+    TargetType::StaticLibrary |
+    TargetType::SharedLibrary if !name.starts_with("lib") => ["lib", &name].join(""),
+    _                                                     => name
+  }
+}
+
+/// Writes the Debian and RPM packaging files, when the project has a `[linux.package]` table.
+pub fn write_files(ctx: &Context) -> RunResult {
+  let package = match &ctx.project.linux.package {
+    None    => return Ok(()),
+    Some(x) => x
+  };
+
+  let packaged = get_packaged(ctx);
+  if packaged.is_empty() {
+    ctx.log.verbose("Distro: skipped, the project has no Linux libraries or console targets");
+    return Ok(());
+  }
+
+  let targets = packaged.iter().map(|&index| Package::new(ctx, &packaged, index)).collect::<Vec<_>>();
+  write_debian(ctx, package, &targets)?;
+  write_spec(ctx, package, &targets)?;
+  Ok(())
+}
+
+/// Installed files of a packaged target, relative to the libdir, bindir and includedir.
+struct Package<'a> {
+  name:    String,
+  kind:    &'static str,
+  target:  &'a str,
+  depends: Vec<String>,
+  bin:     Vec<String>,
+  lib:     Vec<String>,
+  include: Vec<String>
+}
+
+impl<'a> Package<'a> {
+  fn new(ctx: &'a Context, packaged: &[usize], index: usize) -> Self {
+    let target = ctx.get_target(index);
+    let name   = ctx.get_target_name(index);
+
+    // Executables only depend on the shared libraries, static ones are linked in.
+    let depends = ctx.extends[index].iter().cloned()
+      .filter(|i| packaged.contains(i))
+      .filter(|&i| target.target_type != TargetType::Console ||
+              ctx.get_target(i).target_type == TargetType::SharedLibrary)
+      .map(|i| get_package_name(ctx, i))
+      .collect();
+
+    let (kind, bin, lib, include) = match target.target_type {
+      TargetType::Console => ("command-line tool", vec![name.to_string()], Vec::new(), Vec::new()),
+      t => {
+        let (kind, file) = match t {
+          TargetType::SharedLibrary => ("shared library", format!("lib{}.so", name)),
+          _                         => ("static library", format!("lib{}.a",  name))
+        };
+        let lib = vec![file, format!("cmake/{}", name), format!("pkgconfig/{}.pc", name)];
+        (kind, Vec::new(), lib, get_headers(ctx, index))
+      }
+    };
+
+    Package { name: get_package_name(ctx, index), kind, target: name, depends, bin, lib, include }
+  }
+}
+
+/// Entries installed to the includedir by the target's include folders, external ones excluded.
+fn get_headers(ctx: &Context, index: usize) -> Vec<String> {
+  let dirs = ctx.get_target(index).settings.include_dirs.iter()
+    .filter(|x| !x.starts_with("external/") && !x.contains("${"));
+
+  let mut entries = dirs
+    .filter_map(|x| std::fs::read_dir(ctx.input_dir.join(x)).ok())
+    .flat_map(|x| x.filter_map(Result::ok))
+    .filter(|x| x.path().is_dir() || is_header(&x.path()))
+    .map(|x| x.file_name().to_string_lossy().into_owned())
+    .collect::<Vec<_>>();
+  entries.sort();
+  entries.dedup();
+  entries
+}
+
+fn is_header(path: &Path) -> bool {
+  matches!(path.extension().and_then(|x| x.to_str()), Some("h") | Some("hpp"))
+}
+
+/// Distributions order versions with a '~' before pre-releases, and reject '-'.
+fn get_version(ctx: &Context) -> String {
+  ctx.project.version.split('+').next().unwrap().replacen('-', "~", 1)
+}
+
+fn get_summary<'a>(ctx: &'a Context) -> &'a str {
+  match ctx.project.description {
+    ""   => ctx.project.name,
+    desc => desc
+  }
+}
+
+/// Date of the checked out commit, keeping the changelog unchanged between runs.
+fn git_date(dir: &Path) -> String {
+  std::process::Command::new("git")
+    .args(&["log", "-1", "--format=%aD"])
+    .current_dir(dir)
+    .output()
+    .ok()
+    .filter(|x| x.status.success())
+    .map(|x| String::from_utf8_lossy(&x.stdout).trim().to_string())
+    .filter(|x| !x.is_empty())
+    .unwrap_or_else(|| "Thu, 01 Jan 1970 00:00:00 +0000".to_string())
+}
+
+
+// Debian
+// -----------------------------------------------------------------------------
+
+fn write_debian(ctx: &Context, package: &LinuxPackage, targets: &[Package]) -> RunResult {
+  let dir = ctx.build_dir.join("debian");
+  std::fs::create_dir_all(dir.join("source"))?;
+
+  let source  = ctx.project.name.to_lowercase().replace('_', "-");
+  let version = get_version(ctx);
+  let summary = get_summary(ctx);
+
+  let mut f = TextFile::create(ctx, "distro", text::LF, dir.join("control"))?;
+  write!(f, concat!("Source: {source}\n",
+                    "Section: {section}\n",
+                    "Priority: optional\n",
+                    "Maintainer: {maintainer}\n",
+                    "Build-Depends: debhelper-compat (= 13), cmake\n",
+                    "Standards-Version: 4.6.2\n"),
+         source     = source,
+         section    = package.section.unwrap_or("devel"),
+         maintainer = package.maintainer)?;
+  if let Some(homepage) = package.homepage {
+    write!(f, "Homepage: {}\n", homepage)?;
+  }
+
+  for p in targets {
+    let depends = p.depends.iter().map(|x| format!(", {} (= ${{binary:Version}})", x)).collect::<String>();
+    write!(f, concat!("\nPackage: {name}\n",
+                      "Architecture: any\n",
+                      "Depends: ${{shlibs:Depends}}, ${{misc:Depends}}{depends}\n",
+                      "Description: {summary}\n",
+                      " The {target} {kind} of {project}.\n"),
+           name    = p.name,
+           depends = depends,
+           summary = summary,
+           target  = p.target,
+           kind    = p.kind,
+           project = ctx.project.name)?;
+  }
+  f.flush()?;
+
+  let mut f = TextFile::create(ctx, "distro", text::LF, dir.join("changelog"))?;
+  write!(f, concat!("{source} ({version}) unstable; urgency=medium\n\n",
+                    "  * Generated by janky.\n\n",
+                    " -- {maintainer}  {date}\n"),
+         source     = source,
+         version    = version,
+         maintainer = package.maintainer,
+         date       = git_date(&ctx.input_dir))?;
+  f.flush()?;
+
+  // The root project builds every Linux target, the unpackaged applications included.
+  let mut f = TextFile::create(ctx, "distro", MAKEFILE, dir.join("rules"))?;
+  f.write_all(concat!("#!/usr/bin/make -f\n",
+                      "# Generated by janky, do not edit.\n\n",
+                      "%:\n",
+                      "\tdh $@ --buildsystem=cmake --sourcedirectory=CMake_Linux\n\n",
+                      "override_dh_auto_configure:\n",
+                      "\tdh_auto_configure -- -DCMAKE_BUILD_TYPE=Release\n\n",
+                      "override_dh_missing:\n",
+                      "\tdh_missing --list-missing\n").as_bytes())?;
+  f.flush()?;
+  f.set_executable()?;
+
+  let mut f = TextFile::create(ctx, "distro", text::LF, dir.join("source").join("format"))?;
+  f.write_all(b"3.0 (native)\n")?;
+  f.flush()?;
+
+  // Libraries are installed to the multiarch libdir, ie usr/lib/x86_64-linux-gnu.
+  for p in targets {
+    let mut f = TextFile::create(ctx, "distro", text::LF, dir.join(format!("{}.install", p.name)))?;
+    for x in &p.bin {
+      write!(f, "usr/bin/{}\n", x)?;
+    }
+    for x in &p.lib {
+      write!(f, "usr/lib/*/{}\n", x)?;
+    }
+    for x in &p.include {
+      write!(f, "usr/include/{}\n", x)?;
+    }
+    f.flush()?;
+  }
+
+  Ok(())
+}
+
+
+// RPM
+// -----------------------------------------------------------------------------
+
+fn write_spec(ctx: &Context, package: &LinuxPackage, targets: &[Package]) -> RunResult {
+  let mut path = ctx.build_dir.join(ctx.project.name);
+  path.set_extension("spec");
+
+  let summary = get_summary(ctx);
+  let mut f = TextFile::create(ctx, "distro", text::LF, path)?;
+  write!(f, concat!("# Generated by janky, do not edit.\n",
+                    "%global _vpath_srcdir {srcdir}\n",
+                    "# Applications are built by the root project but bundled as AppImages.\n",
+                    "%global _unpackaged_files_terminate_build 0\n\n",
+                    "Name:          {name}\n",
+                    "Version:       {version}\n",
+                    "Release:       1%{{?dist}}\n",
+                    "Summary:       {summary}\n",
+                    "License:       {license}\n"),
+         srcdir  = ctx.build_dir.join("CMake_Linux").display(),
+         name    = ctx.project.name.to_lowercase().replace('_', "-"),
+         version = get_version(ctx),
+         summary = summary,
+         license = package.license.unwrap_or("Proprietary"))?;
+  if let Some(homepage) = package.homepage {
+    write!(f, "URL:           {}\n", homepage)?;
+  }
+  write!(f, concat!("BuildRequires: cmake\n",
+                    "BuildRequires: gcc-c++\n\n",
+                    "%description\n",
+                    "{}\n"),
+         summary)?;
+
+  for p in targets {
+    write!(f, concat!("\n%package -n {name}\n",
+                      "Summary:       {summary}\n"),
+           name    = p.name,
+           summary = summary)?;
+    for x in &p.depends {
+      write!(f, "Requires:      {} = %{{version}}-%{{release}}\n", x)?;
+    }
+    write!(f, concat!("\n%description -n {name}\n",
+                      "The {target} {kind} of {project}.\n"),
+           name    = p.name,
+           target  = p.target,
+           kind    = p.kind,
+           project = ctx.project.name)?;
+  }
+
+  f.write_all(concat!("\n%build\n",
+                      "%cmake -DCMAKE_BUILD_TYPE=Release\n",
+                      "%cmake_build\n\n",
+                      "%install\n",
+                      "%cmake_install\n").as_bytes())?;
+
+  for p in targets {
+    write!(f, "\n%files -n {}\n", p.name)?;
+    for x in &p.bin {
+      write!(f, "%{{_bindir}}/{}\n", x)?;
+    }
+    for x in &p.lib {
+      write!(f, "%{{_libdir}}/{}\n", x)?;
+    }
+    for x in &p.include {
+      write!(f, "%{{_includedir}}/{}\n", x)?;
+    }
+  }
+
+  f.flush()?;
+  Ok(())
+}
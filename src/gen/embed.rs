@@ -0,0 +1,157 @@
+//! Data files compiled into a target as C arrays, listed in its `embed` patterns.
+//! Shaders, fonts and licenses are embedded this way without per-project scripts.
+//!
+//! Every file becomes a C source in the `<target>_embed` folder of the build,
+//! converted by a custom build step of the generated projects whenever the file
+//! changes. The header declaring them all is written along with the project,
+//! named after the target and found in the target's include paths:
+//!
+//! ```c
+//! #include "Game_embed.h"
+//!
+//! // shaders/basic.spv
+//! extern const unsigned char shaders_basic_spv[];
+//! extern const size_t shaders_basic_spv_size;
+//! ```
+//!
+//! Arrays end with an extra null byte not counted in their size, embedded text
+//! files can be used as strings directly.
+
+use std::fmt::Write as FmtWrite;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use crate::ctx::{Context, FileInfo, RunResult, StrError};
+use crate::gen::text::{self, TextFile};
+use crate::gen::{janky_command, update_file};
+
+/// Embedded file, along with its symbol and the source it is converted to.
+pub struct Embed<'a> {
+  pub file:   &'a FileInfo,
+  pub symbol: String,
+  pub output: PathBuf
+}
+
+/// Folder of the converted sources and their header.
+pub fn get_dir(ctx: &Context, index: usize) -> PathBuf {
+  ctx.build_dir.join([ctx.get_target_name(index), "_embed"].join(""))
+}
+
+pub fn get_header_name(ctx: &Context, index: usize) -> String {
+  [ctx.get_target_name(index), "_embed.h"].join("")
+}
+
+/// Files embedded into a target, not including the ones of the targets it extends.
+pub fn get_embeds<'a>(ctx: &'a Context, index: usize) -> Vec<Embed<'a>> {
+  let dir = get_dir(ctx, index);
+  ctx.embeds[index].iter()
+    .filter(|x| x.meta.is_file())
+    .map(|file| {
+      let symbol = get_symbol(&file.path);
+      let output = dir.join([&symbol, ".c"].join(""));
+      Embed { file, symbol, output }
+    })
+    .collect()
+}
+
+/// Command converting the embedded files of a target, or only one of them.
+pub fn command(ctx: &Context, index: usize, file: Option<&FileInfo>) -> String {
+  let mut cmd = format!("embed --target {}", ctx.get_target_name(index));
+  if let Some(file) = file {
+    write!(cmd, " --file \"{}\"", file.to_str()).unwrap();
+  }
+  janky_command(ctx, &cmd)
+}
+
+/// Declares the embedded files of every target, written along with the project.
+pub fn write_headers(ctx: &Context) -> RunResult {
+  for index in 0 .. ctx.project.targets.len() {
+    let embeds = get_embeds(ctx, index);
+    if embeds.is_empty() {
+      continue;
+    }
+
+    let dir = get_dir(ctx, index);
+    std::fs::create_dir_all(&dir)?;
+
+    let mut f = TextFile::create(ctx, "embed", text::LF, dir.join(get_header_name(ctx, index)))?;
+    f.write_all(concat!("// Generated by janky, do not edit.\n",
+                        "#pragma once\n\n",
+                        "#include <stddef.h>\n\n",
+                        "#ifdef __cplusplus\n",
+                        "extern \"C\" {\n",
+                        "#endif\n").as_bytes())?;
+
+    for e in &embeds {
+      write!(f, concat!("\n// {path}\n",
+                        "extern const unsigned char {symbol}[];\n",
+                        "extern const size_t {symbol}_size;\n"),
+             path   = e.file.to_str().replace('\\', "/"),
+             symbol = e.symbol)?;
+    }
+
+    f.write_all(concat!("\n#ifdef __cplusplus\n",
+                        "}\n",
+                        "#endif\n").as_bytes())?;
+    f.flush()?;
+  }
+  Ok(())
+}
+
+/// Converts the embedded files of a target, or only the given one. Run by the
+/// generated projects, sources are left untouched while their file is unchanged.
+pub fn write_sources(ctx: &Context, index: usize, only: Option<&Path>) -> RunResult {
+  let embeds = get_embeds(ctx, index);
+  let mut found = false;
+
+  for e in embeds.iter().filter(|e| only.map_or(true, |x| e.file.path.as_path() == x)) {
+    found = true;
+
+    let path = e.file.to_str().replace('\\', "/");
+    let data = std::fs::read(ctx.input_dir.join(&e.file.path))
+      .map_err(|err| StrError(format!("Failed to read embedded file ({}): {}", path, err)))?;
+    update_file(ctx, &e.output, to_c_array(&path, &e.symbol, &data).as_bytes())?;
+  }
+
+  match (found, only) {
+    (false, Some(path)) => Err(Box::new(StrError(format!("Target {} does not embed {}",
+                                                         ctx.get_target_name(index), path.display())))),
+    _ => Ok(())
+  }
+}
+
+/// Symbol of an embedded file derived from its path, ie `shaders/basic.spv` is `shaders_basic_spv`.
+fn get_symbol(path: &Path) -> String {
+  let mut symbol = path.to_str().unwrap().chars().map(|c| match c.is_ascii_alphanumeric() {
+    true  => c,
+    false => '_'
+  }).collect::<String>();
+  if symbol.starts_with(|c: char| c.is_ascii_digit()) {
+    symbol.insert(0, '_');
+  }
+  symbol
+}
+
+fn to_c_array(path: &str, symbol: &str, data: &[u8]) -> String {
+  // Each byte takes at most 6 characters, ie "0xff, ".
+  let mut s = String::with_capacity(data.len() * 6 + 256);
+  write!(s, concat!("// Generated by janky from {path}, do not edit.\n",
+                    "#include <stddef.h>\n\n",
+                    "const unsigned char {symbol}[] = {{"),
+         path   = path,
+         symbol = symbol).unwrap();
+
+  for (i, byte) in data.iter().chain(std::iter::once(&0)).enumerate() {
+    match i % 16 {
+      0 => s.push_str("\n  "),
+      _ => s.push(' ')
+    }
+    write!(s, "0x{:02x},", byte).unwrap();
+  }
+
+  write!(s, concat!("\n}};\n",
+                    "const size_t {symbol}_size = {size};\n"),
+         symbol = symbol,
+         size   = data.len()).unwrap();
+  s
+}
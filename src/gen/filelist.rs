@@ -0,0 +1,71 @@
+//! Plain lists of each target's resolved files, for other build systems to consume.
+//!
+//! Every target gets newline-delimited `<target>.sources` and `<target>.resources`
+//! files, along with a `<target>.gni` declaring the same lists for GN. Paths are
+//! relative to the `filelist` directory they are written to.
+
+use std::fs::create_dir_all;
+use std::io::Write;
+
+use crate::ctx::{Context, Generator, PlatformType, RunResult, TargetFiles};
+use super::join_prefix;
+use super::text::{self, TextFile};
+
+pub struct FileList;
+
+type IO = std::io::Result<()>;
+
+impl Generator for FileList {
+  fn supports_platform(&self, p: PlatformType) -> bool {
+    assert!(p != PlatformType::Any);
+    true
+  }
+
+  fn run(&self, ctx: &Context) -> RunResult {
+    let dir = ctx.build_dir.join("filelist");
+    create_dir_all(&dir)?;
+
+    let rel    = ctx.input_rel.join("..");
+    let prefix = rel.to_str().unwrap();
+
+    for (index, &name) in ctx.project.targets.keys().enumerate() {
+      let sources   = get_paths(prefix, &ctx.sources[index]);
+      let resources = get_paths(prefix, &ctx.resources[index]);
+
+      for &(ext, paths) in &[("sources", &sources), ("resources", &resources)] {
+        let mut f = TextFile::create(ctx, "filelist", text::LF, dir.join([name, ".", ext].join("")))?;
+        for path in paths {
+          write!(f, "{}\n", path)?;
+        }
+        f.flush()?;
+      }
+
+      let mut f = TextFile::create(ctx, "filelist", text::LF, dir.join([name, ".gni"].join("")))?;
+      write_gn_list(&mut f, name, "sources",   &sources)?;
+      write_gn_list(&mut f, name, "resources", &resources)?;
+
+      let deps = ctx.extends[index].iter()
+        .map(|&i| [":", ctx.get_target_name(i)].join(""))
+        .collect::<Vec<String>>();
+      write_gn_list(&mut f, name, "deps", &deps)?;
+      f.flush()?;
+    }
+
+    Ok(())
+  }
+}
+
+fn get_paths(prefix: &str, files: &TargetFiles) -> Vec<String> {
+  files.iter()
+    .filter(|x| x.meta.is_file())
+    .map(|x| join_prefix(prefix, "/", &x.to_str().replace('\\', "/")))
+    .collect()
+}
+
+fn write_gn_list<W>(f: &mut W, target: &str, name: &str, items: &[String]) -> IO where W: Write {
+  write!(f, "{}_{} = [\n", target, name)?;
+  for item in items {
+    write!(f, "  \"{}\",\n", item)?;
+  }
+  f.write_all(b"]\n")
+}
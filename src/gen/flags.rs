@@ -0,0 +1,540 @@
+//! Compiler flags shared by the generators driving GCC or Clang.
+
+use crate::ctx::{AndroidActivity, Architecture, Context, DebugInfo, FileSettings, FloatModel, Lto,
+                 Optimize, PlatformType, Profiles, Settings, Simd, StdLib, Target, TargetType};
+
+/// MSVC warnings are identified by number, GCC and Clang ones by name.
+pub fn is_msvc_warning(w: &str) -> bool {
+  !w.is_empty() && w.bytes().all(|c| c.is_ascii_digit())
+}
+
+pub fn gcc_optimize(o: Optimize) -> &'static str {
+  match o {
+    Optimize::None  => "-O0",
+    Optimize::Size  => "-Os",
+    Optimize::Speed => "-O2",
+    Optimize::Full  => "-O3"
+  }
+}
+
+pub fn gcc_fp_model(m: FloatModel) -> &'static str {
+  match m {
+    FloatModel::Fast    => "-ffast-math",
+    FloatModel::Precise => "",
+    FloatModel::Strict  => "-frounding-math -fsignaling-nans"
+  }
+}
+
+/// GCC has no ThinLTO, Linux falls back to full LTO while Android and HTML5 use Clang.
+pub fn gcc_lto(lto: Lto, platform: PlatformType) -> &'static str {
+  match (lto, platform) {
+    (Lto::Off,  _)                   => "",
+    (Lto::Thin, PlatformType::Linux) |
+    (Lto::Full, _)                   => " -flto",
+    (Lto::Thin, _)                   => " -flto=thin"
+  }
+}
+
+pub fn gcc_warning_level(level: u8) -> &'static str {
+  match level {
+    0     => "-w",
+    1 | 2 => "",
+    3     => "-Wall",
+    _     => "-Wall -Wextra"
+  }
+}
+
+/// Flags for per-file settings, excluding the defines and language selection
+/// which every generator has its own way to express.
+pub fn gcc_file_flags(s: &FileSettings) -> String {
+  let mut flags = Vec::new();
+
+  if let Some(o) = s.optimize {
+    flags.push(gcc_optimize(o).to_string());
+  }
+
+  if let Some(level) = s.warning_level {
+    let w = gcc_warning_level(level);
+    if !w.is_empty() {
+      flags.push(w.to_string());
+    }
+  }
+
+  for w in s.disable_warnings.iter().filter(|x| !is_msvc_warning(x)) {
+    flags.push(["-Wno-", w].join(""));
+  }
+
+  flags.join(" ")
+}
+
+/// Comma-separated sanitizers of a profile, empty when none are enabled.
+pub fn get_sanitizers(ctx: &Context, target: &Target, profile: &str, platform: PlatformType,
+                      arch: Architecture) -> String
+{
+  ctx.get_setting(target, profile, platform, arch, |s| match s.sanitizers.is_empty() {
+    true  => None,
+    false => Some(s.sanitizers.join(","))
+  }).unwrap_or_default()
+}
+
+// Settings resolved once per target, see `Context::get_target_setting`.
+
+pub fn get_stdlib<'a>(ctx: &Context<'a>, target: &Target<'a>) -> Option<StdLib> {
+  ctx.get_target_setting(target, |s| s.stdlib)
+}
+
+pub fn get_stdlib_static<'a>(ctx: &Context<'a>, target: &Target<'a>) -> Option<bool> {
+  ctx.get_target_setting(target, |s| s.stdlib_static)
+}
+
+pub fn get_simd<'a>(ctx: &Context<'a>, target: &Target<'a>) -> Option<Simd> {
+  ctx.get_target_setting(target, |s| s.simd)
+}
+
+pub fn get_output_name<'a>(ctx: &Context<'a>, target: &Target<'a>) -> Option<&'a str> {
+  ctx.get_target_setting(target, |s| s.output_name)
+}
+
+pub fn get_output_dir<'a>(ctx: &Context<'a>, target: &Target<'a>) -> Option<&'a str> {
+  ctx.get_target_setting(target, |s| s.output_dir)
+}
+
+pub fn get_pic<'a>(ctx: &Context<'a>, target: &Target<'a>) -> Option<bool> {
+  ctx.get_target_setting(target, |s| s.pic)
+}
+
+pub fn get_visibility_hidden<'a>(ctx: &Context<'a>, target: &Target<'a>) -> bool {
+  ctx.get_target_setting(target, |s| s.visibility_hidden).unwrap_or(false)
+}
+
+pub fn get_visibility_inlines_hidden<'a>(ctx: &Context<'a>, target: &Target<'a>) -> bool {
+  ctx.get_target_setting(target, |s| s.visibility_inlines_hidden).unwrap_or(false)
+}
+
+pub fn gcc_visibility_flags<'a>(ctx: &Context<'a>, target: &Target<'a>) -> &'static str {
+  match (get_visibility_hidden(ctx, target), get_visibility_inlines_hidden(ctx, target)) {
+    (false, false) => "",
+    (true,  false) => " -fvisibility=hidden",
+    (false, true)  => " -fvisibility-inlines-hidden",
+    (true,  true)  => " -fvisibility=hidden -fvisibility-inlines-hidden"
+  }
+}
+
+/// NEON is always enabled on ARM64 and by the NDK on ARMv7, it needs no flag.
+pub fn gcc_simd_flags(simd: Simd) -> &'static str {
+  match simd {
+    Simd::SSE2   => " -msse2",
+    Simd::AVX    => " -mavx",
+    Simd::AVX2   => " -mavx2",
+    Simd::AVX512 => " -mavx512f",
+    Simd::NEON   => ""
+  }
+}
+
+pub fn get_pch<'a>(ctx: &Context<'a>, target: &Target<'a>) -> Option<&'a str> {
+  ctx.get_target_setting(target, |s| s.pch)
+}
+
+pub fn get_pch_source<'a>(ctx: &Context<'a>, target: &Target<'a>) -> Option<&'a str> {
+  ctx.get_target_setting(target, |s| s.pch_source)
+}
+
+pub fn use_response_files<'a>(ctx: &Context<'a>, target: &Target<'a>) -> bool {
+  ctx.get_target_setting(target, |s| s.response_files).unwrap_or(false)
+}
+
+pub fn get_compiler_launcher<'a>(ctx: &Context<'a>, target: &Target<'a>) -> Option<&'a str> {
+  ctx.get_target_setting(target, |s| s.compiler_launcher)
+}
+
+/// Headers forced into every source of a target, including the ones of its extended targets.
+pub fn get_force_includes<'a>(ctx: &Context<'a>, index: usize) -> Vec<&'a str> {
+  let mut v = ctx.project.settings.force_includes.to_vec();
+  for &i in ctx.extends[index].iter().chain(std::iter::once(&index)) {
+    v.extend(ctx.get_target(i).settings.force_includes.iter());
+  }
+  v
+}
+
+/// Calls `f` with the settings of a target, the project's first, then the ones of its
+/// extended targets. Without a profile only the settings common to all profiles are
+/// visited, otherwise only the ones of that profile's settings matching the platform
+/// and architecture.
+fn visit_settings<'a, F>(ctx: &Context<'a>, index: usize, profile: Option<&str>,
+                         platform: PlatformType, arch: Architecture, mut f: F) where
+  F: FnMut(&Settings<'a>)
+{
+  let mut add_all = |settings: &Settings<'a>, profiles: &Profiles<'a>| match profile {
+    None    => f(settings),
+    Some(p) => {
+      profiles.get(p).into_iter().flatten()
+        .filter(|x| x.matches(platform, arch))
+        .for_each(|x| f(&x.settings));
+    }
+  };
+
+  add_all(&ctx.project.settings, &ctx.project.profiles);
+  for &i in ctx.extends[index].iter().chain(std::iter::once(&index)) {
+    let target = ctx.get_target(i);
+    add_all(&target.settings, &target.profiles);
+  }
+}
+
+/// Raw C, C++ and linker flags of a target for a generator, see `visit_settings`.
+pub fn get_extra_flags<'a>(ctx: &Context<'a>, index: usize, generator: &str, profile: Option<&str>,
+                           platform: PlatformType,
+                           arch: Architecture) -> (Vec<&'a str>, Vec<&'a str>, Vec<&'a str>)
+{
+  let mut flags = (Vec::new(), Vec::new(), Vec::new());
+  visit_settings(ctx, index, profile, platform, arch, |s| {
+    let o = s.extra_flags.get(generator);
+    flags.0.extend(o.and_then(|x| x.cflags.as_ref()).unwrap_or(&s.extra_cflags).iter());
+    flags.1.extend(o.and_then(|x| x.cxxflags.as_ref()).unwrap_or(&s.extra_cxxflags).iter());
+    flags.2.extend(o.and_then(|x| x.ldflags.as_ref()).unwrap_or(&s.extra_ldflags).iter());
+  });
+  flags
+}
+
+/// Warnings disabled for a target, as MSVC numbers then GCC/Clang names. Same
+/// profile selection as `get_extra_flags`.
+pub fn get_disable_warnings<'a>(ctx: &Context<'a>, index: usize, profile: Option<&str>,
+                                platform: PlatformType,
+                                arch: Architecture) -> (Vec<&'a str>, Vec<&'a str>)
+{
+  let mut warnings = Vec::new();
+  visit_settings(ctx, index, profile, platform, arch, |s| push_unique(&mut warnings, &s.disable_warnings));
+  warnings.into_iter().partition(|x| is_msvc_warning(x))
+}
+
+/// `-Wno-` flags for GCC/Clang warning names, each prefixed with a space.
+pub fn gcc_disable_warnings(warnings: &[&str]) -> String {
+  warnings.iter().map(|x| [" -Wno-", x].join("")).collect()
+}
+
+/// Entries of a target's `libs`, as system library names and library file paths.
+/// Names of project targets are left out, they are linked like extended targets.
+pub fn get_libs<'a>(ctx: &Context, target: &Target<'a>) -> (Vec<&'a str>, Vec<&'a str>) {
+  target.settings.libs.iter().cloned()
+    .filter(|lib| !ctx.project.targets.contains_key(lib))
+    .partition(|lib| !is_lib_file(lib))
+}
+
+/// Library files are given by path, relative to the input folder, ie `./libfoo.a`.
+fn is_lib_file(lib: &str) -> bool {
+  lib.contains('/') || lib.contains('\\')
+}
+
+/// Apple frameworks linked by a target on the given platform, strong then weak ones.
+/// They go in the build phases, those of profile scoped settings link in every profile.
+pub fn get_frameworks<'a>(ctx: &Context<'a>, index: usize, platform: PlatformType,
+                          arch: Architecture) -> (Vec<&'a str>, Vec<&'a str>)
+{
+  let mut frameworks = (Vec::new(), Vec::new());
+  let mut add = |s: &Settings<'a>| {
+    push_unique(&mut frameworks.0, &s.frameworks);
+    push_unique(&mut frameworks.1, &s.weak_frameworks);
+  };
+
+  let mut add_all = |settings: &Settings<'a>, profiles: &Profiles<'a>| {
+    add(settings);
+    profiles.values().flatten()
+      .filter(|x| x.matches(platform, arch))
+      .for_each(|x| add(&x.settings));
+  };
+
+  add_all(&ctx.project.settings, &ctx.project.profiles);
+  for &i in ctx.extends[index].iter().chain(std::iter::once(&index)) {
+    let target = ctx.get_target(i);
+    add_all(&target.settings, &target.profiles);
+  }
+
+  if frameworks.0.is_empty() && frameworks.1.is_empty() {
+    frameworks.0 = default_frameworks(platform).to_vec();
+  }
+
+  frameworks
+}
+
+fn push_unique<'a>(v: &mut Vec<&'a str>, items: &[&'a str]) {
+  for &x in items {
+    if !v.contains(&x) {
+      v.push(x);
+    }
+  }
+}
+
+fn default_frameworks(platform: PlatformType) -> &'static [&'static str] {
+  match platform {
+    PlatformType::WatchOS => &[],
+    PlatformType::MacOS   => &["AppKit", "CoreVideo", "Metal", "OpenGL", "GameController"],
+    _                     => &["UIKit", "Metal", "OpenGLES", "QuartzCore", "GameController"]
+  }
+}
+
+/// Value of ANDROID_STL, the NDK only ships libc++ and links it statically by default.
+pub fn android_stl<'a>(ctx: &Context<'a>, target: &Target<'a>) -> &'static str {
+  match get_stdlib_static(ctx, target).unwrap_or(true) {
+    true  => "c++_static",
+    false => "c++_shared"
+  }
+}
+
+/// Architectures built for Android, as allowed by the project and target filters.
+pub fn android_architectures(ctx: &Context, target: &Target) -> Vec<Architecture> {
+  let android = ctx.platforms.iter()
+    .find(|p| p.get_platform_type() == PlatformType::Android)
+    .unwrap();
+
+  [Architecture::ARM, Architecture::ARM64, Architecture::X86, Architecture::X64].iter().cloned()
+    .filter(|&a| {
+      android.supports_architecture(a) &&
+        ctx.project.filter.matches_architecture(a) &&
+        target.filter.matches_architecture(a)
+    })
+    .collect()
+}
+
+/// Shared libraries a target depends on which are built as Android library modules.
+/// Applications consume them through prefab rather than building them again.
+pub fn android_library_deps<'a>(ctx: &Context<'a>, target: &Target<'a>) -> Vec<&'a str> {
+  target.depends.iter().cloned().filter(|name| {
+    ctx.project.targets.get(name).map_or(false, |t| {
+      t.target_type == TargetType::SharedLibrary && t.filter.matches_platform(PlatformType::Android)
+    })
+  }).collect()
+}
+
+/// Activity hosting an Android target, NativeActivity unless configured otherwise.
+pub fn android_activity(ctx: &Context, target: &Target) -> AndroidActivity {
+  target.android.activity.or(ctx.project.android.activity).unwrap_or(AndroidActivity::Native)
+}
+
+/// Java class of an Android activity, as named in the manifest.
+pub fn android_activity_class(activity: AndroidActivity) -> &'static str {
+  match activity {
+    AndroidActivity::Native => "android.app.NativeActivity",
+    AndroidActivity::Game   => "com.google.androidgamesdk.GameActivity"
+  }
+}
+
+pub const ANDROID_APPLICATION_ID: &str = "com.lambdacoder.Jank";
+pub const ANDROID_VERSION_CODE:   u32  = 1;
+pub const ANDROID_VERSION_NAME:   &str = "1.0";
+
+pub fn android_application_id<'a>(ctx: &Context<'a>, target: &Target<'a>) -> &'a str {
+  target.android.application_id.or(ctx.project.android.application_id).unwrap_or(ANDROID_APPLICATION_ID)
+}
+
+/// Value of ANDROID_ABI, as listed in the Gradle ABI filters.
+pub fn android_abi(a: Architecture) -> &'static str {
+  match a {
+    Architecture::ARM   => "armeabi-v7a",
+    Architecture::ARM64 => "arm64-v8a",
+    Architecture::X86   => "x86",
+    Architecture::X64   => "x86_64",
+    Architecture::Any   => unreachable!()
+  }
+}
+
+/// Compiler and linker flags selecting the standard library. Nothing is
+/// emitted when unset, leaving the toolchain's default in place.
+pub fn gcc_stdlib_flags<'a>(ctx: &Context<'a>, target: &Target<'a>) -> (&'static str, &'static str) {
+  let cflags = match get_stdlib(ctx, target) {
+    Some(StdLib::LibCXX)    => " -stdlib=libc++",
+    Some(StdLib::LibStdCXX) |
+    None                    => ""
+  };
+
+  let ldflags = match (get_stdlib(ctx, target), get_stdlib_static(ctx, target)) {
+    (Some(StdLib::LibCXX), Some(true)) => " -stdlib=libc++ -static-libstdc++",
+    (Some(StdLib::LibCXX), _)          => " -stdlib=libc++",
+    (_,                    Some(true)) => " -static-libstdc++",
+    (_,                    _)          => ""
+  };
+
+  (cflags, ldflags)
+}
+
+/// Compiler and linker flags for the settings resolved per profile, each flag
+/// prefixed with a space. The security flags only have GCC equivalents on Linux.
+pub fn gcc_profile_flags<'a>(ctx: &Context<'a>, target: &Target<'a>, profile: &str,
+                             platform: PlatformType, arch: Architecture, is_exe: bool) -> (String, String)
+{
+  let get = |f: fn(&Settings) -> Option<bool>| ctx.get_setting(target, profile, platform, arch, f);
+
+  let mut cflags  = String::new();
+  let mut ldflags = String::new();
+
+  if let Some(m) = ctx.get_setting(target, profile, platform, arch, |s| s.fp_model) {
+    let fp = gcc_fp_model(m);
+    if !fp.is_empty() {
+      cflags.push(' ');
+      cflags.push_str(fp);
+    }
+  }
+
+  if let Some(lto) = ctx.get_setting(target, profile, platform, arch, |s| s.lto) {
+    cflags.push_str(gcc_lto(lto, platform));
+    ldflags.push_str(gcc_lto(lto, platform));
+  }
+
+  let sanitizers = get_sanitizers(ctx, target, profile, platform, arch);
+  if !sanitizers.is_empty() {
+    cflags.push_str(&format!(" -fsanitize={} -fno-omit-frame-pointer", sanitizers));
+    ldflags.push_str(&format!(" -fsanitize={}", sanitizers));
+  }
+
+  // GCC's gcov data on Linux, Clang's source-based coverage elsewhere. See `janky coverage`.
+  if get(|s| s.coverage) == Some(true) {
+    let coverage = match platform {
+      PlatformType::Linux => (" --coverage", " --coverage"),
+      _                   => (" -fprofile-instr-generate -fcoverage-mapping", " -fprofile-instr-generate")
+    };
+    cflags.push_str(coverage.0);
+    ldflags.push_str(coverage.1);
+  }
+
+  if platform != PlatformType::Linux {
+    return (cflags, ldflags);
+  }
+
+  // libstdc++'s closest equivalents, its debug mode also changes the ABI.
+  if get_stdlib(ctx, target) != Some(StdLib::LibCXX) {
+    match ctx.get_setting(target, profile, platform, arch, |s| s.iterator_debug_level) {
+      Some(0) | None => {},
+      Some(1)        => cflags.push_str(" -D_GLIBCXX_ASSERTIONS"),
+      Some(_)        => cflags.push_str(" -D_GLIBCXX_DEBUG")
+    }
+  }
+
+  if get(|s| s.control_flow_guard) == Some(true) {
+    cflags.push_str(" -fcf-protection=full");
+  }
+
+  if get(|s| s.spectre_mitigation) == Some(true) {
+    cflags.push_str(" -mindirect-branch=thunk -mfunction-return=thunk");
+  }
+
+  match get(|s| s.buffer_security_check) {
+    Some(true)  => cflags.push_str(" -fstack-protector-strong"),
+    Some(false) => cflags.push_str(" -fno-stack-protector"),
+    None        => {}
+  }
+
+  if is_exe {
+    match get(|s| s.aslr) {
+      Some(true)  => { cflags.push_str(" -fPIE"); ldflags.push_str(" -pie"); },
+      Some(false) => ldflags.push_str(" -no-pie"),
+      None        => {}
+    }
+  }
+
+  (cflags, ldflags)
+}
+
+/// Names used by the substitution variables, the CMake generator's platforms only.
+pub fn gcc_platform_name(p: PlatformType) -> &'static str {
+  match p {
+    PlatformType::Android => "android",
+    PlatformType::Linux   => "linux",
+    PlatformType::HTML5   => "html5",
+    _                     => unreachable!()
+  }
+}
+
+pub fn gcc_arch_name(p: PlatformType) -> &'static str {
+  match p { // TODO
+    PlatformType::Android => "arm64",
+    PlatformType::Linux   => "x64",
+    PlatformType::HTML5   => "wasm32",
+    _                     => unreachable!()
+  }
+}
+
+/// Flags common to every profile.
+pub fn gcc_cflags(ctx: &Context, index: usize, platform: PlatformType) -> String {
+  let target = ctx.get_target(index);
+
+  // Android selects its STL through ANDROID_STL, passed in by Gradle.
+  let (stdlib_cflags, _) = match platform {
+    PlatformType::Linux => gcc_stdlib_flags(ctx, target),
+    _                   => ("", "")
+  };
+
+  // TODO hardcoded flags
+  // TODO -Wpedantic is annoying with GCC
+  // HTML5 has its own SIMD switch, which emulates SSE as well.
+  let simd_cflags = match (platform, get_simd(ctx, target)) {
+    (PlatformType::HTML5, _) | (_, None) => "",
+    (_, Some(simd))                      => gcc_simd_flags(simd)
+  };
+
+  let (_, warnings) = get_disable_warnings(ctx, index, None, platform, Architecture::Any);
+  let mut cflags = format!("-Wall -Wextra -fno-exceptions -fno-rtti{}{}{}{}",
+                           stdlib_cflags, simd_cflags, gcc_visibility_flags(ctx, target),
+                           gcc_disable_warnings(&warnings));
+  for inc in get_force_includes(ctx, index) {
+    cflags.push_str(" -include ");
+    cflags.push_str(inc);
+  }
+  cflags
+}
+
+/// Split DWARF keeps the debug information in .dwo files, Emscripten in a .debug.wasm file.
+pub fn gcc_debug_info(x: DebugInfo, platform: PlatformType) -> &'static str {
+  match (x, platform) {
+    (DebugInfo::None,     _)                   => "",
+    (DebugInfo::Embedded, PlatformType::HTML5) => " -g4",
+    (DebugInfo::Embedded, _)                   => " -g",
+    (DebugInfo::Separate, PlatformType::HTML5) => " -g -gseparate-dwarf",
+    (DebugInfo::Separate, _)                   => " -g -gsplit-dwarf"
+  }
+}
+
+/// Compiler and linker flags of a single profile, added to the ones of `gcc_cflags`.
+pub fn gcc_config_flags(ctx: &Context, index: usize, profile: &str, platform: PlatformType,
+                        arch: Architecture, is_exe: bool) -> (String, String)
+{
+  let target = ctx.get_target(index);
+  let (_, stdlib_ldflags) = match platform {
+    PlatformType::Linux => gcc_stdlib_flags(ctx, target),
+    _                   => ("", "")
+  };
+
+  let (mut cflags, ldflags) = gcc_profile_flags(ctx, target, profile, platform, arch, is_exe);
+  let (_, warnings) = get_disable_warnings(ctx, index, Some(profile), platform, arch);
+  cflags.push_str(&gcc_disable_warnings(&warnings));
+
+  // TODO hardcoded, profiles without optimizations use the debug flags
+  let debug = match ctx.get_setting(target, profile, platform, arch, |s| s.optimize) {
+    Some(Optimize::None) => true,
+    _                    => ctx.get_base_profile(profile) == "Debug"
+  };
+
+  let g = match ctx.get_setting(target, profile, platform, arch, |s| s.debug_info) {
+    Some(x)       => gcc_debug_info(x, platform),
+    None if debug => gcc_debug_info(DebugInfo::Embedded, platform),
+    None          => ""
+  };
+
+  // Emscripten uses -s for its settings.
+  let strip = match (platform, ctx.get_setting(target, profile, platform, arch, |s| s.strip)) {
+    (PlatformType::HTML5, _) => "",
+    (_, Some(true))          => " -s",
+    (_, _)                   => ""
+  };
+
+  match debug {
+    true => {
+      let extra_ldflags = match platform {
+        PlatformType::HTML5 => " -s ASSERTIONS=2 -s DEMANGLE_SUPPORT=1",
+        _                   => ""
+      };
+
+      (format!("-D_DEBUG=1{}{}", g, cflags),
+       format!("{}{}{}{}", extra_ldflags, stdlib_ldflags, ldflags, strip))
+    },
+    false => (format!("-Werror{}{}", g, cflags),
+              format!("{}{}{}", stdlib_ldflags, ldflags, strip))
+  }
+}
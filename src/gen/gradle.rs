@@ -1,8 +1,11 @@
-use std::fs::{File, create_dir_all};
-use std::io::{BufWriter, Write};
+use std::fs::create_dir_all;
+use std::io::{Error, ErrorKind, Result as IOResult, Write};
 use std::path::Path;
 
-use crate::ctx::{Context, Generator, PlatformType, RunResult, Target, TargetType};
+use crate::ctx::{AndroidActivity, AndroidDependencies, AndroidFeature, AndroidManifest, AndroidSettings,
+                 Architecture, Context, Generator, PlatformType, RunResult, Target, TargetType};
+use super::{flags, get_resources, link_file};
+use super::text::{self, TextFile};
 
 pub struct Gradle;
 
@@ -17,15 +20,27 @@ impl Generator for Gradle {
 
   fn run(&self, ctx: &Context) -> RunResult {
     if !ctx.project.filter.matches_platform(PlatformType::Android) {
+      ctx.log.verbose("Gradle: skipped, the project is not built for Android");
       return Ok(());
     }
 
     let targets = ctx.project.targets.iter().enumerate().filter_map(|(index, (name, target))| {
-      match target.filter.matches_platform(PlatformType::Android) &&
-        target.target_type == TargetType::Application {
-          false => None,
-          true  => Some(Build { name, target, index, path: [name, "_Android"].join("") })
-        }}).collect::<Vec<Build>>();
+      let library = match target.target_type {
+        TargetType::Application   => false,
+        TargetType::SharedLibrary => true,
+        _                         => return None
+      };
+      match target.filter.matches_platform(PlatformType::Android) {
+        false => {
+          ctx.log.verbose(format!("Gradle: target {} is not built for Android", name));
+          None
+        },
+        true  => Some(Build {
+          name, target, index, library,
+          path:    [name, "_Android"].join(""),
+          android: target.android.or(&ctx.project.android).or(&DEFAULTS)
+        })
+      }}).collect::<Vec<Build>>();
 
     if targets.is_empty() {
       return Ok(());
@@ -36,43 +51,111 @@ impl Generator for Gradle {
     }
 
     write_root_build(&ctx)?;
-    write_properties(&ctx)?;
+    write_properties(ctx, &targets)?;
     write_settings(ctx, &targets)?;
 
     Ok(())
   }
 }
 
-type IO = std::io::Result<()>;
+type IO = IOResult<()>;
+
+fn create_file<P>(ctx: &Context, path: P) -> IOResult<TextFile> where P: AsRef<Path> {
+  TextFile::create(ctx, "gradle", text::LF, path)
+}
 
 struct Build<'a> {
-  path:   String,
-  name:   &'a str,
-  target: &'a Target<'a>,
-  index:  usize
+  path:    String,
+  name:    &'a str,
+  target:  &'a Target<'a>,
+  index:   usize,
+  library: bool, // Shared libraries are library modules, published as AARs.
+  android: AndroidSettings<'a> // Every value is set except the NDK and signing key, see DEFAULTS.
+}
+
+impl<'a> Build<'a> {
+  /// Library modules need a package of their own, derived from the application's.
+  fn package(&self) -> String {
+    let id = self.android.application_id.unwrap();
+    match self.library {
+      true  => [id, ".", self.name.to_lowercase().as_str()].join(""),
+      false => id.to_string()
+    }
+  }
 }
 
-fn write_target_build(ctx: &Context, build: &Build) -> IO {
+const DEFAULTS: AndroidSettings = AndroidSettings {
+  application_id: Some(flags::ANDROID_APPLICATION_ID),
+  version_code:   Some(flags::ANDROID_VERSION_CODE),
+  version_name:   Some(flags::ANDROID_VERSION_NAME),
+  min_sdk:        Some(26),
+  target_sdk:     Some(29),
+  compile_sdk:    Some(29),
+  build_tools:    Some("29.0.2"),
+  ndk_version:    None,
+  cmake_version:  Some("3.10.2"),
+  activity:       Some(AndroidActivity::Native),
+  tv:             Some(false),
+
+  keystore:           None,
+  key_alias:          None,
+  store_password_env: Some("JANK_ANDROID_STORE_PASSWORD"),
+  key_password_env:   Some("JANK_ANDROID_KEY_PASSWORD"),
+
+  manifest:     None,
+  dependencies: None
+};
+
+// TODO dont hardcode
+const APPCOMPAT_VERSION:     &str = "1.6.1";
+const GAME_ACTIVITY_VERSION: &str = "2.0.2";
+
+fn write_target_build<'a>(ctx: &Context<'a>, build: &Build<'a>) -> IO {
   let mut path = ctx.build_dir.join(&build.path);
   create_dir_all(&path)?;
 
-  let mut f = BufWriter::new(File::create(path.join("build.gradle"))?);
+  let mut f = create_file(ctx, path.join("build.gradle"))?;
 
-  write!(f, concat!("apply plugin: 'com.android.application'\n\n",
+  let archs = flags::android_architectures(ctx, build.target);
+  let abi_filters = archs.iter()
+    .map(|&a| ["'", flags::android_abi(a), "'"].join(""))
+    .collect::<Vec<String>>();
+  if abi_filters.is_empty() {
+    return Err(Error::new(ErrorKind::Other,
+                          format!("No Android architecture to build for target {}", build.name)));
+  }
+  let abi_filters = abi_filters.join(", ");
+
+  let (plugin, app_config) = match build.library {
+    true  => ("com.android.library", String::new()),
+    false => ("com.android.application", format!(concat!("    applicationId '{}'\n",
+                                                         "    versionCode {}\n",
+                                                         "    versionName '{}'\n"),
+                                                  build.android.application_id.unwrap(),
+                                                  build.android.version_code.unwrap(),
+                                                  build.android.version_name.unwrap()))
+  };
+
+  write!(f, concat!("apply plugin: '{plugin}'\n\n",
                     "android {{\n",
                     "  compileSdkVersion {compile_sdk_version}\n",
-                    "  buildToolsVersion '{build_tools_version}'\n\n",
+                    "  buildToolsVersion '{build_tools_version}'\n",
+                    "{ndk_version}\n",
                     "  defaultConfig {{\n",
-                    "    applicationId '{application_id}'\n",
+                    "{app_config}",
                     "    minSdkVersion {min_sdk_version}\n",
-                    "    targetSdkVersion {target_sdk_version}\n",
-                    "    versionCode {version_code}\n",
-                    "    versionName '{version_name}'\n\n",
-                    "    ndk.abiFilters 'arm64-v8a'\n\n", // TODO dont hardcode filters
+                    "    targetSdkVersion {target_sdk_version}\n\n",
+                    "    ndk.abiFilters {abi_filters}\n\n",
+                    "    externalNativeBuild {{\n",
+                    "      cmake {{\n",
+                    "        arguments '-DANDROID_STL={android_stl}'\n",
+                    "      }}\n",
+                    "    }}\n\n",
                     "    sourceSets {{\n",
                     "      main {{\n",
                     "        manifest.srcFile 'AndroidManifest.xml'\n",
                     "        res.srcDirs = ['res']\n", // TODO place assets there
+                    "{assets}",
                     "      }}\n",
                     "    }}\n",
                     "  }}\n\n",
@@ -81,31 +164,102 @@ fn write_target_build(ctx: &Context, build: &Build) -> IO {
                     "      version '{cmake_version}'\n",
                     "      path 'CMakeLists.txt'\n",
                     "    }}\n",
-                    "  }}\n\n",
-                    "  buildTypes {{\n"),
-         // TODO dont hardcode
-         compile_sdk_version = 29,
-         build_tools_version = "29.0.2",
-         application_id      = "com.lambdacoder.Jank",
-         version_code        = 1,
-         version_name        = "1.0",
-         min_sdk_version     = 26,
-         target_sdk_version  = 29,
-         cmake_version       = "3.10.2")?;
+                    "  }}\n\n"),
+         plugin              = plugin,
+         assets              = match write_assets(ctx, &path, build)? {
+           true  => "        assets.srcDirs = ['assets']\n",
+           false => ""
+         },
+         app_config          = app_config,
+         compile_sdk_version = build.android.compile_sdk.unwrap(),
+         build_tools_version = build.android.build_tools.unwrap(),
+         ndk_version         = build.android.ndk_version
+           .map_or(String::new(), |x| format!("  ndkVersion '{}'\n", x)),
+         min_sdk_version     = build.android.min_sdk.unwrap(),
+         target_sdk_version  = build.android.target_sdk.unwrap(),
+         abi_filters         = abi_filters,
+         android_stl         = flags::android_stl(ctx, build.target),
+         cmake_version       = build.android.cmake_version.unwrap())?;
+
+  let deps    = flags::android_library_deps(ctx, build.target);
+  let is_game = !build.library && build.android.activity == Some(AndroidActivity::Game);
+
+  if build.library {
+    // Prefab exports the library and its headers to the modules depending on it.
+    write!(f, concat!("  buildFeatures {{\n",
+                      "    prefabPublishing true\n",
+                      "  }}\n\n",
+                      "  prefab {{\n",
+                      "    {} {{\n"),
+           build.name)?;
+    if let Some(dir) = build.target.settings.include_dirs.first() {
+      write!(f, "      headers '{}'\n",
+             ctx.input_dir.join(dir).to_str().unwrap().replace('\\', "/"))?;
+    }
+    f.write_all(b"    }\n  }\n\n")?;
+  }
+  else if !deps.is_empty() || is_game {
+    f.write_all(b"  buildFeatures {\n    prefab true\n  }\n\n")?;
+  }
+
+  let keystore = match build.library {
+    true  => None,
+    false => ctx.env.jank_android_keystore.as_ref()
+      .map(|x| ctx.input_dir.join(x))
+      .or_else(|| build.android.keystore.map(|x| ctx.input_dir.join(x)))
+  };
+
+  if let Some(keystore) = &keystore {
+    let alias = build.android.key_alias.ok_or_else(|| {
+      Error::new(ErrorKind::Other, format!("No Android key alias for target {}", build.name))
+    })?;
+
+    write!(f, concat!("  signingConfigs {{\n",
+                      "    release {{\n",
+                      "      storeFile file('{keystore}')\n",
+                      "      storePassword System.getenv('{store_password_env}')\n",
+                      "      keyAlias '{key_alias}'\n",
+                      "      keyPassword System.getenv('{key_password_env}')\n",
+                      "    }}\n",
+                      "  }}\n\n"),
+           keystore           = keystore.to_str().unwrap().replace('\\', "/"),
+           store_password_env = build.android.store_password_env.unwrap(),
+           key_alias          = alias,
+           key_password_env   = build.android.key_password_env.unwrap())?;
+  }
+
+  f.write_all(b"  buildTypes {\n")?;
 
   for &prof in &ctx.profiles {
     write!(f, "    {} {{\n", prof.to_lowercase())?;
 
-    match prof { // TODO dont hardcode this
-      "Debug" => {
-        f.write_all(concat!("      packagingOptions {\n",
-                            "        doNotStrip '**.so'\n",
-                            "      }\n").as_bytes())?;
-      },
+    // Libraries are only kept unstripped for the ABIs which need it.
+    let base       = ctx.get_base_profile(prof);
+    let unstripped = archs.iter().cloned().filter(|&a| {
+      !ctx.get_setting(build.target, prof, PlatformType::Android, a, |s| s.strip).unwrap_or(base != "Debug")
+    }).collect::<Vec<Architecture>>();
+    if unstripped.len() == archs.len() {
+      f.write_all(concat!("      packagingOptions {\n",
+                          "        doNotStrip '**.so'\n",
+                          "      }\n").as_bytes())?;
+    }
+    else if !unstripped.is_empty() {
+      f.write_all(b"      packagingOptions {\n")?;
+      for &a in &unstripped {
+        write!(f, "        doNotStrip '**/{}/*.so'\n", flags::android_abi(a))?;
+      }
+      f.write_all(b"      }\n")?;
+    }
+
+    match base {
       "Release" => {
         f.write_all(concat!("      minifyEnabled true\n",
                             "      proguardFiles getDefaultProguardFile('proguard-android.txt'),",
                             " 'proguard-rules.pro'\n").as_bytes())?;
+
+        if keystore.is_some() {
+          f.write_all(b"      signingConfig signingConfigs.release\n")?;
+        }
       },
       _ => {}
     }
@@ -117,14 +271,32 @@ fn write_target_build(ctx: &Context, build: &Build) -> IO {
 
   // TODO productFlavors
   // TODO buildVariants
-  // TODO manifest entries
-  // TODO signing
   // TODO splits
   // TODO lintOptions
 
   f.write_all(b"}\n")?;
 
-  // TODO dependencies
+  let default = AndroidDependencies::default();
+  let maven   = build.android.dependencies.as_ref().unwrap_or(&default);
+
+  if !deps.is_empty() || is_game || !maven.implementation.is_empty() || !maven.api.is_empty() {
+    f.write_all(b"\ndependencies {\n")?;
+    if is_game {
+      write!(f, concat!("  implementation 'androidx.appcompat:appcompat:{}'\n",
+                        "  implementation 'androidx.games:games-activity:{}'\n"),
+             APPCOMPAT_VERSION, GAME_ACTIVITY_VERSION)?;
+    }
+    for dep in &deps {
+      write!(f, "  implementation project(':{}_Android')\n", dep)?;
+    }
+    for dep in &maven.implementation {
+      write!(f, "  implementation '{}'\n", dep)?;
+    }
+    for dep in &maven.api {
+      write!(f, "  api '{}'\n", dep)?;
+    }
+    f.write_all(b"}\n")?;
+  }
 
   // TODO handle assets
   // - AndroidManifest.xml
@@ -135,15 +307,33 @@ fn write_target_build(ctx: &Context, build: &Build) -> IO {
   Ok(())
 }
 
+/// Links the target's resources in the module's assets folder, returns whether there are any.
+fn write_assets(ctx: &Context, path: &Path, build: &Build) -> IOResult<bool> {
+  let resources = get_resources(ctx, build.index);
+  if resources.is_empty() {
+    return Ok(false);
+  }
+
+  let dir = path.join("assets");
+  create_dir_all(&dir)?;
+
+  let src = pathdiff::diff_paths(&ctx.input_dir, &dir).unwrap();
+  for file in resources {
+    link_file(ctx, &src.join(&file.path), &dir.join(file.name()))?;
+  }
+
+  Ok(true)
+}
+
 fn write_root_build(ctx: &Context) -> IO {
-  let mut f = File::create(ctx.build_dir.join("build.gradle"))?;
+  let mut f = create_file(ctx, ctx.build_dir.join("build.gradle"))?;
   f.write_all(concat!("buildscript {\n",
                       "  repositories {\n",
                       "    google()\n",
                       "    jcenter()\n",
                       "  }\n\n",
                       "  dependencies {\n",
-                      "    classpath 'com.android.tools.build:gradle:3.5.0'\n",
+                      "    classpath 'com.android.tools.build:gradle:4.1.0'\n",
                       "  }\n",
                       "}\n\n",
                       "allprojects {\n",
@@ -160,14 +350,21 @@ fn write_root_build(ctx: &Context) -> IO {
   Ok(())
 }
 
-fn write_properties(ctx: &Context) -> IO {
-  let mut f = File::create(ctx.build_dir.join("gradle.properties"))?;
+fn write_properties(ctx: &Context, builds: &[Build]) -> IO {
+  let mut f = create_file(ctx, ctx.build_dir.join("gradle.properties"))?;
   f.write_all(b"org.gradle.jvmargs=-Xmx8g\n")?;
+
+  // The GameActivity packages are part of Jetpack.
+  if builds.iter().any(|x| x.android.activity == Some(AndroidActivity::Game)) {
+    f.write_all(b"android.useAndroidX=true\n")?;
+  }
+
+  f.flush()?;
   Ok(())
 }
 
 fn write_settings(ctx: &Context, builds: &[Build]) -> IO {
-  let mut f = BufWriter::new(File::create(ctx.build_dir.join("settings.gradle"))?);
+  let mut f = create_file(ctx, ctx.build_dir.join("settings.gradle"))?;
   f.write_all(b"include ")?;
 
   let mut iter = builds.iter();
@@ -184,82 +381,161 @@ fn write_settings(ctx: &Context, builds: &[Build]) -> IO {
 
 const XML_DECL: &[u8] = b"<?xml version=\"1.0\" encoding=\"utf-8\"?>\n";
 
+/// Manifest entries used when the target does not configure its own.
+fn default_manifest() -> AndroidManifest<'static> {
+  let feature = |name, version, required| AndroidFeature { name, version, required };
+  AndroidManifest {
+    features: vec![feature("android.hardware.audio.output",     None,             true),
+                   feature("android.hardware.screen.landscape", None,             true),
+                   feature("android.hardware.touchscreen",      None,             false),
+                   feature("android.hardware.vulkan.compute",   Some("0"),        true),
+                   feature("android.hardware.vulkan.level",     Some("0"),        true),
+                   feature("android.hardware.vulkan.version",   Some("0x400003"), true)],
+    ..AndroidManifest::default()
+  }
+}
+
 /// https://developer.android.com/guide/topics/manifest/manifest-intro
 fn write_target_manifest(ctx: &Context, path: &Path, build: &Build) -> IO {
-  // TODO android TV banner
-
   // TODO uses-configuration
   // TODO uses-library
-  // TODO uses-permission / uses-permission-sdk-23
   // TODO supports-gl-texture
   // TODO supports-screens
 
-  // TODO dont hardcode
-  let features = ["android.hardware.audio.output",
-                  "android.hardware.screen.landscape"];
-  let feature_versions = [("android.hardware.vulkan.compute", "0"),
-                          ("android.hardware.vulkan.level",   "0"),
-                          ("android.hardware.vulkan.version", "0x400003")];
+  let default;
+  let manifest = match &build.android.manifest {
+    Some(x) => x,
+    None    => {
+      default = default_manifest();
+      &default
+    }
+  };
 
-  let mut f = BufWriter::new(File::create(path.join("AndroidManifest.xml"))?);
+  let mut f = create_file(ctx, path.join("AndroidManifest.xml"))?;
   f.write_all(XML_DECL)?;
 
+  // Library modules only declare their package, the application has the rest.
+  if build.library {
+    write!(f, concat!("<manifest\n",
+                      "    xmlns:android=\"http://schemas.android.com/apk/res/android\"\n",
+                      "    package=\"{}\" />\n"),
+           build.package())?;
+    f.flush()?;
+    return Ok(());
+  }
+
   write!(f, concat!("<manifest\n",
                     "    xmlns:android=\"http://schemas.android.com/apk/res/android\"\n",
-                    "    package=\"{application_id}\"\n",
+                    "    package=\"{package}\"\n",
                     "    android:versionCode=\"{version_code}\"\n",
                     "    android:versionName=\"{version_name}\">\n",
                     "  <uses-sdk\n",
                     "      android:minSdkVersion=\"{min_sdk_version}\"\n",
                     "      android:targetSdkVersion=\"{target_sdk_version}\" />\n"),
-         application_id     = "com.lambdacoder.Jank",
-         version_code       = 1,
-         version_name       = "1.0",
-         min_sdk_version    = 26,
-         target_sdk_version = 29)?;
-
-  for name in &features { // TODO android:required attribute
-    write!(f, "  <uses-feature android:name=\"{}\" />\n", name)?;
+         package            = build.package(),
+         version_code       = build.android.version_code.unwrap(),
+         version_name       = build.android.version_name.unwrap(),
+         min_sdk_version    = build.android.min_sdk.unwrap(),
+         target_sdk_version = build.android.target_sdk.unwrap())?;
+
+  for name in &manifest.permissions {
+    write!(f, "  <uses-permission android:name=\"{}\" />\n", name)?;
   }
 
-  write!(f, "  <uses-feature android:name=\"android.hardware.touchscreen\" android:required=\"false\" />")?;
-
-  for (name, version) in &feature_versions {
-    write!(f, concat!("  <uses-feature\n",
-                      "      android:name=\"{name}\"\n",
-                      "      android:version=\"{version}\"\n",
-                      "      android:required=\"true\" />\n"),
-           name    = name,
-           version = version)?;
+  // Android TV devices have no touchscreen, the leanback feature keeps phones supported.
+  let is_tv = build.android.tv.unwrap();
+  let tv_features = match is_tv {
+    true  => vec![AndroidFeature { name: "android.hardware.touchscreen", version: None, required: false },
+                  AndroidFeature { name: "android.software.leanback",    version: None, required: false }],
+    false => Vec::new()
+  };
+
+  let features = manifest.features.iter().filter(|x| !tv_features.iter().any(|tv| tv.name == x.name));
+  for feature in features.chain(&tv_features) {
+    write!(f, "  <uses-feature android:name=\"{}\"", feature.name)?;
+    if let Some(version) = feature.version {
+      write!(f, " android:version=\"{}\"", version)?;
+    }
+    write!(f, " android:required=\"{:?}\" />\n", feature.required)?;
   }
 
   // TODO android:name ?
-  write!(f, concat!("  <application\n",
-                    "      android:allowBackup=\"false\"\n",
-                    "      android:description=\"@string/app_description\"\n",
-                    "      android:label=\"@string/app_label\"\n",
-                    "      android:icon=\"@mipmap/ic_launcher\"\n",
-                    "      android:roundIcon=\"@mipmap/ic_launcher_round\"\n",
-                    // "      android:theme=\"@style/AppTheme\"\n",
-                    "      android:isGame=\"true\"\n",
-                    "      android:hasCode=\"false\">\n",
-                    "    <activity\n",
-                    "        android:name=\"android.app.NativeActivity\"\n",
-                    "        android:configChanges=\"{config_changes}\">\n",
+  f.write_all(concat!("  <application\n",
+                      "      android:allowBackup=\"false\"\n",
+                      "      android:description=\"@string/app_description\"\n",
+                      "      android:label=\"@string/app_label\"\n",
+                      "      android:icon=\"@mipmap/ic_launcher\"\n",
+                      "      android:roundIcon=\"@mipmap/ic_launcher_round\"\n",
+                      // "      android:theme=\"@style/AppTheme\"\n",
+                      "      android:isGame=\"true\"\n").as_bytes())?;
+
+  if is_tv {
+    f.write_all(b"      android:banner=\"@drawable/banner\"\n")?;
+  }
+
+  // GameActivity is Java code shipped in the application, NativeActivity is part of the OS.
+  let is_game = build.android.activity == Some(AndroidActivity::Game);
+  let activity = flags::android_activity_class(build.android.activity.unwrap());
+  let has_code = match is_game {
+    true  => "true",
+    false => "false"
+  };
+  write!(f, "      android:hasCode=\"{}\">\n", has_code)?;
+
+  for (name, value) in &manifest.meta_data {
+    write!(f, concat!("    <meta-data\n",
+                      "        android:name=\"{}\"\n",
+                      "        android:value=\"{}\" />\n"),
+           name, value)?;
+  }
+
+  write!(f, concat!("    <activity\n",
+                    "        android:name=\"{}\"\n"),
+         activity)?;
+
+  if is_game {
+    f.write_all(b"        android:theme=\"@style/Theme.AppCompat.NoActionBar\"\n")?;
+  }
+
+  if let Some(orientation) = manifest.orientation {
+    write!(f, "        android:screenOrientation=\"{}\"\n", orientation)?;
+  }
+
+  write!(f, concat!("        android:configChanges=\"{config_changes}\">\n",
                     "      <meta-data\n",
                     "          android:name=\"android.app.lib_name\"\n",
                     "          android:value=\"{target_name}\" />\n",
                     "      <intent-filter>\n",
                     "        <action android:name=\"android.intent.action.MAIN\" />\n",
-                    "        <category android:name=\"android.intent.category.LAUNCHER\" />\n",
-                    "      </intent-filter>\n",
-                    "    </activity>\n",
-                    "  </application>\n",
-                    "</manifest>\n"),
+                    "        <category android:name=\"android.intent.category.LAUNCHER\" />\n"),
          // TODO dont hardcode
          target_name        = build.name,
          config_changes     = "keyboardHidden|keyboard|orientation|screenSize")?;
 
+  if is_tv {
+    f.write_all(b"        <category android:name=\"android.intent.category.LEANBACK_LAUNCHER\" />\n")?;
+  }
+
+  f.write_all(b"      </intent-filter>\n")?;
+
+  for filter in &manifest.intent_filters {
+    f.write_all(b"      <intent-filter>\n")?;
+    for name in &filter.actions {
+      write!(f, "        <action android:name=\"{}\" />\n", name)?;
+    }
+    for name in &filter.categories {
+      write!(f, "        <category android:name=\"{}\" />\n", name)?;
+    }
+    for scheme in &filter.schemes {
+      write!(f, "        <data android:scheme=\"{}\" />\n", scheme)?;
+    }
+    f.write_all(b"      </intent-filter>\n")?;
+  }
+
+  f.write_all(concat!("    </activity>\n",
+                      "  </application>\n",
+                      "</manifest>\n").as_bytes())?;
+
   write_strings(ctx, path)?;
   write_mipmaps(ctx, path, build)?;
   // - styles
@@ -273,7 +549,7 @@ fn write_strings(ctx: &Context, path: &Path) -> IO {
   create_dir_all(&res)?;
   res.push("string.xml");
 
-  let mut f = BufWriter::new(File::create(res)?);
+  let mut f = create_file(ctx, res)?;
   f.write_all(XML_DECL)?;
   f.write_all(b"<resources>\n")?;
 
@@ -290,12 +566,19 @@ fn write_strings(ctx: &Context, path: &Path) -> IO {
   Ok(())
 }
 
+/// Launcher icons are named `<name>_<dpi>.png` in the android assets folder.
+/// The TV banner uses the same scheme and is named `banner`.
 fn write_mipmaps(ctx: &Context, path: &Path, build: &Build) -> IO {
+  let is_tv = build.android.tv.unwrap();
   if build.target.assets.is_none() {
+    if is_tv {
+      ctx.warn(format!("No Android TV banner for target {}, it has no assets", build.name));
+    }
     return Ok(());
   }
 
   let src = pathdiff::diff_paths(&ctx.input_dir, &path.join("res/mipmap")).unwrap();
+  let mut has_banner = false;
 
   let pattern = [build.target.assets.unwrap(), "/android/"].join("");
   let assets  = ctx.assets[build.index].iter()
@@ -312,38 +595,37 @@ fn write_mipmaps(ctx: &Context, path: &Path, build: &Build) -> IO {
       let dpi  = &s[pos + 1 .. s.len() - 4];
       let name = &s[0 .. pos];
 
-      let mut res = path.join(["res/mipmap-", dpi].join(""));
+      let kind = match name {
+        "banner" => { has_banner = true; "res/drawable-" },
+        _        => "res/mipmap-"
+      };
+
+      let mut res = path.join([kind, dpi].join(""));
       create_dir_all(&res)?;
 
       res.push([name, ".png"].join(""));
-      // TODO move remove&symlink to shared utility
-      if res.symlink_metadata().is_ok() {
-        std::fs::remove_file(&res)?;
-      }
-
-      #[cfg(unix)]
-      std::os::unix::fs::symlink(src.join(&asset.path), &res)?;
-
-      // TODO
-      // #[cfg(windows)]
-      // std::os::windows::fs::symlink_file(src.join(&asset.path), &res)?;
+      link_file(ctx, &src.join(&asset.path), &res)?;
     }
   }
 
+  if is_tv && !has_banner {
+    ctx.warn(format!("No Android TV banner for target {}, expected {}banner_xhdpi.png", build.name, pattern));
+  }
+
   let adaptive_path = path.join("res/mipmap-anydpi-v26");
   create_dir_all(&adaptive_path)?;
 
   let background = "@mipmap/ic_launcher_background"; // TODO color/vector backgrounds
   let foreground = "@mipmap/ic_launcher_foreground";
 
-  write_adaptive_icon(&adaptive_path.join("ic_launcher.xml"),       background, foreground)?;
-  write_adaptive_icon(&adaptive_path.join("ic_launcher_round.xml"), background, foreground)?;
+  write_adaptive_icon(ctx, &adaptive_path.join("ic_launcher.xml"),       background, foreground)?;
+  write_adaptive_icon(ctx, &adaptive_path.join("ic_launcher_round.xml"), background, foreground)?;
 
   Ok(())
 }
 
-fn write_adaptive_icon(path: &Path, background: &str, foreground: &str) -> IO {
-  let mut f = File::create(path)?;
+fn write_adaptive_icon(ctx: &Context, path: &Path, background: &str, foreground: &str) -> IO {
+  let mut f = create_file(ctx, path)?;
   f.write_all(XML_DECL)?;
 
   write!(f, concat!("<adaptive-icon xmlns:android=\"http://schemas.android.com/apk/res/android\">\n",
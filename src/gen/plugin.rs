@@ -0,0 +1,196 @@
+//! Generators implemented by external commands, declared in the project's
+//! `[plugins.<name>]` tables. Company-internal project formats are added this
+//! way without forking janky.
+//!
+//! The plugin receives the resolved context as a JSON object on its standard
+//! input, along with a `staging_dir` folder to write its files into, using
+//! paths relative to the build folder. It then prints a JSON report on its
+//! standard output and exits with a success status:
+//!
+//! ```json
+//! { "files": ["Game.fbuild", "fbuild/Game.bff"], "warnings": ["..."] }
+//! ```
+//!
+//! Reported files are then committed like the ones of built-in generators:
+//! recorded in the manifest, left untouched when unchanged and only previewed
+//! by dry runs. Settings are given as layers, from the most specific to the
+//! most general, following `Context::get_setting`. The project's `overrides`
+//! from the environment take precedence over every layer. Unset settings are
+//! omitted.
+
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::io::Write;
+use std::path::{Component, Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use crate::ctx::{Architecture, Context, FileInfo, Generator, PlatformType, PluginInfo, Profiles, RunResult,
+                 Settings, StrError};
+use crate::gen::text::TextFile;
+
+/// Version of the protocol, bumped on incompatible changes to the request.
+const PROTOCOL: u32 = 1;
+
+pub struct Plugin {
+  pub name:      &'static str,
+  pub platforms: Vec<PlatformType>
+}
+
+/// Report printed by the plugin once done.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+#[serde(deny_unknown_fields)]
+struct Report {
+  files:    Vec<PathBuf>,
+  warnings: Vec<String>
+}
+
+impl Generator for Plugin {
+  fn supports_platform(&self, p: PlatformType) -> bool {
+    self.platforms.is_empty() || self.platforms.contains(&p)
+  }
+
+  fn run(&self, ctx: &Context) -> RunResult {
+    let info    = &ctx.project.plugins[self.name];
+    let staging = ctx.build_dir.join(".jank-plugins").join(self.name);
+    if staging.exists() {
+      std::fs::remove_dir_all(&staging)?;
+    }
+    std::fs::create_dir_all(&staging)?;
+
+    let input   = serde_json::to_vec(&request(ctx, self.name, info, &staging))?;
+    let program = match info.command.contains('/') || info.command.contains('\\') {
+      true  => ctx.input_dir.join(info.command),
+      false => PathBuf::from(info.command)
+    };
+
+    let mut child = Command::new(&program)
+      .args(&info.args)
+      .current_dir(&ctx.input_dir)
+      .stdin(Stdio::piped())
+      .stdout(Stdio::piped())
+      .spawn()
+      .map_err(|e| StrError(format!("Failed to run plugin {} ({}): {}", self.name, info.command, e)))?;
+
+    // Written from another thread, the plugin may report before reading all of it.
+    let mut stdin = child.stdin.take().unwrap();
+    let writer    = std::thread::spawn(move || stdin.write_all(&input));
+    let output    = child.wait_with_output()?;
+    let _         = writer.join();
+
+    if !output.status.success() {
+      return Err(Box::new(StrError(format!("Plugin {} failed ({})", self.name, output.status))));
+    }
+
+    let report: Report = serde_json::from_slice(&output.stdout)
+      .map_err(|e| StrError(format!("Invalid report from plugin {}: {}", self.name, e)))?;
+
+    for msg in report.warnings {
+      ctx.warn(format!("{}: {}", self.name, msg));
+    }
+
+    for file in &report.files {
+      if !file.components().all(|x| matches!(x, Component::Normal(_))) {
+        return Err(Box::new(StrError(format!("Plugin {} reported a file outside of the build folder: {}",
+                                             self.name, file.display()))));
+      }
+
+      let data = std::fs::read(staging.join(file))
+        .map_err(|e| StrError(format!("Plugin {} reported a missing file ({}): {}",
+                                      self.name, file.display(), e)))?;
+      TextFile::write_raw(ctx, ctx.build_dir.join(file), &data)?;
+    }
+
+    std::fs::remove_dir_all(&staging)?;
+    Ok(())
+  }
+}
+
+fn request(ctx: &Context, name: &str, info: &PluginInfo, staging: &Path) -> Value {
+  let targets = ctx.project.targets.iter().enumerate().map(|(index, (&target_name, target))| json!({
+    "name":          target_name,
+    "type":          format!("{:?}", target.target_type),
+    "platforms":     platform_names(&target.filter.platforms),
+    "architectures": architecture_names(&target.filter.architectures),
+    "sources":       file_paths(&ctx.sources[index]),
+    "resources":     file_paths(&ctx.resources[index]),
+    "assets":        file_paths(&ctx.assets[index]),
+    "depends":       target.depends,
+    "extends":       ctx.extends[index].iter().map(|&i| ctx.get_target_name(i)).collect::<Vec<&str>>(),
+    "settings":      settings(&target.settings),
+    "profiles":      profiles(&target.profiles)
+  })).collect::<Vec<Value>>();
+
+  json!({
+    "protocol":    PROTOCOL,
+    "generator":   name,
+    "input_dir":   ctx.input_dir,
+    "build_dir":   ctx.build_dir,
+    "input_rel":   ctx.input_rel,
+    "staging_dir": staging,
+    "properties":  info.properties,
+    "project": {
+      "name":          ctx.project.name,
+      "version":       ctx.project.version,
+      "description":   ctx.project.description,
+      "platforms":     platform_names(&ctx.project.filter.platforms),
+      "architectures": architecture_names(&ctx.project.filter.architectures),
+      "settings":      settings(&ctx.project.settings),
+      "overrides":     settings(&ctx.project.overrides),
+      "profiles":      profiles(&ctx.project.profiles)
+    },
+    "profiles":    ctx.profiles,
+    "defaults":    profiles(&ctx.defaults),
+    "targets":     targets
+  })
+}
+
+fn platform_names(platforms: &[PlatformType]) -> Vec<&'static str> {
+  platforms.iter().map(|p| p.to_str()).collect()
+}
+
+fn architecture_names(archs: &[Architecture]) -> Vec<String> {
+  archs.iter().map(|a| format!("{:?}", a).to_lowercase()).collect()
+}
+
+fn file_paths(files: &[FileInfo]) -> Vec<&Path> {
+  files.iter().map(|x| x.path.as_path()).collect()
+}
+
+/// Layers of each profile, as scoped to a platform or an architecture.
+fn profiles(profiles: &Profiles) -> Value {
+  profiles.iter().map(|(&name, layers)| {
+    let layers = layers.iter().map(|p| json!({
+      "platform":     match p.platform_type {
+        PlatformType::Any => "any",
+        platform          => platform.to_str()
+      },
+      "architecture": match p.architecture {
+        Architecture::Any => "any".to_string(),
+        arch              => format!("{:?}", arch).to_lowercase()
+      },
+      "settings":     settings(&p.settings)
+    })).collect::<Vec<Value>>();
+    (name.to_string(), Value::Array(layers))
+  }).collect::<serde_json::Map<String, Value>>().into()
+}
+
+fn settings(s: &Settings) -> Value {
+  prune(serde_json::to_value(s).unwrap_or_default())
+}
+
+/// Drops the unset settings, leaving only the ones a layer defines.
+fn prune(value: Value) -> Value {
+  match value {
+    Value::Object(map) => Value::Object(map.into_iter()
+      .map(|(k, v)| (k, prune(v)))
+      .filter(|(_, v)| match v {
+        Value::Null      => false,
+        Value::Array(x)  => !x.is_empty(),
+        Value::Object(x) => !x.is_empty(),
+        _                => true
+      })
+      .collect()),
+    value => value
+  }
+}
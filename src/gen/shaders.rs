@@ -0,0 +1,112 @@
+//! Shader sources compiled by the rules in a target's `[[shaders]]` tables.
+//!
+//! Each rule runs one compiler with the same arguments over its files, for every
+//! platform of the target or only the ones it lists. Generators run the compiler
+//! as a custom build step whenever a shader changes, writing the compiled files
+//! to the `<target>_shaders/<platform>` folder of the build. Compiled files keep
+//! the shader's path and add the rule's extension, ie `shaders/basic.frag` is
+//! compiled to `shaders/basic.frag.spv` by glslang.
+//!
+//! Only the target's own rules are compiled, not the ones of the targets it extends.
+
+use std::path::{Path, PathBuf};
+
+use crate::ctx::{Context, FileInfo, PlatformType, ShaderCompiler, ShaderRule};
+
+/// Shader compiled by a rule, along with its command.
+pub struct Shader<'a> {
+  pub file:    &'a FileInfo,
+  pub rule:    &'a ShaderRule<'a>,
+  pub command: String,
+  pub output:  PathBuf
+}
+
+/// Folder of the shaders compiled for a platform.
+pub fn get_dir(ctx: &Context, index: usize, platform: PlatformType) -> PathBuf {
+  ctx.build_dir.join([ctx.get_target_name(index), "_shaders"].join("")).join(platform.to_str())
+}
+
+/// Shaders compiled by the target on the platform.
+pub fn get_shaders<'a>(ctx: &'a Context, index: usize, platform: PlatformType) -> Vec<Shader<'a>> {
+  let dir = get_dir(ctx, index, platform);
+  ctx.get_target(index).shaders.iter().zip(&ctx.shaders[index])
+    .filter(|(rule, _)| rule.platforms.is_empty() || rule.platforms.contains(&platform))
+    .flat_map(|(rule, files)| {
+      let dir = &dir;
+      files.iter().filter(|x| x.meta.is_file()).map(move |file| get_shader(ctx, dir, rule, file))
+    })
+    .collect()
+}
+
+/// Creates the folders of the compiled shaders, the compilers expect them to exist.
+pub fn create_dirs(ctx: &Context) -> std::io::Result<()> {
+  for index in 0 .. ctx.project.targets.len() {
+    let target = ctx.get_target(index);
+    if target.shaders.is_empty() {
+      continue;
+    }
+
+    let platforms = PlatformType::ALL.iter().cloned()
+      .filter(|&p| ctx.project.filter.matches_platform(p) && target.filter.matches_platform(p));
+    for platform in platforms {
+      for shader in get_shaders(ctx, index, platform) {
+        std::fs::create_dir_all(shader.output.parent().unwrap())?;
+      }
+    }
+  }
+  Ok(())
+}
+
+fn get_shader<'a>(ctx: &Context, dir: &Path, rule: &'a ShaderRule<'a>, file: &'a FileInfo) -> Shader<'a> {
+  // Shaders from source roots outside of the input folder are compiled next to the others.
+  let rel = match file.path.is_absolute() {
+    false => file.path.as_path(),
+    true  => Path::new(file.path.file_name().unwrap())
+  };
+
+  let mut output = dir.join(rel).into_os_string();
+  output.push(".");
+  output.push(rule.extension.unwrap_or_else(|| get_extension(rule)));
+  let output = PathBuf::from(output);
+
+  let program = match rule.program {
+    Some(p) if p.contains('/') || p.contains('\\') => ctx.input_dir.join(p).display().to_string(),
+    Some(p) => p.to_string(),
+    None    => get_program(rule.compiler).to_string()
+  };
+  let args = rule.args.iter().map(|x| format!(" {}", x)).collect::<String>();
+  let input = ctx.input_dir.join(&file.path);
+
+  let command = match rule.compiler {
+    ShaderCompiler::Dxc        =>
+      format!("\"{}\"{} -Fo \"{}\" \"{}\"", program, args, output.display(), input.display()),
+    ShaderCompiler::Glslang    =>
+      format!("\"{}\" -V{} -o \"{}\" \"{}\"", program, args, output.display(), input.display()),
+    ShaderCompiler::SpirvCross =>
+      format!("\"{}\"{} --output \"{}\" \"{}\"", program, args, output.display(), input.display())
+  };
+
+  Shader { file, rule, command, output }
+}
+
+fn get_program(compiler: ShaderCompiler) -> &'static str {
+  match compiler {
+    ShaderCompiler::Dxc        => "dxc",
+    ShaderCompiler::Glslang    => "glslangValidator",
+    ShaderCompiler::SpirvCross => "spirv-cross"
+  }
+}
+
+/// Usual extension of the compiled files, SPIRV-Cross outputs GLSL unless asked for another language.
+fn get_extension(rule: &ShaderRule) -> &'static str {
+  match rule.compiler {
+    ShaderCompiler::Dxc     if rule.args.contains(&"-spirv") => "spv",
+    ShaderCompiler::Dxc                                      => "cso",
+    ShaderCompiler::Glslang                                  => "spv",
+    ShaderCompiler::SpirvCross => match () {
+      _ if rule.args.contains(&"--msl")  => "metal",
+      _ if rule.args.contains(&"--hlsl") => "hlsl",
+      _                                  => "glsl"
+    }
+  }
+}
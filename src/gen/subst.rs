@@ -0,0 +1,57 @@
+//! Substitution variables usable in directory settings and defines.
+//!
+//! Each generator maps them to its native equivalent, so a single setting covers
+//! every profile and architecture. All of them expand to lowercase names:
+//! `${profile}`, `${arch}` and `${platform}`.
+//!
+//! Values known when loading the configuration are expanded in every string
+//! as the files are read instead: `${project.name}`, `${project.version}` and
+//! `${env:NAME}`.
+
+pub struct Vars<'a> {
+  pub profile:  &'a str,
+  pub arch:     &'a str,
+  pub platform: &'a str
+}
+
+impl<'a> Vars<'a> {
+  pub fn expand(&self, s: &str) -> String {
+    s.replace("${profile}",  self.profile)
+     .replace("${arch}",     self.arch)
+     .replace("${platform}", self.platform)
+  }
+}
+
+/// Whether a configuration file uses variables expanded as it is loaded.
+pub fn has_config_vars(s: &str) -> bool {
+  s.contains("${project.") || s.contains("${env:")
+}
+
+/// Expands the load-time variables of a string value, leaving the others to the generators.
+pub fn expand_config(s: &str, name: &str, version: &str) -> Result<String, String> {
+  let mut out  = String::with_capacity(s.len());
+  let mut rest = s;
+
+  while let Some(start) = rest.find("${") {
+    let end = match rest[start ..].find('}') {
+      None    => break,
+      Some(x) => start + x
+    };
+
+    out.push_str(&rest[.. start]);
+    match &rest[start + 2 .. end] {
+      "project.name"    => out.push_str(name),
+      "project.version" => out.push_str(version),
+      var if var.starts_with("env:") => {
+        let value = std::env::var(&var[4 ..])
+          .map_err(|_| format!("Undefined environment variable: {}", &var[4 ..]))?;
+        out.push_str(&value);
+      },
+      _ => out.push_str(&rest[start ..= end])
+    }
+    rest = &rest[end + 1 ..];
+  }
+
+  out.push_str(rest);
+  Ok(out)
+}
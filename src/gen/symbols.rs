@@ -0,0 +1,25 @@
+//! Commands uploading debug symbols to the configured crash reporting service.
+//!
+//! Each generator runs them as a post-build step of its Release builds, with the
+//! paths expressed in its own macros. `janky build --upload-symbols` runs them too.
+
+use crate::ctx::{Context, SymbolService};
+
+/// Profile whose builds upload their symbols.
+pub const PROFILE: &str = "Release";
+
+/// Command uploading the symbols at `file`, found in `dir`. None without a service.
+pub fn upload_command(ctx: &Context, dir: &str, file: &str) -> Option<String> {
+  let s = &ctx.project.symbols;
+  Some(match s.service? {
+    SymbolService::Sentry   =>
+      format!("sentry-cli debug-files upload --org {} --project {} {}", s.org, s.project, file),
+    SymbolService::Crashpad =>
+      format!("symupload {} {}", file, s.url),
+    SymbolService::BugSplat =>
+      format!("symbol-upload -b {} -a {} -v {} -d {}", s.database, s.app, match s.version {
+        "" => ctx.project.version,
+        v  => v
+      }, dir)
+  })
+}
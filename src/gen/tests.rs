@@ -0,0 +1,98 @@
+//! Test targets, the console targets with a `test_framework`.
+//!
+//! They are linked with the framework's library and compiled with a main
+//! running every test case, written along with the project to `<target>_test_main.cpp`
+//! in the build folder. Their sources also get the framework's `JANK_TEST_*`
+//! define. CMake registers them with CTest, discovering their test cases once
+//! they are built, while `janky test` discovers and runs them itself.
+
+use std::io::Write;
+use std::path::PathBuf;
+
+use crate::ctx::{Context, RunResult, TestFramework};
+use crate::gen::text::{self, TextFile};
+
+/// CMake package providing a framework, its imported target, and the module
+/// registering the test cases with CTest.
+pub struct CMakePackage {
+  pub name:     &'static str,
+  pub target:   &'static str,
+  pub module:   &'static str,
+  pub discover: &'static str
+}
+
+pub fn get_main_path(ctx: &Context, index: usize) -> PathBuf {
+  ctx.build_dir.join([ctx.get_target_name(index), "_test_main.cpp"].join(""))
+}
+
+pub fn get_define(framework: TestFramework) -> &'static str {
+  match framework {
+    TestFramework::GTest   => "JANK_TEST_GTEST",
+    TestFramework::Catch2  => "JANK_TEST_CATCH2",
+    TestFramework::Doctest => "JANK_TEST_DOCTEST"
+  }
+}
+
+/// Libraries linked by name, for generators without packages.
+pub fn get_libraries(framework: TestFramework) -> &'static [&'static str] {
+  match framework {
+    TestFramework::GTest   => &["gtest"],
+    TestFramework::Catch2  => &["Catch2"],
+    TestFramework::Doctest => &[]
+  }
+}
+
+pub fn get_cmake_package(framework: TestFramework) -> CMakePackage {
+  match framework {
+    TestFramework::GTest   => CMakePackage {
+      name:     "GTest",
+      target:   "GTest::gtest",
+      module:   "GoogleTest",
+      discover: "gtest_discover_tests"
+    },
+    TestFramework::Catch2  => CMakePackage {
+      name:     "Catch2",
+      target:   "Catch2::Catch2",
+      module:   "${Catch2_DIR}/Catch.cmake",
+      discover: "catch_discover_tests"
+    },
+    TestFramework::Doctest => CMakePackage {
+      name:     "doctest",
+      target:   "doctest::doctest",
+      module:   "${doctest_DIR}/doctest.cmake",
+      discover: "doctest_discover_tests"
+    }
+  }
+}
+
+/// Writes the main of every test target.
+pub fn write_mains(ctx: &Context) -> RunResult {
+  for (index, target) in ctx.project.targets.values().enumerate() {
+    let framework = match target.test_framework {
+      None    => continue,
+      Some(x) => x
+    };
+
+    let mut f = TextFile::create(ctx, "tests", text::LF, get_main_path(ctx, index))?;
+    f.write_all(b"// Generated by janky, do not edit.\n")?;
+    f.write_all(match framework {
+      TestFramework::GTest   => concat!("#include <gtest/gtest.h>\n\n",
+                                        "int main(int argc, char** argv) {\n",
+                                        "  ::testing::InitGoogleTest(&argc, argv);\n",
+                                        "  return RUN_ALL_TESTS();\n",
+                                        "}\n"),
+      TestFramework::Catch2  => concat!("#include <catch2/catch_session.hpp>\n\n",
+                                        "int main(int argc, char** argv) {\n",
+                                        "  return Catch::Session().run(argc, argv);\n",
+                                        "}\n"),
+      TestFramework::Doctest => concat!("#define DOCTEST_CONFIG_IMPLEMENT\n",
+                                        "#include <doctest/doctest.h>\n\n",
+                                        "int main(int argc, char** argv) {\n",
+                                        "  doctest::Context context(argc, argv);\n",
+                                        "  return context.run();\n",
+                                        "}\n")
+    }.as_bytes())?;
+    f.flush()?;
+  }
+  Ok(())
+}
@@ -0,0 +1,470 @@
+//! Shared writer for generated text files.
+//!
+//! Generators write their files using '\n' or "\r\n" line endings. Every
+//! line ending is normalized here to the one configured for the generator in
+//! the project's `[project.text.<generator>]` table, falling back to the
+//! defaults provided by the generator for each file.
+//!
+//! The defaults also describe the indentation each file is written with. When
+//! the project configures a different indent style or size, the leading
+//! indentation of every line is converted level by level.
+//!
+//! The hash of every written file is recorded in the context's manifest. Files
+//! whose current content no longer matches their recorded hash were modified
+//! since they were generated, and are only overwritten when forced to.
+//!
+//! Files are written in memory first, then compared with the ones on disk.
+//! Unchanged files are left untouched, keeping their modification time so IDEs
+//! don't reload them and build systems don't rerun. Changed files go through a
+//! temporary file, renamed over the old one once complete. Dry runs only
+//! report the files which would change, along with their diff when asked for.
+//! With `--message-format json`, both writes and previews are `file` events.
+
+use std::io::{Error, ErrorKind, Result as IOResult, Write};
+use std::path::{Path, PathBuf};
+
+use crate::ctx::{Context, IndentStyle, LineEnding, Log, Manifest, OutputMode, TextFormat,
+                 HASH_INIT, hash_bytes};
+
+pub const LF: TextFormat = TextFormat {
+  line_endings: Some(LineEnding::LF),
+  bom:          Some(false),
+  indent_style: Some(IndentStyle::Space),
+  indent_size:  Some(2)
+};
+
+pub const CRLF: TextFormat = TextFormat {
+  line_endings: Some(LineEnding::CRLF),
+  bom:          Some(false),
+  indent_style: Some(IndentStyle::Space),
+  indent_size:  Some(2)
+};
+
+/// Conversion from the indentation a file is written with to the configured one.
+struct Reindent {
+  from:  u8,
+  width: usize,
+  to:    Vec<u8>
+}
+
+/// Only written by `flush`, files dropped before, ie on errors, leave the old one in place.
+pub struct TextFile {
+  path:       PathBuf,
+  data:       Vec<u8>,
+  dirty:      bool,
+  log:        Log,
+  output:     OutputMode,
+  eol:        &'static [u8],
+  key:        PathBuf,
+  hash:       u64,
+  manifest:   Manifest,
+  indent:     Option<Reindent>,
+  line_start: bool,
+  pending:    usize
+}
+
+fn indent_unit(format: &TextFormat) -> (u8, usize) {
+  match format.indent_style.unwrap_or(IndentStyle::Space) {
+    IndentStyle::Tab   => (b'\t', 1),
+    IndentStyle::Space => (b' ', format.indent_size.unwrap_or(2).max(1) as usize)
+  }
+}
+
+impl TextFile {
+  pub fn create<P>(ctx: &Context, generator: &str, defaults: TextFormat,
+                   path: P) -> IOResult<Self> where P: AsRef<Path>
+  {
+    let path = path.as_ref();
+    let key  = path.strip_prefix(&ctx.build_dir).unwrap_or(path).to_path_buf();
+
+    if let Some(hash) = ctx.manifest.get(&key) {
+      if let Ok(bytes) = std::fs::read(path) {
+        if hash_bytes(HASH_INIT, &bytes) != hash && !ctx.args.is_present("force") {
+          return Err(Error::new(ErrorKind::Other, format!(
+            "{} was modified since it was generated, use --force to overwrite it",
+            path.display())));
+        }
+      }
+    }
+
+    let format = match ctx.project.text.get(generator) {
+      None    => defaults,
+      Some(x) => x.or(defaults)
+    };
+
+    TextFile::new(path.to_path_buf(), key, ctx.log, ctx.output, ctx.manifest.clone(), defaults, format)
+  }
+
+  /// Converts the text written with the defaults to the configured format.
+  fn new(path: PathBuf, key: PathBuf, log: Log, output: OutputMode, manifest: Manifest,
+         defaults: TextFormat, format: TextFormat) -> IOResult<Self>
+  {
+    let (from, width) = indent_unit(&defaults);
+    let (to,   size)  = indent_unit(&format);
+
+    let mut file = TextFile {
+      path,
+      data:       Vec::new(),
+      dirty:      true,
+      log,
+      output,
+      eol:        match format.line_endings.unwrap_or(LineEnding::LF) {
+        LineEnding::LF   => b"\n",
+        LineEnding::CRLF => b"\r\n"
+      },
+      key,
+      hash:       HASH_INIT,
+      manifest,
+      indent:     match (from, width) == (to, size) {
+        true  => None,
+        false => Some(Reindent { from, width, to: vec![to; size] })
+      },
+      line_start: true,
+      pending:    0
+    };
+
+    if format.bom.unwrap_or(false) {
+      file.put(b"\xEF\xBB\xBF")?;
+    }
+
+    Ok(file)
+  }
+
+  fn put(&mut self, bytes: &[u8]) -> IOResult<()> {
+    self.hash  = hash_bytes(self.hash, bytes);
+    self.dirty = true;
+    self.data.extend_from_slice(bytes);
+    Ok(())
+  }
+
+  /// Writes the content to disk, unless the file already has it.
+  fn commit(&mut self) -> IOResult<()> {
+    self.manifest.insert(self.key.clone(), self.hash);
+    if !self.dirty {
+      return Ok(());
+    }
+    self.dirty = false;
+
+    let old = std::fs::read(&self.path).ok();
+    if old.as_ref().map_or(false, |x| *x == self.data) {
+      self.log.trace(format!("Unchanged {}", self.key.display()));
+      return Ok(());
+    }
+
+    if self.output != OutputMode::Write {
+      preview(self.log, self.output, &self.key, old.as_deref(), Some(&self.data));
+      return Ok(());
+    }
+
+    self.log.verbose(format!("Writing {}", self.key.display()));
+    let mut tmp = self.path.clone().into_os_string();
+    tmp.push(".jank-tmp");
+    std::fs::write(&tmp, &self.data)?;
+    std::fs::rename(&tmp, &self.path)?;
+    report_write(self.log, &self.key, old.as_deref(), Some(&self.data));
+    Ok(())
+  }
+
+  /// Writes a file generated elsewhere, keeping its bytes as they are. No text
+  /// format is configured for the unnamed generator.
+  pub fn write_raw<P>(ctx: &Context, path: P, data: &[u8]) -> IOResult<()> where P: AsRef<Path> {
+    let mut file = TextFile::create(ctx, "", LF, path)?;
+    file.put(data)?;
+    file.commit()
+  }
+
+  /// Marks the flushed file as executable, previews leave it untouched.
+  #[cfg(unix)]
+  pub fn set_executable(&self) -> IOResult<()> {
+    use std::os::unix::fs::PermissionsExt;
+    match self.output {
+      OutputMode::Write => std::fs::set_permissions(&self.path, std::fs::Permissions::from_mode(0o755)),
+      _                 => Ok(())
+    }
+  }
+
+  /// Emits the indentation collected at the start of the current line.
+  fn put_indent(&mut self) -> IOResult<()> {
+    // Spaces left over from an incomplete level are kept as they are.
+    let (levels, rest) = match &self.indent {
+      None    => return Ok(()),
+      Some(x) => (x.to.repeat(self.pending / x.width), b" ".repeat(self.pending % x.width))
+    };
+    self.pending = 0;
+    self.put(&levels)?;
+    self.put(&rest)
+  }
+}
+
+impl Write for TextFile {
+  fn write(&mut self, buf: &[u8]) -> IOResult<usize> {
+    // Generated text never contains lone carriage returns, drop them all.
+    let mut start = 0;
+    for (i, &c) in buf.iter().enumerate() {
+      if self.line_start && c != b'\r' && c != b'\n' {
+        if let Some(from) = self.indent.as_ref().map(|x| x.from) {
+          if c == from {
+            self.pending += 1;
+            start = i + 1;
+            continue;
+          }
+          self.put_indent()?;
+        }
+        self.line_start = false;
+      }
+
+      match c {
+        b'\r' => {
+          self.put(&buf[start .. i])?;
+          start = i + 1;
+        },
+        b'\n' => {
+          self.put(&buf[start .. i])?;
+          let eol = self.eol;
+          self.put(eol)?;
+          start = i + 1;
+          self.line_start = true;
+          self.pending    = 0;
+        },
+        _ => {}
+      }
+    }
+
+    self.put(&buf[start ..])?;
+    Ok(buf.len())
+  }
+
+  fn flush(&mut self) -> IOResult<()> {
+    self.commit()
+  }
+}
+
+
+// Previews
+// -----------------------------------------------------------------------------
+
+/// Lines of context around each change in diffs.
+const CONTEXT: usize = 3;
+
+/// Largest number of line pairs compared between the common prefix and suffix
+/// of two files. Larger changes are shown as a single block.
+const MAX_DIFF_CELLS: usize = 1 << 22;
+
+/// Reports a change instead of making it. Contents are `None` for files which
+/// don't exist, either before or after the change.
+pub fn preview(log: Log, output: OutputMode, key: &Path, old: Option<&[u8]>, new: Option<&[u8]>) {
+  if log.is_json() {
+    log.event("file", serde_json::json!({
+      "path":    key,
+      "action":  action(old, new),
+      "written": false,
+      "diff":    match output {
+        OutputMode::Diff => Some(unified_diff(key, old, new)),
+        _                => None
+      }
+    }));
+    return;
+  }
+
+  match output {
+    OutputMode::Write  => {},
+    OutputMode::DryRun => println!("{} {}", action(old, new), key.display()),
+    OutputMode::Diff   => print!("{}", unified_diff(key, old, new))
+  }
+}
+
+/// Reports a change once made, only as a JSON event.
+pub fn report_write(log: Log, key: &Path, old: Option<&[u8]>, new: Option<&[u8]>) {
+  log.event("file", serde_json::json!({ "path": key, "action": action(old, new), "written": true }));
+}
+
+fn action(old: Option<&[u8]>, new: Option<&[u8]>) -> &'static str {
+  match (old, new) {
+    (None, _) => "create",
+    (_, None) => "delete",
+    (_, _)    => "modify"
+  }
+}
+
+fn unified_diff(key: &Path, old: Option<&[u8]>, new: Option<&[u8]>) -> String {
+  let name = |x: Option<&[u8]>| match x {
+    None    => "/dev/null".to_string(),
+    Some(_) => key.display().to_string()
+  };
+  let mut s = format!("--- {}\n+++ {}\n", name(old), name(new));
+
+  let (old, new) = (old.unwrap_or(b""), new.unwrap_or(b""));
+  if old.contains(&0) || new.contains(&0) {
+    s.push_str("Binary files differ\n");
+    return s;
+  }
+
+  let old = String::from_utf8_lossy(old);
+  let new = String::from_utf8_lossy(new);
+  let ops = diff_lines(&old.split_terminator('\n').collect::<Vec<&str>>(),
+                       &new.split_terminator('\n').collect::<Vec<&str>>());
+
+  let changes = ops.iter().enumerate()
+    .filter(|x| (x.1).0 != ' ')
+    .map(|x| x.0)
+    .collect::<Vec<usize>>();
+
+  // Changes closer than twice the context share the same hunk.
+  let mut k = 0;
+  while k < changes.len() {
+    let start = changes[k].saturating_sub(CONTEXT);
+    let mut end = changes[k];
+    while k < changes.len() && changes[k] <= end + 2 * CONTEXT {
+      end = changes[k];
+      k  += 1;
+    }
+    let end  = std::cmp::min(end + CONTEXT + 1, ops.len());
+    let hunk = &ops[start .. end];
+
+    let count = |ops: &[(char, &str)], skip| ops.iter().filter(|x| x.0 != skip).count();
+    s.push_str(&format!("@@ -{} +{} @@\n",
+                        hunk_range(count(&ops[.. start], '+'), count(hunk, '+')),
+                        hunk_range(count(&ops[.. start], '-'), count(hunk, '-'))));
+
+    for (op, line) in hunk {
+      s.push(*op);
+      s.push_str(line);
+      s.push('\n');
+    }
+  }
+
+  s
+}
+
+/// Starts at 1, empty ranges start at the line preceding them instead.
+fn hunk_range(start: usize, len: usize) -> String {
+  format!("{},{}", if len == 0 { start } else { start + 1 }, len)
+}
+
+/// Line by line edit script, tagging lines with ' ', '-' or '+'. Only the lines
+/// between the common prefix and suffix are compared, using their longest
+/// common subsequence.
+fn diff_lines<'s>(a: &[&'s str], b: &[&'s str]) -> Vec<(char, &'s str)> {
+  let pre = a.iter().zip(b).take_while(|(x, y)| x == y).count();
+  let suf = a[pre ..].iter().rev().zip(b[pre ..].iter().rev()).take_while(|(x, y)| x == y).count();
+  let ma  = &a[pre .. a.len() - suf];
+  let mb  = &b[pre .. b.len() - suf];
+
+  let mut ops = a[.. pre].iter().map(|&x| (' ', x)).collect::<Vec<(char, &str)>>();
+
+  if ma.len() * mb.len() > MAX_DIFF_CELLS {
+    ops.extend(ma.iter().map(|&x| ('-', x)));
+    ops.extend(mb.iter().map(|&x| ('+', x)));
+  }
+  else {
+    // Length of the longest common subsequence of every pair of suffixes.
+    let w = mb.len() + 1;
+    let mut lcs = vec![0u32; (ma.len() + 1) * w];
+    for i in (0 .. ma.len()).rev() {
+      for j in (0 .. mb.len()).rev() {
+        lcs[i * w + j] = match ma[i] == mb[j] {
+          true  => lcs[(i + 1) * w + j + 1] + 1,
+          false => std::cmp::max(lcs[(i + 1) * w + j], lcs[i * w + j + 1])
+        };
+      }
+    }
+
+    let (mut i, mut j) = (0, 0);
+    while i < ma.len() || j < mb.len() {
+      if i < ma.len() && j < mb.len() && ma[i] == mb[j] {
+        ops.push((' ', ma[i]));
+        i += 1;
+        j += 1;
+      }
+      else if j == mb.len() || (i < ma.len() && lcs[(i + 1) * w + j] >= lcs[i * w + j + 1]) {
+        ops.push(('-', ma[i]));
+        i += 1;
+      }
+      else {
+        ops.push(('+', mb[j]));
+        j += 1;
+      }
+    }
+  }
+
+  ops.extend(a[a.len() - suf ..].iter().map(|&x| (' ', x)));
+  ops
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::ctx::MessageFormat;
+
+  const TABS: TextFormat = TextFormat {
+    line_endings: Some(LineEnding::CRLF),
+    bom:          Some(true),
+    indent_style: Some(IndentStyle::Tab),
+    indent_size:  Some(1)
+  };
+
+  /// Text written with the defaults, as converted to the format. Nothing is flushed to disk.
+  fn convert(defaults: TextFormat, format: TextFormat, chunks: &[&str]) -> Vec<u8> {
+    let log      = Log::new(true, 0, MessageFormat::Human);
+    let mut file = TextFile::new(PathBuf::from("test.txt"), PathBuf::from("test.txt"), log,
+                                 OutputMode::DryRun, Manifest::default(), defaults, format).unwrap();
+    for chunk in chunks {
+      file.write_all(chunk.as_bytes()).unwrap();
+    }
+    file.data
+  }
+
+  #[test]
+  fn line_endings() {
+    assert_eq!(convert(LF, LF, &["a\r\nb\nc"]), b"a\nb\nc");
+    assert_eq!(convert(LF, CRLF, &["a\nb\r\n"]), b"a\r\nb\r\n");
+    assert_eq!(convert(CRLF, LF, &["a\r", "\nb\r\n"]), b"a\nb\n");
+  }
+
+  #[test]
+  fn bom() {
+    let format = TextFormat { bom: Some(true), ..LF };
+    assert_eq!(convert(LF, format, &["a\n"]), b"\xEF\xBB\xBFa\n");
+    assert_eq!(convert(format, LF, &["a\n"]), b"a\n");
+  }
+
+  #[test]
+  fn reindent() {
+    let tabs = TextFormat { line_endings: Some(LineEnding::LF), bom: Some(false), ..TABS };
+    let four = TextFormat { indent_size: Some(4), ..LF };
+    assert_eq!(convert(LF, tabs, &["a\n  b\n    c\n   d  e\n"]), b"a\n\tb\n\t\tc\n\t d  e\n");
+    assert_eq!(convert(LF, four, &["  a\n\n", "    b\n"]), b"    a\n\n        b\n");
+    assert_eq!(convert(tabs, LF, &["\t", "\ta\tb\n"]), b"    a\tb\n");
+  }
+
+  #[test]
+  fn round_trip() {
+    let text = "[a]\n  b = 1\n\n  [c]\n    d = 2\n";
+    let tabs = convert(LF, TABS, &[text]);
+    assert_eq!(tabs, b"\xEF\xBB\xBF[a]\r\n\tb = 1\r\n\r\n\t[c]\r\n\t\td = 2\r\n".to_vec());
+
+    let tabs = String::from_utf8(tabs[3 ..].to_vec()).unwrap();
+    let back = convert(TABS, LF, &[&tabs]);
+    assert_eq!(back, text.as_bytes());
+  }
+
+  #[test]
+  fn diff() {
+    let old = b"1\n2\n3\n4\n5\n6\n7\n8\n9\n10\n11\n12\n";
+    let new = b"1\n2\nthree\n4\n5\n6\n7\n8\n9\n10\n12\n13\n";
+    assert_eq!(unified_diff(Path::new("a.txt"), Some(old), Some(new)), concat!(
+      "--- a.txt\n+++ a.txt\n",
+      "@@ -1,6 +1,6 @@\n 1\n 2\n-3\n+three\n 4\n 5\n 6\n",
+      "@@ -8,5 +8,5 @@\n 8\n 9\n 10\n-11\n 12\n+13\n"));
+  }
+
+  #[test]
+  fn diff_created_and_binary() {
+    assert_eq!(unified_diff(Path::new("a.txt"), None, Some(b"x\ny\n")),
+               "--- /dev/null\n+++ a.txt\n@@ -0,0 +1,2 @@\n+x\n+y\n");
+    assert_eq!(unified_diff(Path::new("a.bin"), Some(b"\0"), Some(b"\x01")),
+               "--- a.bin\n+++ a.bin\nBinary files differ\n");
+    assert_eq!(unified_diff(Path::new("a.txt"), Some(b"x\n"), Some(b"x\n")), "--- a.txt\n+++ a.txt\n");
+  }
+}
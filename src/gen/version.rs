@@ -0,0 +1,133 @@
+//! Header embedding the project's version into its sources, configured by the
+//! project's `[version_header]` table.
+//!
+//! The header is written along with the project files, then again by each
+//! generator's pre-build step with the profile being built and the current git
+//! commit. It is only rewritten when its content changes, leaving the sources
+//! including it alone otherwise. The default layout defines, for a `GAME` prefix:
+//!
+//! ```c
+//! #define GAME_NAME "Game"
+//! #define GAME_VERSION "1.2.0-beta.1"
+//! #define GAME_VERSION_MAJOR 1
+//! #define GAME_VERSION_MINOR 2
+//! #define GAME_VERSION_PATCH 0
+//! #define GAME_VERSION_PRE "beta.1"
+//! #define GAME_GIT_HASH "4f2c1a9e07d3"
+//! #define GAME_PROFILE "Debug"
+//! #define GAME_PROFILE_DEBUG 1
+//! ```
+//!
+//! Templates expand the `${name}`, `${version}`, `${version.major}`,
+//! `${version.minor}`, `${version.patch}`, `${version.pre}`, `${git.hash}`,
+//! `${profile}` and `${prefix}` variables instead.
+
+use semver::Version;
+use std::path::{Path, PathBuf};
+
+use crate::ctx::{Context, DynResult, RunResult, StrError};
+use crate::gen::{janky_command, update_file};
+
+/// File written when the project does not name one, relative to the input folder.
+pub const DEFAULT_PATH: &str = "janky_version.h";
+
+/// Where the header is written, none when the project has no `[version_header]`.
+pub fn get_path(ctx: &Context) -> Option<PathBuf> {
+  ctx.project.version_header.as_ref().map(|x| ctx.input_dir.join(match x.path {
+    "" => DEFAULT_PATH,
+    p  => p
+  }))
+}
+
+/// Command regenerating the header before a build of `profile`, expressed in the
+/// generator's own macros. None without a version header.
+pub fn command(ctx: &Context, profile: &str) -> Option<String> {
+  get_path(ctx)?;
+  Some(janky_command(ctx, &format!("version-header --profile {}", profile)))
+}
+
+/// Writes the header for the given profile, leaving it untouched when unchanged.
+/// Profile macros are omitted without a profile, ie when generating the project.
+pub fn write(ctx: &Context, profile: Option<&str>) -> RunResult {
+  match get_path(ctx) {
+    None       => Ok(()),
+    Some(path) => Ok(update_file(ctx, &path, contents(ctx, profile)?.as_bytes())?)
+  }
+}
+
+fn contents(ctx: &Context, profile: Option<&str>) -> DynResult<String> {
+  let info    = ctx.project.version_header.as_ref().unwrap();
+  let version = Version::parse(ctx.project.version)
+    .map_err(|e| StrError(format!("The version header needs a semantic version ({}): {}",
+                                  ctx.project.version, e)))?;
+  let pre     = version.pre.iter().map(|x| x.to_string()).collect::<Vec<String>>().join(".");
+  let hash    = git_hash(&ctx.input_dir);
+  let prefix  = match info.prefix {
+    "" => get_prefix(ctx.project.name),
+    p  => p.to_string()
+  };
+
+  if let Some(template) = info.template {
+    let path = ctx.input_dir.join(template);
+    let text = std::fs::read_to_string(&path)
+      .map_err(|e| StrError(format!("Failed to read version header template ({:?}): {}", path, e)))?;
+    return Ok(text
+      .replace("${name}",          ctx.project.name)
+      .replace("${version}",       ctx.project.version)
+      .replace("${version.major}", &version.major.to_string())
+      .replace("${version.minor}", &version.minor.to_string())
+      .replace("${version.patch}", &version.patch.to_string())
+      .replace("${version.pre}",   &pre)
+      .replace("${git.hash}",      &hash)
+      .replace("${profile}",       profile.unwrap_or(""))
+      .replace("${prefix}",        &prefix));
+  }
+
+  let mut s = format!(concat!("// Generated by janky, do not edit.\n",
+                              "#pragma once\n\n",
+                              "#define {p}_NAME \"{name}\"\n",
+                              "#define {p}_VERSION \"{version}\"\n",
+                              "#define {p}_VERSION_MAJOR {major}\n",
+                              "#define {p}_VERSION_MINOR {minor}\n",
+                              "#define {p}_VERSION_PATCH {patch}\n",
+                              "#define {p}_VERSION_PRE \"{pre}\"\n",
+                              "#define {p}_GIT_HASH \"{hash}\"\n"),
+                      p       = prefix,
+                      name    = ctx.project.name,
+                      version = ctx.project.version,
+                      major   = version.major,
+                      minor   = version.minor,
+                      patch   = version.patch,
+                      pre     = pre,
+                      hash    = hash);
+
+  if let Some(profile) = profile {
+    s.push_str(&format!(concat!("\n#define {p}_PROFILE \"{name}\"\n",
+                                "#define {p}_PROFILE_{upper} 1\n"),
+                        p     = prefix,
+                        name  = profile,
+                        upper = get_prefix(profile)));
+  }
+
+  Ok(s)
+}
+
+/// Macro prefix derived from a name, ie "my-game" is MY_GAME.
+fn get_prefix(name: &str) -> String {
+  name.chars().map(|c| match c.is_ascii_alphanumeric() {
+    true  => c.to_ascii_uppercase(),
+    false => '_'
+  }).collect()
+}
+
+/// Abbreviated hash of the checked out commit, "unknown" outside of git repositories.
+fn git_hash(dir: &Path) -> String {
+  std::process::Command::new("git")
+    .args(&["rev-parse", "--short=12", "HEAD"])
+    .current_dir(dir)
+    .output()
+    .ok()
+    .filter(|x| x.status.success())
+    .map(|x| String::from_utf8_lossy(&x.stdout).trim().to_string())
+    .unwrap_or_else(|| "unknown".to_string())
+}
@@ -1,11 +1,16 @@
 use std::collections::HashSet;
-use std::fs::File;
-use std::io::{BufWriter, Result as IOResult, Write};
+use std::fmt::Write as FmtWrite;
+use std::io::{Result as IOResult, Write};
 use std::path::Path;
-use uuid::Uuid;
 
-use crate::ctx::{Architecture, Context, Generator, FileInfo,
-                 PlatformType, RunResult, Target, TargetFiles, TargetType};
+use crate::ctx::{AndroidActivity, Architecture, CharacterSet, Context, DebugInfo, Generator, FileInfo,
+                 FileSettings, FloatModel, Lto, MsvcRuntime, Optimize, PlatformType, RunResult, Settings,
+                 Simd, StrError, Target, TargetFiles, TargetType, TextFormat, Toolset};
+use super::{copy_file, flags, get_resources, join_prefix, stable_uuid};
+use super::subst::Vars;
+use super::symbols;
+use super::text::{self, TextFile};
+use super::{benchmarks, codegen, embed, shaders, tests, version};
 
 pub struct VisualStudio;
 
@@ -25,21 +30,86 @@ impl Generator for VisualStudio {
 
     projs.push(Proj {
       kind:   ProjKind::Items,
-      uuid:   random_uuid(),
-      name:   ctx.project.name,
+      uuid:   stable_uuid(&[ctx.project.name, ".vcxitems"]),
+      name:   ctx.project.name.to_string(),
+      index:  0,
       target: None
     });
 
-    projs.extend(ctx.project.targets.iter().map(|(name, target)| { Proj {
+    projs.extend(ctx.project.targets.iter().enumerate().map(|(index, (name, target))| { Proj {
       kind:   ProjKind::CXX,
-      uuid:   random_uuid(),
-      name:   name,
+      uuid:   stable_uuid(&[ctx.project.name, *name, ".vcxproj"]),
+      name:   name.to_string(),
+      index,
       target: Some(target)
     }}));
 
-    for (i, proj) in projs.iter().skip(1).enumerate() {
-      write_proj     (ctx, i, proj, &tools)?;
-      write_filters  (ctx, i, proj)?;
+    if ctx.project.filter.matches_platform(PlatformType::Android) {
+      for (index, (name, target)) in ctx.project.targets.iter().enumerate() {
+        if !target.filter.matches_platform(PlatformType::Android) {
+          ctx.log.verbose(format!("Visual Studio: target {} is not built for Android", name));
+          continue;
+        }
+        if flags::android_architectures(ctx, target).is_empty() {
+          ctx.log.verbose(format!("Visual Studio: target {} has no Android architecture", name));
+          continue;
+        }
+
+        match target.target_type {
+          TargetType::Application   |
+          TargetType::SharedLibrary |
+          TargetType::StaticLibrary => {},
+          _                         => continue
+        }
+
+        let native = [*name, "_Android"].join("");
+
+        if target.target_type == TargetType::Application {
+          // Ant packaging has no dependencies to pull the GameActivity classes from.
+          if flags::android_activity(ctx, target) == AndroidActivity::Game {
+            return Err(Box::new(StrError(format!(
+              "Visual Studio: GameActivity of target {} is not supported, use NativeActivity", name))));
+          }
+
+          projs.push(Proj {
+            kind:   ProjKind::Android,
+            uuid:   stable_uuid(&[ctx.project.name, native.as_str(), ".androidproj"]),
+            name:   [native.as_str(), "_Packaging"].join(""),
+            index,
+            target: Some(target)
+          });
+        }
+
+        projs.push(Proj {
+          kind:   ProjKind::AndroidCXX,
+          uuid:   stable_uuid(&[ctx.project.name, native.as_str(), ".vcxproj"]),
+          name:   native,
+          index,
+          target: Some(target)
+        });
+      }
+    }
+
+    write_props(ctx)?;
+
+    for proj in projs.iter().skip(1) {
+      match proj.kind {
+        ProjKind::CXX => {
+          write_proj   (ctx, proj, &tools)?;
+          write_filters(ctx, proj)?;
+        },
+        ProjKind::AndroidCXX => {
+          write_android_proj(ctx, proj, &tools)?;
+          write_filters     (ctx, proj)?;
+        },
+        ProjKind::Android => {
+          let native = projs.iter()
+            .find(|x| x.kind == ProjKind::AndroidCXX && x.index == proj.index)
+            .unwrap();
+          write_packaging_proj(ctx, proj, native)?;
+        },
+        ProjKind::Items => unreachable!()
+      }
     }
 
     write_items(ctx, &projs[0])?;
@@ -50,9 +120,24 @@ impl Generator for VisualStudio {
 
 type IO = IOResult<()>;
 
+/// Disabled in every project, they are mostly noise under EnableAllWarnings.
 const DISABLE_WARNINGS: &str =
   "4324;4514;4571;4623;4625;4626;4710;4711;4820;5026;5027;5045;6031;6387;26444;26812";
 
+const IS_CLANG_CL: &str = "'$(PlatformToolset)'=='ClangCL'";
+
+const WINDOWS_VARS: Vars = Vars {
+  profile:  "$(Configuration.ToLowerInvariant())",
+  arch:     "$(PlatformTarget.ToLowerInvariant())",
+  platform: "windows"
+};
+
+const ANDROID_VARS: Vars = Vars {
+  profile:  "$(Configuration.ToLowerInvariant())",
+  arch:     "$(Platform.ToLowerInvariant())",
+  platform: "android"
+};
+
 const ARCHITECTURES: &[Architecture] = &[ // TODO derive from project
   // Architecture::ARM, // TODO only when using the android toolchain
   // Architecture::ARM64,
@@ -61,6 +146,8 @@ const ARCHITECTURES: &[Architecture] = &[ // TODO derive from project
   Architecture::X64
 ];
 
+const ANDROID_API_LEVEL: u8 = 26;
+
 #[derive(Clone, Copy)]
 enum Version {
   VS2015,
@@ -94,58 +181,91 @@ impl Tools {
 
 #[derive(PartialEq)]
 enum ProjKind {
+  /// Android packaging project, builds the APK from an AndroidCXX project.
   Android,
+  /// Android native code project, builds a shared library using Clang.
+  AndroidCXX,
+  /// Windows native code project.
   CXX,
+  /// Shared items project, lists the project's metafiles.
   Items
 }
 
 struct Proj<'a> {
   kind:   ProjKind,
   uuid:   String,
-  name:   &'a str,
+  name:   String,
+  index:  usize, // Target index, unused for Items projects.
   target: Option<&'a Target<'a>>
 }
 
 impl<'a> Proj<'a> {
   fn ext(&self) -> &'static str {
     match self.kind {
-      ProjKind::Android => "androidproj",
-      ProjKind::CXX     => "vcxproj",
-      ProjKind::Items   => "vcxitems"
+      ProjKind::Android    => "androidproj",
+      ProjKind::AndroidCXX |
+      ProjKind::CXX        => "vcxproj",
+      ProjKind::Items      => "vcxitems"
     }
   }
 
-  fn create(&self, base: &Path, ext: &str) -> IOResult<BufWriter<File>> {
-    let mut path = base.join(self.name);
+  fn create(&self, ctx: &Context, ext: &str) -> IOResult<TextFile> {
+    let mut path = ctx.build_dir.join(&self.name);
     path.set_extension(ext);
-
-    let mut f = BufWriter::new(File::create(&path)?);
-    f.write_all(concat!(
-      "<?xml version=\"1.0\" encoding=\"utf-8\"?>\r\n",
-      "<Project xmlns=\"http://schemas.microsoft.com/developer/msbuild/2003\">\r\n"
-    ).as_bytes())?;
-
-    Ok(f)
+    create_msbuild_file(ctx, path)
   }
 
   fn get_kind_guid(&self) -> &str {
     // TODO use solution folders? GUID = "2150E333-8FDC-42A3-9474-1A3956D46DE8"
     match self.kind {
-      ProjKind::Android => "39E2626F-3545-4960-A6E8-258AD8476CE5",
-      ProjKind::Items   |
-      ProjKind::CXX     => "8BC9CEB8-8B4A-11D0-8D11-00A0C91BC942"
+      ProjKind::Android    => "39E2626F-3545-4960-A6E8-258AD8476CE5",
+      ProjKind::AndroidCXX |
+      ProjKind::Items      |
+      ProjKind::CXX        => "8BC9CEB8-8B4A-11D0-8D11-00A0C91BC942"
     }
   }
 
-  fn get_platform_toolset(&self, v: Version) -> &'static str {
+  fn is_clang_cl(&self, ctx: &Context) -> bool {
+    self.kind == ProjKind::CXX && self.target.map_or(false, |t| {
+      t.settings.toolset.or(ctx.project.settings.toolset) == Some(Toolset::LLVM)
+    })
+  }
+
+  fn get_platform_toolset(&self, ctx: &Context, v: Version) -> &'static str {
     match self.kind {
-      ProjKind::Android => "Clang_5_0",
-      ProjKind::CXX     => match v {
+      ProjKind::AndroidCXX => "Clang_5_0",
+      ProjKind::CXX if self.is_clang_cl(ctx) => "ClangCL",
+      ProjKind::CXX        => match v {
         Version::VS2015 => "", // TODO
         Version::VS2017 => "v141",
         Version::VS2019 => "v142"
       },
-      ProjKind::Items   => unreachable!()
+      ProjKind::Android    |
+      ProjKind::Items      => unreachable!()
+    }
+  }
+
+  fn get_platform(&self) -> PlatformType {
+    match self.kind {
+      ProjKind::Android    |
+      ProjKind::AndroidCXX => PlatformType::Android,
+      ProjKind::CXX        |
+      ProjKind::Items      => PlatformType::Windows
+    }
+  }
+
+  /// Android architectures follow the project and target filters, like Gradle's ABI filters.
+  fn get_architectures(&self, ctx: &Context) -> Vec<Architecture> {
+    match self.get_platform() {
+      PlatformType::Android => flags::android_architectures(ctx, self.target.unwrap()),
+      _                     => ARCHITECTURES.to_vec()
+    }
+  }
+
+  fn get_arch_platform(&self, arch: Architecture) -> &'static str {
+    match self.get_platform() {
+      PlatformType::Android => get_arch_name(arch),
+      _                     => get_arch_platform(arch)
     }
   }
 }
@@ -170,53 +290,144 @@ fn get_arch_platform(arch: Architecture) -> &'static str {
   }
 }
 
-fn get_item_group_element(target: &Target, file: &FileInfo) -> &'static str {
-  if !target.match_file(&file.path, PlatformType::Windows) {
-    return "None";
+fn get_item_group_element(file: &FileInfo, platform: PlatformType) -> &'static str {
+  // TODO more types (ie image)
+  match (file.extension(), platform) {
+    ("h",    _)                     |
+    ("hpp",  _)                     => "ClInclude",
+    ("c",    _)                     |
+    ("cpp",  _)                     |
+    ("s",    PlatformType::Android) |
+    ("S",    PlatformType::Android) => "ClCompile",
+    ("asm",  PlatformType::Windows) => "MASM",
+    ("nasm", PlatformType::Windows) => "CustomBuild",
+    ("proto", _)                    |
+    ("fbs",  _)                     => "CustomBuild",
+    ("xml",  _)                     => "Xml",
+    _                               => "None"
   }
+}
 
-  // TODO more types (ie image)
-  match file.extension() {
-    "h" | "hpp" => "ClInclude",
-    "c" | "cpp" => "ClCompile",
-    "xml"       => "Xml",
-    _           => "None"
+/// First file of a target and the ones it extends with the given extension, built on the platform.
+fn find_source<'b>(ctx: &'b Context, index: usize, platform: PlatformType,
+                   ext: &str) -> Option<&'b FileInfo>
+{
+  ctx.extends[index].iter().cloned().chain(std::iter::once(index)).find_map(|i| {
+    let target = ctx.get_target(i);
+    ctx.sources[i].iter().find(|x| {
+      x.meta.is_file() && x.extension() == ext && target.builds_file(&x.path, platform)
+    })
+  })
+}
+
+fn get_character_set(ctx: &Context, target: &Target) -> CharacterSet {
+  target.settings.windows_character_set
+    .or(ctx.project.settings.windows_character_set)
+    .unwrap_or(CharacterSet::Unicode)
+}
+
+fn get_bool(x: bool) -> &'static str {
+  match x {
+    true  => "true",
+    false => "false"
+  }
+}
+
+fn get_debug_information_format(x: DebugInfo) -> &'static str {
+  match x {
+    DebugInfo::None     => "None",
+    DebugInfo::Embedded => "OldStyle",
+    DebugInfo::Separate => "ProgramDatabase"
+  }
+}
+
+/// SSE2 is the default on x64, NEON on ARM.
+fn get_instruction_set(x: Simd) -> Option<&'static str> {
+  match x {
+    Simd::SSE2   => Some("StreamingSIMDExtensions2"),
+    Simd::AVX    => Some("AdvancedVectorExtensions"),
+    Simd::AVX2   => Some("AdvancedVectorExtensions2"),
+    Simd::AVX512 => Some("AdvancedVectorExtensions512"),
+    Simd::NEON   => None
+  }
+}
+
+fn get_runtime_library(x: MsvcRuntime, debug: bool) -> &'static str {
+  match (x, debug) {
+    (MsvcRuntime::Static,  false) => "MultiThreaded",
+    (MsvcRuntime::Static,  true)  => "MultiThreadedDebug",
+    (MsvcRuntime::Dynamic, false) => "MultiThreadedDLL",
+    (MsvcRuntime::Dynamic, true)  => "MultiThreadedDebugDLL"
+  }
+}
+
+fn get_fp_model(m: FloatModel) -> &'static str {
+  match m {
+    FloatModel::Fast    => "Fast",
+    FloatModel::Precise => "Precise",
+    FloatModel::Strict  => "Strict"
+  }
+}
+
+fn get_android_api_level(ctx: &Context, target: &Target) -> u8 {
+  target.settings.android_target_api_level
+    .or(ctx.project.settings.android_target_api_level)
+    .unwrap_or(ANDROID_API_LEVEL)
+}
+
+fn create_file<P>(ctx: &Context, path: P) -> IOResult<TextFile> where P: AsRef<Path> {
+  TextFile::create(ctx, "vs", text::CRLF, path)
+}
+
+fn create_msbuild_file<P>(ctx: &Context, path: P) -> IOResult<TextFile> where P: AsRef<Path> {
+  let mut f = create_file(ctx, path)?;
+  f.write_all(concat!(
+    "<?xml version=\"1.0\" encoding=\"utf-8\"?>\r\n",
+    "<Project xmlns=\"http://schemas.microsoft.com/developer/msbuild/2003\">\r\n"
+  ).as_bytes())?;
+  Ok(f)
+}
+
+fn get_props_name(ctx: &Context, profile: Option<&str>) -> String {
+  match profile {
+    None       => [ctx.project.name, ".props"].join(""),
+    Some(prof) => [ctx.project.name, ".", prof, ".props"].join("")
   }
 }
 
-fn random_uuid() -> String {
-  Uuid::new_v4().to_string().to_uppercase()
+fn get_dir(prefix: &str, vars: &Vars, dir: &str) -> String {
+  join_prefix(prefix, "\\", &vars.expand(dir).replace("/", "\\"))
 }
 
 
 // Resources
 // -----------------------------------------------------------------------------
 
-fn write_resources(ctx: &Context, index: usize, proj: &Proj) -> IO {
+fn write_resources(ctx: &Context, proj: &Proj) -> IO {
   let target = proj.target.unwrap();
   if target.target_type != TargetType::Application {
     return Ok(());
   }
 
-  let path = ctx.build_dir.join([proj.name, "_Windows"].join(""));
+  let path = ctx.build_dir.join([proj.name.as_str(), "_Windows"].join(""));
   std::fs::create_dir_all(&path)?;
 
   write_manifest_xml(&path, ctx)?;
-  write_resource_rc(&path)?;
+  write_resource_rc(&path, ctx)?;
 
   let pattern = [target.assets.unwrap(), "\\windows\\"].join("");
-  let assets  = ctx.assets[index].iter()
+  let assets  = ctx.assets[proj.index].iter()
     .filter(|info| info.meta.is_file() && info.to_str().starts_with(&pattern));
 
   for asset in assets {
-    std::fs::copy(ctx.input_dir.join(&asset.path), path.join(asset.name()))?;
+    copy_file(ctx, &ctx.input_dir.join(&asset.path), &path.join(asset.name()))?;
   }
 
   Ok(())
 }
 
 fn write_manifest_xml(path: &Path, ctx: &Context) -> IO {
-  let mut f = File::create(path.join("Manifest.xml"))?;
+  let mut f = create_file(ctx, path.join("Manifest.xml"))?;
 
   write!(f, concat!(
     "<?xml version=\"1.0\" encoding=\"utf-8\" standalone=\"yes\"?>\r\n",
@@ -254,12 +465,13 @@ fn write_manifest_xml(path: &Path, ctx: &Context) -> IO {
     "  </dependency>\r\n",
     "</assembly>\r\n"
   ), ctx.project.name, ctx.project.version, ctx.project.description)?;
+  f.flush()?;
 
   Ok(())
 }
 
-fn write_resource_rc(path: &Path) -> IO {
-  let mut f = File::create(path.join("Resource.rc"))?;
+fn write_resource_rc(path: &Path, ctx: &Context) -> IO {
+  let mut f = create_file(ctx, path.join("Resource.rc"))?;
 
   // TODO VERSIONINFO
   write!(f, concat!(
@@ -273,6 +485,7 @@ fn write_resource_rc(path: &Path) -> IO {
     "\r\n",
     "APP_ICON ICON Icon.ico\r\n"
   ))?;
+  f.flush()?;
 
   Ok(())
 }
@@ -281,11 +494,13 @@ fn write_resource_rc(path: &Path) -> IO {
 // Filter File
 // -----------------------------------------------------------------------------
 
-fn write_filters(ctx: &Context, index: usize, proj: &Proj) -> IO {
-  assert!(proj.kind == ProjKind::CXX);
+fn write_filters(ctx: &Context, proj: &Proj) -> IO {
+  assert!(proj.kind == ProjKind::CXX || proj.kind == ProjKind::AndroidCXX);
+  let index  = proj.index;
   let target = proj.target.unwrap();
+  let is_app = proj.kind == ProjKind::CXX && target.target_type == TargetType::Application;
 
-  let mut f = proj.create(&ctx.build_dir, "vcxproj.filters")?;
+  let mut f = proj.create(ctx, "vcxproj.filters")?;
   f.write_all(b"  <ItemGroup>\r\n")?;
 
   let files = &ctx.sources[index];
@@ -298,7 +513,11 @@ fn write_filters(ctx: &Context, index: usize, proj: &Proj) -> IO {
   }
 
   let asset_filter = "resources";
-  if target.target_type == TargetType::Application {
+  let resources    = match proj.kind {
+    ProjKind::CXX => get_resources(ctx, index),
+    _             => Vec::new()
+  };
+  if is_app || !resources.is_empty() {
     write_filter_element(&mut f, asset_filter)?;
   }
 
@@ -306,14 +525,18 @@ fn write_filters(ctx: &Context, index: usize, proj: &Proj) -> IO {
                       "  <ItemGroup>\r\n").as_bytes())?;
 
   let prefix = ctx.input_rel.to_str().unwrap();
+  let platform = proj.get_platform();
   for &extend_index in &ctx.extends[index] {
-    write_filter_files(&mut f, prefix, &ctx.sources[extend_index],
-                       ctx.get_target(extend_index))?;
+    write_filter_files(&mut f, prefix, &ctx.sources[extend_index], platform)?;
   }
-  write_filter_files(&mut f, prefix, files, target)?;
+  write_filter_files(&mut f, prefix, files, platform)?;
 
-  if target.target_type == TargetType::Application {
-    let prefix = [proj.name, "_Windows"].join("");
+  for file in &resources {
+    write_filter_file(&mut f, "CopyFileToFolders", prefix, file.to_str(), asset_filter)?;
+  }
+
+  if is_app {
+    let prefix = [proj.name.as_str(), "_Windows"].join("");
     write_filter_file(&mut f, "Xml",             &prefix, "Manifest.xml", asset_filter)?;
     write_filter_file(&mut f, "Image",           &prefix, "Icon.ico",     asset_filter)?;
     write_filter_file(&mut f, "ResourceCompile", &prefix, "Resource.rc",  asset_filter)?;
@@ -364,15 +587,15 @@ fn write_filter_element<W>(f: &mut W, path: &str) -> IO where W: Write {
                     "      <UniqueIdentifier>{{{uuid}}}</UniqueIdentifier>\r\n",
                     "    </Filter>\r\n"),
          dir  = path,
-         uuid = random_uuid())
+         uuid = stable_uuid(&["filter", path]))
 }
 
 fn write_filter_files<W>(f: &mut W, prefix: &str, files: &TargetFiles,
-                         target: &Target) -> IO where W: Write
+                        platform: PlatformType) -> IO where W: Write
 {
   for file in files.iter().filter(|x| x.meta.is_file()) {
     if let Some(filter) = file.path.parent() {
-      write_filter_file(f, get_item_group_element(target, file),
+      write_filter_file(f, get_item_group_element(file, platform),
                            prefix, file.to_str(), filter.to_str().unwrap())?;
     }
   }
@@ -380,225 +603,420 @@ fn write_filter_files<W>(f: &mut W, prefix: &str, files: &TargetFiles,
 }
 
 fn write_filter_file<W>(f: &mut W, element: &str, prefix: &str, file: &str, filter: &str) -> IO where W: Write {
-  write!(f, concat!("    <{element} Include=\"{prefix}\\{file}\">\r\n",
+  write!(f, concat!("    <{element} Include=\"{path}\">\r\n",
                     "      <Filter>{filter}</Filter>\r\n",
                     "    </{element}>\r\n"),
          element = element,
-         prefix  = prefix,
-         file    = file,
+         path    = join_prefix(prefix, "\\", file),
          filter  = filter)
 }
 
 
-// C++ Project File
+// Property Sheets
 // -----------------------------------------------------------------------------
 
-fn write_proj(ctx: &Context, index: usize, proj: &Proj, tools: &Tools) -> IO {
-  let mut f = proj.create(&ctx.build_dir, proj.ext())?;
+fn write_props(ctx: &Context) -> IO {
+  let mut f = create_msbuild_file(ctx, ctx.build_dir.join(get_props_name(ctx, None)))?;
 
-  f.write_all(b"  <ItemGroup Label=\"ProjectConfigurations\">\r\n")?;
+  f.write_all(concat!("  <ImportGroup Label=\"PropertySheets\" />\r\n",
+                      "  <PropertyGroup>\r\n",
+                      "    <GenerateManifest>false</GenerateManifest>\r\n",
+                      "  </PropertyGroup>\r\n").as_bytes())?;
 
-  for arch in ARCHITECTURES {
-    for prof in &ctx.profiles {
-      write!(f, concat!("    <ProjectConfiguration Include=\"{profile}|{platform}\">\r\n",
-                        "       <Configuration>{profile}</Configuration>\r\n",
-                        "       <Platform>{platform}</Platform>\r\n",
-                        "    </ProjectConfiguration>\r\n"),
-             profile  = prof,
-             platform = get_arch_platform(*arch))?;
+  write!(f, concat!("  <ItemDefinitionGroup>\r\n",
+                    "    <ClCompile>\r\n",
+                    "      <WarningLevel>EnableAllWarnings</WarningLevel>\r\n",
+                    "      <SDLCheck>true</SDLCheck>\r\n",
+                    "      <ConformanceMode>true</ConformanceMode>\r\n",
+                    "      <MultiProcessorCompilation>true</MultiProcessorCompilation>\r\n",
+                    "      <LanguageStandard>stdcpp17</LanguageStandard>\r\n",
+                    "      <RuntimeTypeInfo>false</RuntimeTypeInfo>\r\n",
+                    // TODO disable exceptions
+                    "      <CompileAsManaged>false</CompileAsManaged>\r\n",
+                    "      <DisableSpecificWarnings>{warnings}</DisableSpecificWarnings>\r\n",
+                    // clang-cl maps EnableAllWarnings to -Weverything.
+                    "      <WarningLevel Condition=\"{clang_cl}\">Level4</WarningLevel>\r\n",
+                    "    </ClCompile>\r\n",
+                    "    <Link>\r\n",
+                    "      <SubSystem>{subsystem}</SubSystem>\r\n",
+                    "    </Link>\r\n",
+                    "  </ItemDefinitionGroup>\r\n",
+                    "</Project>\r\n"),
+         warnings  = DISABLE_WARNINGS,
+         clang_cl  = IS_CLANG_CL,
+         subsystem = "Windows")?;
+
+  f.flush()?;
+
+  // TODO hardcoded
+  for prof in &ctx.profiles {
+    let path = ctx.build_dir.join(get_props_name(ctx, Some(prof)));
+
+    let mut f = create_msbuild_file(ctx, path)?;
+
+    write!(f, concat!("  <ImportGroup Label=\"PropertySheets\" />\r\n",
+                      "  <ItemDefinitionGroup>\r\n",
+                      "    <ClCompile>\r\n",
+                      "      <Optimization>{optimization}</Optimization>\r\n"),
+           optimization = match ctx.get_base_profile(prof) == "Release" {
+             true  => "MaxSpeed",
+             false => "Disabled"
+           })?;
+
+    if ctx.get_base_profile(prof) == "Release" {
+      f.write_all(concat!("      <FunctionLevelLinking>true</FunctionLevelLinking>\r\n",
+                          "      <IntrinsicFunctions>true</IntrinsicFunctions>\r\n").as_bytes())?;
+    }
+
+    // https://devblogs.microsoft.com/cppblog/msvc-preprocessor-progress-towards-conformance/
+    // https://devblogs.microsoft.com/cppblog/broken-warnings-theory/
+    // clang-cl has neither switch, its system include directories serve the same purpose.
+    write!(f, concat!("      <AdditionalOptions Condition=\"!({clang_cl})\">",
+                      "/experimental:preprocessor /experimental:external /external:W0 ",
+                      "%(AdditionalOptions)</AdditionalOptions>\r\n",
+                      "    </ClCompile>\r\n",
+                      "    <Link>\r\n"),
+           clang_cl = IS_CLANG_CL)?;
+
+    if ctx.get_base_profile(prof) == "Release" {
+      f.write_all(concat!("      <EnableCOMDATFolding>true</EnableCOMDATFolding>\r\n",
+                          "      <OptimizeReferences>true</OptimizeReferences>\r\n").as_bytes())?;
     }
+
+    f.write_all(concat!("    </Link>\r\n",
+                        "  </ItemDefinitionGroup>\r\n",
+                        "</Project>\r\n").as_bytes())?;
+
+    f.flush()?;
   }
 
-  f.write_all(concat!("  </ItemGroup>\r\n",
-                      "  <PropertyGroup Label=\"Globals\">\r\n",
+  Ok(())
+}
+
+
+// C++ Project File
+// -----------------------------------------------------------------------------
+
+fn write_proj_output<'a, W>(f: &mut W, ctx: &Context<'a>, proj: &Proj<'a>, vars: &Vars) -> IO where
+  W: Write
+{
+  let target  = proj.target.unwrap();
+  let out_dir = match flags::get_output_dir(ctx, target) {
+    Some(dir) => get_dir(ctx.input_rel.to_str().unwrap(), vars, dir) + "\\",
+    None      => format!("$(Platform)\\$(Configuration)\\{}\\", proj.name)
+  };
+
+  write!(f, concat!("    <OutDir>{out_dir}</OutDir>\r\n",
+                    "    <IntDir>$(Platform)\\$(Configuration)\\{project_name}\\</IntDir>\r\n"),
+         out_dir      = out_dir,
+         project_name = proj.name)?;
+
+  if let Some(name) = flags::get_output_name(ctx, target) {
+    write!(f, "    <TargetName>{}</TargetName>\r\n", vars.expand(name))?;
+  }
+
+  Ok(())
+}
+
+fn write_proj<'a>(ctx: &Context<'a>, proj: &Proj<'a>, tools: &Tools) -> IO {
+  assert!(proj.kind == ProjKind::CXX);
+  let index = proj.index;
+
+  let mut f = proj.create(ctx, proj.ext())?;
+  write_proj_configs(&mut f, ctx, proj)?;
+
+  f.write_all(concat!("  <PropertyGroup Label=\"Globals\">\r\n",
                       "    <VCProjectVersion>16.0</VCProjectVersion>\r\n").as_bytes())?;
 
   write!(f, "    <ProjectGuid>{{{}}}</ProjectGuid>\r\n", proj.uuid)?;
   //f.write_fmt(format_args!("    <Keyword>{}</Keyword>\r\n", "Android"))?;
-  write!(f, concat!("    <RootNamespace>{project_name}</RootNamespace>\r\n",
-                    "    <OutDir>$(Platform)\\$(Configuration)\\{project_name}\\</OutDir>\r\n",
-                    "    <IntDir>$(Platform)\\$(Configuration)\\{project_name}\\</IntDir>\r\n"),
-         project_name = proj.name)?;
+  write!(f, "    <RootNamespace>{}</RootNamespace>\r\n", proj.name)?;
+  write_proj_output(&mut f, ctx, proj, &WINDOWS_VARS)?;
 
   f.write_all(concat!("    <WindowsTargetPlatformVersion>10.0</WindowsTargetPlatformVersion>\r\n",
                       "  </PropertyGroup>\r\n").as_bytes())?;
 
-  write_proj_import(&mut f, match proj.kind {
-    ProjKind::Android => r#"$(AndroidTargetsPath)\Android.Default.props"#,
-    ProjKind::CXX     => r#"$(VCTargetsPath)\Microsoft.Cpp.Default.props"#,
-    ProjKind::Items   => unreachable!()
-  })?;
+  write_proj_import(&mut f, r#"$(VCTargetsPath)\Microsoft.Cpp.Default.props"#)?;
 
   write!(f, concat!("  <PropertyGroup Label=\"Configuration\">\r\n",
                     "    <ConfigurationType>{config_type}</ConfigurationType>\r\n",
                     "    <PlatformToolset>{toolset}</PlatformToolset>\r\n",
-                    "    <CharacterSet>Unicode</CharacterSet>\r\n",
+                    "    <CharacterSet>{charset:?}</CharacterSet>\r\n",
                     "  </PropertyGroup>\r\n"),
          // TODO
          config_type = "Application",
-         toolset     = "v142")?;
+         charset     = get_character_set(ctx, proj.target.unwrap()),
+         toolset     = proj.get_platform_toolset(ctx, tools.version))?;
+
+  let target = proj.target.unwrap();
 
   // TODO hardcoded
   for prof in &ctx.profiles {
-    write!(f, concat!("  <PropertyGroup Condition=\"'$(Configuration)'=='{profile}'\"",
-                      " Label=\"Configuration\">\r\n",
-                      "    <UseDebugLibraries>{debug:?}</UseDebugLibraries>\r\n"),
-           profile = prof,
-           debug   = *prof != "Release")?;
+    let values = get_arch_values(ctx, proj, |a| {
+      (ctx.get_setting(target, prof, PlatformType::Windows, a, |s| s.lto),
+       ctx.get_setting(target, prof, PlatformType::Windows, a, |s| s.spectre_mitigation),
+       flags::get_sanitizers(ctx, target, prof, PlatformType::Windows, a))
+    });
 
-    if *prof == "Release" {
-      f.write_all(b"    <WholeProgramOptimization>true</WholeProgramOptimization>\r\n")?;
-    }
+    for (arch, (lto, spectre_mitigation, sanitizers)) in values {
+      write!(f, concat!("  <PropertyGroup Condition=\"{condition}\"",
+                        " Label=\"Configuration\">\r\n",
+                        "    <UseDebugLibraries>{debug:?}</UseDebugLibraries>\r\n"),
+             condition = get_profile_condition(proj, prof, arch),
+             debug     = ctx.get_base_profile(prof) != "Release")?;
 
-    f.write_all(b"  </PropertyGroup>\r\n")?;
-  }
+      if let Some(lto) = lto {
+        write!(f, "    <WholeProgramOptimization>{:?}</WholeProgramOptimization>\r\n",
+               lto != Lto::Off)?;
+      }
 
-  write_proj_import(&mut f, match proj.kind {
-    ProjKind::Android => r#"$(AndroidTargetsPath)\Android.props"#,
-    ProjKind::CXX     => r#"$(VCTargetsPath)\Microsoft.Cpp.props"#,
-    ProjKind::Items   => unreachable!()
-  })?;
-  f.write_all(b"  <ImportGroup Label=\"ExtensionSettings\">\r\n  </ImportGroup>\r\n")?;
-  f.write_all(b"  <ImportGroup Label=\"Shared\">\r\n  </ImportGroup>\r\n")?;
+      if let Some(x) = spectre_mitigation {
+        write!(f, "    <SpectreMitigation>{}</SpectreMitigation>\r\n", match x {
+          true  => "Spectre",
+          false => "false"
+        })?;
+      }
 
-  write!(f, concat!("  <ImportGroup Label=\"PropertySheets\">\r\n",
-                    "    <Import Project=\"{path}\" Condition=\"exists('{path}')\" ",
-                    "Label=\"LocalAppDataPlatform\" />\r\n",
-                    "  </ImportGroup>\r\n"),
-         path = "$(UserRootDir)\\Microsoft.Cpp.$(Platform).user.props")?;
+      // MSVC only implements the address sanitizer.
+      for s in sanitizers.split_terminator(',') {
+        match s {
+          "address" => f.write_all(b"    <EnableASAN>true</EnableASAN>\r\n")?,
+          _         => ctx.warn(format!("Visual Studio: sanitizer {} of target {} is ignored ({})",
+                                        s, ctx.get_target_name(index), prof))
+        }
+      }
 
-  f.write_all(b"  <PropertyGroup Label=\"UserMacros\" />\r\n")?;
+      f.write_all(b"  </PropertyGroup>\r\n")?;
+    }
+  }
 
-  f.write_all(concat!("  <PropertyGroup>\r\n",
-                      "    <GenerateManifest>false</GenerateManifest>\r\n",
-                      "  </PropertyGroup>\r\n").as_bytes())?;
+  write_proj_import(&mut f, r#"$(VCTargetsPath)\Microsoft.Cpp.props"#)?;
+  let masm = find_source(ctx, index, PlatformType::Windows, "asm").is_some();
+  write_proj_sheets(&mut f, ctx, true, masm)?;
 
-  // TODO general properties for profiles/architectures
+  let prefix = ctx.input_rel.to_str().unwrap();
 
-  write!(f, concat!("  <ItemDefinitionGroup>\r\n",
-                    "    <ClCompile>\r\n",
-                    "      <WarningLevel>EnableAllWarnings</WarningLevel>\r\n",
-                    "      <SDLCheck>true</SDLCheck>\r\n",
-                    "      <ConformanceMode>true</ConformanceMode>\r\n",
-                    "      <MultiProcessorCompilation>true</MultiProcessorCompilation>\r\n",
-                    "      <LanguageStandard>stdcpp17</LanguageStandard>\r\n",
-                    "      <RuntimeTypeInfo>false</RuntimeTypeInfo>\r\n",
-                    // TODO disable exceptions
-                    "      <CompileAsManaged>false</CompileAsManaged>\r\n",
-                    "      <DisableSpecificWarnings>{warnings}</DisableSpecificWarnings>\r\n"),
-         warnings = DISABLE_WARNINGS)?;
+  // Shared settings live in the property sheets, only target specific ones are added here.
+  f.write_all(concat!("  <ItemDefinitionGroup>\r\n",
+                      "    <ClCompile>\r\n",
+                      "      <AdditionalIncludeDirectories>").as_bytes())?;
 
-  let prefix = ctx.input_rel.to_str().unwrap();
-  let target = proj.target.unwrap();
+  for &extend_index in &ctx.extends[index] {
+    write_includes(&mut f, prefix, ctx.get_target(extend_index))?;
+  }
+  write_includes(&mut f, prefix, target)?;
+  write_embed_includes(&mut f, ctx, index)?;
+  write_schema_includes(&mut f, ctx, index)?;
 
-  write!(f, concat!("      <EnableEnhancedInstructionSet>AdvancedVectorExtensions2</EnableEnhancedInstructionSet>\r\n",
-                    "    </ClCompile>\r\n",
-                    "    <Link>\r\n",
-                    "      <SubSystem>{subsystem}</SubSystem>\r\n",
-                    "    </Link>\r\n",
-                    "  </ItemDefinitionGroup>\r\n"),
-         subsystem = "Windows")?;
+  f.write_all(concat!("%(AdditionalIncludeDirectories)</AdditionalIncludeDirectories>\r\n",
+                      "      <PreprocessorDefinitions>").as_bytes())?;
 
-  // TODO hardcoded
-  for prof in &ctx.profiles {
-    let prof_lc = prof.to_lowercase();
+  for &extend_index in &ctx.extends[index] {
+    write_defines(&mut f, &WINDOWS_VARS, ctx.get_target(extend_index))?;
+  }
+  write_defines(&mut f, &WINDOWS_VARS, target)?;
 
-    write!(f, concat!("  <ItemDefinitionGroup Condition=\"'$(Configuration)'=='{profile}'\">\r\n",
-                      "    <ClCompile>\r\n",
-                      "      <Optimization>{optimization}</Optimization>\r\n"),
-           profile      = prof,
-           optimization = match *prof == "Release" {
-             true  => "MaxSpeed",
-             false => "Disabled"
-           })?;
+  f.write_all(b"%(PreprocessorDefinitions)</PreprocessorDefinitions>\r\n")?;
 
-    if *prof == "Release" {
-      f.write_all(concat!("      <FunctionLevelLinking>true</FunctionLevelLinking>\r\n",
-                          "      <IntrinsicFunctions>true</IntrinsicFunctions>\r\n",
-                          "      <FloatingPointModel>fast</FloatingPointModel>\r\n").as_bytes())?;
+  let clang_cl = proj.is_clang_cl(ctx);
+  let mut external = Vec::new();
+  for &extend_index in &ctx.extends[index] {
+    write_external_includes(&mut external, prefix, ctx.get_target(extend_index), clang_cl)?;
+  }
+  write_external_includes(&mut external, prefix, target, clang_cl)?;
+
+  match flags::use_response_files(ctx, target) && !external.is_empty() {
+    false => {
+      f.write_all(b"      <AdditionalOptions>%(AdditionalOptions)")?;
+      f.write_all(&external)?;
+    },
+    true  => {
+      // The compiler runs from the project's directory, where the response file is written.
+      let mut rsp = create_file(ctx, ctx.build_dir.join([proj.name.as_str(), ".rsp"].join("")))?;
+      rsp.write_all(&external)?;
+      rsp.flush()?;
+      write!(f, "      <AdditionalOptions>%(AdditionalOptions) @{}.rsp", proj.name)?;
     }
+  }
 
-    f.write_all(b"      <AdditionalIncludeDirectories>")?;
+  let (extra_cflags, extra_cxxflags, extra_ldflags) =
+    flags::get_extra_flags(ctx, index, "vs", None, PlatformType::Windows, Architecture::Any);
+  for flag in extra_cflags.iter().chain(extra_cxxflags.iter()) {
+    write!(f, " {}", flag)?;
+  }
 
-    for &extend_index in &ctx.extends[index] {
-      write_includes(&mut f, prefix, ctx.get_target(extend_index))?;
+  f.write_all(b"</AdditionalOptions>\r\n")?;
+
+  for &prof in &ctx.profiles {
+    for (arch, options) in get_arch_values(ctx, proj, |a| {
+      let (cflags, cxxflags, _) =
+        flags::get_extra_flags(ctx, index, "vs", Some(prof), PlatformType::Windows, a);
+      [cflags, cxxflags].concat()
+    }) {
+      write_profile_options(&mut f, &get_profile_condition(proj, prof, arch), &options)?;
     }
-    write_includes(&mut f, prefix, target)?;
+  }
 
-    f.write_all(concat!("%(AdditionalIncludeDirectories)</AdditionalIncludeDirectories>\r\n",
-                        "      <PreprocessorDefinitions>").as_bytes())?;
+  // The GCC/Clang warning names only apply to clang-cl.
+  let (msvc_warnings, gcc_warnings) =
+    flags::get_disable_warnings(ctx, index, None, PlatformType::Windows, Architecture::Any);
+  write_disable_warnings(&mut f, None, &msvc_warnings, &gcc_warnings, clang_cl)?;
+  for &prof in &ctx.profiles {
+    for (arch, (msvc_warnings, gcc_warnings)) in get_arch_values(ctx, proj, |a| {
+      flags::get_disable_warnings(ctx, index, Some(prof), PlatformType::Windows, a)
+    }) {
+      let condition = get_profile_condition(proj, prof, arch);
+      write_disable_warnings(&mut f, Some(&condition), &msvc_warnings, &gcc_warnings, clang_cl)?;
+    }
+  }
+
+  if let Some(x) = flags::get_simd(ctx, target).and_then(get_instruction_set) {
+    write!(f, "      <EnableEnhancedInstructionSet>{}</EnableEnhancedInstructionSet>\r\n", x)?;
+  }
+
+  let force_includes = flags::get_force_includes(ctx, index);
+  if !force_includes.is_empty() {
+    write!(f, "      <ForcedIncludeFiles>{};%(ForcedIncludeFiles)</ForcedIncludeFiles>\r\n",
+           force_includes.join(";"))?;
+  }
 
-    if *prof == "Debug" {
-      f.write_all(b"_ITERATOR_DEBUG_LEVEL=1;")?;
+  if let Some(pch) = flags::get_pch(ctx, target) {
+    write!(f, concat!("      <PrecompiledHeader>Use</PrecompiledHeader>\r\n",
+                      "      <PrecompiledHeaderFile>{}</PrecompiledHeaderFile>\r\n"),
+           pch)?;
+  }
+
+  write_profile_settings(&mut f, ctx, proj, "PreprocessorDefinitions",
+                         |s| s.iterator_debug_level, |x| {
+    match x {
+      0 => "_ITERATOR_DEBUG_LEVEL=0;%(PreprocessorDefinitions)",
+      1 => "_ITERATOR_DEBUG_LEVEL=1;%(PreprocessorDefinitions)",
+      _ => "_ITERATOR_DEBUG_LEVEL=2;%(PreprocessorDefinitions)"
     }
-    for &extend_index in &ctx.extends[index] {
-      write_defines(&mut f, ctx.get_target(extend_index))?;
+  })?;
+  write_profile_settings(&mut f, ctx, proj, "FloatingPointModel", |s| s.fp_model, get_fp_model)?;
+  write_profile_settings(&mut f, ctx, proj, "ControlFlowGuard", |s| s.control_flow_guard, |x| {
+    match x {
+      true  => "Guard",
+      false => "false"
     }
-    write_defines(&mut f, target)?;
+  })?;
+  write_profile_settings(&mut f, ctx, proj, "BufferSecurityCheck",
+                         |s| s.buffer_security_check, get_bool)?;
+  write_profile_settings(&mut f, ctx, proj, "DebugInformationFormat",
+                         |s| s.debug_info, get_debug_information_format)?;
+
+  for &prof in &ctx.profiles {
+    for (arch, x) in get_arch_values(ctx, proj, |a| {
+      ctx.get_setting(target, prof, PlatformType::Windows, a, |s| s.msvc_runtime)
+    }) {
+      if let Some(x) = x {
+        write!(f, "      <RuntimeLibrary Condition=\"{}\">{}</RuntimeLibrary>\r\n",
+               get_profile_condition(proj, prof, arch),
+               get_runtime_library(x, ctx.get_base_profile(prof) != "Release"))?;
+      }
+    }
+  }
 
-    write!(f, concat!("%(PreprocessorDefinitions)</PreprocessorDefinitions>\r\n",
-                      "      <AdditionalOptions>/experimental:preprocessor /experimental:external ",
-                      "/external:W0 /external:I {}\\3rdparty\\include\\{}"),
-           prefix, prof_lc)?;
+  f.write_all(concat!("    </ClCompile>\r\n",
+                      "    <Link>\r\n",
+                      "      <AdditionalDependencies>").as_bytes())?;
 
-    // https://devblogs.microsoft.com/cppblog/msvc-preprocessor-progress-towards-conformance/
-    // https://devblogs.microsoft.com/cppblog/broken-warnings-theory/
+  for &extend_index in &ctx.extends[index] {
+    write_lib_names(&mut f, ctx, ctx.get_target(extend_index), PlatformType::Windows, ".lib")?;
+    write_lib_files(&mut f, ctx, prefix, &WINDOWS_VARS, ctx.get_target(extend_index))?;
+  }
+  write_lib_names(&mut f, ctx, target, PlatformType::Windows, ".lib")?;
+  write_lib_files(&mut f, ctx, prefix, &WINDOWS_VARS, target)?;
 
-    for &extend_index in &ctx.extends[index] {
-      write_external_includes(&mut f, prefix, ctx.get_target(extend_index))?;
-    }
-    write_external_includes(&mut f, prefix, target)?;
+  f.write_all(concat!("%(AdditionalDependencies)</AdditionalDependencies>\r\n",
+                      "      <AdditionalLibraryDirectories>").as_bytes())?;
+
+  for &extend_index in &ctx.extends[index] {
+    write_lib_dirs(&mut f, prefix, &WINDOWS_VARS, ctx.get_target(extend_index))?;
+  }
+  write_lib_dirs(&mut f, prefix, &WINDOWS_VARS, target)?;
 
-    f.write_all(concat!("%(AdditionalOptions)</AdditionalOptions>\r\n",
-                        "    </ClCompile>\r\n",
-                        "    <Link>\r\n").as_bytes())?;
+  f.write_all(b"%(AdditionalLibraryDirectories)</AdditionalLibraryDirectories>\r\n")?;
 
-    if *prof == "Release" {
-      f.write_all(concat!("      <EnableCOMDATFolding>true</EnableCOMDATFolding>\r\n",
-                          "      <OptimizeReferences>true</OptimizeReferences>\r\n").as_bytes())?;
-    }
+  // The linker only accepts a single module-definition file.
+  if let Some(def) = find_source(ctx, index, PlatformType::Windows, "def") {
+    write!(f, "      <ModuleDefinitionFile>{}</ModuleDefinitionFile>\r\n",
+           join_prefix(prefix, "\\", def.to_str()))?;
+  }
 
-    // TODO hardcoded
-    f.write_all(b"      <AdditionalDependencies>OpenGL32.lib;")?;
-    for &extend_index in &ctx.extends[index] {
-      for lib in &*ctx.get_target(extend_index).settings.libs {
-        write!(f, "{}.lib;", lib)?;
-      }
+  if !extra_ldflags.is_empty() {
+    write!(f, "      <AdditionalOptions>{} %(AdditionalOptions)</AdditionalOptions>\r\n",
+           extra_ldflags.join(" "))?;
+  }
+
+  for &prof in &ctx.profiles {
+    for (arch, ldflags) in get_arch_values(ctx, proj, |a| {
+      flags::get_extra_flags(ctx, index, "vs", Some(prof), PlatformType::Windows, a).2
+    }) {
+      write_profile_options(&mut f, &get_profile_condition(proj, prof, arch), &ldflags)?;
     }
-    for lib in &*target.settings.libs {
-      write!(f, "{}.lib;", lib)?;
+  }
+
+  write_profile_settings(&mut f, ctx, proj, "RandomizedBaseAddress", |s| s.aslr, get_bool)?;
+  // Profiling images can be instrumented by the Visual Studio coverage tools.
+  write_profile_settings(&mut f, ctx, proj, "Profile", |s| s.coverage, get_bool)?;
+  write_profile_settings(&mut f, ctx, proj, "GenerateDebugInformation", |s| s.debug_info, |x| {
+    get_bool(x != DebugInfo::None)
+  })?;
+  write_profile_settings(&mut f, ctx, proj, "AdditionalOptions", |s| s.control_flow_guard, |x| {
+    match x {
+      true  => "/guard:cf %(AdditionalOptions)",
+      false => "%(AdditionalOptions)"
     }
+  })?;
 
-    f.write_all(concat!("%(AdditionalDependencies)</AdditionalDependencies>\r\n",
-                        "      <AdditionalLibraryDirectories>").as_bytes())?;
+  f.write_all(b"    </Link>\r\n")?;
 
-    write!(f, "{}\\3rdparty\\lib\\windows\\x64\\{}", prefix, prof_lc)?;
+  write_version_header(&mut f, ctx, "$(Configuration)")?;
 
-    f.write_all(concat!("</AdditionalLibraryDirectories>\r\n",
-                        "    </Link>\r\n",
-                        "  </ItemDefinitionGroup>\r\n").as_bytes())?;
+  if target.target_type != TargetType::StaticLibrary {
+    if let Some(cmd) = symbols::upload_command(ctx, "$(OutDir)", "$(OutDir)$(TargetName).pdb") {
+      write!(f, concat!("    <PostBuildEvent Condition=\"'$(Configuration)'=='{}'\">\r\n",
+                        "      <Command>{}</Command>\r\n",
+                        "      <Message>Uploading debug symbols</Message>\r\n",
+                        "    </PostBuildEvent>\r\n"),
+             symbols::PROFILE, cmd)?;
+    }
   }
 
+  f.write_all(b"  </ItemDefinitionGroup>\r\n")?;
+
   // TODO project references
 
-  // TODO per file settings? (at least create PCH)
-  f.write_all(b"  <ItemGroup>\r\n")?;
-  match proj.kind {
-    ProjKind::Android => {
+  let pch_source = flags::get_pch(ctx, target).and(flags::get_pch_source(ctx, target));
 
-    },
-    ProjKind::CXX => {
-      for &extend_index in &ctx.extends[index] {
-        write_files(&mut f, ctx, extend_index, prefix, ctx.get_target(extend_index))?;
-      }
-      write_files(&mut f, ctx, index, prefix, target)?;
-    },
-    ProjKind::Items => unreachable!()
+  f.write_all(b"  <ItemGroup>\r\n")?;
+  for &extend_index in &ctx.extends[index] {
+    write_files(&mut f, ctx, extend_index, prefix, ctx.get_target(extend_index),
+                PlatformType::Windows, pch_source)?;
   }
+  write_files(&mut f, ctx, index, prefix, target, PlatformType::Windows, pch_source)?;
+  write_embeds(&mut f, ctx, index, prefix)?;
+  write_schemas(&mut f, ctx, index, prefix, PlatformType::Windows)?;
+  write_shaders(&mut f, ctx, index, prefix, PlatformType::Windows)?;
+  write_test_main(&mut f, ctx, index, target)?;
   f.write_all(b"  </ItemGroup>\r\n")?;
 
+  let resources = get_resources(ctx, index);
+  if !resources.is_empty() {
+    f.write_all(b"  <ItemGroup>\r\n")?;
+    for file in &resources {
+      write!(f, concat!("    <CopyFileToFolders Include=\"{}\">\r\n",
+                        "      <DestinationFolders>$(OutDir)</DestinationFolders>\r\n",
+                        "    </CopyFileToFolders>\r\n"),
+             join_prefix(prefix, "\\", file.to_str()))?;
+    }
+    f.write_all(b"  </ItemGroup>\r\n")?;
+  }
+
   if target.target_type == TargetType::Application {
-    write_resources(ctx, index, proj)?;
+    write_resources(ctx, proj)?;
 
     write!(f, concat!("  <ItemGroup>\r\n",
                       "    <Xml Include=\"{0}_Windows\\Manifest.xml\" />\r\n",
@@ -613,12 +1031,14 @@ fn write_proj(ctx: &Context, index: usize, proj: &Proj, tools: &Tools) -> IO {
   // - icon.ico
   // - manifest.xml
 
-  write_proj_import(&mut f, match proj.kind {
-    ProjKind::Android => r#"$(AndroidTargetsPath)\Android.targets"#,
-    ProjKind::CXX     => r#"$(VCTargetsPath)\Microsoft.Cpp.Targets"#,
-    ProjKind::Items   => unreachable!()
-  })?;
-  f.write_all(b"  <ImportGroup Label=\"ExtensionTargets\" />\r\n")?;
+  write_proj_import(&mut f, r#"$(VCTargetsPath)\Microsoft.Cpp.Targets"#)?;
+
+  match masm {
+    false => f.write_all(b"  <ImportGroup Label=\"ExtensionTargets\" />\r\n")?,
+    true  => f.write_all(concat!("  <ImportGroup Label=\"ExtensionTargets\">\r\n",
+                                 "    <Import Project=\"$(VCTargetsPath)\\BuildCustomizations\\masm.targets\" />\r\n",
+                                 "  </ImportGroup>\r\n").as_bytes())?
+  }
 
   // TODO extensions? (ie PIX)
   // TODO nuget?
@@ -628,10 +1048,122 @@ fn write_proj(ctx: &Context, index: usize, proj: &Proj, tools: &Tools) -> IO {
   Ok(())
 }
 
-fn write_external_includes<W>(f: &mut W, prefix: &str, target: &Target) -> IO where W: Write {
+/// Regenerates the project's version header before each build, see `gen::version`.
+fn write_version_header<W>(f: &mut W, ctx: &Context, profile: &str) -> IO where W: Write {
+  if let Some(cmd) = version::command(ctx, profile) {
+    write!(f, concat!("    <PreBuildEvent>\r\n",
+                      "      <Command>{}</Command>\r\n",
+                      "      <Message>Updating the version header</Message>\r\n",
+                      "    </PreBuildEvent>\r\n"),
+           cmd)?;
+  }
+  Ok(())
+}
+
+/// Converts each embedded file to a C source whenever it changes, see `gen::embed`.
+fn write_embeds<W>(f: &mut W, ctx: &Context, index: usize, prefix: &str) -> IO where W: Write {
+  for e in &embed::get_embeds(ctx, index) {
+    let output = e.output.to_str().unwrap();
+    write!(f, concat!("    <CustomBuild Include=\"{input}\">\r\n",
+                      "      <Command>{cmd}</Command>\r\n",
+                      "      <Outputs>{output}</Outputs>\r\n",
+                      "      <Message>Embedding {path}</Message>\r\n",
+                      "      <LinkObjects>false</LinkObjects>\r\n",
+                      "    </CustomBuild>\r\n",
+                      "    <ClCompile Include=\"{output}\">\r\n",
+                      "      <PrecompiledHeader>NotUsing</PrecompiledHeader>\r\n",
+                      "    </ClCompile>\r\n"),
+           input  = join_prefix(prefix, "\\", e.file.to_str()),
+           cmd    = embed::command(ctx, index, Some(e.file)),
+           output = output,
+           path   = e.file.to_str())?;
+  }
+  Ok(())
+}
+
+fn write_embed_includes<W>(f: &mut W, ctx: &Context, index: usize) -> IO where W: Write {
+  if !ctx.embeds[index].is_empty() {
+    write!(f, "{};", embed::get_dir(ctx, index).display())?;
+  }
+  Ok(())
+}
+
+/// Compiles the schemas of the target and the ones it extends, see `gen::codegen`.
+fn write_schemas<W>(f: &mut W, ctx: &Context, index: usize, prefix: &str,
+                    platform: PlatformType) -> IO where W: Write
+{
+  let schemas = ctx.extends[index].iter().chain(std::iter::once(&index))
+    .flat_map(|&i| codegen::get_schemas(ctx, index, i, platform));
+  for schema in schemas {
+    let outputs = schema.outputs.iter().map(|x| x.to_str().unwrap()).collect::<Vec<&str>>();
+    write!(f, concat!("    <CustomBuild Include=\"{input}\">\r\n",
+                      "      <Command>{cmd}</Command>\r\n",
+                      "      <Outputs>{outputs}</Outputs>\r\n",
+                      "      <Message>Compiling {path}</Message>\r\n",
+                      "      <LinkObjects>false</LinkObjects>\r\n",
+                      "    </CustomBuild>\r\n"),
+           input   = join_prefix(prefix, "\\", schema.file.to_str()),
+           cmd     = schema.command,
+           outputs = outputs.join(";"),
+           path    = schema.file.to_str())?;
+
+    for output in schema.sources() {
+      write!(f, concat!("    <ClCompile Include=\"{}\">\r\n",
+                        "      <PrecompiledHeader>NotUsing</PrecompiledHeader>\r\n",
+                        "    </ClCompile>\r\n"),
+             output.display())?;
+    }
+  }
+  Ok(())
+}
+
+/// Compiles the target's shaders whenever they change, see `gen::shaders`.
+fn write_shaders<W>(f: &mut W, ctx: &Context, index: usize, prefix: &str,
+                    platform: PlatformType) -> IO where W: Write
+{
+  for shader in shaders::get_shaders(ctx, index, platform) {
+    write!(f, concat!("    <CustomBuild Include=\"{input}\">\r\n",
+                      "      <Command>{cmd}</Command>\r\n",
+                      "      <Outputs>{output}</Outputs>\r\n",
+                      "      <Message>Compiling shader {path}</Message>\r\n",
+                      "      <LinkObjects>false</LinkObjects>\r\n",
+                      "    </CustomBuild>\r\n"),
+           input  = join_prefix(prefix, "\\", shader.file.to_str()),
+           cmd    = shader.command,
+           output = shader.output.display(),
+           path   = shader.file.to_str())?;
+  }
+  Ok(())
+}
+
+fn write_test_main<W>(f: &mut W, ctx: &Context, index: usize, target: &Target) -> IO where W: Write {
+  if target.test_framework.is_some() {
+    write!(f, concat!("    <ClCompile Include=\"{}\">\r\n",
+                      "      <PrecompiledHeader>NotUsing</PrecompiledHeader>\r\n",
+                      "    </ClCompile>\r\n"),
+           tests::get_main_path(ctx, index).display())?;
+  }
+  Ok(())
+}
+
+fn write_schema_includes<W>(f: &mut W, ctx: &Context, index: usize) -> IO where W: Write {
+  if codegen::has_schemas(ctx, index) {
+    write!(f, "{};", codegen::get_dir(ctx, index).display())?;
+  }
+  Ok(())
+}
+
+fn write_external_includes<W>(f: &mut W, prefix: &str, target: &Target,
+                              clang_cl: bool) -> IO where W: Write
+{
+  let switch = match clang_cl {
+    true  => "/imsvc",
+    false => "/external:I"
+  };
+
   for &inc in &*target.settings.include_dirs {
     if inc.starts_with("external/") {
-      write!(f, " /external:I {}\\{}", prefix, inc.replace("/", "\\"))?;
+      write!(f, " {} {}", switch, get_dir(prefix, &WINDOWS_VARS, inc))?;
     }
   }
   Ok(())
@@ -640,37 +1172,562 @@ fn write_external_includes<W>(f: &mut W, prefix: &str, target: &Target) -> IO wh
 fn write_includes<W>(f: &mut W, prefix: &str, target: &Target) -> IO where W: Write {
   for &inc in &*target.settings.include_dirs {
     if !inc.starts_with("external/") {
-      write!(f, "{}\\{};", prefix, inc.replace("/", "\\"))?;
+      write!(f, "{};", get_dir(prefix, &WINDOWS_VARS, inc))?;
+    }
+  }
+  Ok(())
+}
+
+/// Values of `get` for each architecture of a project, a single one without an
+/// architecture when they are all the same.
+fn get_arch_values<T, F>(ctx: &Context, proj: &Proj, get: F) -> Vec<(Option<Architecture>, T)> where
+  T: PartialEq,
+  F: Fn(Architecture) -> T
+{
+  let archs  = proj.get_architectures(ctx);
+  let values = archs.iter().map(|&a| get(a)).collect::<Vec<T>>();
+  match values.windows(2).all(|x| x[0] == x[1]) {
+    true  => values.into_iter().take(1).map(|x| (None, x)).collect(),
+    false => archs.into_iter().map(Some).zip(values).collect()
+  }
+}
+
+/// MSBuild condition selecting a profile, and an architecture when given.
+fn get_profile_condition(proj: &Proj, profile: &str, arch: Option<Architecture>) -> String {
+  match arch {
+    Some(a) => format!("'$(Configuration)|$(Platform)'=='{}|{}'", profile, proj.get_arch_platform(a)),
+    None    => format!("'$(Configuration)'=='{}'", profile)
+  }
+}
+
+/// Writes item metadata resolved per profile, only where a value is set.
+fn write_profile_settings<W, T, F, M>(f: &mut W, ctx: &Context, proj: &Proj, element: &str,
+                                      get: F, map: M) -> IO where
+  W: Write,
+  T: PartialEq,
+  F: Fn(&Settings) -> Option<T>,
+  M: Fn(T) -> &'static str
+{
+  let target = proj.target.unwrap();
+  for prof in &ctx.profiles {
+    for (arch, x) in get_arch_values(ctx, proj, |a| {
+      ctx.get_setting(target, prof, PlatformType::Windows, a, &get)
+    }) {
+      if let Some(x) = x {
+        write!(f, "      <{0} Condition=\"{1}\">{2}</{0}>\r\n",
+               element, get_profile_condition(proj, prof, arch), map(x))?;
+      }
     }
   }
   Ok(())
 }
 
-fn write_defines<W>(f: &mut W, target: &Target) -> IO where W: Write {
+fn write_profile_options<W>(f: &mut W, condition: &str, options: &[&str]) -> IO where W: Write {
+  if !options.is_empty() {
+    write!(f, concat!("      <AdditionalOptions Condition=\"{}\">",
+                      "{} %(AdditionalOptions)</AdditionalOptions>\r\n"),
+           condition, options.join(" "))?;
+  }
+  Ok(())
+}
+
+fn write_disable_warnings<W>(f: &mut W, condition: Option<&str>, msvc_warnings: &[&str],
+                             gcc_warnings: &[&str], is_clang: bool) -> IO where W: Write
+{
+  let condition = match condition {
+    Some(x) => format!(" Condition=\"{}\"", x),
+    None    => String::new()
+  };
+
+  if !msvc_warnings.is_empty() {
+    write!(f, concat!("      <DisableSpecificWarnings{}>{};%(DisableSpecificWarnings)",
+                      "</DisableSpecificWarnings>\r\n"),
+           condition, msvc_warnings.join(";"))?;
+  }
+
+  if is_clang && !gcc_warnings.is_empty() {
+    write!(f, "      <AdditionalOptions{}>{} %(AdditionalOptions)</AdditionalOptions>\r\n",
+           condition, flags::gcc_disable_warnings(gcc_warnings).trim_start())?;
+  }
+
+  Ok(())
+}
+
+fn write_lib_dirs<W>(f: &mut W, prefix: &str, vars: &Vars, target: &Target) -> IO where W: Write {
+  for &dir in &*target.settings.lib_dirs {
+    write!(f, "{};", get_dir(prefix, vars, dir))?;
+  }
+  Ok(())
+}
+
+fn write_lib_names<W>(f: &mut W, ctx: &Context, target: &Target, platform: PlatformType,
+                      ext: &str) -> IO where W: Write
+{
+  let framework = target.test_framework.map_or(&[][..], tests::get_libraries);
+  let benchmark = match target.benchmark {
+    true  => benchmarks::get_libraries(platform),
+    false => &[]
+  };
+  for lib in flags::get_libs(ctx, target).0.iter().chain(framework).chain(benchmark) {
+    write!(f, "{}{};", lib, ext)?;
+  }
+  Ok(())
+}
+
+fn write_lib_files<W>(f: &mut W, ctx: &Context, prefix: &str, vars: &Vars, target: &Target) -> IO where
+  W: Write
+{
+  for file in flags::get_libs(ctx, target).1 {
+    write!(f, "{};", get_dir(prefix, vars, file))?;
+  }
+  Ok(())
+}
+
+fn write_defines<W>(f: &mut W, vars: &Vars, target: &Target) -> IO where W: Write {
   for def in &*target.settings.defines {
-    write!(f, "{};", def)?;
+    write!(f, "{};", vars.expand(def))?;
+  }
+  if let Some(framework) = target.test_framework {
+    write!(f, "{};", tests::get_define(framework))?;
   }
   Ok(())
 }
 
-fn write_files<W>(f: &mut W, ctx: &Context, index: usize,
-                  prefix: &str, target: &Target) -> IO where W: Write
+fn write_files<W>(f: &mut W, ctx: &Context, index: usize, prefix: &str,
+                  target: &Target, platform: PlatformType, pch_source: Option<&str>) -> IO where
+  W: Write
 {
-  for file in ctx.sources[index].iter().filter(|x| x.meta.is_file()) {
-    let element  = get_item_group_element(target, file);
+  // Schemas are listed by `write_schemas`, along with the files they generate.
+  for file in ctx.sources[index].iter().filter(|x| x.meta.is_file() && codegen::get_compiler(x).is_none()) {
+    let element  = get_item_group_element(file, platform);
     let filename = file.to_str();
+    let settings = target.get_file_settings(&file.path);
+
+    if element == "None" && file.extension() != "def" && target.builds_file(&file.path, platform) {
+      ctx.warn(format!("Visual Studio: {} of target {} is not built on {}",
+                       filename, ctx.get_target_name(index), platform.to_str()));
+    }
+
+    let mut meta = String::new();
     if filename.starts_with("external\\") && !file.is_header() {
-      write!(f, concat!("    <{0} Include=\"{1}\\{2}\">\r\n",
-                        "      <PrecompiledHeader>NotUsing</PrecompiledHeader>\r\n",
-                        "      <WarningLevel>TurnOffAllWarnings</WarningLevel>\r\n",
-                        "    </{0}>\r\n"),
-             element, prefix, filename)?;
+      meta.push_str(concat!("      <PrecompiledHeader>NotUsing</PrecompiledHeader>\r\n",
+                            "      <WarningLevel>TurnOffAllWarnings</WarningLevel>\r\n"));
+    }
+
+    // Files filtered away from this platform are still listed in the project.
+    if element != "None" {
+      if !target.match_file(&file.path, platform) ||
+        settings.as_ref().map_or(false, |s| s.is_excluded_platform(platform))
+      {
+        meta.push_str("      <ExcludedFromBuild>true</ExcludedFromBuild>\r\n");
+      }
+      else if let Some(s) = &settings {
+        for prof in ctx.profiles.iter().filter(|x| s.is_excluded_profile(x)) {
+          write!(meta, concat!("      <ExcludedFromBuild Condition=\"'$(Configuration)'=='{}'\">",
+                               "true</ExcludedFromBuild>\r\n"),
+                 prof).unwrap();
+        }
+      }
+    }
+
+    if element == "ClCompile" {
+      if pch_source.map_or(false, |x| Path::new(x) == file.path) {
+        meta.push_str("      <PrecompiledHeader>Create</PrecompiledHeader>\r\n");
+      }
+
+      if let Some(s) = &settings {
+        write_file_settings(&mut meta, s, platform);
+      }
+    }
+
+    // TODO nasm from the path, make the executable configurable
+    if element == "CustomBuild" {
+      meta.push_str(concat!("      <Command>nasm -f win$(PlatformArchitecture) ",
+                            "-o \"$(IntDir)%(Filename).obj\" \"%(FullPath)\"</Command>\r\n",
+                            "      <Outputs>$(IntDir)%(Filename).obj</Outputs>\r\n",
+                            "      <Message>Assembling %(Filename)%(Extension)</Message>\r\n"));
+    }
+
+    let path = join_prefix(prefix, "\\", filename);
+    match meta.is_empty() {
+      true  => write!(f, "    <{} Include=\"{}\" />\r\n", element, path)?,
+      false => write!(f, concat!("    <{0} Include=\"{1}\">\r\n",
+                                 "{2}",
+                                 "    </{0}>\r\n"),
+                      element, path, meta)?
+    }
+  }
+
+  Ok(())
+}
+
+fn write_file_settings(s: &mut String, settings: &FileSettings, platform: PlatformType) {
+  let is_msvc = platform == PlatformType::Windows;
+
+  if !settings.defines.is_empty() {
+    write!(s, "      <PreprocessorDefinitions>{};%(PreprocessorDefinitions)</PreprocessorDefinitions>\r\n",
+           settings.defines.join(";")).unwrap();
+  }
+
+  let warnings = settings.disable_warnings.iter()
+    .filter(|x| flags::is_msvc_warning(x) == is_msvc)
+    .cloned()
+    .collect::<Vec<&str>>();
+
+  if !warnings.is_empty() {
+    match is_msvc {
+      true  => write!(s, "      <DisableSpecificWarnings>{};%(DisableSpecificWarnings)</DisableSpecificWarnings>\r\n",
+                      warnings.join(";")).unwrap(),
+      false => write!(s, "      <AdditionalOptions>-Wno-{} %(AdditionalOptions)</AdditionalOptions>\r\n",
+                      warnings.join(" -Wno-")).unwrap()
+    }
+  }
+
+  if let Some(level) = settings.warning_level {
+    write!(s, "      <WarningLevel>{}</WarningLevel>\r\n", match (level, is_msvc) {
+      (0, _)         => "TurnOffAllWarnings",
+      (1, true)      => "Level1",
+      (2, true)      => "Level2",
+      (3, true)      => "Level3",
+      (4, true)      => "Level4",
+      (_, _)         => "EnableAllWarnings"
+    }).unwrap();
+  }
+
+  if let Some(o) = settings.optimize {
+    write!(s, "      <Optimization>{}</Optimization>\r\n", match (o, is_msvc) {
+      (Optimize::None,  _)     => "Disabled",
+      (Optimize::Size,  true)  => "MinSpace",
+      (Optimize::Size,  false) => "MinSize",
+      (Optimize::Speed, _)     => "MaxSpeed",
+      (Optimize::Full,  _)     => "Full"
+    }).unwrap();
+  }
+
+  if settings.compile_as_c == Some(true) {
+    s.push_str("      <CompileAs>CompileAsC</CompileAs>\r\n");
+  }
+}
+
+// Android Project Files
+// -----------------------------------------------------------------------------
+
+fn write_android_proj<'a>(ctx: &Context<'a>, proj: &Proj<'a>, tools: &Tools) -> IO {
+  assert!(proj.kind == ProjKind::AndroidCXX);
+  let index  = proj.index;
+  let target = proj.target.unwrap();
+  let name   = ctx.get_target_name(index);
+
+  let mut f = proj.create(ctx, proj.ext())?;
+  write_proj_configs(&mut f, ctx, proj)?;
+
+  write!(f, concat!("  <PropertyGroup Label=\"Globals\">\r\n",
+                    "    <ProjectGuid>{{{uuid}}}</ProjectGuid>\r\n",
+                    "    <Keyword>Android</Keyword>\r\n",
+                    "    <RootNamespace>{name}</RootNamespace>\r\n",
+                    "    <DefaultLanguage>en-US</DefaultLanguage>\r\n",
+                    "    <MinimumVisualStudioVersion>14.0</MinimumVisualStudioVersion>\r\n",
+                    "    <ApplicationType>Android</ApplicationType>\r\n",
+                    "    <ApplicationTypeRevision>3.0</ApplicationTypeRevision>\r\n"),
+         uuid = proj.uuid,
+         name = name)?;
+
+  write_proj_output(&mut f, ctx, proj, &ANDROID_VARS)?;
+  f.write_all(b"  </PropertyGroup>\r\n")?;
+
+  write_proj_import(&mut f, r#"$(VCTargetsPath)\Microsoft.Cpp.Default.props"#)?;
+
+  let api_level = get_android_api_level(ctx, target);
+
+  // TODO hardcoded
+  for prof in &ctx.profiles {
+    write!(f, concat!("  <PropertyGroup Condition=\"'$(Configuration)'=='{profile}'\"",
+                      " Label=\"Configuration\">\r\n",
+                      "    <ConfigurationType>{config_type}</ConfigurationType>\r\n",
+                      "    <UseDebugLibraries>{debug:?}</UseDebugLibraries>\r\n",
+                      "    <PlatformToolset>{toolset}</PlatformToolset>\r\n",
+                      "    <AndroidAPILevel>android-{api_level}</AndroidAPILevel>\r\n",
+                      "    <UseOfStl>{stl}</UseOfStl>\r\n",
+                      "  </PropertyGroup>\r\n"),
+           profile     = prof,
+           debug       = ctx.get_base_profile(prof) != "Release",
+           toolset     = proj.get_platform_toolset(ctx, tools.version),
+           api_level   = api_level,
+           stl         = flags::android_stl(ctx, target),
+           config_type = match target.target_type {
+             TargetType::StaticLibrary => "StaticLibrary",
+             _                         => "DynamicLibrary"
+           })?;
+  }
+
+  write_proj_import(&mut f, r#"$(VCTargetsPath)\Microsoft.Cpp.props"#)?;
+  write_proj_sheets(&mut f, ctx, false, false)?;
+
+  let prefix = ctx.input_rel.to_str().unwrap();
+  let is_app = target.target_type == TargetType::Application;
+
+  // TODO hardcoded
+  for prof in &ctx.profiles {
+    write!(f, concat!("  <ItemDefinitionGroup Condition=\"'$(Configuration)'=='{profile}'\">\r\n",
+                      "    <ClCompile>\r\n",
+                      "      <PrecompiledHeader>NotUsing</PrecompiledHeader>\r\n",
+                      "      <CppLanguageStandard>c++1z</CppLanguageStandard>\r\n",
+                      "      <ExceptionHandling>Disabled</ExceptionHandling>\r\n",
+                      "      <RuntimeTypeInfo>false</RuntimeTypeInfo>\r\n",
+                      "      <Optimization>{optimization}</Optimization>\r\n",
+                      "      <AdditionalIncludeDirectories>"),
+           profile      = prof,
+           optimization = match ctx.get_base_profile(prof) == "Release" {
+             true  => "Full",
+             false => "Disabled"
+           })?;
+
+    if is_app {
+      f.write_all(b"$(VS_NdkRoot)\\sources\\android\\native_app_glue;")?;
+    }
+
+    for &extend_index in &ctx.extends[index] {
+      write_android_includes(&mut f, prefix, ctx.get_target(extend_index))?;
+    }
+    write_android_includes(&mut f, prefix, target)?;
+    write_embed_includes(&mut f, ctx, index)?;
+    write_schema_includes(&mut f, ctx, index)?;
+
+    f.write_all(concat!("%(AdditionalIncludeDirectories)</AdditionalIncludeDirectories>\r\n",
+                        "      <PreprocessorDefinitions>").as_bytes())?;
+
+    for &extend_index in &ctx.extends[index] {
+      write_defines(&mut f, &ANDROID_VARS, ctx.get_target(extend_index))?;
+    }
+    write_defines(&mut f, &ANDROID_VARS, target)?;
+
+    f.write_all(b"%(PreprocessorDefinitions)</PreprocessorDefinitions>\r\n")?;
+
+    if flags::get_visibility_hidden(ctx, target) {
+      f.write_all(b"      <SymbolsHiddenByDefault>true</SymbolsHiddenByDefault>\r\n")?;
+    }
+    if flags::get_visibility_inlines_hidden(ctx, target) {
+      f.write_all(concat!("      <AdditionalOptions>-fvisibility-inlines-hidden %(AdditionalOptions)",
+                          "</AdditionalOptions>\r\n").as_bytes())?;
+    }
+    if let Some(pic) = flags::get_pic(ctx, target) {
+      write!(f, "      <PositionIndependentCode>{}</PositionIndependentCode>\r\n", get_bool(pic))?;
+    }
+
+    // The item definition group already selects the profile, only the architecture is left.
+    let (_, common) = flags::get_disable_warnings(ctx, index, None, PlatformType::Android, Architecture::Any);
+    for (arch, warnings) in get_arch_values(ctx, proj, |a| {
+      flags::get_disable_warnings(ctx, index, Some(prof), PlatformType::Android, a).1
+    }) {
+      let condition = arch.map(|a| format!("'$(Platform)'=='{}'", proj.get_arch_platform(a)));
+      write_disable_warnings(&mut f, condition.as_deref(), &[], &[&common[..], &warnings].concat(), true)?;
+    }
+
+    f.write_all(concat!("    </ClCompile>\r\n",
+                        "    <Link>\r\n",
+                        // TODO hardcoded
+                        "      <LibraryDependencies>android;log;EGL;GLESv3;").as_bytes())?;
+
+    for &extend_index in &ctx.extends[index] {
+      write_lib_names(&mut f, ctx, ctx.get_target(extend_index), PlatformType::Android, "")?;
+    }
+    write_lib_names(&mut f, ctx, target, PlatformType::Android, "")?;
+
+    f.write_all(concat!("%(LibraryDependencies)</LibraryDependencies>\r\n",
+                        "      <AdditionalDependencies>").as_bytes())?;
+
+    for &extend_index in &ctx.extends[index] {
+      write_lib_files(&mut f, ctx, prefix, &ANDROID_VARS, ctx.get_target(extend_index))?;
     }
-    else {
-      write!(f, "    <{} Include=\"{}\\{}\" />\r\n", element, prefix, filename)?;
+    write_lib_files(&mut f, ctx, prefix, &ANDROID_VARS, target)?;
+
+    f.write_all(concat!("%(AdditionalDependencies)</AdditionalDependencies>\r\n",
+                        "      <AdditionalLibraryDirectories>").as_bytes())?;
+
+    for &extend_index in &ctx.extends[index] {
+      write_lib_dirs(&mut f, prefix, &ANDROID_VARS, ctx.get_target(extend_index))?;
     }
+    write_lib_dirs(&mut f, prefix, &ANDROID_VARS, target)?;
+
+    f.write_all(concat!("%(AdditionalLibraryDirectories)</AdditionalLibraryDirectories>\r\n",
+                        "    </Link>\r\n").as_bytes())?;
+
+    write_version_header(&mut f, ctx, prof)?;
+    f.write_all(b"  </ItemDefinitionGroup>\r\n")?;
+  }
+
+  f.write_all(b"  <ItemGroup>\r\n")?;
+  for &extend_index in &ctx.extends[index] {
+    write_files(&mut f, ctx, extend_index, prefix, ctx.get_target(extend_index),
+                PlatformType::Android, None)?;
+  }
+  write_files(&mut f, ctx, index, prefix, target, PlatformType::Android, None)?;
+  write_embeds(&mut f, ctx, index, prefix)?;
+  write_schemas(&mut f, ctx, index, prefix, PlatformType::Android)?;
+  write_shaders(&mut f, ctx, index, prefix, PlatformType::Android)?;
+  write_test_main(&mut f, ctx, index, target)?;
+
+  if is_app {
+    f.write_all(concat!("    <ClInclude Include=\"$(VS_NdkRoot)\\sources\\android\\native_app_glue\\",
+                        "android_native_app_glue.h\" />\r\n",
+                        "    <ClCompile Include=\"$(VS_NdkRoot)\\sources\\android\\native_app_glue\\",
+                        "android_native_app_glue.c\" />\r\n").as_bytes())?;
   }
 
+  f.write_all(b"  </ItemGroup>\r\n")?;
+
+  write_proj_import(&mut f, r#"$(VCTargetsPath)\Microsoft.Cpp.targets"#)?;
+  f.write_all(b"  <ImportGroup Label=\"ExtensionTargets\" />\r\n")?;
+
+  f.write_all(b"</Project>\r\n")?;
+  f.flush()?;
+  Ok(())
+}
+
+fn write_android_includes<W>(f: &mut W, prefix: &str, target: &Target) -> IO where W: Write {
+  for &inc in &*target.settings.include_dirs {
+    write!(f, "{};", get_dir(prefix, &ANDROID_VARS, inc))?;
+  }
+  Ok(())
+}
+
+fn write_packaging_proj(ctx: &Context, proj: &Proj, native: &Proj) -> IO {
+  assert!(proj.kind == ProjKind::Android);
+  let name      = ctx.get_target_name(proj.index);
+  let path      = ctx.build_dir.join(&proj.name);
+  let api_level = get_android_api_level(ctx, proj.target.unwrap());
+  std::fs::create_dir_all(&path)?;
+
+  write_packaging_manifest(ctx, ctx.get_target(proj.index), &path, name, api_level)?;
+  write_packaging_ant(ctx, &path, name, api_level)?;
+
+  let mut f = proj.create(ctx, proj.ext())?;
+  write_proj_configs(&mut f, ctx, proj)?;
+
+  write!(f, concat!("  <PropertyGroup Label=\"Globals\">\r\n",
+                    "    <RootNamespace>{name}</RootNamespace>\r\n",
+                    "    <MinimumVisualStudioVersion>14.0</MinimumVisualStudioVersion>\r\n",
+                    "    <ProjectVersion>1.0</ProjectVersion>\r\n",
+                    "    <ProjectGuid>{{{uuid}}}</ProjectGuid>\r\n",
+                    "  </PropertyGroup>\r\n"),
+         name = name,
+         uuid = proj.uuid)?;
+
+  write_proj_import(&mut f, r#"$(AndroidTargetsPath)\Android.Default.props"#)?;
+
+  for prof in &ctx.profiles {
+    write!(f, concat!("  <PropertyGroup Condition=\"'$(Configuration)'=='{profile}'\">\r\n",
+                      "    <UseDebugLibraries>{debug:?}</UseDebugLibraries>\r\n",
+                      "    <ConfigurationType>Application</ConfigurationType>\r\n",
+                      "  </PropertyGroup>\r\n"),
+           profile = prof,
+           debug   = ctx.get_base_profile(prof) != "Release")?;
+  }
+
+  write_proj_import(&mut f, r#"$(AndroidTargetsPath)\Android.props"#)?;
+
+  write!(f, concat!("  <ImportGroup Label=\"ExtensionSettings\" />\r\n",
+                    "  <PropertyGroup Label=\"UserMacros\" />\r\n",
+                    "  <PropertyGroup>\r\n",
+                    "    <TargetName>$(RootNamespace)</TargetName>\r\n",
+                    "  </PropertyGroup>\r\n",
+                    "  <ItemDefinitionGroup>\r\n",
+                    "    <AntPackage>\r\n",
+                    "      <AndroidAppLibName>$(RootNamespace)</AndroidAppLibName>\r\n",
+                    "    </AntPackage>\r\n",
+                    "  </ItemDefinitionGroup>\r\n",
+                    "  <ItemGroup>\r\n",
+                    "    <AndroidManifest Include=\"{dir}\\AndroidManifest.xml\" />\r\n",
+                    "    <AntBuildXml Include=\"{dir}\\build.xml\" />\r\n",
+                    "    <AntProjectPropertiesFile Include=\"{dir}\\project.properties\" />\r\n",
+                    "    <Content Include=\"{dir}\\res\\values\\strings.xml\" />\r\n",
+                    "  </ItemGroup>\r\n",
+                    "  <ItemGroup>\r\n",
+                    "    <ProjectReference Include=\"{native_name}.{native_ext}\">\r\n",
+                    "      <Project>{{{native_uuid}}}</Project>\r\n",
+                    "    </ProjectReference>\r\n",
+                    "  </ItemGroup>\r\n"),
+         dir         = proj.name,
+         native_name = native.name,
+         native_ext  = native.ext(),
+         native_uuid = native.uuid)?;
+
+  write_proj_import(&mut f, r#"$(AndroidTargetsPath)\Android.targets"#)?;
+  f.write_all(b"  <ImportGroup Label=\"ExtensionTargets\" />\r\n")?;
+
+  f.write_all(b"</Project>\r\n")?;
+  f.flush()?;
+  Ok(())
+}
+
+fn write_packaging_manifest<'a>(ctx: &Context<'a>, target: &Target<'a>, path: &Path, name: &str,
+                                api_level: u8) -> IO
+{
+  let android = target.android.or(&ctx.project.android);
+  let mut f   = create_file(ctx, path.join("AndroidManifest.xml"))?;
+
+  write!(f, concat!("<?xml version=\"1.0\" encoding=\"utf-8\"?>\r\n",
+                    "<manifest xmlns:android=\"http://schemas.android.com/apk/res/android\"\r\n",
+                    "          package=\"{application_id}\"\r\n",
+                    "          android:versionCode=\"{version_code}\"\r\n",
+                    "          android:versionName=\"{version_name}\">\r\n",
+                    "  <uses-sdk android:minSdkVersion=\"{api_level}\"",
+                    " android:targetSdkVersion=\"{api_level}\" />\r\n",
+                    "  <application android:label=\"@string/app_name\" android:hasCode=\"false\">\r\n",
+                    "    <activity android:name=\"android.app.NativeActivity\"\r\n",
+                    "              android:label=\"@string/app_name\"\r\n",
+                    "              android:configChanges=\"keyboardHidden|keyboard|orientation|screenSize\">\r\n",
+                    "      <meta-data android:name=\"android.app.lib_name\" android:value=\"{name}\" />\r\n",
+                    "      <intent-filter>\r\n",
+                    "        <action android:name=\"android.intent.action.MAIN\" />\r\n",
+                    "        <category android:name=\"android.intent.category.LAUNCHER\" />\r\n",
+                    "      </intent-filter>\r\n",
+                    "    </activity>\r\n",
+                    "  </application>\r\n",
+                    "</manifest>\r\n"),
+         application_id = flags::android_application_id(ctx, target),
+         version_code   = android.version_code.unwrap_or(flags::ANDROID_VERSION_CODE),
+         version_name   = android.version_name.unwrap_or(flags::ANDROID_VERSION_NAME),
+         api_level      = api_level,
+         name           = name)?;
+  f.flush()?;
+
+  let res = path.join("res").join("values");
+  std::fs::create_dir_all(&res)?;
+
+  let mut f = create_file(ctx, res.join("strings.xml"))?;
+  write!(f, concat!("<?xml version=\"1.0\" encoding=\"utf-8\"?>\r\n",
+                    "<resources>\r\n",
+                    "  <string name=\"app_name\">{}</string>\r\n",
+                    "</resources>\r\n"),
+         ctx.project.name)?;
+  f.flush()?;
+
+  Ok(())
+}
+
+fn write_packaging_ant(ctx: &Context, path: &Path, name: &str, api_level: u8) -> IO {
+  let mut f = create_file(ctx, path.join("build.xml"))?;
+  write!(f, concat!("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\r\n",
+                    "<project name=\"{}\" default=\"help\">\r\n",
+                    "  <property file=\"local.properties\" />\r\n",
+                    "  <property file=\"ant.properties\" />\r\n",
+                    "  <property environment=\"env\" />\r\n",
+                    "  <condition property=\"sdk.dir\" value=\"${{env.ANDROID_HOME}}\">\r\n",
+                    "    <isset property=\"env.ANDROID_HOME\" />\r\n",
+                    "  </condition>\r\n",
+                    "  <loadproperties srcFile=\"project.properties\" />\r\n",
+                    "  <fail message=\"sdk.dir is missing.\" unless=\"sdk.dir\" />\r\n",
+                    "  <import file=\"custom_rules.xml\" optional=\"true\" />\r\n",
+                    "  <import file=\"${{sdk.dir}}/tools/ant/build.xml\" />\r\n",
+                    "</project>\r\n"),
+         name)?;
+  f.flush()?;
+
+  let mut f = create_file(ctx, path.join("project.properties"))?;
+  write!(f, "target=android-{}\r\n", api_level)?;
+  f.flush()?;
+
   Ok(())
 }
 
@@ -679,7 +1736,7 @@ fn write_files<W>(f: &mut W, ctx: &Context, index: usize,
 // -----------------------------------------------------------------------------
 
 fn write_items(ctx: &Context, proj: &Proj) -> IO {
-  let mut f = proj.create(&ctx.build_dir, proj.ext())?;
+  let mut f = proj.create(ctx, proj.ext())?;
   write!(f, concat!("  <PropertyGroup Label=\"Globals\">\r\n",
                     "    <ItemsProjectGuid>{{{}}}</ItemsProjectGuid>\r\n",
                     "  </PropertyGroup>\r\n",
@@ -703,13 +1760,12 @@ fn write_items(ctx: &Context, proj: &Proj) -> IO {
 // -----------------------------------------------------------------------------
 
 fn write_sln(ctx: &Context, projs: &[Proj], tools: &Tools) -> IO {
-  let mut f = BufWriter::new(File::create({
-    let mut path = ctx.build_dir.join(&ctx.project.name);
-    path.set_extension("sln");
-    path
-  })?);
+  let mut path = ctx.build_dir.join(&ctx.project.name);
+  path.set_extension("sln");
 
-  f.write_all(b"\xEF\xBB\xBF\r\n")?;
+  let mut f = TextFile::create(ctx, "vs", TextFormat { bom: Some(true), ..text::CRLF }, path)?;
+
+  f.write_all(b"\r\n")?;
   write!(f, concat!("Microsoft Visual Studio Solution File, Format Version 12.00\r\n",
                     "# Visual Studio Version {0}\r\n",
                     "VisualStudioVersion = {0}.{1}\r\n",
@@ -738,9 +1794,19 @@ fn write_sln(ctx: &Context, projs: &[Proj], tools: &Tools) -> IO {
 
   f.write_all(b"Global\r\n")?;
 
+  // Solution platforms are the union of every project's architectures.
+  let archs = projs.iter().fold(Vec::new(), |mut archs, proj| {
+    for arch in proj.get_architectures(ctx) {
+      if !archs.contains(&arch) {
+        archs.push(arch);
+      }
+    }
+    archs
+  });
+
   f.write_all(b"  GlobalSection(SolutionConfigurationPlatforms) = preSolution\r\n")?;
   for prof in &ctx.profiles {
-    for arch in ARCHITECTURES {
+    for arch in &archs {
       write!(f, "    {0}|{1} = {0}|{1}\r\n", prof, get_arch_name(*arch))?;
     }
   }
@@ -748,12 +1814,28 @@ fn write_sln(ctx: &Context, projs: &[Proj], tools: &Tools) -> IO {
 
   f.write_all(b"  GlobalSection(ProjectConfigurationPlatforms) = postSolution\r\n")?;
   for proj in projs {
+    let proj_archs = proj.get_architectures(ctx);
     for prof in &ctx.profiles {
-      for arch in ARCHITECTURES {
+      for &arch in &archs {
+        // Projects without this architecture are mapped to one they do have, but not built.
+        let supported = proj_archs.contains(&arch);
+        let proj_arch = match supported {
+          true  => arch,
+          false => proj_archs[0]
+        };
+
         // TODO dont enable all 3 for everything
-        write_sln_config(&mut f, &proj.uuid, &prof, *arch, "ActiveCfg")?;
-        write_sln_config(&mut f, &proj.uuid, &prof, *arch, "Build.0")?;
-        // write_sln_config(&mut f, &proj.uuid, &prof, *arch, "Deploy.0")?;
+        // Benchmarks are only built by the optimized profiles.
+        let built = proj.target.map_or(true, |t| benchmarks::is_built(ctx, t, prof, proj.get_platform()));
+
+        write_sln_config(&mut f, proj, &prof, arch, proj_arch, "ActiveCfg")?;
+        if supported && built {
+          write_sln_config(&mut f, proj, &prof, arch, proj_arch, "Build.0")?;
+
+          if proj.kind == ProjKind::Android {
+            write_sln_config(&mut f, proj, &prof, arch, proj_arch, "Deploy.0")?;
+          }
+        }
       }
     }
   }
@@ -768,7 +1850,7 @@ fn write_sln(ctx: &Context, projs: &[Proj], tools: &Tools) -> IO {
   f.write_all(b"  EndGlobalSection\r\n")?;
 
   f.write_all(b"  GlobalSection(ExtensibilityGlobals) = postSolution\r\n")?;
-  write!(f, "    SolutionGuid = {{{}}}\r\n", random_uuid())?;
+  write!(f, "    SolutionGuid = {{{}}}\r\n", stable_uuid(&[ctx.project.name, ".sln"]))?;
   f.write_all(b"  EndGlobalSection\r\n")?;
 
   f.write_all(b"EndGlobal\r\n")?;
@@ -780,13 +1862,63 @@ fn write_proj_import<W>(f: &mut W, v: &str) -> IO where W: Write {
   write!(f, "  <Import Project=\"{}\" />\r\n", v)
 }
 
-fn write_sln_config<W>(f: &mut W, uuid: &str, prof: &str, arch: Architecture,
-                       action: &str) -> IO where W: Write
+fn write_proj_configs<W>(f: &mut W, ctx: &Context, proj: &Proj) -> IO where W: Write {
+  f.write_all(b"  <ItemGroup Label=\"ProjectConfigurations\">\r\n")?;
+
+  for arch in proj.get_architectures(ctx) {
+    for prof in &ctx.profiles {
+      write!(f, concat!("    <ProjectConfiguration Include=\"{profile}|{platform}\">\r\n",
+                        "       <Configuration>{profile}</Configuration>\r\n",
+                        "       <Platform>{platform}</Platform>\r\n",
+                        "    </ProjectConfiguration>\r\n"),
+             profile  = prof,
+             platform = proj.get_arch_platform(arch))?;
+    }
+  }
+
+  f.write_all(b"  </ItemGroup>\r\n")
+}
+
+fn write_proj_sheets<W>(f: &mut W, ctx: &Context, shared: bool, masm: bool) -> IO where W: Write {
+  f.write_all(b"  <ImportGroup Label=\"ExtensionSettings\">\r\n")?;
+  if masm {
+    f.write_all(b"    <Import Project=\"$(VCTargetsPath)\\BuildCustomizations\\masm.props\" />\r\n")?;
+  }
+  f.write_all(b"  </ImportGroup>\r\n")?;
+
+  f.write_all(b"  <ImportGroup Label=\"Shared\">\r\n  </ImportGroup>\r\n")?;
+
+  write!(f, concat!("  <ImportGroup Label=\"PropertySheets\">\r\n",
+                    "    <Import Project=\"{path}\" Condition=\"exists('{path}')\" ",
+                    "Label=\"LocalAppDataPlatform\" />\r\n"),
+         path = "$(UserRootDir)\\Microsoft.Cpp.$(Platform).user.props")?;
+
+  // Generated sheets come first, the local sheet is never written by janky and
+  // is where hand-tuned overrides go so they survive regeneration.
+  if shared {
+    write!(f, "    <Import Project=\"{}\" />\r\n", get_props_name(ctx, None))?;
+
+    for prof in &ctx.profiles {
+      write!(f, "    <Import Project=\"{}\" Condition=\"'$(Configuration)'=='{}'\" />\r\n",
+             get_props_name(ctx, Some(prof)), prof)?;
+    }
+
+    write!(f, "    <Import Project=\"{0}\" Condition=\"exists('{0}')\" />\r\n",
+           get_props_name(ctx, Some("Local")))?;
+  }
+
+  f.write_all(b"  </ImportGroup>\r\n")?;
+
+  f.write_all(b"  <PropertyGroup Label=\"UserMacros\" />\r\n")
+}
+
+fn write_sln_config<W>(f: &mut W, proj: &Proj, prof: &str, arch: Architecture,
+                       proj_arch: Architecture, action: &str) -> IO where W: Write
 {
   write!(f, "    {{{uuid}}}.{profile}|{arch}.{action} = {profile}|{platform}\r\n",
-         uuid     = uuid,
+         uuid     = proj.uuid,
          action   = action,
          profile  = prof,
          arch     = get_arch_name(arch),
-         platform = get_arch_platform(arch))
+         platform = proj.get_arch_platform(proj_arch))
 }
@@ -0,0 +1,133 @@
+//! WiX v4 source of a Windows installer, written as `<project>.wxs` when the
+//! project has a `[wix]` table.
+//!
+//! The installer copies the application, the shared libraries it depends on and
+//! its resources to Program Files, and adds a Start Menu shortcut. Binaries are
+//! located from the `BuildDir` and `Profile` preprocessor variables, so a single
+//! source builds the MSI of any profile, see `janky build`.
+
+use std::io::Write;
+
+use crate::ctx::{Context, Generator, PlatformType, RunResult, StrError, TargetType};
+use super::{get_resources, stable_uuid};
+use super::text::{self, TextFile};
+
+pub struct Wix;
+
+impl Generator for Wix {
+  fn supports_platform(&self, p: PlatformType) -> bool {
+    assert!(p != PlatformType::Any);
+    p == PlatformType::Windows
+  }
+
+  fn run(&self, ctx: &Context) -> RunResult {
+    let wix = match &ctx.project.wix {
+      None    => {
+        ctx.log.verbose("WiX: skipped, the project has no [wix] table");
+        return Ok(());
+      },
+      Some(x) => x
+    };
+
+    let index = ctx.project.targets.iter().position(|(name, t)| {
+      t.target_type == TargetType::Application && t.filter.matches_platform(PlatformType::Windows) &&
+        wix.target.map_or(true, |x| x == *name)
+    }).ok_or_else(|| StrError("WiX: no Windows application to install".to_string()))?;
+
+    let name         = ctx.get_target_name(index);
+    let product      = ctx.project.name;
+    let manufacturer = wix.manufacturer.unwrap_or(product);
+    let upgrade_code = match wix.upgrade_code {
+      Some(x) => x.trim_matches(|c| c == '{' || c == '}').to_string(),
+      None    => stable_uuid(&[product, ".wxs"])
+    };
+
+    let version = get_version(ctx);
+
+    let mut path = ctx.build_dir.join(product);
+    path.set_extension("wxs");
+    let mut f = TextFile::create(ctx, "wix", text::CRLF, path)?;
+
+    write!(f, concat!("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n",
+                      "<!-- Generated by janky, do not edit. -->\n",
+                      "<Wix xmlns=\"http://wixtoolset.org/schemas/v4/wxs\">\n",
+                      "  <Package Name=\"{product}\" Manufacturer=\"{manufacturer}\" Version=\"{version}\"\n",
+                      "           UpgradeCode=\"{{{upgrade_code}}}\" Scope=\"perMachine\">\n",
+                      "    <MajorUpgrade DowngradeErrorMessage=\"A newer {product} is installed.\" />\n",
+                      "    <MediaTemplate EmbedCab=\"yes\" />\n\n"),
+           product      = product,
+           manufacturer = manufacturer,
+           version      = version,
+           upgrade_code = upgrade_code)?;
+
+    let icon = match wix.icon {
+      None    => "",
+      Some(x) => {
+        write!(f, concat!("    <Icon Id=\"Product.ico\" SourceFile=\"{}\" />\n",
+                          "    <Property Id=\"ARPPRODUCTICON\" Value=\"Product.ico\" />\n\n"),
+               ctx.input_dir.join(x).display())?;
+        " Icon=\"Product.ico\""
+      }
+    };
+
+    write!(f, concat!("    <StandardDirectory Id=\"ProgramFiles64Folder\">\n",
+                      "      <Directory Id=\"INSTALLFOLDER\" Name=\"{}\" />\n",
+                      "    </StandardDirectory>\n"),
+           product)?;
+    f.write_all(b"    <StandardDirectory Id=\"ProgramMenuFolder\" />\n")?;
+    if wix.desktop_shortcut {
+      f.write_all(b"    <StandardDirectory Id=\"DesktopFolder\" />\n")?;
+    }
+
+    // Advertised shortcuts need no registry key to track their installation.
+    let bin = "$(var.BuildDir)\\x64\\$(var.Profile)";
+    write!(f, concat!("\n    <ComponentGroup Id=\"ProductFiles\" Directory=\"INSTALLFOLDER\">\n",
+                      "      <Component>\n",
+                      "        <File Source=\"{bin}\\{name}\\{name}.exe\" KeyPath=\"yes\">\n",
+                      "          <Shortcut Name=\"{product}\" Directory=\"ProgramMenuFolder\" ",
+                      "WorkingDirectory=\"INSTALLFOLDER\" Advertise=\"yes\"{icon} />\n"),
+           bin     = bin,
+           name    = name,
+           product = product,
+           icon    = icon)?;
+    if wix.desktop_shortcut {
+      write!(f, concat!("          <Shortcut Name=\"{}\" Directory=\"DesktopFolder\" ",
+                        "WorkingDirectory=\"INSTALLFOLDER\" Advertise=\"yes\"{} />\n"),
+             product, icon)?;
+    }
+    f.write_all(concat!("        </File>\n",
+                        "      </Component>\n").as_bytes())?;
+
+    // Shared libraries are built to their own folders, installed next to the application.
+    let target = ctx.get_target(index);
+    let libs = ctx.project.targets.iter().filter(|(lib, t)| {
+      t.target_type == TargetType::SharedLibrary && t.filter.matches_platform(PlatformType::Windows) &&
+        (target.depends.contains(lib) || target.extends.contains(lib))
+    });
+    for (lib, _) in libs {
+      write!(f, "      <Component><File Source=\"{0}\\{1}\\{1}.dll\" /></Component>\n", bin, lib)?;
+    }
+
+    // Resources are copied next to the application, without their folders.
+    let resources = get_resources(ctx, index).into_iter()
+      .filter(|x| target.builds_file(&x.path, PlatformType::Windows));
+    for file in resources {
+      write!(f, "      <Component><File Source=\"{}\" /></Component>\n",
+             ctx.input_dir.join(&file.path).display())?;
+    }
+
+    f.write_all(concat!("    </ComponentGroup>\n\n",
+                        "    <Feature Id=\"Main\">\n",
+                        "      <ComponentGroupRef Id=\"ProductFiles\" />\n",
+                        "    </Feature>\n",
+                        "  </Package>\n",
+                        "</Wix>\n").as_bytes())?;
+    f.flush()?;
+    Ok(())
+  }
+}
+
+/// Windows Installer versions only have numeric major, minor and build fields.
+pub fn get_version<'a>(ctx: &Context<'a>) -> &'a str {
+  ctx.project.version.split(['-', '+']).next().unwrap()
+}
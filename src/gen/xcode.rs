@@ -28,7 +28,7 @@
 //! by type, with comments as delimiters between different types. A comment is
 //! also added after an object identifier to describe the object.
 //!
-//! ```
+//! ```text
 //! /* Begin <SECTION-NAME> section */
 //! <OBJECT-ID> /* <OBJECT-NAME> */ = <OBJECT-PROPERTIES-DICTIONARY>,
 //! ...
@@ -75,13 +75,20 @@ use serde::Serialize;
 use std::borrow::Cow;
 use std::collections::HashMap;
 use std::fmt::Write as FmtWrite;
-use std::fs::{File, create_dir_all, remove_file};
-use std::io::{BufWriter, Write as IOWrite};
+use std::fs::create_dir_all;
+use std::io::{Result as IOResult, Write as IOWrite};
 use std::path::{Path, PathBuf};
 use std::str::from_utf8;
 use std::sync::atomic::{AtomicU32, Ordering};
 
-use crate::ctx::{Context, Generator, PlatformType, RunResult, StrError, Target, TargetFiles, TargetType};
+use crate::ctx::{Architecture, Context, DebugInfo, FloatModel, Generator, IndentStyle, Lto, PlatformType,
+                 RunResult, Simd, StdLib, StrError, Target, TargetFiles, TargetType, TextFormat, HASH_INIT,
+                 hash_bytes};
+use super::{flags, get_resources, link_file};
+use super::subst::Vars;
+use super::symbols;
+use super::text::{self, TextFile};
+use super::{benchmarks, codegen, embed, shaders, tests, version};
 
 const PLATFORMS: &[PlatformType] = &[
   PlatformType::MacOS,
@@ -131,30 +138,68 @@ impl Generator for XCode {
     create_dir_all(&path)?;
     path.push("project.pbxproj");
     write_pbx(ctx, &path, team)?;
+
+    if let Some(launcher) = ctx.project.settings.compiler_launcher {
+      write_launcher(ctx, &ctx.build_dir.join(LAUNCHER), launcher)?;
+    }
+
+    warn_unsupported(ctx);
     Ok(())
   }
 }
 
+/// Xcode has no launcher setting, CC is pointed to a script wrapping clang instead.
+const LAUNCHER: &str = "jank-cc";
+
+fn write_launcher(ctx: &Context, path: &Path, launcher: &str) -> IO {
+  let mut f = create_file(ctx, path)?;
+  write!(f, concat!("#!/bin/sh\n",
+                    "exec {} \"$(xcrun -f clang)\" \"$@\"\n"),
+         launcher)?;
+  f.flush()?;
+
+  #[cfg(unix)]
+  f.set_executable()?;
+  Ok(())
+}
+
+/// Reports the settings which have no equivalent in the generated project yet.
+fn warn_unsupported(ctx: &Context) {
+  for index in 0 .. ctx.project.targets.len() {
+    let target = ctx.get_target(index);
+    let name   = ctx.get_target_name(index);
+
+    if flags::get_pch(ctx, target).is_some() {
+      ctx.warn(format!("Xcode: precompiled headers of target {} are ignored", name));
+    }
+
+    if !flags::get_force_includes(ctx, index).is_empty() {
+      ctx.warn(format!("Xcode: forced includes of target {} are ignored", name));
+    }
+  }
+}
+
 
 // Utilities
 // -----------------------------------------------------------------------------
 
-type IO = std::io::Result<()>;
+type IO = IOResult<()>;
+
+fn create_file<P>(ctx: &Context, path: P) -> IOResult<TextFile> where P: AsRef<Path> {
+  TextFile::create(ctx, "xcode", text::LF, path)
+}
 
 static NEXT_ID_PREFIX: AtomicU32 = AtomicU32::new(0);
 
-fn random_id() -> String {
-  // TODO deterministic IDs? try and keep the same IDs between generator runs
-  use rand::RngCore;
-  let mut bytes: [u8; 12] = unsafe { std::mem::MaybeUninit::uninit().assume_init() };
-  rand::thread_rng().fill_bytes(&mut bytes[4..]);
+/// Objects are created in the same order on every run, their IDs are derived
+/// from that order so unchanged projects are generated byte for byte.
+fn next_id() -> String {
+  let mut bytes = [0u8; 12];
 
   // Use a counter as the first ID bytes to try and prevent Xcode from reordering objects.
   let prefix = NEXT_ID_PREFIX.fetch_add(1, Ordering::Relaxed);
-  bytes[0] =  (prefix >> 24)         as u8;
-  bytes[1] = ((prefix >> 16) & 0xFF) as u8;
-  bytes[2] = ((prefix >> 8)  & 0xFF) as u8;
-  bytes[3] =  (prefix        & 0xFF) as u8;
+  bytes[.. 4].copy_from_slice(&prefix.to_be_bytes());
+  bytes[4 ..].copy_from_slice(&hash_bytes(HASH_INIT, &prefix.to_be_bytes()).to_be_bytes());
 
   let mut id = String::with_capacity(24);
   for b in &bytes {
@@ -172,7 +217,7 @@ fn hex_char(b: u8) -> char {
 }
 
 fn quote(s: &str) -> Cow<'_, str> {
-  match s.is_empty() || s.contains(' ') || s.contains('-') || s.contains('=') {
+  match s.is_empty() || s.contains(' ') || s.contains('-') || s.contains('=') || s.contains('[') {
     true  => Cow::Owned(["\"", s, "\""].join("")),
     false => Cow::Borrowed(s)
   }
@@ -198,6 +243,9 @@ fn get_file_type(ext: &'_ str) -> (Phase, &'static str) {
     "cc" | "cpp"   => (Phase::Source,   "sourcecode.cpp.cpp"),
     "m"            => (Phase::Source,   "sourcecode.c.objc"),
     "mm"           => (Phase::Source,   "sourcecode.cpp.objcpp"),
+    "s" | "S"      => (Phase::Source,   "sourcecode.asm"),
+    "nasm"         => (Phase::Source,   "sourcecode.nasm"),
+    "asm"          => (Phase::None,     "sourcecode.asm.asm"), // MASM syntax
     "plist"        => (Phase::Resource, "text.plist.xml"),
     "bmp"          => (Phase::None,     "image.bmp"),
     "jpg" | "jpeg" => (Phase::None,     "image.jpeg"),
@@ -269,6 +317,12 @@ impl<'a> Group<'a> {
   }
 
   fn push_path(&mut self, id: &str, path: &'a Path) {
+    // Files from source roots on other volumes are referenced by their absolute path.
+    if path.is_absolute() {
+      self.push(id, path.file_name().unwrap().to_str().unwrap());
+      return;
+    }
+
     let mut parts = path.iter();
     let mut curr  = parts.next().unwrap();
     let mut next  = parts.next();
@@ -304,7 +358,7 @@ impl<'a> Group<'a> {
       g.write(f)?;
     }
 
-    self.id = random_id();
+    self.id = next_id();
 
     match self.path.or(self.name) {
       None        => write!(f, "\t\t{} = {{\n",          self.id)?,
@@ -348,7 +402,7 @@ struct CfgList {
 impl CfgList {
   fn new() -> Self {
     CfgList {
-      id:   random_id(),
+      id:   next_id(),
       cfgs: String::new()
     }
   }
@@ -377,15 +431,65 @@ impl CfgList {
 fn build_file(phase: &mut String, files: &mut String, file_name: &str,
               ref_id: &str, phase_name: &str)
 {
-  let id = random_id();
+  build_file_with_flags(phase, files, file_name, ref_id, phase_name, "");
+}
+
+fn build_file_with_flags(phase: &mut String, files: &mut String, file_name: &str,
+                         ref_id: &str, phase_name: &str, compiler_flags: &str)
+{
+  let settings = match compiler_flags.is_empty() {
+    true  => String::new(),
+    false => format!("COMPILER_FLAGS = \"{}\"; ", compiler_flags.replace('"', "\\\""))
+  };
+  build_file_with_settings(phase, files, file_name, ref_id, phase_name, &settings);
+}
+
+fn build_file_with_settings(phase: &mut String, files: &mut String, file_name: &str,
+                            ref_id: &str, phase_name: &str, settings: &str)
+{
+  let id = next_id();
   write!(phase, "\t\t\t\t{} /* {} in {} */,\n", id, file_name, phase_name).unwrap();
   write!(files, concat!("\t\t{id} /* {name} in {phase} */ = {{",
                         "isa = PBXBuildFile; ",
-                        "fileRef = {refid} /* {name} */; }};\n"),
+                        "fileRef = {refid} /* {name} */; "),
          id    = id,
          name  = file_name,
          refid = ref_id,
          phase = phase_name).unwrap();
+
+  if !settings.is_empty() {
+    write!(files, "settings = {{{}}}; ", settings).unwrap();
+  }
+
+  files.push_str("};\n");
+}
+
+/// Per-file settings expressed as Clang flags, since build files have no other
+/// way to override build settings.
+fn file_compiler_flags(target: &Target, path: &Path) -> String {
+  let settings = match target.get_file_settings(path) {
+    None    => return String::new(),
+    Some(x) => x
+  };
+
+  let mut s = flags::gcc_file_flags(&settings);
+
+  for def in &*settings.defines {
+    if !s.is_empty() {
+      s.push(' ');
+    }
+    s.push_str("-D");
+    s.push_str(def);
+  }
+
+  if settings.compile_as_c == Some(true) {
+    if !s.is_empty() {
+      s.push(' ');
+    }
+    s.push_str("-x c");
+  }
+
+  s
 }
 
 fn build_files(sources: &mut String, resources: &mut String, files: &mut String,
@@ -393,7 +497,7 @@ fn build_files(sources: &mut String, resources: &mut String, files: &mut String,
                target_files: &TargetFiles, target: &Target)
 {
   for file_info in target_files {
-    if file_info.meta.is_dir() || !target.match_file(&file_info.path, platform) {
+    if file_info.meta.is_dir() || !target.builds_file(&file_info.path, platform) {
       continue;
     }
     let name = file_info.name();
@@ -401,7 +505,8 @@ fn build_files(sources: &mut String, resources: &mut String, files: &mut String,
 
     match file.phase {
       Phase::None     => {},
-      Phase::Source   => build_file(sources,   files, name, &file.id, "Sources"),
+      Phase::Source   => build_file_with_flags(sources, files, name, &file.id, "Sources",
+                                               &file_compiler_flags(target, &file_info.path)),
       Phase::Resource => build_file(resources, files, name, &file.id, "Resources")
     }
   }
@@ -426,16 +531,47 @@ fn settings_list(prop: &str, is_open: &mut bool, s: &mut String, list: &[&str])
   }
 }
 
-fn library_paths(has_libraries: &mut bool, s: &mut String, libs: &[&str]) {
-  settings_list("LIBRARY_SEARCH_PATHS", has_libraries, s, libs);
+fn library_paths(has_libraries: &mut bool, s: &mut String, vars: &Vars, libs: &[&str]) {
+  let libs = libs.iter().map(|x| vars.expand(x)).collect::<Vec<String>>();
+  settings_list("LIBRARY_SEARCH_PATHS", has_libraries, s,
+                &libs.iter().map(String::as_str).collect::<Vec<&str>>());
+}
+
+fn header_paths(has_includes: &mut bool, s: &mut String, vars: &Vars, incs: &[&str]) {
+  let incs = incs.iter().map(|x| vars.expand(x)).collect::<Vec<String>>();
+  settings_list("HEADER_SEARCH_PATHS", has_includes, s,
+                &incs.iter().map(String::as_str).collect::<Vec<&str>>());
+}
+
+fn define_macros(has_defines: &mut bool, s: &mut String, vars: &Vars, defs: &[&str]) {
+  let defs = defs.iter().map(|x| vars.expand(x)).collect::<Vec<String>>();
+  settings_list("GCC_PREPROCESSOR_DEFINITIONS", has_defines, s,
+                &defs.iter().map(String::as_str).collect::<Vec<&str>>());
 }
 
-fn header_paths(has_includes: &mut bool, s: &mut String, incs: &[&str]) {
-  settings_list("HEADER_SEARCH_PATHS", has_includes, s, incs);
+/// Xcode matches the patterns against full paths as well as file names, the
+/// absolute paths only exclude the files themselves and not their namesakes.
+fn excluded_files(has_excluded: &mut bool, s: &mut String, ctx: &Context, profile: &str,
+                  files: &TargetFiles, target: &Target)
+{
+  let paths = files.iter()
+    .filter(|info| {
+      info.meta.is_file() && target.get_file_settings(&info.path)
+        .map_or(false, |x| x.is_excluded_profile(profile))
+    })
+    .map(|info| escape_pattern(&ctx.input_dir.join(&info.path).to_string_lossy()))
+    .collect::<Vec<String>>();
+
+  settings_list("EXCLUDED_SOURCE_FILE_NAMES", has_excluded, s,
+                &paths.iter().map(String::as_str).collect::<Vec<&str>>());
 }
 
-fn define_macros(has_defines: &mut bool, s: &mut String, defs: &[&str]) {
-  settings_list("GCC_PREPROCESSOR_DEFINITIONS", has_defines, s, defs);
+/// Wildcards in file names match themselves as bracket expressions.
+fn escape_pattern(path: &str) -> String {
+  path.chars().map(|c| match c {
+    '*' | '?' | '[' => ['[', c, ']'].iter().collect(),
+    c               => c.to_string()
+  }).collect()
 }
 
 fn build_cfg<F>(cfg: &mut String, id: &str, name: &str, f: F) where F: FnOnce(&mut String) {
@@ -456,8 +592,8 @@ fn build_cfg<F>(cfg: &mut String, id: &str, name: &str, f: F) where F: FnOnce(&m
 // Assets
 // -----------------------------------------------------------------------------
 
-fn write_info_plist(path: &Path) -> IO {
-  let mut f = File::create(path)?;
+fn write_info_plist(ctx: &Context, path: &Path) -> IO {
+  let mut f = create_file(ctx, path)?;
 
   f.write_all(concat!(r#"<?xml version="1.0" encoding="UTF-8"?>"#, "\n",
                       r#"<!DOCTYPE plist PUBLIC "-//APPLE//DTD PLIST 1.0//EN" "#,
@@ -621,7 +757,8 @@ impl<'a> AssetContent<'a> {
   }
 }
 
-fn fold_asset<'a, 'b>(asset: &'b mut AssetContent<'a>, p: &ParsedAsset<'a>) where 'a: 'b {
+/// Returns whether the asset is known, others are left out of the catalog.
+fn fold_asset<'a, 'b>(asset: &'b mut AssetContent<'a>, p: &ParsedAsset<'a>) -> bool where 'a: 'b {
   // TODO reuse "App Icon", handle by platform
   match p.name {
     "icon" => {
@@ -648,11 +785,10 @@ fn fold_asset<'a, 'b>(asset: &'b mut AssetContent<'a>, p: &ParsedAsset<'a>) wher
       asset.brand("2320x720", "top-shelf-image-wide", "Top Shelf Image Wide.imageset")
         .image("tv", p);
     },
-    "Launch Image" => {
-      // ???
-    },
-    &_ => {}
+    "Launch Image" => return false, // ???
+    &_             => return false
   }
+  true
 }
 
 #[derive(Debug)]
@@ -771,30 +907,21 @@ fn parse_asset<'a>(path: &'a Path, s: &'a str) -> Option<ParsedAsset<'a>> {
   Some(ParsedAsset { path, name, size, idiom, layer, scale })
 }
 
-fn write_contents_json(root: &Path, path: &Path, content: &AssetContent) -> IO {
+fn write_contents_json(ctx: &Context, root: &Path, path: &Path, content: &AssetContent) -> IO {
   create_dir_all(&path)?;
 
-  let mut f = BufWriter::new(File::create(path.join("Contents.json"))?);
+  let mut f = create_file(ctx, path.join("Contents.json"))?;
   serde_json::to_writer_pretty(&mut f, content)?;
   f.flush()?;
 
   let src = pathdiff::diff_paths(&root, &path).unwrap();
 
   for image in &content.images {
-    let target = path.join(image.path.file_name().unwrap());
-    if target.symlink_metadata().is_ok() {
-      remove_file(&target)?;
-    }
-
-    #[cfg(unix)]
-    std::os::unix::fs::symlink(src.join(image.path), &target)?;
-
-    // #[cfg(windows)]
-    // std::os::windows::fs::symlink_file(src.join(image.path), &target)?;
+    link_file(ctx, &src.join(image.path), &path.join(image.path.file_name().unwrap()))?;
   }
 
   for child in &content.children {
-    write_contents_json(root, &path.join(child.name), &child)?;
+    write_contents_json(ctx, root, &path.join(child.name), &child)?;
   }
 
   Ok(())
@@ -802,9 +929,47 @@ fn write_contents_json(root: &Path, path: &Path, content: &AssetContent) -> IO {
 
 
 // PBXFileReference
+/// Shell script phase running the commands in order, only when its outputs are
+/// older than its inputs.
+fn write_script_phase(scripts: &mut String, build_phases: &mut String, name: &str,
+                      inputs: &[PathBuf], outputs: &[PathBuf], cmds: &[String])
+{
+  let id    = next_id();
+  let paths = |list: &[PathBuf]| list.iter()
+    .map(|x| format!("\t\t\t\t{},\n", quote(x.to_str().unwrap())))
+    .collect::<String>();
+  let script = cmds.iter()
+    .map(|x| format!("{}\\n", x.replace('"', "\\\"")))
+    .collect::<String>();
+
+  write!(scripts, concat!("\t\t{id} /* {name} */ = {{\n",
+                          "\t\t\tisa = PBXShellScriptBuildPhase;\n",
+                          "\t\t\tbuildActionMask = 2147483647;\n",
+                          "\t\t\tfiles = (\n",
+                          "\t\t\t);\n",
+                          "\t\t\tinputPaths = (\n",
+                          "{inputs}",
+                          "\t\t\t);\n",
+                          "\t\t\tname = \"{name}\";\n",
+                          "\t\t\toutputPaths = (\n",
+                          "{outputs}",
+                          "\t\t\t);\n",
+                          "\t\t\trunOnlyForDeploymentPostprocessing = 0;\n",
+                          "\t\t\tshellPath = /bin/sh;\n",
+                          "\t\t\tshellScript = \"set -e\\n{script}\";\n",
+                          "\t\t}};\n"),
+         id      = id,
+         name    = name,
+         inputs  = paths(inputs),
+         outputs = paths(outputs),
+         script  = script).unwrap();
+  write!(build_phases, "\t\t\t\t{} /* {} */,\n", id, name).unwrap();
+}
+
 // -----------------------------------------------------------------------------
 
-const GROUP_REF: &str = "\"<group>\"";
+const GROUP_REF:    &str = "\"<group>\"";
+const ABSOLUTE_REF: &str = "\"<absolute>\"";
 
 fn write_file_ref(s: &mut String, id: &str, name: &str, path: Option<&Path>,
                   pbx_type: &str, source: &str)
@@ -858,8 +1023,8 @@ fn sdk_info(p: PlatformType) -> (&'static str, &'static str) {
 
 fn build_project_group<'a>(ctx: &Context, refs: &mut String) -> Group<'a> {
   let mut g = Group::new(Some("Project"), None);
-  for f in ctx.metafiles {
-    let id   = random_id();
+  for f in &ctx.metafiles {
+    let id   = next_id();
     let name = f.name();
     write_file_ref(refs, &id, name, None, "text", GROUP_REF);
     g.push(&id, name);
@@ -875,10 +1040,11 @@ type FileStatsMap<'a> = HashMap<&'a PathBuf, FileStats>;
 
 fn write_pbx(ctx: &Context, path: &Path, team: Option<&str>) -> IO {
   // Open the file for writing right away to bail out early on failure.
-  let mut f = BufWriter::new(File::create(path)?);
+  let format = TextFormat { indent_style: Some(IndentStyle::Tab), ..text::LF };
+  let mut f  = TextFile::create(ctx, "xcode", format, path)?;
 
   // Prepare to collect all the required data to generate the PBX objects.
-  let     project_id       = random_id();
+  let     project_id       = next_id();
   let mut project_cfgs     = CfgList::new();
   let mut cfgs             = String::new();
   let mut files            = String::new();
@@ -886,6 +1052,8 @@ fn write_pbx(ctx: &Context, path: &Path, team: Option<&str>) -> IO {
   let mut sources          = String::new();
   let mut frameworks       = String::new();
   let mut resources        = String::new();
+  let mut copies           = String::new();
+  let mut scripts          = String::new();
   let mut main_group       = Group::new(None, None);
   let mut shared_group     = Group::new(Some("Shared"), None);
   let mut product_group    = Group::new(Some("Products"), None);
@@ -906,9 +1074,12 @@ fn write_pbx(ctx: &Context, path: &Path, team: Option<&str>) -> IO {
       false => &mut main_group
     };
 
-    ctx.sources.iter().flatten()
-      .filter(|info| info.meta.is_file())
-      .fold(FileStatsMap::new(), |mut m, info| {
+    // Resources are copied by their own build phase, whatever their type.
+    let sources   = ctx.sources.iter().flatten().map(|info| (info, false));
+    let resources = ctx.resources.iter().flatten().map(|info| (info, true));
+    sources.chain(resources)
+      .filter(|(info, _)| info.meta.is_file())
+      .fold(FileStatsMap::new(), |mut m, (info, is_resource)| {
         m.entry(&info.path)
           .and_modify(|e| {
             if e.num_targets == 1 {
@@ -918,9 +1089,16 @@ fn write_pbx(ctx: &Context, path: &Path, team: Option<&str>) -> IO {
             e.num_targets += 1;
           })
           .or_insert_with(|| {
-            let id = random_id();
-            let (phase, pbx_type) = get_file_type(info.extension());
-            write_file_ref(&mut refs, &id, info.name(), None, pbx_type, GROUP_REF);
+            let id = next_id();
+            let (phase, pbx_type) = match get_file_type(info.extension()) {
+              (_, pbx_type) if is_resource => (Phase::None, pbx_type),
+              x                            => x
+            };
+            match info.path.is_absolute() {
+              true  => write_file_ref(&mut refs, &id, info.name(), Some(&info.path), pbx_type,
+                                      ABSOLUTE_REF),
+              false => write_file_ref(&mut refs, &id, info.name(), None, pbx_type, GROUP_REF)
+            }
             FileStats { id, phase, pbx_type, num_targets: 1 }
           });
         m
@@ -942,12 +1120,16 @@ fn write_pbx(ctx: &Context, path: &Path, team: Option<&str>) -> IO {
     // }
 
     // TODO also use settings from dependencies?
-    let id = random_id();
+    let id = next_id();
     build_cfg(&mut cfgs, &id, prof, |mut s| {
       s.push_str("\t\t\t\tALWAYS_SEARCH_USER_PATHS = NO;\n"); // Deprecated, must be set to NO.
 
+      if ctx.project.settings.compiler_launcher.is_some() {
+        write!(s, "\t\t\t\tCC = \"$(PROJECT_DIR)/{}\";\n", LAUNCHER).unwrap();
+      }
+
       // TODO dont hardcode
-      let release   = *prof == "Release";
+      let release   = ctx.get_base_profile(prof) == "Release";
       let debug_fmt = match release {
         true  => "\"dwarf-with-dsym\"",
         false => "dwarf"
@@ -955,15 +1137,14 @@ fn write_pbx(ctx: &Context, path: &Path, team: Option<&str>) -> IO {
       write!(s, concat!("\t\t\t\tCLANG_ANALYZER_NONNULL = YES;\n",
                         "\t\t\t\tCLANG_ANALYZER_NUMBER_OBJECT_CONVERSION = YES_AGGRESSIVE;\n",
                         "\t\t\t\tCLANG_CXX_LANGUAGE_STANDARD = \"gnu++17\";\n",
-                        "\t\t\t\tCLANG_CXX_LIBRARY = \"libc++\";\n",
+                        "\t\t\t\tCLANG_CXX_LIBRARY = \"{stdlib}\";\n",
                         "\t\t\t\tCLANG_ENABLE_MODULES = YES;\n",
                         "\t\t\t\tCLANG_ENABLE_OBJC_ARC = YES;\n",
                         "\t\t\t\tCLANG_ENABLE_OBJC_WEAK = YES;\n",
                         "\t\t\t\tCOPY_PHASE_STRIP = NO;\n",
-                        "\t\t\t\tDEBUG_INFORMATION_FORMAT = {};\n"),
-             debug_fmt).unwrap();
-
-      // TODO AVX2
+                        "\t\t\t\tDEBUG_INFORMATION_FORMAT = {debug_fmt};\n"),
+             debug_fmt = debug_fmt,
+             stdlib    = ctx.project.settings.stdlib.unwrap_or(StdLib::LibCXX).to_str()).unwrap();
 
       if release {
         s.push_str("\t\t\t\tENABLE_NS_ASSERTIONS = NO;\n");
@@ -1004,9 +1185,6 @@ fn write_pbx(ctx: &Context, path: &Path, team: Option<&str>) -> IO {
       if !release {
         s.push_str("\t\t\t\tONLY_ACTIVE_ARCH = YES;\n");
       }
-      else {
-        s.push_str("\t\t\t\tLLVM_LTO = YES;\n");
-      }
 
       write!(s, concat!("\t\t\t\tWARNING_CFLAGS = (\n",
                         "\t\t\t\t\t\"-Wall\",\n",
@@ -1026,6 +1204,10 @@ fn write_pbx(ctx: &Context, path: &Path, team: Option<&str>) -> IO {
         ctx.project.filter.matches_platform(p) && target.filter.matches_platform(p)
       }).collect::<Vec<(usize, PlatformType)>>();
 
+    if platforms.is_empty() {
+      ctx.log.verbose(format!("Xcode: target {} is not built for any Apple platform", target_name));
+    }
+
     let has_multiple_platforms = platforms.len() > 1;
     let target_files = &ctx.sources[target_index];
     let data = &mut targets[target_index];
@@ -1036,7 +1218,7 @@ fn write_pbx(ctx: &Context, path: &Path, team: Option<&str>) -> IO {
       false => &mut main_group
     };
 
-    for file_info in target_files {
+    for file_info in target_files.iter().chain(&ctx.resources[target_index]) {
       if file_info.meta.is_dir() {continue}
       let file = &file_stats[&file_info.path];
       if file.num_targets == 1 {
@@ -1044,6 +1226,44 @@ fn write_pbx(ctx: &Context, path: &Path, team: Option<&str>) -> IO {
       }
     }
 
+    // Converted sources live in the build folder, referenced by their absolute path.
+    let mut embeds = Vec::new();
+    for e in embed::get_embeds(ctx, target_index) {
+      let id   = next_id();
+      let name = e.output.file_name().unwrap().to_str().unwrap().to_string();
+      write_file_ref(&mut refs, &id, &name, Some(e.output.as_path()), "sourcecode.c.c", ABSOLUTE_REF);
+      group.push(&id, &name);
+      embeds.push((id, name, e));
+    }
+
+    let test_main = target.test_framework.map(|_| {
+      let id   = next_id();
+      let path = tests::get_main_path(ctx, target_index);
+      let name = path.file_name().unwrap().to_str().unwrap().to_string();
+      write_file_ref(&mut refs, &id, &name, Some(path.as_path()), "sourcecode.cpp.cpp", ABSOLUTE_REF);
+      group.push(&id, &name);
+      (id, name)
+    });
+
+    // Generated sources are referenced likewise, once per target whichever platforms compile them.
+    let mut generated = HashMap::new();
+    for &(_, platform) in &platforms {
+      for &index in ctx.extends[target_index].iter().chain(std::iter::once(&target_index)) {
+        for schema in codegen::get_schemas(ctx, target_index, index, platform) {
+          for output in schema.sources() {
+            generated.entry(output.clone()).or_insert_with(|| {
+              let id   = next_id();
+              let name = output.file_name().unwrap().to_str().unwrap().to_string();
+              write_file_ref(&mut refs, &id, &name, Some(output.as_path()), "sourcecode.cpp.cpp",
+                             ABSOLUTE_REF);
+              group.push(&id, &name);
+              (id, name)
+            });
+          }
+        }
+      }
+    }
+
     for (platform_index, platform) in platforms {
       let mut cfg_list       = CfgList::new();
       let mut build_phases   = String::new();
@@ -1053,34 +1273,116 @@ fn write_pbx(ctx: &Context, path: &Path, team: Option<&str>) -> IO {
 
       // Initialize the target's build phases.
       {
-        let sources_id    = random_id();
-        let frameworks_id = random_id();
-        let resources_id  = random_id();
+        let sources_id    = next_id();
+        let frameworks_id = next_id();
+        let resources_id  = next_id();
 
         write_build_phase(&mut sources,    &sources_id,    "Sources");
         write_build_phase(&mut frameworks, &frameworks_id, "Frameworks");
         write_build_phase(&mut resources,  &resources_id,  "Resources");
 
+        // Runs first, before the sources including the header are compiled.
+        if let Some(cmd) = version::command(ctx, "\"${CONFIGURATION}\"") {
+          let script_id = next_id();
+          write!(&mut scripts, concat!("\t\t{id} /* Version Header */ = {{\n",
+                                       "\t\t\tisa = PBXShellScriptBuildPhase;\n",
+                                       "\t\t\talwaysOutOfDate = 1;\n",
+                                       "\t\t\tbuildActionMask = 2147483647;\n",
+                                       "\t\t\tfiles = (\n",
+                                       "\t\t\t);\n",
+                                       "\t\t\tinputPaths = (\n",
+                                       "\t\t\t);\n",
+                                       "\t\t\tname = \"Version Header\";\n",
+                                       "\t\t\toutputPaths = (\n",
+                                       "\t\t\t);\n",
+                                       "\t\t\trunOnlyForDeploymentPostprocessing = 0;\n",
+                                       "\t\t\tshellPath = /bin/sh;\n",
+                                       "\t\t\tshellScript = \"{cmd}\\n\";\n",
+                                       "\t\t}};\n"),
+                 id  = script_id,
+                 cmd = cmd.replace('"', "\\\"")).unwrap();
+          write!(&mut build_phases, "\t\t\t\t{} /* Version Header */,\n", script_id).unwrap();
+        }
+
+        // Converts the embedded files before the sources are compiled, see `gen::embed`.
+        if !embeds.is_empty() {
+          let inputs  = embeds.iter().map(|(_, _, e)| ctx.input_dir.join(&e.file.path)).collect::<Vec<_>>();
+          let outputs = embeds.iter().map(|(_, _, e)| e.output.clone()).collect::<Vec<_>>();
+          write_script_phase(&mut scripts, &mut build_phases, "Embed Files", &inputs, &outputs,
+                             &[embed::command(ctx, target_index, None)]);
+        }
+
+        // Compiles the schemas built on this platform, see `gen::codegen`.
+        let schemas = ctx.extends[target_index].iter().chain(std::iter::once(&target_index))
+          .flat_map(|&i| codegen::get_schemas(ctx, target_index, i, platform))
+          .collect::<Vec<_>>();
+        if !schemas.is_empty() {
+          let inputs  = schemas.iter().map(|x| ctx.input_dir.join(&x.file.path)).collect::<Vec<_>>();
+          let outputs = schemas.iter().flat_map(|x| x.outputs.iter().cloned()).collect::<Vec<_>>();
+          let cmds    = schemas.iter().map(|x| x.command.clone()).collect::<Vec<_>>();
+          write_script_phase(&mut scripts, &mut build_phases, "Compile Schemas", &inputs, &outputs, &cmds);
+
+          for output in schemas.iter().flat_map(|x| x.sources()) {
+            let (id, name) = &generated[output];
+            build_file(&mut sources, &mut files, name, id, "Sources");
+          }
+        }
+
+        // Compiles the shaders of this platform, see `gen::shaders`.
+        let target_shaders = shaders::get_shaders(ctx, target_index, platform);
+        if !target_shaders.is_empty() {
+          let inputs  = target_shaders.iter().map(|x| ctx.input_dir.join(&x.file.path)).collect::<Vec<_>>();
+          let outputs = target_shaders.iter().map(|x| x.output.clone()).collect::<Vec<_>>();
+          let cmds    = target_shaders.iter().map(|x| x.command.clone()).collect::<Vec<_>>();
+          write_script_phase(&mut scripts, &mut build_phases, "Compile Shaders", &inputs, &outputs, &cmds);
+        }
+
         write!(&mut build_phases, concat!("\t\t\t\t{} /* Sources */,\n",
                                           "\t\t\t\t{} /* Frameworks */,\n",
                                           "\t\t\t\t{} /* Resources */,\n"),
                sources_id, frameworks_id, resources_id).unwrap();
+
+        // Xcode only generates the dSYM bundles once the target is linked.
+        if target.target_type != TargetType::StaticLibrary {
+          let dir  = "\\\"${DWARF_DSYM_FOLDER_PATH}\\\"";
+          let file = "\\\"${DWARF_DSYM_FOLDER_PATH}/${DWARF_DSYM_FILE_NAME}\\\"";
+          if let Some(cmd) = symbols::upload_command(ctx, dir, file) {
+            let script_id = next_id();
+            write!(&mut scripts, concat!("\t\t{id} /* Upload Symbols */ = {{\n",
+                                         "\t\t\tisa = PBXShellScriptBuildPhase;\n",
+                                         "\t\t\tbuildActionMask = 2147483647;\n",
+                                         "\t\t\tfiles = (\n",
+                                         "\t\t\t);\n",
+                                         "\t\t\tinputPaths = (\n",
+                                         "\t\t\t);\n",
+                                         "\t\t\tname = \"Upload Symbols\";\n",
+                                         "\t\t\toutputPaths = (\n",
+                                         "\t\t\t);\n",
+                                         "\t\t\trunOnlyForDeploymentPostprocessing = 0;\n",
+                                         "\t\t\tshellPath = /bin/sh;\n",
+                                         "\t\t\tshellScript = \"if [ \\\"${{CONFIGURATION}}\\\" = {profile} ]; ",
+                                         "then\\n  {cmd}\\nfi\\n\";\n",
+                                         "\t\t}};\n"),
+                   id      = script_id,
+                   profile = symbols::PROFILE,
+                   cmd     = cmd).unwrap();
+            write!(&mut build_phases, "\t\t\t\t{} /* Upload Symbols */,\n", script_id).unwrap();
+          }
+        }
       }
 
       // Link frameworks
       let (sdk_source, sdk_prefix) = sdk_info(platform);
-      let link_frameworks = match platform { // TODO dont hardcode
-        PlatformType::WatchOS => &[] as &[&str],
-        PlatformType::MacOS   => &["AppKit", "CoreVideo", "Metal", "OpenGL", "GameController"],
-        _                     => &["UIKit", "Metal", "OpenGLES", "QuartzCore", "GameController"]
-      };
+      let (strong, weak) = flags::get_frameworks(ctx, target_index, platform, Architecture::Any);
+      let link_frameworks = strong.iter().filter(|x| !weak.contains(x)).map(|&x| (x, ""))
+        .chain(weak.iter().map(|&x| (x, "ATTRIBUTES = (Weak, ); ")));
 
-      for lf in link_frameworks {
-        let ref_id = random_id();
+      for (lf, settings) in link_frameworks {
+        let ref_id = next_id();
         let name = [lf, ".framework"].join("");
         let path = PathBuf::from([sdk_prefix, "System/Library/Frameworks/", &name].join(""));
         frameworks_group.push(&ref_id, &name);
-        build_file(&mut frameworks, &mut files, &name, &ref_id, "Frameworks");
+        build_file_with_settings(&mut frameworks, &mut files, &name, &ref_id, "Frameworks", settings);
         write_file_ref(&mut refs, &ref_id, &name, Some(&path), "wrapper.framework", sdk_source);
       }
 
@@ -1091,11 +1393,11 @@ fn write_pbx(ctx: &Context, path: &Path, team: Option<&str>) -> IO {
         // TODO don't generate info.plist if it exists in assets
         let plist = gen_dir.join("Info.plist");
         create_dir_all(&gen_dir)?;
-        write_info_plist(&ctx.build_dir.join(&plist))?;
+        write_info_plist(ctx, &ctx.build_dir.join(&plist))?;
 
         let plist_name   = pretty_name(has_multiple_platforms, "Info.plist", platform);
         let plist_ref    = ctx.build_rel.join(plist);
-        let plist_ref_id = random_id();
+        let plist_ref_id = next_id();
         group.push(&plist_ref_id, &plist_name);
         write_file_ref(&mut refs, &plist_ref_id, &plist_name, Some(&plist_ref),
                        "text.plist.xml", GROUP_REF);
@@ -1121,15 +1423,17 @@ fn write_pbx(ctx: &Context, path: &Path, team: Option<&str>) -> IO {
               name: &assets_name,
               ..AssetContent::default()
             }, |mut assets, parsed| {
-              fold_asset(&mut assets, &parsed); // TODO generic platform
+              if !fold_asset(&mut assets, &parsed) { // TODO generic platform
+                ctx.warn(format!("Xcode: unsupported asset {} skipped", parsed.path.display()));
+              }
               assets
             });
 
           let assets_path = gen_dir.join("Assets.xcassets");
-          write_contents_json(&ctx.input_dir, &ctx.build_dir.join(&assets_path), &assets)?;
+          write_contents_json(ctx, &ctx.input_dir, &ctx.build_dir.join(&assets_path), &assets)?;
 
           let assets_ref    = ctx.build_rel.join(assets_path);
-          let assets_ref_id = random_id();
+          let assets_ref_id = next_id();
           group.push(&assets_ref_id, assets.name);
           build_file(&mut resources, &mut files, &assets_name, &assets_ref_id, "Resources");
           write_file_ref(&mut refs, &assets_ref_id, &assets_name, Some(&assets_ref),
@@ -1161,10 +1465,12 @@ fn write_pbx(ctx: &Context, path: &Path, team: Option<&str>) -> IO {
         _ => unreachable!()
       };
 
+      // Universal builds share their configurations between architectures.
+      let arch = Architecture::Any;
+
       // Generate the build configurations for this target.
       for prof in &ctx.profiles {
-        let prof_lc = prof.to_lowercase();
-        let id = random_id();
+        let id = next_id();
         build_cfg(&mut cfgs, &id, prof, |mut s| {
           s.push_str(&settings_app_icon);
 
@@ -1176,28 +1482,83 @@ fn write_pbx(ctx: &Context, path: &Path, team: Option<&str>) -> IO {
             write!(s, "\t\t\t\tDEVELOPMENT_TEAM = {};\n", id).unwrap();
           }
 
-          let extra_inc = ["3rdparty/include/", &prof_lc].join("");
-          let extra_lib = ["3rdparty/lib/", platform_dir, "/x64/", &prof_lc].join("");
+          let fast_math = ctx.get_setting(target, prof, platform, arch, |s| s.fp_model);
+          let fast_math = match fast_math == Some(FloatModel::Fast) {
+            true  => "YES",
+            false => "NO"
+          };
+          write!(s, concat!("\t\t\t\tGCC_FAST_MATH = {0};\n",
+                            "\t\t\t\tMTL_FAST_MATH = {0};\n"),
+                 fast_math).unwrap();
+
+          if ctx.get_setting(target, prof, platform, arch, |s| s.coverage) == Some(true) {
+            s.push_str("\t\t\t\tCLANG_ENABLE_CODE_COVERAGE = YES;\n");
+          }
+
+          for san in flags::get_sanitizers(ctx, target, prof, platform, arch).split_terminator(',') {
+            match san {
+              "address"   => s.push_str("\t\t\t\tENABLE_ADDRESS_SANITIZER = YES;\n"),
+              "thread"    => s.push_str("\t\t\t\tENABLE_THREAD_SANITIZER = YES;\n"),
+              "undefined" => s.push_str("\t\t\t\tENABLE_UNDEFINED_BEHAVIOR_SANITIZER = YES;\n"),
+              _           => ctx.warn(format!("Xcode: sanitizer {} of target {} is ignored ({})",
+                                              san, target_name, prof))
+            }
+          }
+
+          if let Some(lto) = ctx.get_setting(target, prof, platform, arch, |s| s.lto) {
+            write!(s, "\t\t\t\tLLVM_LTO = {};\n", match lto {
+              Lto::Off  => "NO",
+              Lto::Thin => "YES_THIN",
+              Lto::Full => "YES"
+            }).unwrap();
+          }
+
+
+          let vars = Vars {
+            profile:  "$(CONFIGURATION:lower)",
+            arch:     "$(CURRENT_ARCH)",
+            platform: platform_dir
+          };
 
           let mut has_defines = false;
           for &index in &ctx.extends[target_index] {
-            define_macros(&mut has_defines, &mut s, &*ctx.get_target(index).settings.defines);
+            define_macros(&mut has_defines, &mut s, &vars, &*ctx.get_target(index).settings.defines);
+          }
+          define_macros(&mut has_defines, &mut s, &vars, &*target.settings.defines);
+          if let Some(framework) = target.test_framework {
+            define_macros(&mut has_defines, &mut s, &vars, &[tests::get_define(framework)]);
           }
-          define_macros(&mut has_defines, &mut s, &*target.settings.defines);
           end_settings_list(has_defines, &mut s);
 
           let mut has_includes = false;
-          header_paths(&mut has_includes, &mut s, &[extra_inc.as_str()]);
           for &index in &ctx.extends[target_index] {
-            header_paths(&mut has_includes, &mut s, &*ctx.get_target(index).settings.include_dirs);
+            header_paths(&mut has_includes, &mut s, &vars, &*ctx.get_target(index).settings.include_dirs);
+          }
+          header_paths(&mut has_includes, &mut s, &vars, &*target.settings.include_dirs);
+          if !embeds.is_empty() {
+            let dir = embed::get_dir(ctx, target_index);
+            header_paths(&mut has_includes, &mut s, &vars, &[dir.to_str().unwrap()]);
+          }
+          if codegen::has_schemas(ctx, target_index) {
+            let dir = codegen::get_dir(ctx, target_index);
+            header_paths(&mut has_includes, &mut s, &vars, &[dir.to_str().unwrap()]);
           }
-          header_paths(&mut has_includes, &mut s, &*target.settings.include_dirs);
           end_settings_list(has_includes, &mut s);
 
           let mut has_libraries = false;
-          library_paths(&mut has_libraries, &mut s, &[extra_lib.as_str()]);
+          for &index in &ctx.extends[target_index] {
+            library_paths(&mut has_libraries, &mut s, &vars, &*ctx.get_target(index).settings.lib_dirs);
+          }
+          library_paths(&mut has_libraries, &mut s, &vars, &*target.settings.lib_dirs);
           end_settings_list(has_libraries, &mut s);
 
+          let mut has_excluded = false;
+          for &index in &ctx.extends[target_index] {
+            excluded_files(&mut has_excluded, &mut s, ctx, prof, &ctx.sources[index], ctx.get_target(index));
+          }
+          excluded_files(&mut has_excluded, &mut s, ctx, prof, target_files, target);
+          end_settings_list(has_excluded, &mut s);
+
           s.push_str(&settings_info_plist);
 
           // TODO libraries
@@ -1271,9 +1632,18 @@ fn write_pbx(ctx: &Context, path: &Path, team: Option<&str>) -> IO {
             s.push_str(sdk_version);
           }
 
+          let product_name = match flags::get_output_name(ctx, target) {
+            Some(name) => vars.expand(name),
+            None       => target_name.to_string()
+          };
+
           write!(s, concat!("\t\t\t\tPRODUCT_BUNDLE_IDENTIFIER = com.lambdacoder.Jank;\n",
                             "\t\t\t\tPRODUCT_NAME = {};\n"),
-                 quote(target_name)).unwrap();
+                 quote(&product_name)).unwrap();
+
+          if let Some(dir) = flags::get_output_dir(ctx, target) {
+            write!(s, "\t\t\t\tCONFIGURATION_BUILD_DIR = {};\n", quote(&vars.expand(dir))).unwrap();
+          }
 
           write!(s, "\t\t\t\tSDKROOT = {};\n", sdk).unwrap();
 
@@ -1287,22 +1657,100 @@ fn write_pbx(ctx: &Context, path: &Path, team: Option<&str>) -> IO {
 
           // if !target.settings.libs.is_empty() {
           s.push_str("\t\t\t\tOTHER_LDFLAGS = (\n");
-          for &index in &ctx.extends[target_index] {
-            for lib in &*ctx.get_target(index).settings.libs {
+          for &index in ctx.extends[target_index].iter().chain(std::iter::once(&target_index)) {
+            let (libs, files) = flags::get_libs(ctx, ctx.get_target(index));
+            for lib in libs {
               write!(s, "\t\t\t\t\t\"-l{}\",\n", lib).unwrap();
             }
+            for file in files {
+              write!(s, "\t\t\t\t\t\"{}\",\n", vars.expand(file)).unwrap();
+            }
           }
-          for lib in &*target.settings.libs {
+          let (mut raw_cflags, mut raw_cxxflags, mut raw_ldflags) =
+            flags::get_extra_flags(ctx, target_index, "xcode", None, platform, arch);
+          let (prof_cflags, prof_cxxflags, prof_ldflags) =
+            flags::get_extra_flags(ctx, target_index, "xcode", Some(*prof), platform, arch);
+          raw_cflags.extend(prof_cflags);
+          raw_cxxflags.extend(prof_cxxflags);
+          raw_ldflags.extend(prof_ldflags);
+          let benchmark = match target.benchmark {
+            true  => benchmarks::get_libraries(platform),
+            false => &[]
+          };
+          for lib in target.test_framework.map_or(&[][..], tests::get_libraries).iter().chain(benchmark) {
             write!(s, "\t\t\t\t\t\"-l{}\",\n", lib).unwrap();
           }
+          for flag in &raw_ldflags {
+            write!(s, "\t\t\t\t\t\"{}\",\n", flag).unwrap();
+          }
           s.push_str("\t\t\t\t);\n");
           // }
 
+          // C++ sources get the C flags through OTHER_CPLUSPLUSFLAGS, as they do by default.
+          let raw_lists = [("OTHER_CFLAGS", "$(inherited)", raw_cflags),
+                           ("OTHER_CPLUSPLUSFLAGS", "$(OTHER_CFLAGS)", raw_cxxflags)];
+          for (name, inherited, raw_flags) in raw_lists.iter().filter(|x| !x.2.is_empty()) {
+            write!(s, "\t\t\t\t{} = (\n\t\t\t\t\t\"{}\",\n", name, inherited).unwrap();
+            for flag in raw_flags {
+              write!(s, "\t\t\t\t\t\"{}\",\n", flag).unwrap();
+            }
+            s.push_str("\t\t\t\t);\n");
+          }
+
+          let (_, mut warnings) = flags::get_disable_warnings(ctx, target_index, None, platform, arch);
+          warnings.extend(flags::get_disable_warnings(ctx, target_index, Some(*prof), platform, arch).1);
+          if !warnings.is_empty() {
+            s.push_str("\t\t\t\tWARNING_CFLAGS = (\n\t\t\t\t\t\"$(inherited)\",\n");
+            for w in warnings {
+              write!(s, "\t\t\t\t\t\"-Wno-{}\",\n", w).unwrap();
+            }
+            s.push_str("\t\t\t\t);\n");
+          }
+
+          // Apple platforms only ship libc++ as a system library, it is never linked statically.
+          if let Some(stdlib) = flags::get_stdlib(ctx, target) {
+            write!(s, "\t\t\t\tCLANG_CXX_LIBRARY = \"{}\";\n", stdlib.to_str()).unwrap();
+          }
+
+          if flags::get_visibility_hidden(ctx, target) {
+            s.push_str("\t\t\t\tGCC_SYMBOLS_PRIVATE_EXTERN = YES;\n");
+          }
+          if flags::get_visibility_inlines_hidden(ctx, target) {
+            s.push_str("\t\t\t\tGCC_INLINES_ARE_PRIVATE_EXTERN = YES;\n");
+          }
+          if let Some(pic) = flags::get_pic(ctx, target) {
+            write!(s, "\t\t\t\tGCC_DYNAMIC_NO_PIC = {};\n", match pic {
+              true  => "NO",
+              false => "YES"
+            }).unwrap();
+          }
+
+          let debug_info = match ctx.get_setting(target, prof, platform, arch, |s| s.debug_info) {
+            None                      => "",
+            Some(DebugInfo::None)     => "\t\t\t\tGCC_GENERATE_DEBUGGING_SYMBOLS = NO;\n",
+            Some(DebugInfo::Embedded) => "\t\t\t\tDEBUG_INFORMATION_FORMAT = dwarf;\n",
+            Some(DebugInfo::Separate) => "\t\t\t\tDEBUG_INFORMATION_FORMAT = \"dwarf-with-dsym\";\n"
+          };
+          s.push_str(debug_info);
+          if let Some(strip) = ctx.get_setting(target, prof, platform, arch, |s| s.strip) {
+            write!(s, concat!("\t\t\t\tDEPLOYMENT_POSTPROCESSING = {0};\n",
+                              "\t\t\t\tSTRIP_INSTALLED_PRODUCT = {0};\n"),
+                   match strip {
+                     true  => "YES",
+                     false => "NO"
+                   }).unwrap();
+          }
+
+          // Only applies to the x86 slices, SSE2 and NEON are always enabled.
+          match flags::get_simd(ctx, target) {
+            None | Some(Simd::SSE2) | Some(Simd::NEON) => {},
+            Some(x) => write!(s, "\t\t\t\tCLANG_X86_VECTOR_INSTRUCTIONS = {};\n", x.to_str()).unwrap()
+          }
+
           // TODO compiler
           // CLANG_ANALYZER_NONNULL = YES;
           // CLANG_ANALYZER_NUMBER_OBJECT_CONVERSION = YES_AGGRESSIVE;
           // CLANG_CXX_LANGUAGE_STANDARD = "gnu++14";
-          // CLANG_CXX_LIBRARY = "libc++";
           // CLANG_ENABLE_MODULES = YES;
           // CLANG_ENABLE_OBJC_ARC = YES;
           // CLANG_ENABLE_OBJC_WEAK = YES;
@@ -1344,6 +1792,43 @@ fn write_pbx(ctx: &Context, path: &Path, team: Option<&str>) -> IO {
 
       build_files(&mut sources, &mut resources, &mut files, platform, &file_stats, target_files, &target);
 
+      for (id, name, _) in &embeds {
+        build_file(&mut sources, &mut files, name, id, "Sources");
+      }
+
+      if let Some((id, name)) = &test_main {
+        build_file(&mut sources, &mut files, name, id, "Sources");
+      }
+
+      // Bundles get the resources in their Resources folder, other products next to them.
+      let target_resources = get_resources(ctx, target_index).into_iter()
+        .filter(|x| target.builds_file(&x.path, platform))
+        .collect::<Vec<_>>();
+      let copies_phase = match target.target_type {
+        TargetType::Application => None,
+        _ if target_resources.is_empty() => None,
+        _ => Some(next_id())
+      };
+
+      if let Some(id) = &copies_phase {
+        write!(&mut build_phases, "\t\t\t\t{} /* CopyFiles */,\n", id).unwrap();
+        write!(&mut copies, concat!("\t\t{} /* CopyFiles */ = {{\n",
+                                    "\t\t\tisa = PBXCopyFilesBuildPhase;\n",
+                                    "\t\t\tbuildActionMask = 2147483647;\n",
+                                    "\t\t\tdstPath = \"\";\n",
+                                    "\t\t\tdstSubfolderSpec = 16;\n",
+                                    "\t\t\tfiles = (\n"),
+               id).unwrap();
+      }
+
+      for file_info in &target_resources {
+        let id = &file_stats[&file_info.path].id;
+        match copies_phase {
+          None    => build_file(&mut resources, &mut files, file_info.name(), id, "Resources"),
+          Some(_) => build_file(&mut copies,    &mut files, file_info.name(), id, "CopyFiles")
+        }
+      }
+
       // Finalize the target's build phase objects.
       const BUILD_PHASE_END: &str = concat!("\t\t\t);\n",
                                             "\t\t\trunOnlyForDeploymentPostprocessing = 0;\n",
@@ -1351,9 +1836,12 @@ fn write_pbx(ctx: &Context, path: &Path, team: Option<&str>) -> IO {
       sources.push_str(BUILD_PHASE_END);
       frameworks.push_str(BUILD_PHASE_END);
       resources.push_str(BUILD_PHASE_END);
+      if copies_phase.is_some() {
+        copies.push_str(BUILD_PHASE_END);
+      }
 
       // Generate the target's product.
-      let product_id   = random_id();
+      let product_id   = next_id();
       let product_name = pretty_name(has_multiple_platforms, target_name, platform);
       let target_ext   = get_target_ext(target.target_type);
       write!(&mut refs, concat!("\t\t{product_id} /* {comment_name} */ = {{",
@@ -1386,7 +1874,7 @@ fn write_pbx(ctx: &Context, path: &Path, team: Option<&str>) -> IO {
 
       // Finalize this target.
       data[platform_index] = Some(TargetData {
-        target_id: random_id(),
+        target_id: next_id(),
         target,
         target_name,
         product_id,
@@ -1426,6 +1914,10 @@ fn write_pbx(ctx: &Context, path: &Path, team: Option<&str>) -> IO {
                     "{files}",
                     "/* End PBXBuildFile section */\n",
                     "\n",
+                    "/* Begin PBXCopyFilesBuildPhase section */\n",
+                    "{copies}",
+                    "/* End PBXCopyFilesBuildPhase section */\n",
+                    "\n",
                     "/* Begin PBXFileReference section */\n",
                     "{refs}",
                     "/* End PBXFileReference section */\n",
@@ -1435,8 +1927,9 @@ fn write_pbx(ctx: &Context, path: &Path, team: Option<&str>) -> IO {
                     "/* End PBXFrameworksBuildPhase section */\n",
                     "\n",
                     "/* Begin PBXGroup section */\n"),
-         files = files,
-         refs  = refs,
+         files      = files,
+         copies     = copies,
+         refs       = refs,
          frameworks = frameworks)?;
 
   main_group.write(&mut f)?;
@@ -1537,6 +2030,10 @@ fn write_pbx(ctx: &Context, path: &Path, team: Option<&str>) -> IO {
                     "{resources}",
                     "/* End PBXResourcesBuildPhase section */\n",
                     "\n",
+                    "/* Begin PBXShellScriptBuildPhase section */\n",
+                    "{scripts}",
+                    "/* End PBXShellScriptBuildPhase section */\n",
+                    "\n",
                     "/* Begin PBXSourcesBuildPhase section */\n",
                     "{sources}",
                     "/* End PBXSourcesBuildPhase section */\n",
@@ -1551,6 +2048,7 @@ fn write_pbx(ctx: &Context, path: &Path, team: Option<&str>) -> IO {
                     "\n",
                     "/* Begin XCConfigurationList section */\n"),
          resources = resources,
+         scripts   = scripts,
          sources   = sources,
          // variants  = variants,
          cfgs      = cfgs)?;
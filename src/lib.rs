@@ -0,0 +1,25 @@
+//! Project generator, from a single configuration file to the build files of
+//! every supported platform. The `janky` command line is a thin layer over this
+//! library, see the `load` module to embed the generator in other tools.
+
+#![allow(clippy::cognitive_complexity)]
+#![allow(clippy::match_bool)]
+#![allow(clippy::write_with_newline)]
+
+#![cfg_attr(debug_assertions, allow(dead_code))]
+#![cfg_attr(debug_assertions, allow(unused_assignments))]
+#![cfg_attr(debug_assertions, allow(unused_mut))]
+#![cfg_attr(debug_assertions, allow(unused_variables))]
+
+pub mod cmd;
+pub mod ctx;
+pub mod diag;
+pub mod gen;
+pub mod load;
+pub mod paths;
+pub mod platform;
+pub mod script;
+pub mod spans;
+
+pub use crate::ctx::{Context, Generator, Generators, Project};
+pub use crate::load::{Options, ProjectFiles};
@@ -0,0 +1,927 @@
+//! Loading of a project from its configuration files, then resolution of the
+//! context its commands and generators run in. The command line is only one
+//! user of these, editors and CI bots embed the generator the same way:
+//!
+//! ```ignore
+//! let files   = ProjectFiles::read(&input_dir, "Jank.toml", &diagnostics)?;
+//! let project = Project::load(&files, &env, &diagnostics)?;
+//!
+//! let mut generators = janky::gen::init();
+//! generators.insert("ninja", Box::new(Ninja));
+//!
+//! let ctx = Context::resolve(&project, Options { generators, ... })?;
+//! ctx.commands["gen"].run(&ctx)?;
+//! ```
+//!
+//! Nothing here exits the process. Configuration problems are collected in the
+//! diagnostics, along with their location, and fail with a `ConfigError` once
+//! they prevent going any further. Other failures are returned as they are.
+
+use clap::ArgMatches;
+use semver::Version;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt::{self, Display};
+use std::path::{Path, PathBuf};
+
+use crate::ctx::{self, Context, DynResult, StrError};
+use crate::diag::Diagnostics;
+use crate::gen;
+use crate::paths;
+use crate::script;
+use crate::spans;
+
+/// Configuration files of a project, read from disk with their load-time
+/// variables expanded. Projects borrow their strings from them.
+pub struct ProjectFiles {
+  pub path:  PathBuf,
+  data:      Vec<u8>,
+  fragments: Vec<Fragment>,
+  scripts:   Vec<(PathBuf, Vec<u8>)>
+}
+
+/// Included file, or file of a workspace member.
+struct Fragment {
+  path:   PathBuf,
+  data:   Vec<u8>,
+  member: Option<Member>
+}
+
+/// Keys of the member's tables keyed by paths, which are rebased once parsed.
+/// Its path values are rebased in the text itself.
+struct Member {
+  prefix: String,
+  keys:   BTreeMap<String, String>
+}
+
+impl ProjectFiles {
+  /// Reads the configuration file of the input folder, then the files it includes
+  /// and the ones of its workspace members.
+  pub fn read(input_dir: &Path, config: &str, diagnostics: &Diagnostics) -> DynResult<Self> {
+    let path = input_dir.join(config);
+    let data = std::fs::read(&path)
+      .describe(|| format!("Failed to load config file ({:?})", path))?;
+    diagnostics.add_source(&path, &String::from_utf8_lossy(&data));
+
+    let includes = parse_toml::<ctx::ProjectIncludes>(diagnostics, &path, &data, "project file");
+    diagnostics.check_errors()?;
+
+    let includes = includes.unwrap();
+    let names    = &includes.project;
+    let data     = expand_vars(&path, data, names)?;
+    let scripts  = script::results(input_dir, names)?;
+
+    let mut fragments = Vec::new();
+    for pattern in &includes.include {
+      for path in find_includes(input_dir, pattern)? {
+        let data = std::fs::read(&path)
+          .describe(|| format!("Failed to load included file ({:?})", path))?;
+        diagnostics.add_source(&path, &String::from_utf8_lossy(&data));
+        let data = expand_vars(&path, data, names)?;
+        fragments.push(Fragment { path, data, member: None });
+      }
+    }
+
+    for member in &includes.workspace.members {
+      load_member(input_dir, member, names, diagnostics, &mut fragments)?;
+    }
+
+    Ok(ProjectFiles { path, data, fragments, scripts })
+  }
+}
+
+impl<'a> ctx::Project<'a> {
+  /// Parses a project and merges the files it includes, then resolves its scoped
+  /// settings and the inheritance of its profiles. Flags and settings from the
+  /// environment, ie injected by CI, apply to every target.
+  ///
+  /// Problems which don't prevent loading the project are left in the
+  /// diagnostics, `Context::resolve` fails on them.
+  pub fn load(files: &'a ProjectFiles, env: &'a ctx::Env, diagnostics: &Diagnostics) -> DynResult<Self> {
+    // Every file is parsed before giving up, to report all of their errors at once.
+    let project = parse_toml::<ctx::Project>(diagnostics, &files.path, &files.data, "project file");
+    let fragments = files.fragments.iter().filter_map(|x| {
+      let fragment = match &x.member {
+        None    => parse_toml::<ctx::ProjectFragment>(diagnostics, &x.path, &x.data, "included file"),
+        Some(m) => parse_toml::<ctx::MemberFragment>(diagnostics, &x.path, &x.data, "workspace member")
+          .map(|f| m.rebase(f))
+      };
+      fragment.map(|f| (&x.path, f))
+    }).collect::<Vec<_>>();
+    let scripts = files.scripts.iter().filter_map(|(path, data)| {
+      parse_toml::<ctx::ScriptSettings>(diagnostics, path, data, "script result").map(|x| (path, x))
+    }).collect::<Vec<_>>();
+    diagnostics.check_errors()?;
+
+    let mut project = project.unwrap();
+    for (path, fragment) in fragments {
+      if let Err(e) = project.include(fragment) {
+        diagnostics.error(format!("Failed to include file ({:?}): {}", path, e));
+      }
+    }
+    for (path, script) in scripts {
+      if let Err(e) = project.apply_script(script) {
+        diagnostics.error(format!("Failed to apply script ({:?}): {}", path, e));
+      }
+    }
+    diagnostics.check_errors()?;
+
+    validate_scopes(&project, diagnostics);
+    let names = profile_names(&ctx::Settings::defaults(&project.builtin_profiles), &project);
+    project.resolve_scopes(&names).describe(|| "Failed to resolve scoped settings")?;
+
+    let bases = project.profile_bases().describe(|| "Failed to resolve profile inheritance")?;
+    project.extend_profiles(&bases);
+    project.bases = bases;
+
+    let settings = &mut project.info.settings;
+    settings.extra_cflags.to_mut().extend(env.cflags.split_whitespace());
+    settings.extra_cxxflags.to_mut().extend(env.cxxflags.split_whitespace());
+    settings.extra_ldflags.to_mut().extend(env.ldflags.split_whitespace());
+    env.settings.apply(&mut project);
+
+    validate_project(&project, diagnostics);
+    Ok(project)
+  }
+}
+
+/// Settings applying to whole targets are only read from the project and target
+/// settings, reject them where they would be silently ignored.
+fn validate_scopes(project: &ctx::Project, diagnostics: &Diagnostics) {
+  let check = |path: &[&str], settings: &ctx::Settings| {
+    for key in settings.get_target_keys() {
+      diagnostics.error_at(&[path, &[key]].concat(),
+                           format!("Setting {} applies to the whole target and cannot be scoped", key));
+    }
+  };
+
+  let check_all = |scopes: &ctx::Scopes, profiles: &ctx::Profiles, path: &[&str], profiles_path: &[&str]| {
+    for (&key, scope) in scopes {
+      check(&[path, &[key]].concat(), &scope.settings);
+      for (&nested, settings) in &scope.nested {
+        check(&[path, &[key, nested]].concat(), settings);
+      }
+    }
+    for (&name, profiles) in profiles {
+      for profile in profiles {
+        check(&[profiles_path, &[name]].concat(), &profile.settings);
+      }
+    }
+  };
+
+  check_all(&project.info.scopes, &project.profiles, &["project", "settings"], &["profiles"]);
+  for (&name, target) in &project.targets {
+    check_all(&target.scopes, &target.profiles,
+              &["targets", name, "settings"], &["targets", name, "profiles"]);
+  }
+}
+
+/// Validates the configuration, every problem is reported before giving up.
+fn validate_project(project: &ctx::Project, diagnostics: &Diagnostics) {
+  if let Err(e) = is_supported(project.min_janky_version) {
+    diagnostics.error_at(&["project", "min_janky_version"],
+                         format!("Min version check failed: {}", e));
+  }
+
+  let version = project.config_version.unwrap_or(1);
+  if version > ctx::ProjectInfo::CONFIG_VERSION {
+    diagnostics.error_at(&["project", "config_version"],
+                         format!("Configuration version {} requires a newer janky, this one supports up to {}",
+                                 version, ctx::ProjectInfo::CONFIG_VERSION));
+  }
+  else if version < ctx::ProjectInfo::CONFIG_VERSION {
+    diagnostics.warning_at(&["project", "config_version"],
+                           format!("Configuration version {} is outdated, run `janky migrate` to upgrade it to {}",
+                                   version, ctx::ProjectInfo::CONFIG_VERSION));
+  }
+
+  if project.version_header.is_some() {
+    if let Err(e) = Version::parse(project.version) {
+      diagnostics.error_at(&["project", "version"],
+                           format!("The version header needs a semantic version ({}): {}", project.version, e));
+    }
+  }
+
+  if project.targets.is_empty() {
+    diagnostics.error("No targets in project configuration");
+  }
+
+  for name in &project.builtin_profiles {
+    if !ctx::Settings::OPTIONAL_PROFILES.contains(name) {
+      diagnostics.error_at(&["project", "builtin_profiles"],
+                           format!("No such built-in profile: {}", name));
+    }
+  }
+
+  if project.html5.webgl != 1 && project.html5.webgl != 2 {
+    diagnostics.error_at(&["project", "html5", "webgl"],
+                         format!("Unsupported WebGL version: {}", project.html5.webgl));
+  }
+
+  for (name, target) in &project.targets {
+    if target.settings.stdlib.or(project.settings.stdlib) == Some(ctx::StdLib::LibStdCXX) {
+      use ctx::PlatformType::*;
+      for &p in &[Android, MacOS, IOS, TVOS, WatchOS] {
+        if project.filter.matches_platform(p) && target.filter.matches_platform(p) {
+          diagnostics.error_at(&["targets", *name, "stdlib"],
+                               format!("Target {} cannot use libstdc++ on {}", name, p.to_str()));
+        }
+      }
+    }
+
+    let charset = target.settings.windows_character_set
+      .or(project.settings.windows_character_set)
+      .unwrap_or(ctx::CharacterSet::Unicode);
+    let other = match charset {
+      ctx::CharacterSet::Unicode   => ctx::CharacterSet::MultiByte,
+      ctx::CharacterSet::MultiByte => ctx::CharacterSet::Unicode
+    };
+    for def in target.settings.defines.iter().chain(project.settings.defines.iter()) {
+      if other.defines().contains(def) {
+        diagnostics.error_at(&["targets", *name, "defines"],
+                             format!("Target {} defines {} but uses the {:?} character set",
+                                     name, def, charset));
+      }
+    }
+
+    for pattern in target.file_settings.keys() {
+      if let Err(e) = glob::Pattern::new(pattern) {
+        diagnostics.error_at(&["targets", *name, "file_settings", *pattern],
+                             format!("Invalid file_settings pattern in target {} ({}): {}",
+                                     name, pattern, e));
+      }
+    }
+
+    if target.test_framework.is_some() && target.target_type != ctx::TargetType::Console {
+      diagnostics.error_at(&["targets", *name, "test_framework"],
+                           format!("Target {} has a test framework but is not a console target", name));
+    }
+
+    if target.benchmark && target.target_type != ctx::TargetType::Console {
+      diagnostics.error_at(&["targets", *name, "benchmark"],
+                           format!("Target {} is a benchmark but is not a console target", name));
+    }
+    else if target.benchmark && target.test_framework.is_some() {
+      diagnostics.error_at(&["targets", *name, "benchmark"],
+                           format!("Target {} cannot be both a benchmark and a test target", name));
+    }
+  }
+}
+
+
+// Context Resolution
+// -----------------------------------------------------------------------------
+
+/// Everything a context is resolved from, besides the project itself.
+pub struct Options<'a> {
+  pub env:         &'a ctx::Env,
+  pub args:        &'a ArgMatches<'a>,
+  pub input_dir:   PathBuf,
+  pub build_dir:   PathBuf,
+  pub output:      ctx::OutputMode,
+  pub commands:    ctx::Commands,
+  pub platforms:   ctx::Platforms,
+  /// Built-in generators from `gen::init`, along with any registered by the embedder.
+  pub generators:  ctx::Generators,
+  pub warnings:    ctx::Warnings,
+  pub log:         ctx::Log,
+  pub diagnostics: Diagnostics,
+  pub timings:     ctx::Timings
+}
+
+impl<'a> Context<'a> {
+  /// Loads the external platforms of a project and resolves the files of its
+  /// targets, along with the targets they extend.
+  ///
+  /// Problems with settings only known once the context is resolved are left in
+  /// the diagnostics, for the check command to report along with its own.
+  pub fn resolve(project: &'a ctx::Project<'a>, options: Options<'a>) -> DynResult<Self> {
+    let Options {
+      env, args, input_dir, build_dir, output, commands, platforms, generators,
+      warnings, log, diagnostics, timings
+    } = options;
+
+    let mut generators = generators;
+    register_plugins(project, &mut generators, &diagnostics);
+
+    let externals = load_externals(project, &input_dir, &generators, &diagnostics)?;
+    diagnostics.check_errors()?;
+
+    let (sources, resources, assets, embeds, shaders, metafiles) = timings.time("files", || {
+      resolve_files(project, &input_dir, &warnings, log)
+    })?;
+
+    let (extends, extended) = timings.time("extends", || resolve_extends(project))?;
+
+    let mut defaults = ctx::Settings::defaults(&project.builtin_profiles);
+    ctx::extend_profiles(&mut defaults, &project.bases);
+    let ctx = Context {
+      env,
+      args,
+      project,
+      extends,
+      extended,
+      sources,
+      resources,
+      assets,
+      embeds,
+      shaders,
+      metafiles,
+      externals,
+      profiles:  profile_names(&defaults, project),
+      manifest:  ctx::Manifest::load(&build_dir),
+      output,
+      warnings,
+      log,
+      diagnostics,
+      timings,
+      build_rel: pathdiff::diff_paths(&build_dir, &input_dir).unwrap(),
+      input_rel: pathdiff::diff_paths(&input_dir, &build_dir).unwrap(),
+      input_dir,
+      build_dir,
+      defaults,
+      profile_bases: project.bases.clone(),
+      commands,
+      platforms,
+      generators
+    };
+
+    validate_context(&ctx);
+    Ok(ctx)
+  }
+}
+
+/// Registers the project's plugins along with the other generators.
+fn register_plugins(project: &ctx::Project, generators: &mut ctx::Generators, diagnostics: &Diagnostics) {
+  for (&name, info) in &project.plugins {
+    // Plugins stage their files to a folder of the same name, which must stay in the build folder.
+    if !is_plugin_name(name) {
+      diagnostics.error_at(&["plugins", name],
+                           format!("Plugin names are letters, digits, '_' and '-' only ({})", name));
+      continue;
+    }
+    if generators.contains_key(name) {
+      diagnostics.error_at(&["plugins", name], format!("Plugin shadows a registered generator ({})", name));
+      continue;
+    }
+
+    // Generators are named for the whole run, which plugin names outlive anyway.
+    let name: &'static str = Box::leak(name.to_string().into_boxed_str());
+    generators.insert(name, Box::new(gen::plugin::Plugin { name, platforms: info.platforms.clone() }));
+  }
+}
+
+fn is_plugin_name(name: &str) -> bool {
+  let mut chars = name.chars();
+  chars.next().map_or(false, |c| c.is_ascii_alphabetic() || c == '_') &&
+    chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+/// Loads the platforms defined outside of janky.
+fn load_externals(project: &ctx::Project, input_dir: &Path, generators: &ctx::Generators,
+                  diagnostics: &Diagnostics) -> DynResult<Vec<ctx::ExternalPlatform>>
+{
+  let mut externals = Vec::new();
+  for name in &project.external_platforms {
+    let path = input_dir.join(name);
+    let data = std::fs::read(&path)
+      .describe(|| format!("Failed to load platform descriptor ({:?})", path))?;
+    diagnostics.add_source(&path, &String::from_utf8_lossy(&data));
+    if let Some(x) = parse_toml::<ctx::PlatformDescriptor>(diagnostics, &path, &data, "platform descriptor") {
+      externals.extend(x.platforms);
+    }
+  }
+
+  for p in &externals {
+    if !generators.contains_key(p.generator.as_str()) {
+      diagnostics.error_at(&["platform", "generator"],
+                           format!("No generator {} for external platform {}", p.generator, p.name));
+    }
+  }
+
+  let ext_settings = std::iter::once((None, &project.settings))
+    .chain(project.targets.iter().map(|(name, t)| (Some(*name), &t.settings)));
+  for (owner, settings) in ext_settings {
+    for name in settings.ext.keys() {
+      if !externals.iter().any(|p| p.name == *name) {
+        let (key, owner) = match owner {
+          None    => (vec!["project", "ext", *name], "project"),
+          Some(t) => (vec!["targets", t, "ext", *name], t)
+        };
+        diagnostics.error_at(&key,
+                             format!("Settings of {} reference an unknown platform ({})", owner, name));
+      }
+    }
+  }
+
+  Ok(externals)
+}
+
+type ResolvedFiles = (ctx::AllFiles, ctx::AllFiles, ctx::AllFiles, ctx::AllFiles, Vec<ctx::AllFiles>,
+                      ctx::TargetFiles);
+
+/// Sources, resources, assets, embedded files and shaders of every target, then
+/// the files at the project's root.
+fn resolve_files(project: &ctx::Project, input_dir: &PathBuf, warnings: &ctx::Warnings,
+                 log: ctx::Log) -> DynResult<ResolvedFiles>
+{
+  let mut roots = Roots::new();
+  for (&name, dir) in &project.roots {
+    let path = paths::canonicalize(&input_dir.join(dir))
+      .describe(|| format!("Failed to resolve source root {} ({})", name, dir))?;
+    roots.insert(name, path);
+  }
+
+  let sources   = find_all_files(input_dir, &roots, warnings, log, &project.targets, |x| &x.sources)?;
+  let resources = find_all_files(input_dir, &roots, warnings, log, &project.targets, |x| &x.resources)?;
+  let embeds    = find_all_files(input_dir, &roots, warnings, log, &project.targets, |x| &x.embed)?;
+
+  let mut shaders = Vec::new();
+  for (name, target) in &project.targets {
+    shaders.push(target.shaders.iter()
+                 .map(|rule| find_files(input_dir, &roots, warnings, log, name, &rule.files))
+                 .collect::<DynResult<ctx::AllFiles>>()
+                 .describe(|| format!("Failed to resolve shaders for target {}", name))?);
+  }
+
+  let mut assets = ctx::AllFiles::new();
+  for (name, target) in &project.targets {
+    assets.push(match target.assets {
+      None => Vec::new(),
+      Some(dir) if !dir.starts_with('@') && !input_dir.join(dir).is_dir() => {
+        warnings.warn(format!("Missing assets folder for target {} ({})", name, dir));
+        Vec::new()
+      },
+      Some(dir) => find_files(input_dir, &roots, warnings, log, name,
+                              &[[dir, "/**/*"].join("").as_str()])
+        .describe(|| format!("Failed to resolve assets for target {}", name))?
+    });
+  }
+
+  // Directory listings come in no particular order, sort them for stable output.
+  let mut metafiles = Vec::new();
+  for e in std::fs::read_dir(input_dir)?.flatten() {
+    // Poor man's gitignore, didn't use gitignore.rs because it is too slow.
+    match e.file_name().to_str() {
+      Some(".git") | Some(".DS_Store") => {},
+      None => warnings.warn(format!("Skipping {}, its name is not valid UTF-8", e.path().display())),
+      Some(_) => if let Ok(meta) = e.metadata() {
+        metafiles.push(ctx::FileInfo { path: e.path(), meta });
+      }
+    }
+  }
+  metafiles.sort_by(|a, b| a.path.cmp(&b.path));
+
+  Ok((sources, resources, assets, embeds, shaders, metafiles))
+}
+
+/// Targets referenced by each target, then the targets referencing each target.
+/// (TODO: should probably check if arch/platform matches)
+fn resolve_extends(project: &ctx::Project) -> DynResult<(ctx::Extends, ctx::Extends)> {
+  let references = project.targets.values()
+    .map(|target| target_references(project, target))
+    .collect::<DynResult<Vec<Vec<&str>>>>()?;
+
+  let extends = references.iter().map(|names| {
+    names.iter().map(|target_name| {
+      project.targets.keys()
+        .position(|name| name == target_name)
+        .describe(|| format!("No such target to extend: {}", target_name))
+    }).collect::<DynResult<Vec<usize>>>()
+  }).collect::<DynResult<ctx::Extends>>()?;
+
+  let extended = project.targets.keys().map(|target_name| {
+    references.iter().enumerate()
+      .filter(|(_, names)| names.contains(target_name))
+      .map(|(index, _)| index)
+      .collect::<Vec<usize>>()
+  }).collect::<ctx::Extends>();
+
+  Ok((extends, extended))
+}
+
+fn validate_context(ctx: &Context) {
+  let project     = ctx.project;
+  let diagnostics = &ctx.diagnostics;
+
+  for (name, base) in &ctx.profile_bases {
+    if !ctx.profiles.contains(base) {
+      diagnostics.error_at(&["profiles", *name, "extends"],
+                           format!("Profile {} extends an unknown profile ({})", name, base));
+    }
+  }
+
+  // Vector extensions only exist on their own architectures.
+  for (index, name) in project.targets.keys().enumerate() {
+    let target = ctx.get_target(index);
+    if let Some(simd) = gen::flags::get_simd(ctx, target) {
+      let mut archs = target.filter.architectures.clone();
+      archs.extend(&project.filter.architectures);
+      let android = ctx::PlatformType::Android;
+      if project.filter.matches_platform(android) && target.filter.matches_platform(android) {
+        archs.extend(gen::flags::android_architectures(ctx, target));
+      }
+
+      for a in archs.into_iter().filter(|&a| !simd.supports_architecture(a)) {
+        diagnostics.error_at(&["targets", *name, "simd"],
+                             format!("Target {} cannot use {} instructions on {:?}", name, simd.to_str(), a));
+      }
+    }
+  }
+
+  // Mismatched iterator debug levels only fail at link time, catch them early.
+  for (index, name) in project.targets.keys().enumerate() {
+    for prof in &ctx.profiles {
+      let get = |i| {
+        ctx.get_setting(ctx.get_target(i), prof, ctx::PlatformType::Any, ctx::Architecture::Any,
+                        |s| s.iterator_debug_level)
+      };
+
+      let level = get(index);
+      if level.unwrap_or(0) > 2 {
+        diagnostics.error_at(&["targets", *name, "iterator_debug_level"],
+                             format!("Invalid iterator debug level for target {} in profile {}", name, prof));
+      }
+
+      for &extend_index in &ctx.extends[index] {
+        if get(extend_index) != level {
+          diagnostics.error_at(&["targets", *name, "iterator_debug_level"],
+                               format!("Target {} and {} use different iterator debug levels in profile {}",
+                                       name, ctx.get_target_name(extend_index), prof));
+        }
+      }
+    }
+  }
+}
+
+
+// Utilities
+// -----------------------------------------------------------------------------------
+
+#[derive(Debug)]
+struct MinVerError {
+  expected: Version,
+  current:  Version
+}
+
+impl Display for MinVerError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "Project does not support this version: expected {} but running {}",
+           self.expected, self.current)
+  }
+}
+
+impl Error for MinVerError {
+  fn description(&self) -> &str {
+    "Project does not support this version"
+  }
+}
+
+fn is_supported(min_version: &str) -> DynResult<()> {
+  if !min_version.is_empty() {
+    let expected = Version::parse(min_version)?;
+    let current  = Version::parse(env!("CARGO_PKG_VERSION")).unwrap();
+    if expected > current {
+      return Err(Box::new(MinVerError { expected, current }))
+    }
+  }
+  Ok(())
+}
+
+/// Targets extended by a target, followed by the library targets named in its `libs`,
+/// which get linked the same way.
+fn target_references<'a>(project: &ctx::Project<'a>, target: &ctx::Target<'a>) -> DynResult<Vec<&'a str>> {
+  let mut names = target.extends.clone();
+  for &lib in &*target.settings.libs {
+    if let Some(t) = project.targets.get(lib) {
+      match t.target_type {
+        ctx::TargetType::StaticLibrary | ctx::TargetType::SharedLibrary => {},
+        _ => return Err(Box::new(StrError(format!("Only library targets can be linked: {}", lib))))
+      }
+      if !names.contains(&lib) {
+        names.push(lib);
+      }
+    }
+  }
+  Ok(names)
+}
+
+/// Names of the built-in profiles, followed by the ones of the project and its targets.
+pub fn profile_names<'a>(profiles: &ctx::Profiles<'a>, project: &ctx::Project<'a>) -> Vec<&'a str> {
+  let mut v = profiles.keys().cloned().collect::<Vec<&'a str>>();
+
+  v.extend(project.profiles.keys().cloned());
+
+  for t in project.targets.values() {
+    v.extend(t.profiles.keys().cloned());
+  }
+
+  v.sort_unstable();
+  v.dedup();
+  v
+}
+
+/// Additional source roots by name, as absolute paths.
+type Roots<'a> = std::collections::BTreeMap<&'a str, PathBuf>;
+
+fn find_all_files<'a, 'b, F>(input_dir: &PathBuf, roots: &Roots, warnings: &ctx::Warnings, log: ctx::Log,
+                             targets: &'b std::collections::BTreeMap<&'a str, ctx::Target<'a>>,
+                             get_patterns: F) -> DynResult<ctx::AllFiles> where
+  F: Fn(&'b ctx::Target<'a>) -> &'b Vec<&'a str>
+{
+  let mut files = ctx::AllFiles::new();
+  for (name, target) in targets {
+    files.push(find_files(input_dir, roots, warnings, log, name, get_patterns(target))
+               .describe(|| format!("Failed to resolve files for target {}", name))?);
+  }
+  Ok(files)
+}
+
+/// Patterns starting with `@name/` are matched from the source root of that name.
+/// Patterns starting with `!` exclude the files they match from the other patterns.
+/// The resulting paths are relative to the input folder, or absolute when the
+/// root cannot be reached with a relative path. Patterns matching nothing are
+/// reported as warnings for the given target.
+fn find_files(input_dir: &PathBuf, roots: &Roots, warnings: &ctx::Warnings, log: ctx::Log,
+              target: &str, patterns: &[&str]) -> DynResult<ctx::TargetFiles>
+{
+  let excludes = patterns.iter()
+    .filter(|x| x.starts_with('!'))
+    .map(|x| {
+      let (_, _, full) = resolve_pattern(input_dir, roots, &x[1 ..])?;
+      Ok(glob::Pattern::new(&full)?)
+    })
+    .collect::<DynResult<Vec<glob::Pattern>>>()?;
+
+  let mut files = Vec::new();
+  for &pattern in patterns.iter().filter(|x| !x.starts_with('!')) {
+    let num_files = files.len();
+    let (dir, prefix_path, full) = resolve_pattern(input_dir, roots, pattern)?;
+
+    for m in glob::glob(&full)? {
+      let m = m?;
+      paths::utf8(&m)?;
+      if excludes.iter().any(|x| x.matches_path(&m)) {
+        log.trace(format!("Target {}: {} excluded", target, m.display()));
+        continue;
+      }
+
+      let path = PathBuf::from(m.strip_prefix(&prefix_path)?);
+      let meta = std::fs::metadata(dir.join(&path))?;
+      let path = match dir == input_dir {
+        true  => path,
+        false => relative_path(&dir.join(&path), input_dir)
+      };
+      log.trace(format!("Target {}: {} matched by {}", target, path.display(), pattern));
+      files.push(ctx::FileInfo { path, meta });
+    }
+
+    if files.len() == num_files {
+      warnings.warn(format!("Pattern {} of target {} matched no files", pattern, target));
+    }
+  }
+  Ok(files)
+}
+
+/// Returns the folder a pattern is matched from, the prefix to strip from its
+/// matches and the full pattern to give to glob.
+fn resolve_pattern<'a>(input_dir: &'a PathBuf, roots: &'a Roots, pattern: &str)
+                       -> DynResult<(&'a PathBuf, PathBuf, String)>
+{
+  let mut sep_buf = [0; 2]; // FIXME there has to be a better way
+  let sep = std::path::MAIN_SEPARATOR.encode_utf8(&mut sep_buf);
+
+  let (dir, pattern) = match pattern.starts_with('@') {
+    false => (input_dir, pattern),
+    true  => {
+      let mut it = pattern[1 ..].splitn(2, '/');
+      let name = it.next().unwrap();
+      let dir  = roots.get(name)
+        .ok_or_else(|| StrError(format!("No such source root: {}", name)))?;
+      (dir, it.next().unwrap_or(""))
+    }
+  };
+
+  // Folders were resolved by paths::canonicalize, without verbatim prefixes.
+  let prefix = paths::utf8(dir)?;
+
+  #[cfg(windows)]      let fixed_pattern = pattern.replace("/", "\\");
+  #[cfg(windows)]      let pattern_str = &fixed_pattern;
+  #[cfg(not(windows))] let pattern_str = pattern;
+
+  Ok((dir, PathBuf::from(prefix), [prefix, sep, pattern_str].join("")))
+}
+
+/// Expands the load-time variables of a configuration file, see `gen::subst`.
+fn expand_vars(path: &Path, bytes: Vec<u8>, names: &ctx::ProjectNames) -> DynResult<Vec<u8>> {
+  let text = std::str::from_utf8(&bytes)
+    .describe(|| format!("Invalid UTF-8 in config file ({:?})", path))?;
+  if !gen::subst::has_config_vars(text) {
+    return Ok(bytes);
+  }
+
+  let root = spans::Node::parse(text)
+    .describe(|| format!("Failed to read config file ({:?})", path))?;
+  let text = rewrite_strings(text, &root, |_, s| expand_str(s, names))
+    .describe(|| format!("Failed to expand variables in config file ({:?})", path))?;
+  Ok(text.into_bytes())
+}
+
+fn expand_str(s: &str, names: &ctx::ProjectNames) -> Result<Option<String>, String> {
+  match gen::subst::has_config_vars(s) {
+    true  => gen::subst::expand_config(s, &names.name, &names.version).map(Some),
+    false => Ok(None)
+  }
+}
+
+/// Replaces the string values `f` returns a new value for, keeping the rest of
+/// the text and its line numbers as they are.
+fn rewrite_strings<F>(text: &str, root: &spans::Node, mut f: F) -> Result<String, String> where
+  F: FnMut(&[&str], &str) -> Result<Option<String>, String>
+{
+  let mut edits = Vec::new();
+  let mut error = None;
+  root.visit_strings(&mut Vec::new(), &mut |keys, s, span| match f(keys, s) {
+    Ok(None)    => {},
+    Ok(Some(x)) => edits.push((span.clone(), spans::quote(&x, &text[span.clone()]))),
+    Err(e)      => { error.get_or_insert(e); }
+  });
+  match error {
+    None    => Ok(spans::replace(text, edits)),
+    Some(e) => Err(e)
+  }
+}
+
+/// Deserializes a configuration file, reporting failures as diagnostics.
+fn parse_toml<'de, T>(diagnostics: &Diagnostics, path: &Path, data: &'de [u8],
+                      what: &str) -> Option<T> where T: Deserialize<'de>
+{
+  toml::from_slice(data)
+    .map_err(|e| diagnostics.toml_error(path, data, &e, format!("Failed to read {} ({:?})", what, path)))
+    .ok()
+}
+
+fn find_includes(dir: &Path, pattern: &str) -> DynResult<Vec<PathBuf>> {
+  let mut paths = glob::glob(paths::utf8(&dir.join(pattern))?)
+    .map(|x| x.filter_map(Result::ok).collect::<Vec<PathBuf>>())
+    .describe(|| format!("Invalid include pattern ({})", pattern))?;
+  paths.sort();
+  Ok(paths)
+}
+
+/// Relative path from `base` to `path`, or `path` itself when they are on different drives.
+fn relative_path(path: &Path, base: &Path) -> PathBuf {
+  match path.components().next() == base.components().next() {
+    true  => pathdiff::diff_paths(path, base).unwrap(),
+    false => path.to_path_buf()
+  }
+}
+
+/// Prefixes errors with what was being done, like the command line's own messages.
+trait Describe {
+  type R;
+  fn describe<F, S>(self, msg: F) -> DynResult<Self::R> where F: FnOnce() -> S, S: Display;
+}
+
+impl<T> Describe for Option<T> {
+  type R = T;
+  fn describe<F, S>(self, msg: F) -> DynResult<T> where F: FnOnce() -> S, S: Display {
+    self.ok_or_else(|| StrError(msg().to_string()).into())
+  }
+}
+
+impl<T, E> Describe for Result<T, E> where E: Display {
+  type R = T;
+  fn describe<F, S>(self, msg: F) -> DynResult<T> where F: FnOnce() -> S, S: Display {
+    self.map_err(|e| StrError(format!("{}: {}", msg(), e)).into())
+  }
+}
+
+
+// Workspaces
+// -----------------------------------------------------------------------------
+
+/// Settings holding paths relative to the input folder.
+const MEMBER_PATHS: &[&str] = &[
+  "sources", "resources", "assets", "include_dirs", "lib_dirs", "pch_source"
+];
+
+/// Tables keyed by paths or patterns relative to the input folder.
+const MEMBER_KEYED_PATHS: &[&str] = &["filters", "file_settings"];
+
+/// Reads the targets and profiles of a workspace member, along with the files it
+/// includes, as fragments of the root project. Their paths are rewritten to be
+/// relative to the root's input folder.
+fn load_member(input_dir: &Path, member: &str, names: &ctx::ProjectNames,
+               diagnostics: &Diagnostics, fragments: &mut Vec<Fragment>) -> DynResult<()>
+{
+  let dir  = input_dir.join(member);
+  let path = dir.join("Jank.toml");
+
+  let data = std::fs::read_to_string(&path)
+    .describe(|| format!("Failed to load workspace member ({:?})", path))?;
+  diagnostics.add_source(&path, &data);
+  let value: toml::Value = toml::from_str(&data)
+    .describe(|| format!("Failed to read workspace member ({:?})", path))?;
+
+  // Members expand the `${project.*}` variables to their own name and version.
+  let member_names = ctx::ProjectNames {
+    name:    get_project_str(&value, "name").unwrap_or(&names.name).to_string(),
+    version: get_project_str(&value, "version").unwrap_or(&names.version).to_string(),
+    scripts: Vec::new()
+  };
+
+  let mut files = vec![(path, data)];
+  if let Some(toml::Value::Array(patterns)) = value.get("include") {
+    for pattern in patterns.iter().filter_map(toml::Value::as_str) {
+      for path in find_includes(&dir, pattern)? {
+        let data = std::fs::read_to_string(&path)
+          .describe(|| format!("Failed to load included file ({:?})", path))?;
+        diagnostics.add_source(&path, &data);
+        files.push((path, data));
+      }
+    }
+  }
+
+  // Only the profiles and targets are part of the root project.
+  let prefix = member.trim_end_matches('/');
+  for (path, data) in files {
+    let root = spans::Node::parse(&data)
+      .describe(|| format!("Failed to read workspace member ({:?})", path))?;
+    let data = rewrite_strings(&data, &root, |keys, s| match keys.first() {
+      Some(&"profiles") | Some(&"targets") => {
+        let rebased = match is_member_path(keys) {
+          true  => Some(rebase_path(s, prefix)),
+          false => None
+        };
+        Ok(expand_str(rebased.as_deref().unwrap_or(s), &member_names)?.or(rebased))
+      },
+      _ => Ok(None)
+    }).describe(|| format!("Failed to expand variables in workspace member ({:?})", path))?;
+
+    let mut keys = BTreeMap::new();
+    if let spans::Value::Table(entries) = &root.value {
+      for (_, node) in entries.iter().filter(|x| x.0 == "profiles" || x.0 == "targets") {
+        collect_keyed_paths(node, prefix, &mut keys);
+      }
+    }
+
+    let member = Member { prefix: prefix.to_string(), keys };
+    fragments.push(Fragment { path, data: data.into_bytes(), member: Some(member) });
+  }
+
+  Ok(())
+}
+
+fn get_project_str<'v>(value: &'v toml::Value, key: &str) -> Option<&'v str> {
+  value.get("project").and_then(|x| x.get(key)).and_then(toml::Value::as_str)
+}
+
+/// Whether a string value is one of the paths of a `MEMBER_PATHS` setting.
+fn is_member_path(keys: &[&str]) -> bool {
+  keys.last().map_or(false, |x| MEMBER_PATHS.contains(x)) &&
+    !keys.iter().any(|x| MEMBER_KEYED_PATHS.contains(x))
+}
+
+/// Rebased keys of the `MEMBER_KEYED_PATHS` tables, by their original key.
+fn collect_keyed_paths(node: &spans::Node, prefix: &str, keys: &mut BTreeMap<String, String>) {
+  match &node.value {
+    spans::Value::Table(entries) => for (key, x) in entries {
+      match (MEMBER_KEYED_PATHS.contains(&key.as_str()), &x.value) {
+        (true, spans::Value::Table(t)) => {
+          keys.extend(t.iter().map(|(k, _)| (k.clone(), rebase_path(k, prefix))));
+        },
+        _ => collect_keyed_paths(x, prefix, keys)
+      }
+    },
+    spans::Value::Array(nodes) => nodes.iter().for_each(|x| collect_keyed_paths(x, prefix, keys)),
+    _ => {}
+  }
+}
+
+impl Member {
+  fn rebase<'a>(&'a self, fragment: ctx::MemberFragment<'a>) -> ctx::ProjectFragment<'a> {
+    let mut targets = fragment.targets;
+    for target in targets.values_mut() {
+      target.file_settings = std::mem::take(&mut target.file_settings).into_iter()
+        .map(|(k, v)| (self.keys.get(k).map_or(k, String::as_str), v))
+        .collect();
+      target.filters = std::mem::take(&mut target.filters).into_iter()
+        .map(|(k, v)| (PathBuf::from(rebase_path(&k.to_string_lossy(), &self.prefix)), v))
+        .collect();
+    }
+    ctx::ProjectFragment { profiles: fragment.profiles, targets }
+  }
+}
+
+/// Source roots, absolute paths and substitution variables are left untouched.
+fn rebase_path(path: &str, prefix: &str) -> String {
+  match path.strip_prefix('!') {
+    Some(x) => ["!", rebase_path(x, prefix).as_str()].join(""),
+    None    => match path.starts_with('@') || path.starts_with('$') || Path::new(path).is_absolute() {
+      true  => path.to_string(),
+      false => [prefix, "/", path].join("")
+    }
+  }
+}
@@ -1,252 +1,189 @@
-#![allow(clippy::cognitive_complexity)]
 #![allow(clippy::match_bool)]
-#![allow(clippy::write_with_newline)]
 
-#![cfg_attr(debug_assertions, allow(dead_code))]
-#![cfg_attr(debug_assertions, allow(unused_assignments))]
-#![cfg_attr(debug_assertions, allow(unused_mut))]
-#![cfg_attr(debug_assertions, allow(unused_variables))]
-
-mod cmd;
-mod ctx;
-mod gen;
-mod platform;
-
-use clap::{Arg, App, SubCommand};
-use semver::Version;
+use clap::{Arg, App, AppSettings, SubCommand};
 use std::error::Error;
-use std::{fmt, fmt::{Display}};
-use std::path::PathBuf;
+use std::fmt::Display;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use janky::{cmd, ctx, diag, gen, load, paths, platform};
 
 fn main() {
   // Initialize.
-  let commands   = cmd::init();
-  let platforms  = platform::init();
-  let generators = gen::init();
+  let timings     = ctx::Timings::default();
+  let diagnostics = diag::Diagnostics::default();
+  let commands    = cmd::init();
+  let platforms   = platform::init();
+  let generators  = gen::init();
 
   // Parse the environment variables.
-  let env: ctx::Env = envy::from_env()
+  let env: ctx::Env = timings.time("env", ctx::Env::from_env)
     .check(|| "Failed to parse environment variables");
 
   // Parse the command line.
-  let args = App::new(env!("CARGO_PKG_NAME"))
-    .version(env!("CARGO_PKG_VERSION"))
-    .author(env!("CARGO_PKG_AUTHORS"))
-    .about(env!("CARGO_PKG_DESCRIPTION"))
-    .arg(Arg::with_name("FOLDER")
-         .help("Input folder containing source files")
-         .required(true))
-    .arg(Arg::with_name("build")
-         .short("b")
-         .long("build")
-         .value_name("FOLDER")
-         .help("Where to store the generated project files")
-         .takes_value(true))
-    .arg(Arg::with_name("config")
-         .short("c")
-         .long("config")
-         .value_name("FILE")
-         .help("Name of the build file")
-         .takes_value(true))
-    // .arg(Arg::with_name("v") // TODO use this
-    //      .short("v")
-    //      .multiple(true)
-    //      .help("Verbosity level"))
-    .subcommands(commands.iter().map(|(name, cmd)| {
-      cmd.init(SubCommand::with_name(name))
-    }))
-    .get_matches();
+  let argv = std::env::args().collect::<Vec<String>>();
+  let args = app(&commands).get_matches_from(&argv);
 
-  let input_dir = PathBuf::from(args.value_of("FOLDER").unwrap())
-    .canonicalize()
-    .unwrap();
+  let format = match args.value_of("message-format") {
+    Some("json") => ctx::MessageFormat::Json,
+    _            => ctx::MessageFormat::Human
+  };
+  JSON_MESSAGES.store(format == ctx::MessageFormat::Json, Ordering::Relaxed);
+
+  let log      = ctx::Log::new(args.is_present("quiet"), args.occurrences_of("verbose"), format);
+  let warnings = ctx::Warnings::new(log);
+
+  let input_dir = paths::canonicalize(Path::new(args.value_of("FOLDER").unwrap()))
+    .check(|| "Failed to resolve the input folder");
   let build_dir = args.value_of("build")
     .map(PathBuf::from)
     .or_else(|| Some(std::env::current_dir().unwrap()))
+    .map(|x| paths::canonicalize(&x))
     .unwrap()
-    .canonicalize().unwrap();
-
-  // Load the project's configuration file.
-  let mut bytes = Vec::new();
-  let project: ctx::Project = {
-    use std::io::Read;
-    let path = input_dir.join(args.value_of("config").unwrap_or("Jank.toml"));
-
-    let mut f = std::fs::File::open(&path)
-      .check(|| format!("Failed to open config file ({:?})", path));
+    .check(|| "Failed to resolve the build folder");
+
+  // Load the project's configuration file, then the files it includes.
+  let config  = args.value_of("config").unwrap_or("Jank.toml");
+  let files   = timings.time("read", || load::ProjectFiles::read(&input_dir, config, &diagnostics))
+    .unwrap_or_else(|e| abort(&diagnostics, &warnings, e));
+  let project = timings.time("config", || ctx::Project::load(&files, &env, &diagnostics))
+    .unwrap_or_else(|e| abort(&diagnostics, &warnings, e));
+
+  for name in project.alias.keys() {
+    if commands.contains_key(name) {
+      diagnostics.error_at(&["alias", *name], format!("Alias shadows a built-in command ({})", name));
+    }
+  }
 
-    f.read_to_end(&mut bytes)
-      .check(|| format!("Failed to load config file ({:?})", path));
+  diagnostics.abort_on_errors(&warnings);
 
-    toml::from_slice(&bytes)
-      .check(|| format!("Failed to read the project file ({:?})", path))
+  // Expand command aliases, now that they are known.
+  let args = match cmd::expand_alias(&commands, &project.alias, &argv, &args)
+    .check(|| "Failed to expand command alias")
+  {
+    None       => args,
+    Some(argv) => app(&commands).get_matches_from(argv)
   };
 
-  is_supported(&project.min_janky_version).check(|| "Min version check failed");
-
-  (!project.targets.is_empty()).check(|| "No targets in project configuration");
-
-  // Resolve the project's files.
-  let sources   = find_all_files(&input_dir, &project.targets, |x| &x.sources);
-  let resources = find_all_files(&input_dir, &project.targets, |x| &x.resources);
-
-  let assets = project.targets.iter()
-    .fold(ctx::AllFiles::new(), |mut assets, (name, target)| {
-      assets.push(match target.assets {
-        None      => Vec::new(),
-        Some(dir) => find_files(&input_dir, &[[dir, "/**/*"].join("").as_str()])
-          .check(|| format!("Failed to resolve assets for target {}", name))
-      });
-      assets
-    });
-
-  let metafiles = std::fs::read_dir(&input_dir).unwrap()
-    .fold(Vec::new(), |mut files, entry| {
-      if let Ok(e) = entry {
-        // Poor man's gitignore, didn't use gitignore.rs because it is too slow.
-        match e.path().to_str().unwrap() {
-          ".git" | ".DS_Store" => {},
-          _ => if let Ok(meta) = e.metadata() {
-            files.push(ctx::FileInfo { path: e.path(), meta });
-          }
-        }
-      }
-      files
-    });
-
-  // Resolve target references (TODO: should probably check if arch/platform matches)
-  let extends = project.targets.values().map(|target| {
-    target.extends.iter().map(|target_name| {
-      project.targets.keys()
-        .position(|name| name == target_name)
-        .check(|| format!("No such target to extend: {}", target_name))
-    }).collect::<Vec<usize>>()
-  }).collect::<ctx::Extends>();
-
-  let extended = project.targets.keys().map(|target_name| {
-    project.targets.values().enumerate().map(|(index, target)| {
-      match target.extends.contains(target_name) {
-        true  => Some(index),
-        false => None
-      }
-    }).flatten()
-      .collect::<Vec<usize>>()
-  }).collect::<ctx::Extends>();
-
-  // println!("{:#?}", project);
-
-  // Execute the requested command.
-  let defaults = ctx::Settings::defaults();
-  let ctx = ctx::Context {
-    env:       &env,
-    args:      &args,
-    project:   &project,
-    extends:   &extends,
-    extended:  &extended,
-    sources:   &sources,
-    resources: &resources,
-    assets:    &assets,
-    metafiles: &metafiles,
-    profiles:  profile_names(&defaults, &project),
-    build_rel: pathdiff::diff_paths(&build_dir, &input_dir).unwrap(),
-    input_rel: pathdiff::diff_paths(&input_dir, &build_dir).unwrap(),
+  // Platforms and architectures from the command line narrow down the project's.
+  let mut project = project;
+  let only_platforms = args.values_of("platform").into_iter().flatten().map(|x| {
+    ctx::PlatformType::parse(x).check(|| format!("No such platform: {}", x))
+  }).collect::<Vec<ctx::PlatformType>>();
+  let only_archs = args.values_of("arch").into_iter().flatten().map(|x| {
+    ctx::Architecture::parse(x).check(|| format!("No such architecture: {}", x))
+  }).collect::<Vec<ctx::Architecture>>();
+  project.info.filter.intersect(&only_platforms, &only_archs)
+    .check(|| "The project builds none of the requested platforms or architectures");
+
+  // Resolve the project's files and targets.
+  let output = match args.subcommand_matches("gen") {
+    Some(x) if x.is_present("diff")    => ctx::OutputMode::Diff,
+    Some(x) if x.is_present("dry-run") => ctx::OutputMode::DryRun,
+    _                                  => ctx::OutputMode::Write
+  };
+  let ctx = ctx::Context::resolve(&project, load::Options {
+    env:         &env,
+    args:        &args,
     input_dir,
     build_dir,
-    defaults,
+    output,
     commands,
     platforms,
-    generators
-  };
-
+    generators,
+    warnings:    warnings.clone(),
+    log,
+    diagnostics: diagnostics.clone(),
+    timings:     timings.clone()
+  }).unwrap_or_else(|e| abort(&diagnostics, &warnings, e));
+
+  // Execute the requested command, the check command reports the problems
+  // found above along with its own.
   let cmd_name = ctx.args.subcommand_name().unwrap_or("gen");
-  ctx.commands[cmd_name].run(&ctx)
-    .check(|| format!("Failed to run command ({})", cmd_name));
-}
-
-
-// Utilities
-// -----------------------------------------------------------------------------------
-
-#[derive(Debug)]
-struct MinVerError {
-  expected: Version,
-  current:  Version
-}
-
-impl Display for MinVerError {
-  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-    write!(f, "{}: expected {} but running {}",
-           self.description(), self.expected, self.current)
+  if cmd_name != "check" {
+    diagnostics.abort_on_errors(&warnings);
   }
-}
 
-impl Error for MinVerError {
-  fn description(&self) -> &str {
-    "Project does not support this version"
-  }
-}
+  ctx.commands.get(cmd_name)
+    .check(|| format!("Alias must expand to a built-in command ({})", cmd_name))
+    .run(&ctx)
+    .check(|| format!("Failed to run command ({})", cmd_name));
 
-fn is_supported(min_version: &str) -> ctx::DynResult<()> {
-  if !min_version.is_empty() {
-    let expected = Version::parse(min_version)?;
-    let current  = Version::parse(env!("CARGO_PKG_VERSION")).unwrap();
-    if expected > current {
-      return Err(Box::new(MinVerError { expected, current }))
-    }
+  if ctx.args.is_present("timings") {
+    timings.report(&log);
   }
-  Ok(())
-}
-
-pub fn profile_names<'a>(profiles: &ctx::Profiles<'a>, project: &ctx::Project<'a>) -> Vec<&'a str> {
-  let mut v = profiles.keys().cloned().collect::<Vec<&'a str>>();
 
-  v.extend(project.profiles.keys().cloned());
+  let num_warnings = warnings.count();
+  (num_warnings == 0 || !(ctx.args.is_present("strict") || project.strict))
+    .check(|| format!("{} warning(s) reported in strict mode", num_warnings));
 
-  for t in project.targets.values() {
-    v.extend(t.profiles.keys().cloned());
-  }
-
-  v.sort_unstable();
-  v.dedup();
-  v
+  log.event("finished", serde_json::json!({ "success": true, "warnings": num_warnings }));
 }
 
-fn find_all_files<'a, F>(input_dir: &PathBuf,
-                         targets: &'a std::collections::HashMap<&str, ctx::Target<'a>>,
-                         get_patterns: F) -> ctx::AllFiles where
-  F: Fn(&'a ctx::Target<'a>) -> &Vec<&str>
-{
-  let mut files = ctx::AllFiles::new();
-  for (name, target) in targets {
-    files.push(find_files(&input_dir, get_patterns(target))
-               .check(|| format!("Failed to resolve files for target {}", name)));
-  }
-  files
-}
-
-fn find_files(dir: &PathBuf, patterns: &[&str]) -> ctx::DynResult<ctx::TargetFiles> {
-  let mut sep_buf = [0; 2]; // FIXME there has to be a better way
-  let sep = std::path::MAIN_SEPARATOR.encode_utf8(&mut sep_buf);
-
-  // FIXME: ugly hack because glob() does not handle windows verbatim paths
-  #[cfg(windows)]      let prefix = &dir.to_str().unwrap()[4..];
-  #[cfg(not(windows))] let prefix = dir.to_str().unwrap();
-  #[cfg(windows)]      let prefix_path = PathBuf::from(prefix);
-  #[cfg(not(windows))] let prefix_path = dir;
-
-  let mut files = Vec::new();
-  for pattern in patterns {
-    #[cfg(windows)]      let fixed_pattern = pattern.replace("/", "\\");
-    #[cfg(windows)]      let pattern_str = &fixed_pattern;
-    #[cfg(not(windows))] let pattern_str = pattern;
-
-    for m in glob::glob(&[prefix, sep, pattern_str].join(""))? {
-      let path = PathBuf::from(m?.strip_prefix(&prefix_path)?);
-      let meta = std::fs::metadata(dir.join(&path))?;
-      files.push(ctx::FileInfo { path, meta });
-    }
-  }
-  Ok(files)
+fn app<'a, 'b>(commands: &ctx::Commands) -> App<'a, 'b> {
+  App::new(env!("CARGO_PKG_NAME"))
+    .version(env!("CARGO_PKG_VERSION"))
+    .author(env!("CARGO_PKG_AUTHORS"))
+    .about(env!("CARGO_PKG_DESCRIPTION"))
+    .arg(Arg::with_name("FOLDER")
+         .help("Input folder containing source files")
+         .required(true))
+    .arg(Arg::with_name("build")
+         .short("b")
+         .long("build")
+         .value_name("FOLDER")
+         .help("Where to store the generated project files")
+         .takes_value(true))
+    .arg(Arg::with_name("config")
+         .short("c")
+         .long("config")
+         .value_name("FILE")
+         .help("Name of the build file")
+         .takes_value(true))
+    .arg(Arg::with_name("force")
+         .short("f")
+         .long("force")
+         .help("Overwrite generated files even if they were modified since the last run"))
+    .arg(Arg::with_name("platform")
+         .long("platform")
+         .value_name("PLATFORMS")
+         .takes_value(true)
+         .multiple(true)
+         .use_delimiter(true)
+         .help("Only generate, build and run these of the project's platforms"))
+    .arg(Arg::with_name("arch")
+         .long("arch")
+         .value_name("ARCHS")
+         .takes_value(true)
+         .multiple(true)
+         .use_delimiter(true)
+         .help("Only generate, build and run these of the project's architectures"))
+    .arg(Arg::with_name("timings")
+         .long("timings")
+         .help("Report the time spent in each phase of the run"))
+    .arg(Arg::with_name("message-format")
+         .long("message-format")
+         .value_name("FORMAT")
+         .possible_values(&["human", "json"])
+         .default_value("human")
+         .help("Report messages as text, or as line-delimited JSON events on stdout"))
+    .arg(Arg::with_name("strict")
+         .long("strict")
+         .help("Fail when any warning is reported"))
+    .arg(Arg::with_name("verbose")
+         .short("v")
+         .multiple(true)
+         .help("Report the files written and the targets skipped, -vv also reports every file matched"))
+    .arg(Arg::with_name("quiet")
+         .short("q")
+         .long("quiet")
+         .conflicts_with("verbose")
+         .help("Only report errors"))
+    .subcommands(commands.iter().map(|(name, cmd)| {
+      cmd.init(SubCommand::with_name(name))
+    }))
+    // Project-defined aliases are only known after loading the configuration.
+    .setting(AppSettings::AllowExternalSubcommands)
 }
 
 
@@ -287,7 +224,24 @@ impl<T, E> Check for Result<T, E> where E: Display {
   }
 }
 
+/// Configuration errors are printed from the diagnostics, which locate them.
+fn abort(diagnostics: &diag::Diagnostics, warnings: &ctx::Warnings, e: Box<dyn Error>) -> ! {
+  match e.is::<diag::ConfigError>() {
+    true  => diagnostics.fail(warnings),
+    false => fatal(e)
+  }
+}
+
+/// Set once the command line is parsed, fatal errors are then reported as events.
+static JSON_MESSAGES: AtomicBool = AtomicBool::new(false);
+
 fn fatal<S: Display>(msg: S) -> ! {
-  eprintln!("{}", msg);
+  match JSON_MESSAGES.load(Ordering::Relaxed) {
+    false => eprintln!("{}", msg),
+    true  => {
+      let log = ctx::Log::new(false, 0, ctx::MessageFormat::Json);
+      log.event("finished", serde_json::json!({ "success": false, "message": msg.to_string() }));
+    }
+  }
   std::process::exit(1)
 }
@@ -0,0 +1,43 @@
+//! Paths entering the context, as the input and build folders, source roots and
+//! matched files, are checked here once. Generators write them to text files
+//! and rely on them being valid UTF-8, failing with the offending path instead
+//! of panicking halfway through a run.
+//!
+//! Canonical paths on Windows start with a verbatim prefix, `\\?\C:\` or
+//! `\\?\UNC\server\share\`, which neither glob nor the generated projects
+//! understand. It is removed, paths longer than `MAX_PATH` then rely on the
+//! system's long path support.
+
+use std::path::{Path, PathBuf};
+
+use crate::ctx::{DynResult, StrError};
+
+/// The path as text, or an error naming it.
+pub fn utf8(path: &Path) -> Result<&str, StrError> {
+  path.to_str().ok_or_else(|| StrError(format!("Path is not valid UTF-8: {}", path.display())))
+}
+
+/// Absolute path of an existing file or folder, without symlinks or a verbatim prefix.
+pub fn canonicalize(path: &Path) -> DynResult<PathBuf> {
+  let full = path.canonicalize()
+    .map_err(|e| StrError(format!("{}: {}", path.display(), e)))?;
+  utf8(&full)?;
+  Ok(strip_verbatim(full))
+}
+
+#[cfg(windows)]
+fn strip_verbatim(path: PathBuf) -> PathBuf {
+  let s = path.to_str().unwrap();
+  let stripped = match s.strip_prefix(r"\\?\UNC\") {
+    Some(rest) => Some([r"\\", rest].join("")),
+    None       => s.strip_prefix(r"\\?\")
+      .filter(|rest| rest.as_bytes().get(1) == Some(&b':'))
+      .map(String::from)
+  };
+  stripped.map_or(path, PathBuf::from)
+}
+
+#[cfg(not(windows))]
+fn strip_verbatim(path: PathBuf) -> PathBuf {
+  path
+}
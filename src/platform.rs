@@ -1,13 +1,15 @@
-mod android;
+pub mod android;
 mod ios;
 mod html5;
 mod linux;
-mod macos;
+pub mod macos;
 mod tvos;
 mod watchos;
 mod windows;
 
-use crate::ctx::Platforms;
+use std::path::{Path, PathBuf};
+
+use crate::ctx::{PlatformType, Platforms, Tool};
 
 pub fn init() -> Platforms {
   let platforms: Platforms = vec!(
@@ -29,3 +31,65 @@ pub fn init() -> Platforms {
 
   platforms
 }
+
+
+// Toolchain Probes
+// -----------------------------------------------------------------------------
+
+/// Runs a tool, keeping the first line of its output as its version.
+pub fn probe_command(name: &'static str, required: bool, program: &str, args: &[&str]) -> Tool {
+  let found = match std::process::Command::new(program).args(args).output() {
+    Err(e) => Err(format!("{} not found ({})", program, e)),
+    Ok(out) if !out.status.success() => {
+      Err(format!("{} {} failed ({})", program, args.join(" "), out.status))
+    },
+    Ok(out) => {
+      // Some tools, like java, print their version to stderr.
+      let text = [out.stdout, out.stderr].concat();
+      match String::from_utf8_lossy(&text).lines().next().map(str::trim) {
+        None | Some("") => Err(format!("{} printed no version", program)),
+        Some(x)         => Ok(x.to_string())
+      }
+    }
+  };
+  Tool { name, required, found }
+}
+
+/// Checks for a folder, ie an SDK located by an environment variable.
+pub fn probe_dir(name: &'static str, required: bool, dir: Option<PathBuf>, missing: &str) -> Tool {
+  let found = match dir {
+    None                  => Err(missing.to_string()),
+    Some(x) if x.is_dir() => Ok(x.display().to_string()),
+    Some(x)               => Err(format!("{} does not exist", x.display()))
+  };
+  Tool { name, required, found }
+}
+
+/// Windows platforms are only built on Windows hosts and Apple platforms on macOS,
+/// Linux, Android and HTML5 from any of them.
+pub fn builds_on_host(platform: PlatformType) -> bool {
+  match platform {
+    PlatformType::Windows => cfg!(windows),
+    PlatformType::MacOS   |
+    PlatformType::IOS     |
+    PlatformType::TVOS    |
+    PlatformType::WatchOS => cfg!(target_os = "macos"),
+    _                     => true
+  }
+}
+
+/// Tools probed on other hosts are reported as missing.
+pub fn probe_host(name: &'static str, required: bool, host: &str) -> Tool {
+  Tool { name, required, found: Err(format!("only available on {} hosts", host)) }
+}
+
+/// Highest version among the folders named after one, ie the installed SDKs.
+pub fn latest_version_dir(dir: &Path) -> Option<PathBuf> {
+  let parse = |x: &PathBuf| {
+    x.file_name()?.to_str()?.split('.').map(|x| x.parse::<u32>().ok()).collect::<Option<Vec<u32>>>()
+  };
+  std::fs::read_dir(dir).ok()?
+    .filter_map(|x| x.ok().map(|x| x.path()))
+    .filter(|x| x.is_dir() && parse(x).is_some())
+    .max_by_key(|x| parse(x))
+}
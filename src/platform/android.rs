@@ -1,4 +1,7 @@
-use crate::{ctx, ctx::{Architecture, PlatformType}};
+use std::path::{Path, PathBuf};
+
+use crate::{ctx, ctx::{Architecture, PlatformType, Tool}};
+use super::{latest_version_dir, probe_command, probe_dir};
 
 pub struct Android;
 
@@ -13,11 +16,43 @@ impl ctx::Platform for Android {
       Architecture::ARM   => true,
       Architecture::ARM64 => true,
       Architecture::X86   => true,
-      Architecture::X64   => false
+      Architecture::X64   => true
     }
   }
 
-  fn run(&self, _ctx: &ctx::Context) -> ctx::RunResult {
-    Ok(())
+  fn run(&self, ctx: &ctx::Context) -> Vec<Tool> {
+    let sdk = ctx.env.android_sdk_root.as_ref().or_else(|| ctx.env.android_home.as_ref()).map(PathBuf::from);
+    let ndk = match (&ctx.env.android_ndk_home, &sdk) {
+      (Some(ndk), _)    => Some(PathBuf::from(ndk)),
+      (None, Some(sdk)) => latest_version_dir(&sdk.join("ndk")),
+      (None, None)      => None
+    };
+
+    let mut ndk_tool = probe_dir("Android NDK", true, ndk.clone(),
+                                 "neither ANDROID_NDK_HOME nor ANDROID_SDK_ROOT are set");
+    let revision = ndk.as_ref().and_then(|x| ndk_revision(x));
+    if let (Ok(found), Some(revision)) = (&mut ndk_tool.found, revision) {
+      *found = format!("{} ({})", revision, found);
+    }
+
+    vec![
+      probe_dir("Android SDK", true, sdk, "neither ANDROID_SDK_ROOT nor ANDROID_HOME are set"),
+      ndk_tool,
+      probe_command("Java", true, "java", &["-version"])
+    ]
   }
 }
+
+/// Version of an NDK, as found in its source.properties.
+pub fn ndk_revision(path: &Path) -> Option<String> {
+  let props = std::fs::read_to_string(path.join("source.properties")).ok()?;
+  props.lines()
+    .filter_map(|line| {
+      let mut it = line.splitn(2, '=');
+      match it.next()?.trim() {
+        "Pkg.Revision" => it.next().map(|x| x.trim().to_string()),
+        _              => None
+      }
+    })
+    .next()
+}
@@ -1,4 +1,7 @@
-use crate::{ctx, ctx::{Architecture, PlatformType}};
+use std::path::PathBuf;
+
+use crate::{ctx, ctx::{Architecture, PlatformType, Tool}};
+use super::{probe_command, probe_dir};
 
 pub struct HTML5;
 
@@ -17,7 +20,11 @@ impl ctx::Platform for HTML5 {
     }
   }
 
-  fn run(&self, _ctx: &ctx::Context) -> ctx::RunResult {
-    Ok(())
+  fn run(&self, _ctx: &ctx::Context) -> Vec<Tool> {
+    let emcc = if cfg!(windows) { "emcc.bat" } else { "emcc" };
+    vec![
+      probe_command("Emscripten",     true,  emcc, &["--version"]),
+      probe_dir    ("Emscripten SDK", false, std::env::var_os("EMSDK").map(PathBuf::from), "EMSDK is not set")
+    ]
   }
 }
@@ -1,4 +1,5 @@
-use crate::{ctx, ctx::{Architecture, PlatformType}};
+use crate::{ctx, ctx::{Architecture, PlatformType, Tool}};
+use super::probe_command;
 
 pub struct Linux;
 
@@ -17,7 +18,11 @@ impl ctx::Platform for Linux {
     }
   }
 
-  fn run(&self, _ctx: &ctx::Context) -> ctx::RunResult {
-    Ok(())
+  fn run(&self, _ctx: &ctx::Context) -> Vec<Tool> {
+    vec![
+      probe_command("C++ compiler", true,  "c++",   &["--version"]),
+      probe_command("CMake",        true,  "cmake", &["--version"]),
+      probe_command("Ninja",        false, "ninja", &["--version"])
+    ]
   }
 }
@@ -1,4 +1,5 @@
-use crate::{ctx, ctx::{Architecture, PlatformType}};
+use crate::{ctx, ctx::{Architecture, PlatformType, Tool}};
+use super::{probe_command, probe_host};
 
 pub struct MacOS;
 
@@ -17,7 +18,20 @@ impl ctx::Platform for MacOS {
     }
   }
 
-  fn run(&self, _ctx: &ctx::Context) -> ctx::RunResult {
-    Ok(())
+  fn run(&self, _ctx: &ctx::Context) -> Vec<Tool> {
+    probe_xcode("macOS SDK", "macosx")
   }
 }
+
+/// Xcode builds every Apple platform, from the SDK of each.
+pub fn probe_xcode(name: &'static str, sdk: &str) -> Vec<Tool> {
+  if !cfg!(target_os = "macos") {
+    return vec![probe_host("Xcode", true, "macOS")];
+  }
+
+  vec![
+    probe_command("Xcode",           true, "xcodebuild",   &["-version"]),
+    probe_command("Developer tools", true, "xcode-select", &["-p"]),
+    probe_command(name,              true, "xcrun",        &["--sdk", sdk, "--show-sdk-version"])
+  ]
+}
@@ -1,4 +1,5 @@
-use crate::{ctx, ctx::{Architecture, PlatformType}};
+use crate::{ctx, ctx::{Architecture, PlatformType, Tool}};
+use super::macos::probe_xcode;
 
 pub struct WatchOS;
 
@@ -17,7 +18,7 @@ impl ctx::Platform for WatchOS {
     }
   }
 
-  fn run(&self, _ctx: &ctx::Context) -> ctx::RunResult {
-    Ok(())
+  fn run(&self, _ctx: &ctx::Context) -> Vec<Tool> {
+    probe_xcode("watchOS SDK", "watchos")
   }
 }
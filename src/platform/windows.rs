@@ -1,4 +1,7 @@
-use crate::{ctx, ctx::{Architecture, PlatformType}};
+use std::path::Path;
+
+use crate::{ctx, ctx::{Architecture, PlatformType, Tool}};
+use super::{latest_version_dir, probe_command, probe_dir, probe_host};
 
 pub struct Windows;
 
@@ -17,7 +20,19 @@ impl ctx::Platform for Windows {
     }
   }
 
-  fn run(&self, _ctx: &ctx::Context) -> ctx::RunResult {
-    Ok(())
+  fn run(&self, _ctx: &ctx::Context) -> Vec<Tool> {
+    if !cfg!(windows) {
+      return vec![probe_host("Visual Studio", true, "Windows"), probe_host("Windows SDK", true, "Windows")];
+    }
+
+    let program_files = std::env::var("ProgramFiles(x86)")
+      .unwrap_or_else(|_| r"C:\Program Files (x86)".to_string());
+    let vswhere = Path::new(&program_files).join(r"Microsoft Visual Studio\Installer\vswhere.exe");
+    let kits    = Path::new(&program_files).join(r"Windows Kits\10\Include");
+    vec![
+      probe_command("Visual Studio", true, &vswhere.to_string_lossy(),
+                    &["-latest", "-property", "catalog_productDisplayVersion"]),
+      probe_dir("Windows SDK", true, latest_version_dir(&kits), "Windows Kits 10 not found")
+    ]
   }
 }
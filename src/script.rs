@@ -0,0 +1,103 @@
+//! Scripts computing settings as the project is loaded, listed in the project's
+//! `scripts`. Written in Rhai, each script returns a map shaped like the
+//! configuration itself, limited to the `project`, `profiles` and `targets`
+//! tables:
+//!
+//! ```rhai
+//! let version = run("git describe --tags");
+//! #{ project: #{ defines: ["GAME_VERSION=\"" + version + "\""] } }
+//! ```
+//!
+//! Results are merged into the project once its files are parsed and included,
+//! before its settings are resolved. The `project` and `targets` tables only
+//! take settings, `profiles` is shaped like the configuration's own. Settings
+//! are merged like profiles merge them: arrays are appended to and other values
+//! replaced.
+//!
+//! Besides the `PROJECT_NAME` and `PROJECT_VERSION` constants, scripts can call
+//! `env(name)`, `read(path)` and `run(command)`. Commands run in a shell from
+//! the input folder, their output is returned without trailing whitespace.
+//!
+//! Scripting is behind the `scripting` feature, builds without it reject the
+//! projects using scripts.
+
+use std::path::{Path, PathBuf};
+
+use crate::ctx::{DynResult, ProjectNames, StrError};
+
+/// Runs the scripts of a project, returning their results as TOML to be parsed
+/// along with the configuration files.
+pub fn results(input_dir: &Path, names: &ProjectNames) -> DynResult<Vec<(PathBuf, Vec<u8>)>> {
+  let mut results = Vec::with_capacity(names.scripts.len());
+  for script in &names.scripts {
+    let path   = input_dir.join(script);
+    let result = eval(input_dir, &path, names)
+      .map_err(|e| StrError(format!("Failed to run script ({:?}): {}", path, e)))?;
+    if !result.is_table() {
+      return Err(Box::new(StrError(format!("Script {} must return a map of settings", script))));
+    }
+
+    let data = toml::to_vec(&result)
+      .map_err(|e| StrError(format!("Failed to read the result of script ({:?}): {}", path, e)))?;
+    results.push((path, data));
+  }
+  Ok(results)
+}
+
+#[cfg(feature = "scripting")]
+fn eval(input_dir: &Path, path: &Path, names: &ProjectNames) -> DynResult<toml::Value> {
+  use rhai::{Dynamic, Engine, EvalAltResult, ImmutableString, Scope};
+
+  let source = std::fs::read_to_string(path)?;
+
+  let mut engine = Engine::new();
+  engine.register_fn("env", |name: ImmutableString| std::env::var(name.as_str()).unwrap_or_default());
+
+  let dir = input_dir.to_path_buf();
+  engine.register_fn("read", move |file: ImmutableString| -> Result<Dynamic, Box<EvalAltResult>> {
+    std::fs::read_to_string(dir.join(file.as_str()))
+      .map(Dynamic::from)
+      .map_err(|e| format!("Failed to read {}: {}", file, e).into())
+  });
+
+  let dir = input_dir.to_path_buf();
+  engine.register_fn("run", move |command: ImmutableString| -> Result<Dynamic, Box<EvalAltResult>> {
+    run(&dir, &command)
+      .map(Dynamic::from)
+      .map_err(|e| e.into())
+  });
+
+  let mut scope = Scope::new();
+  scope.push_constant("PROJECT_NAME",    names.name.clone());
+  scope.push_constant("PROJECT_VERSION", names.version.clone());
+
+  let result = engine.eval_with_scope::<Dynamic>(&mut scope, &source)
+    .map_err(|e| StrError(e.to_string()))?;
+  Ok(rhai::serde::from_dynamic(&result).map_err(|e| StrError(e.to_string()))?)
+}
+
+#[cfg(not(feature = "scripting"))]
+fn eval(_input_dir: &Path, _path: &Path, _names: &ProjectNames) -> DynResult<toml::Value> {
+  Err(Box::new(StrError("janky was built without the scripting feature".to_string())))
+}
+
+/// Runs a shell command from the input folder, the same way as hooks.
+#[cfg(feature = "scripting")]
+fn run(dir: &Path, command: &str) -> Result<String, String> {
+  #[cfg(windows)]
+  let mut cmd = std::process::Command::new("cmd");
+  #[cfg(windows)]
+  cmd.args(&["/C", command]);
+
+  #[cfg(not(windows))]
+  let mut cmd = std::process::Command::new("sh");
+  #[cfg(not(windows))]
+  cmd.args(&["-c", command]);
+
+  let output = cmd.current_dir(dir).output()
+    .map_err(|e| format!("Failed to run {}: {}", command, e))?;
+  if !output.status.success() {
+    return Err(format!("Command failed ({}): {}", output.status, command));
+  }
+  Ok(String::from_utf8_lossy(&output.stdout).trim_end().to_string())
+}
@@ -0,0 +1,218 @@
+//! Positions of the values of a configuration file, as found by the `toml` parser.
+//!
+//! Values deserialized as `toml::Spanned` get the byte range they were parsed
+//! from, which `Node` asks for at every level. Tables written with a `[header]`
+//! are the exception, only their values are spanned.
+//!
+//! Load-time rewrites, ie variable expansion, replace the spans of string values
+//! in the original text. Comments, formatting and line numbers are kept, so the
+//! diagnostics of the rewritten file still point to the lines of the original.
+
+use serde::{Deserialize, Deserializer};
+use serde::de::{IgnoredAny, MapAccess, SeqAccess, Visitor};
+use std::fmt;
+use std::ops::Range;
+
+/// Names `toml` recognizes a `Spanned` by, see `toml::spanned`.
+const SPANNED: &str = "$__toml_private_Spanned";
+const FIELDS:  &[&str] = &["$__toml_private_start", "$__toml_private_end", "$__toml_private_value"];
+
+/// Parsed value along with its position, `None` for tables written with a header.
+#[derive(Debug)]
+pub struct Node {
+  pub span:  Option<Range<usize>>,
+  pub value: Value
+}
+
+#[derive(Debug)]
+pub enum Value {
+  Table(Vec<(String, Node)>),
+  Array(Vec<Node>),
+  String(String),
+  Other
+}
+
+impl Node {
+  pub fn parse(text: &str) -> Result<Node, toml::de::Error> {
+    toml::from_str(text)
+  }
+
+  /// Calls `f` with every spanned string, along with the keys leading to it.
+  /// Strings in arrays have the key of their array.
+  pub fn visit_strings<'n, F>(&'n self, keys: &mut Vec<&'n str>, f: &mut F) where
+    F: FnMut(&[&'n str], &'n str, &Range<usize>)
+  {
+    match &self.value {
+      Value::Table(entries) => {
+        for (key, node) in entries {
+          keys.push(key);
+          node.visit_strings(keys, f);
+          keys.pop();
+        }
+      },
+      Value::Array(nodes) => nodes.iter().for_each(|x| x.visit_strings(keys, f)),
+      Value::String(s)    => if let Some(span) = &self.span {
+        f(keys, s, span);
+      },
+      Value::Other => {}
+    }
+  }
+
+  /// Deepest spanned value along the key, with the number of keys matched.
+  pub fn find(&self, key: &[&str]) -> Option<(usize, usize)> {
+    let own  = self.span.as_ref().map(|x| (0, x.start));
+    let next = match (&self.value, key.split_first()) {
+      (Value::Table(entries), Some((first, rest))) => {
+        entries.iter().filter(|x| x.0 == *first).filter_map(|x| x.1.find(rest)).max_by_key(|x| x.0)
+      },
+      _ => None
+    };
+    next.map(|(depth, pos)| (depth + 1, pos)).or(own)
+  }
+}
+
+/// Replaces byte ranges of the text, given in any order and without overlaps.
+pub fn replace(text: &str, mut edits: Vec<(Range<usize>, String)>) -> String {
+  edits.sort_by_key(|x| x.0.start);
+  let mut out = String::with_capacity(text.len());
+  let mut pos = 0;
+  for (range, s) in edits {
+    out.push_str(&text[pos .. range.start]);
+    out.push_str(&s);
+    pos = range.end;
+  }
+  out.push_str(&text[pos ..]);
+  out
+}
+
+/// Quotes a string as TOML, spanning as many lines as the text it replaces.
+/// Literal strings are preferred, the project borrows their contents as they are.
+pub fn quote(s: &str, replaced: &str) -> String {
+  let lines   = replaced.matches('\n').count();
+  let literal = !s.contains('\'') && !s.chars().any(|c| c.is_control() && c != '\n' && c != '\t');
+  match lines.checked_sub(s.matches('\n').count()) {
+    Some(0) if literal && lines == 0           => return ["'", s, "'"].join(""),
+    Some(0) if literal && !s.starts_with('\n') => return ["'''", s, "'''"].join(""),
+    Some(1) if literal                         => return ["'''\n", s, "'''"].join(""),
+    _ => {}
+  }
+
+  // Line breaks are trimmed by the backslash ending the first line.
+  let mut out = String::with_capacity(s.len() + 2);
+  for c in s.chars() {
+    match c {
+      '"'  => out.push_str("\\\""),
+      '\\' => out.push_str("\\\\"),
+      '\n' => out.push_str("\\n"),
+      '\r' => out.push_str("\\r"),
+      '\t' => out.push_str("\\t"),
+      c if c.is_control() => out.push_str(&format!("\\u{:04X}", c as u32)),
+      c => out.push(c)
+    }
+  }
+  match lines {
+    0 => ["\"", &out, "\""].join(""),
+    n => ["\"\"\"", &out, "\\", &"\n".repeat(n), "\"\"\""].join("")
+  }
+}
+
+impl<'de> Deserialize<'de> for Node {
+  fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+    d.deserialize_struct(SPANNED, FIELDS, NodeVisitor)
+  }
+}
+
+impl<'de> Deserialize<'de> for Value {
+  fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+    d.deserialize_any(ValueVisitor)
+  }
+}
+
+/// Spanned values are maps of their start, end and value. Tables written with a
+/// header are given as their own maps instead, arrays of them as sequences.
+struct NodeVisitor;
+
+impl<'de> Visitor<'de> for NodeVisitor {
+  type Value = Node;
+
+  fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    f.write_str("a TOML value")
+  }
+
+  fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Node, A::Error> {
+    let first = match map.next_key::<String>()? {
+      None    => return Ok(Node { span: None, value: Value::Table(Vec::new()) }),
+      Some(x) => x
+    };
+
+    if first != FIELDS[0] {
+      let mut entries = vec![(first, map.next_value::<Node>()?)];
+      while let Some(key) = map.next_key::<String>()? {
+        entries.push((key, map.next_value::<Node>()?));
+      }
+      return Ok(Node { span: None, value: Value::Table(entries) });
+    }
+
+    let start = map.next_value::<usize>()?;
+    map.next_key::<IgnoredAny>()?;
+    let end   = map.next_value::<usize>()?;
+    map.next_key::<IgnoredAny>()?;
+    let value = map.next_value::<Value>()?;
+    Ok(Node { span: Some(start .. end), value })
+  }
+
+  fn visit_seq<A: SeqAccess<'de>>(self, seq: A) -> Result<Node, A::Error> {
+    ValueVisitor.visit_seq(seq).map(|value| Node { span: None, value })
+  }
+
+  // Only the fields of datetimes are given without a span.
+  fn visit_str<E>(self, s: &str) -> Result<Node, E> {
+    Ok(Node { span: None, value: Value::String(s.to_string()) })
+  }
+}
+
+struct ValueVisitor;
+
+impl<'de> Visitor<'de> for ValueVisitor {
+  type Value = Value;
+
+  fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    f.write_str("a TOML value")
+  }
+
+  fn visit_str<E>(self, s: &str) -> Result<Value, E> {
+    Ok(Value::String(s.to_string()))
+  }
+
+  fn visit_bool<E>(self, _: bool) -> Result<Value, E> {
+    Ok(Value::Other)
+  }
+
+  fn visit_i64<E>(self, _: i64) -> Result<Value, E> {
+    Ok(Value::Other)
+  }
+
+  fn visit_u64<E>(self, _: u64) -> Result<Value, E> {
+    Ok(Value::Other)
+  }
+
+  fn visit_f64<E>(self, _: f64) -> Result<Value, E> {
+    Ok(Value::Other)
+  }
+
+  fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Value, A::Error> {
+    let mut entries = Vec::new();
+    while let Some(key) = map.next_key::<String>()? {
+      entries.push((key, map.next_value::<Node>()?));
+    }
+    Ok(Value::Table(entries))
+  }
+
+  fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Value, A::Error> {
+    let mut nodes = Vec::new();
+    while let Some(x) = seq.next_element::<Node>()? {
+      nodes.push(x);
+    }
+    Ok(Value::Array(nodes))
+  }
+}